@@ -0,0 +1,24 @@
+#!/usr/bin/env bash
+# Measures creating, voting and listing at 10k, 100k and 1M proposals, see src/final_project_backend/src/bench.rs.
+# Needs a running local replica (dfx start --clean --background). Never point this at a real deployment.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+canister=final_project_backend
+cargo build --release --target wasm32-unknown-unknown --package "$canister" --features bench
+dfx canister create "$canister"
+dfx canister install "$canister" --mode reinstall --yes \
+    --wasm "target/wasm32-unknown-unknown/release/$canister.wasm"
+
+seeded=0
+for target in 10000 100000 1000000; do
+    dfx canister call "$canister" seed_proposals "($((target - seeded)) : nat64)"
+    while [ "$(dfx canister call "$canister" get_seeding_remaining --query)" != "(0 : nat64)" ]; do
+        sleep 5
+    done
+    seeded=$target
+
+    echo "== $target proposals"
+    dfx canister call "$canister" run_benchmarks
+done
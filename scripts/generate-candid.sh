@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+# Regenerates the .did files from the Rust code, so they can never drift from the canisters.
+# Needs candid-extractor: cargo install candid-extractor
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+for canister in final_project_backend final_project_archive; do
+    cargo build --release --target wasm32-unknown-unknown --package "$canister"
+    candid-extractor "target/wasm32-unknown-unknown/release/$canister.wasm" > "src/$canister/$canister.did"
+done
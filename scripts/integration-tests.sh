@@ -0,0 +1,24 @@
+#!/usr/bin/env bash
+# Runs the PocketIC tests in src/final_project_backend/tests against a fresh release build.
+# Needs the PocketIC server that matches the pocket-ic crate, from https://github.com/dfinity/pocketic/releases,
+# with POCKET_IC_BIN pointing at it.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+if [ -z "${POCKET_IC_BIN:-}" ]; then
+    echo "POCKET_IC_BIN isn't set" >&2
+    exit 1
+fi
+
+# The first release that was deployed, the upgrade test starts from its wasm.
+BASELINE=147e2f07274f91419b1c39e33f8f85a58cff35d1
+
+baseline_dir="$(mktemp -d)"
+trap 'git worktree remove --force "$baseline_dir"' EXIT
+git worktree add --detach "$baseline_dir" "$BASELINE"
+CARGO_TARGET_DIR="$PWD/target/baseline" cargo build --release --target wasm32-unknown-unknown \
+    --manifest-path "$baseline_dir/Cargo.toml" --package final_project_backend
+
+cargo build --release --target wasm32-unknown-unknown --package final_project_backend
+cargo test --package final_project_backend --test state_machine -- --ignored
@@ -0,0 +1,100 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{
+    BoundedStorable, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable,
+};
+use std::{borrow::Cow, cell::RefCell};
+
+/*
+    The archive keeps the proposals the backend moved out of its own memory.
+    It doesn't know what a proposal looks like, it stores the Candid bytes the backend sent
+    and hands them back as they are. Only the backend (its owner) can write.
+*/
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Same bound the backend uses for a stored proposal.
+const MAX_VALUE_SIZE: u32 = 5000;
+
+const OWNER_MEMORY_ID: MemoryId = MemoryId::new(0);
+const PROPOSAL_MEMORY_ID: MemoryId = MemoryId::new(1);
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct ArchivedProposal(Vec<u8>);
+
+impl Storable for ArchivedProposal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ArchivedProposal(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for ArchivedProposal {
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StorablePrincipal(Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_slice().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(bytes.as_ref()))
+    }
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // The backend canister, set on install.
+    static OWNER: RefCell<StableCell<StorablePrincipal, Memory>> = RefCell::new(StableCell::init(get_memory(OWNER_MEMORY_ID), StorablePrincipal(Principal::anonymous())).unwrap());
+
+    // Proposal key -> the proposal, Candid encoded by the backend.
+    static PROPOSALS: RefCell<StableBTreeMap<u64, ArchivedProposal, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PROPOSAL_MEMORY_ID)));
+}
+
+fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+#[ic_cdk::init]
+fn init(owner: Principal) {
+    OWNER.with(|o| o.borrow_mut().set(StorablePrincipal(owner)).unwrap());
+}
+
+// Appending a key twice replaces the older copy, so the backend can simply retry.
+#[ic_cdk::update]
+fn append_proposals(proposals: Vec<(u64, Vec<u8>)>) {
+    if ic_cdk::caller() != OWNER.with(|o| o.borrow().get().0) {
+        ic_cdk::trap("Only the owner can append to the archive.");
+    }
+
+    PROPOSALS.with(|p| {
+        let mut map = p.borrow_mut();
+
+        for (key, bytes) in proposals {
+            if bytes.len() > MAX_VALUE_SIZE as usize {
+                ic_cdk::trap("Proposal too large.");
+            }
+            map.insert(key, ArchivedProposal(bytes));
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_archived_proposal(key: u64) -> Option<Vec<u8>> {
+    PROPOSALS.with(|p| p.borrow().get(&key)).map(|p| p.0)
+}
+
+#[ic_cdk::query]
+fn get_archived_count() -> u64 {
+    PROPOSALS.with(|p| p.borrow().len())
+}
+
+ic_cdk::export_candid!();
@@ -0,0 +1,177 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    audit, authenticated_caller, changes, config, get_memory, listing::ListingStatus, rate_limit,
+    visibility, Memory, Proposal, Role, StorablePrincipal, VoteError, ABUSE_COUNT_MEMORY_ID,
+    ABUSE_REPORT_MEMORY_ID, MAX_BATCH_SIZE, PROPOSAL_MAP,
+};
+
+const MAX_REASON_LEN: usize = 500;
+
+/*
+    Anybody who can see a proposal can report it once. When the reports reach the
+    configured threshold the proposal is `Suspended`: it drops out of listings and takes
+    no votes until a moderator dismisses the reports.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct AbuseReport {
+    reason: String,
+    reported_at: u64,
+}
+
+impl Storable for AbuseReport {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AbuseReport {
+    const MAX_SIZE: u32 = MAX_REASON_LEN as u32 + 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ReportedProposal {
+    key: u64,
+    reports: u64,
+    suspended: bool,
+}
+
+thread_local! {
+    // (proposal key, reporter) -> report.
+    static REPORTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), AbuseReport, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ABUSE_REPORT_MEMORY_ID)));
+
+    // Proposal key -> how many reports it has, only for reported proposals.
+    static COUNTS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ABUSE_COUNT_MEMORY_ID)));
+}
+
+pub(crate) fn remove(key: u64) {
+    REPORTS.with(|r| {
+        let mut map = r.borrow_mut();
+        let reporters: Vec<(u64, StorablePrincipal)> = map
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in reporters {
+            map.remove(&entry);
+        }
+    });
+    COUNTS.with(|c| c.borrow_mut().remove(&key));
+}
+
+fn set_listing(key: u64, mut proposal: Proposal, status: ListingStatus) {
+    proposal.listing = status;
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+}
+
+#[ic_cdk::update]
+fn report_proposal(key: u64, reason: String) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    if reason.is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(VoteError::InvalidReason);
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if visibility::can_see(&value, &caller) => value,
+        _ => return Err(VoteError::NoSuchProposal),
+    };
+
+    let entry: (u64, StorablePrincipal) = (key, StorablePrincipal(caller));
+    if REPORTS.with(|r| r.borrow().contains_key(&entry)) {
+        return Err(VoteError::AlreadyReported);
+    }
+
+    let report: AbuseReport = AbuseReport {
+        reason,
+        reported_at: ic_cdk::api::time(),
+    };
+    REPORTS.with(|r| r.borrow_mut().insert(entry, report));
+
+    let count: u64 = COUNTS.with(|c| c.borrow().get(&key)).unwrap_or(0) + 1;
+    COUNTS.with(|c| c.borrow_mut().insert(key, count));
+
+    // Only what is out in the open gets suspended, drafts and submissions aren't listed anyway.
+    let over_threshold: bool =
+        config::report_threshold().is_some_and(|threshold| count >= threshold as u64);
+
+    if over_threshold && proposal.listing == ListingStatus::Listed {
+        set_listing(key, proposal, ListingStatus::Suspended);
+        audit::record(ic_cdk::id(), key, audit::AuditEvent::ProposalSuspended);
+    }
+
+    Ok(())
+}
+
+// Drops the reports of a proposal and lifts its suspension.
+#[ic_cdk::update]
+fn dismiss_reports(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_moderator(&caller) {
+        return Err(VoteError::access_rejected(Role::Moderator, caller));
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    remove(key);
+
+    if proposal.listing == ListingStatus::Suspended {
+        set_listing(key, proposal, ListingStatus::Listed);
+    }
+
+    audit::record(caller, key, audit::AuditEvent::ReportsDismissed);
+    Ok(())
+}
+
+// In key order, for moderators only.
+#[ic_cdk::query]
+fn list_reported_proposals(offset: u64, limit: u64) -> Vec<ReportedProposal> {
+    if !config::is_moderator(&ic_cdk::caller()) {
+        return vec![];
+    }
+
+    COUNTS.with(|c| {
+        c.borrow()
+            .range(offset..)
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|(key, reports)| ReportedProposal {
+                key,
+                reports,
+                suspended: PROPOSAL_MAP
+                    .with(|p| p.borrow().get(&key))
+                    .is_some_and(|proposal| proposal.listing == ListingStatus::Suspended),
+            })
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_proposal_reports(key: u64, offset: u64, limit: u64) -> Vec<(Principal, AbuseReport)> {
+    if !config::is_moderator(&ic_cdk::caller()) {
+        return vec![];
+    }
+
+    REPORTS.with(|r| {
+        r.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .skip(offset as usize)
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|((_, reporter), report)| (reporter.0, report))
+            .collect()
+    })
+}
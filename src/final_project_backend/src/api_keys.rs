@@ -0,0 +1,248 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap};
+
+use crate::{
+    attachments::{self, HttpRequest, HttpResponse},
+    authenticated_caller, backup, config, embargo, get_memory, pause, tally, Memory, Proposal,
+    Role, VoteError, API_KEY_HASH_MEMORY_ID, API_KEY_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const MAX_LABEL_LEN: usize = 100;
+const MAX_REQUESTS_PER_MINUTE: u32 = 600;
+const MINUTE: u64 = 60 * 1_000_000_000;
+
+/*
+    Indexers and other servers can't sign calls with a principal, they talk plain HTTP.
+    An admin issues them an opaque key, which they send as `Authorization: Bearer <key>`.
+    Only the SHA-256 of a key is stored, the key itself is shown once when it's issued.
+
+    A key reads every proposal, whatever its visibility. The counts still follow the
+    embargo and the results visibility the same way they do for an anonymous caller.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ApiKey {
+    label: String, // Who it was given to.
+    hash: [u8; 32],
+    requests_per_minute: u32,
+    issued_by: Principal,
+    issued_at: u64,
+    revoked_at: Option<u64>,
+}
+
+impl Storable for ApiKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ApiKey {
+    const MAX_SIZE: u32 = 300;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct IssuedApiKey {
+    id: u64,
+    key: String, // Not stored anywhere, nobody can show it again.
+}
+
+thread_local! {
+    static API_KEYS: RefCell<StableBTreeMap<u64, ApiKey, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(API_KEY_MEMORY_ID)));
+
+    // Hash of the key -> its id.
+    static BY_HASH: RefCell<StableBTreeMap<[u8; 32], u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(API_KEY_HASH_MEMORY_ID)));
+
+    // Key id -> (start of the current minute, requests in it). On the heap like `rate_limit`.
+    static USAGE: RefCell<HashMap<u64, (u64, u32)>> = RefCell::new(HashMap::new());
+}
+
+fn hash(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+pub(crate) fn is_api_path(path: &str) -> bool {
+    path.starts_with("/api/")
+}
+
+#[ic_cdk::update]
+async fn issue_api_key(label: String, requests_per_minute: u32) -> Result<IssuedApiKey, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    if label.is_empty()
+        || label.len() > MAX_LABEL_LEN
+        || requests_per_minute == 0
+        || requests_per_minute > MAX_REQUESTS_PER_MINUTE
+    {
+        return Err(VoteError::InvalidApiKey);
+    }
+
+    let key: String = match raw_rand().await {
+        Ok((bytes,)) => hex::encode(bytes),
+        Err(_) => return Err(VoteError::RandomnessUnavailable),
+    };
+
+    let id: u64 = API_KEYS.with(|k| k.borrow().last_key_value().map_or(0, |(id, _)| id + 1));
+    let record: ApiKey = ApiKey {
+        label,
+        hash: hash(&key),
+        requests_per_minute,
+        issued_by: caller,
+        issued_at: ic_cdk::api::time(),
+        revoked_at: None,
+    };
+
+    BY_HASH.with(|b| b.borrow_mut().insert(record.hash, id));
+    API_KEYS.with(|k| k.borrow_mut().insert(id, record));
+
+    Ok(IssuedApiKey { id, key })
+}
+
+// The record stays, so the admins can still see which keys existed.
+#[ic_cdk::update]
+fn revoke_api_key(id: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    let mut record: ApiKey = match API_KEYS.with(|k| k.borrow().get(&id)) {
+        Some(value) if value.revoked_at.is_none() => value,
+        _ => return Err(VoteError::InvalidApiKey),
+    };
+
+    BY_HASH.with(|b| b.borrow_mut().remove(&record.hash));
+    record.revoked_at = Some(ic_cdk::api::time());
+    API_KEYS.with(|k| k.borrow_mut().insert(id, record));
+    USAGE.with(|u| u.borrow_mut().remove(&id));
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_api_keys() -> Vec<(u64, ApiKey)> {
+    if !config::is_admin(&ic_cdk::caller()) {
+        return vec![];
+    }
+
+    API_KEYS.with(|k| k.borrow().iter().collect())
+}
+
+// The key id the request carries a valid key for.
+fn authorize(request: &HttpRequest) -> Option<(u64, ApiKey)> {
+    let key: &str = request
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.strip_prefix("Bearer "))?;
+
+    let id: u64 = BY_HASH.with(|b| b.borrow().get(&hash(key.trim())))?;
+    API_KEYS
+        .with(|k| k.borrow().get(&id))
+        .filter(|record| record.revoked_at.is_none())
+        .map(|record| (id, record))
+}
+
+// A fixed window per minute, good enough to keep one indexer from eating all the cycles.
+fn take_request(id: u64, limit: u32) -> bool {
+    let minute: u64 = ic_cdk::api::time() / MINUTE * MINUTE;
+
+    USAGE.with(|u| {
+        let mut usage = u.borrow_mut();
+        let (start, count) = usage.entry(id).or_insert((minute, 0));
+
+        if *start != minute {
+            *start = minute;
+            *count = 0;
+        }
+
+        if *count >= limit {
+            return false;
+        }
+
+        *count += 1;
+        true
+    })
+}
+
+fn json(status_code: u16, body: Vec<u8>) -> HttpResponse {
+    HttpResponse::new(
+        status_code,
+        vec![("Content-Type".to_string(), "application/json".to_string())],
+        body,
+    )
+}
+
+fn error(status_code: u16, message: &str) -> HttpResponse {
+    json(
+        status_code,
+        serde_json::json!({ "error": message })
+            .to_string()
+            .into_bytes(),
+    )
+}
+
+/*
+    `/api/proposals/<key>` and `/api/tallies/<key>`, as JSON.
+    `http_request` sends these here, the gateway retries them as an update call.
+*/
+#[ic_cdk::update]
+fn http_request_update(request: HttpRequest) -> HttpResponse {
+    if backup::check().is_err() {
+        return error(503, "Unavailable while a backup is restored.");
+    }
+
+    if pause::check().is_err() {
+        return error(503, "The canister is paused.");
+    }
+
+    let path: &str = request.url.split('?').next().unwrap_or("");
+
+    if request.method != "GET" || !is_api_path(path) {
+        return attachments::not_found();
+    }
+
+    let (id, record) = match authorize(&request) {
+        Some(value) => value,
+        None => return error(401, "Missing or invalid API key."),
+    };
+
+    if !take_request(id, record.requests_per_minute) {
+        return error(429, "Too many requests.");
+    }
+
+    let (resource, key) = match path["/api/".len()..].split_once('/') {
+        Some((resource, key)) => match key.parse::<u64>() {
+            Ok(key) => (resource, key),
+            Err(_) => return error(404, "Not found."),
+        },
+        None => return error(404, "Not found."),
+    };
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return error(404, "No such proposal."),
+    };
+
+    let body: Result<Vec<u8>, serde_json::Error> = match resource {
+        "proposals" => serde_json::to_vec(&embargo::redact(proposal, &Principal::anonymous())),
+        "tallies" => serde_json::to_vec(&tally::present(&proposal, &Principal::anonymous())),
+        _ => return error(404, "Not found."),
+    };
+
+    match body {
+        Ok(body) => json(200, body),
+        Err(_) => error(500, "Could not encode the response."),
+    }
+}
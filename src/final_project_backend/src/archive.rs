@@ -0,0 +1,239 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::Cell, cell::RefCell, time::Duration};
+
+use crate::{
+    abuse, ballot_chain, changes, config, deposits, drafts, execution, gc, get_memory, invites,
+    nft_gate, reports, snapshot, sns, stats, tally_history, timers, upgrade, visibility,
+    voter_list, workflow, Memory, Proposal, ProposalView, ARCHIVE_MEMORY_ID, MAX_BATCH_SIZE,
+    PROPOSAL_MAP,
+};
+
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// How many proposals one run looks at, so a map full of open proposals can't eat the instruction limit.
+const MAX_SCAN: usize = 1000;
+
+/*
+    Once `PROPOSAL_MAP` holds more than `threshold` proposals, finalized ones are moved
+    to the archive canister (see `final_project_archive`). Nothing is lost: `find_proposal`
+    looks in the archive for anything that isn't here anymore.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ArchiveConfig {
+    canister: Principal,
+    threshold: u64,
+}
+
+// Remembered per proposal, so proposals stay reachable when the config points to a new archive.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct ArchiveLocation {
+    canister: Principal,
+    archived_at: u64,
+}
+
+impl Storable for ArchiveLocation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ArchiveLocation {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> where it went. Archived keys can't be used for new proposals.
+    static ARCHIVED: RefCell<StableBTreeMap<u64, ArchiveLocation, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ARCHIVE_MEMORY_ID)));
+
+    // Set while a batch is on its way to the archive, so two runs never send the same proposals.
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Clears `RUNNING` even when the run traps after its await.
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        RUNNING.with(|r| r.set(false));
+    }
+}
+
+pub(crate) fn start_archiving() {
+    timers::set_timer_interval(ARCHIVE_INTERVAL, || ic_cdk::spawn(run()));
+}
+
+pub(crate) fn is_archived(key: u64) -> bool {
+    ARCHIVED.with(|a| a.borrow().contains_key(&key))
+}
+
+pub(crate) fn last_key() -> Option<u64> {
+    ARCHIVED.with(|a| a.borrow().last_key_value().map(|(key, _)| key))
+}
+
+// Finalized means nothing will ever change it again: voting is over and nothing is left to run or pay back.
+pub(crate) fn finalized(key: u64, proposal: &Proposal) -> bool {
+    !proposal.is_active
+        && proposal.results_hidden_until.is_none()
+        && !workflow::is_running(key)
+        && !drafts::is_pending(key)
+        && execution::is_settled(key)
+        && deposits::is_settled(key)
+}
+
+async fn run() {
+    let archive: ArchiveConfig = match config::archive() {
+        Some(value) => value,
+        None => return,
+    };
+
+    let count: u64 = PROPOSAL_MAP.with(|p| p.borrow().len());
+    if count <= archive.threshold {
+        return;
+    }
+    let excess: usize = ((count - archive.threshold) as usize).min(MAX_BATCH_SIZE);
+
+    let keys: Vec<u64> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .iter()
+            .take(MAX_SCAN)
+            .filter(|(key, proposal)| finalized(*key, proposal))
+            .take(excess)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    move_to_archive(keys).await;
+}
+
+// Also used by the garbage collector. Keys that aren't finalized (anymore) are left alone.
+pub(crate) async fn move_to_archive(keys: Vec<u64>) {
+    let archive: ArchiveConfig = match config::archive() {
+        Some(value) => value,
+        None => return,
+    };
+
+    if RUNNING.with(|r| r.get()) {
+        return;
+    }
+
+    let batch: Vec<(u64, Vec<u8>)> = keys
+        .into_iter()
+        .take(MAX_BATCH_SIZE)
+        .filter_map(|key| {
+            let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+            finalized(key, &proposal).then(|| (key, proposal.to_bytes().into_owned()))
+        })
+        .collect();
+
+    if batch.is_empty() {
+        return;
+    }
+
+    RUNNING.with(|r| r.set(true));
+    let _guard = RunGuard;
+
+    let res: Result<(), _> =
+        ic_cdk::call(archive.canister, "append_proposals", (batch.clone(),)).await;
+
+    if res.is_err() {
+        return;
+    }
+
+    let archived_at: u64 = ic_cdk::api::time();
+
+    for (key, bytes) in batch {
+        // Only drop what the archive really has, a proposal edited in the meantime stays here.
+        let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+            Some(value) if value.to_bytes().as_ref() == bytes.as_slice() => value,
+            _ => continue,
+        };
+
+        PROPOSAL_MAP.with(|p| p.borrow_mut().remove(&key));
+        stats::keep_archived(key);
+        snapshot::remove(key);
+        nft_gate::remove(key);
+        sns::remove(key);
+        gc::remove(key);
+        tally_history::remove(key);
+        voter_list::remove(key);
+        invites::remove(key);
+        ballot_chain::remove_links(key);
+        abuse::remove(key);
+        upgrade::remove(key);
+        // The report outlives the proposal here, unless nobody but its access list may see it.
+        if !visibility::is_public(&proposal) {
+            reports::remove(key);
+        }
+        ARCHIVED.with(|a| {
+            a.borrow_mut().insert(
+                key,
+                ArchiveLocation {
+                    canister: archive.canister,
+                    archived_at,
+                },
+            )
+        });
+        changes::record_change(key);
+    }
+}
+
+/*
+    Takes `voter` off the voters of an archived proposal, for `purge_my_data`. The archive
+    replaces a key that is appended again, so its copy is read, changed and written back.
+*/
+pub(crate) async fn forget_voter(key: u64, voter: Principal) -> Result<(), String> {
+    let location: ArchiveLocation = match ARCHIVED.with(|a| a.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    let res: Result<(Option<Vec<u8>>,), _> =
+        ic_cdk::call(location.canister, "get_archived_proposal", (key,)).await;
+    let bytes: Vec<u8> = match res {
+        Ok((Some(bytes),)) => bytes,
+        Ok((None,)) => return Ok(()),
+        Err((code, msg)) => {
+            return Err(format!("get_archived_proposal failed: {:?} {}", code, msg))
+        }
+    };
+
+    let mut proposal: Proposal = Proposal::from_bytes(Cow::Owned(bytes));
+    if !proposal.voted.contains(&voter) {
+        return Ok(());
+    }
+    proposal.voted.retain(|v| *v != voter);
+    proposal.compacted_voters = Some(proposal.compacted_voters.unwrap_or(0) + 1);
+
+    let batch: Vec<(u64, Vec<u8>)> = vec![(key, proposal.to_bytes().into_owned())];
+    let res: Result<(), _> = ic_cdk::call(location.canister, "append_proposals", (batch,)).await;
+
+    res.map_err(|(code, msg)| format!("append_proposals failed: {:?} {}", code, msg))
+}
+
+// Only from a composite query, the archive is asked with a query call.
+pub(crate) async fn fetch(key: u64, caller: &Principal) -> Option<ProposalView> {
+    let location: ArchiveLocation = ARCHIVED.with(|a| a.borrow().get(&key))?;
+    let res: Result<(Option<Vec<u8>>,), _> =
+        ic_cdk::call(location.canister, "get_archived_proposal", (key,)).await;
+
+    let bytes: Vec<u8> = res.ok()?.0?;
+    visibility::present(Proposal::from_bytes(Cow::Owned(bytes)), caller)
+}
+
+// Like `get_proposal`, but also finds proposals that were moved to the archive.
+#[ic_cdk::query(composite = true)]
+async fn find_proposal(key: u64) -> Option<ProposalView> {
+    let caller: Principal = ic_cdk::caller();
+
+    if let Some(proposal) = PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        return visibility::present(proposal, &caller);
+    }
+
+    fetch(key, &caller).await
+}
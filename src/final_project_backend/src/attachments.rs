@@ -0,0 +1,445 @@
+use candid::{define_function, CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    api_keys, authenticated_caller, get_memory, metrics, visibility, workflow, Memory, Role,
+    VoteError, ATTACHMENT_CHUNK_MEMORY_ID, ATTACHMENT_INDEX_MEMORY_ID, ATTACHMENT_MEMORY_ID,
+    PROPOSAL_MAP,
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_ATTACHMENTS_PER_PROPOSAL: usize = 5;
+const MAX_BYTES_PER_PROPOSAL: u64 = 10 * 1024 * 1024;
+const MAX_NAME_LEN: usize = 100;
+const MAX_CONTENT_TYPE_LEN: usize = 100;
+
+/*
+    Documents are uploaded in chunks, because one message can't carry a whole PDF:
+    `begin_attachment` reserves the space, `upload_chunk` fills it in any order and
+    `commit_attachment` checks everything arrived. Only committed attachments can be downloaded,
+    from `/attachments/<id>` over HTTP (on the raw domain, the responses aren't certified).
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Attachment {
+    proposal_key: u64,
+    name: String,
+    content_type: String,
+    size: u64,
+    chunk_count: u32,
+    uploader: Principal,
+    created_at: u64,
+    sha256: Option<Vec<u8>>, // Set on commit, `None` while the upload is still going.
+}
+
+impl Storable for Attachment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Attachment {
+    const MAX_SIZE: u32 = 500;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Chunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Attachment id -> what it is.
+    static ATTACHMENTS: RefCell<StableBTreeMap<u64, Attachment, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ATTACHMENT_MEMORY_ID)));
+
+    // (attachment id, chunk index) -> bytes. Lives in its own memory, it's by far the largest structure.
+    static CHUNKS: RefCell<StableBTreeMap<(u64, u32), Chunk, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ATTACHMENT_CHUNK_MEMORY_ID)));
+
+    // (proposal key, attachment id), to find the attachments of a proposal.
+    static BY_PROPOSAL: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ATTACHMENT_INDEX_MEMORY_ID)));
+}
+
+fn attachments_of(key: u64) -> Vec<(u64, Attachment)> {
+    let ids: Vec<u64> = BY_PROPOSAL.with(|b| {
+        b.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+
+    ATTACHMENTS.with(|a| {
+        let map = a.borrow();
+        ids.into_iter()
+            .filter_map(|id| map.get(&id).map(|attachment| (id, attachment)))
+            .collect()
+    })
+}
+
+fn expected_chunk_len(attachment: &Attachment, index: u32) -> usize {
+    if index + 1 < attachment.chunk_count {
+        CHUNK_SIZE
+    } else {
+        attachment.size as usize - (attachment.chunk_count as usize - 1) * CHUNK_SIZE
+    }
+}
+
+fn remove_attachment(id: u64) {
+    let attachment: Attachment = match ATTACHMENTS.with(|a| a.borrow_mut().remove(&id)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    CHUNKS.with(|c| {
+        let mut map = c.borrow_mut();
+
+        for index in 0..attachment.chunk_count {
+            map.remove(&(id, index));
+        }
+    });
+    BY_PROPOSAL.with(|b| b.borrow_mut().remove(&(attachment.proposal_key, id)));
+}
+
+// Called when the proposal itself goes away.
+pub(crate) fn remove(key: u64) {
+    for (id, _) in attachments_of(key) {
+        remove_attachment(id);
+    }
+}
+
+// Returns the id to upload the chunks to.
+#[ic_cdk::update]
+fn begin_attachment(
+    key: u64,
+    name: String,
+    content_type: String,
+    size: u64,
+) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(proposal) if proposal.owner == caller => {}
+        Some(_) => return Err(VoteError::access_rejected(Role::Owner, caller)),
+        None => return Err(VoteError::NoSuchProposal),
+    }
+
+    // Attachments are part of what people vote on, they change only when the proposal may.
+    if !workflow::editing_allowed(key) {
+        return Err(VoteError::NotAllowedInCurrentStage);
+    }
+
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || content_type.is_empty()
+        || content_type.len() > MAX_CONTENT_TYPE_LEN
+        || size == 0
+    {
+        return Err(VoteError::InvalidAttachment);
+    }
+
+    // Unfinished uploads count as well, otherwise they would be a way around the quota.
+    let existing: Vec<(u64, Attachment)> = attachments_of(key);
+    let used: u64 = existing.iter().map(|(_, a)| a.size).sum();
+
+    if existing.len() >= MAX_ATTACHMENTS_PER_PROPOSAL
+        || used.saturating_add(size) > MAX_BYTES_PER_PROPOSAL
+    {
+        return Err(VoteError::AttachmentQuotaExceeded);
+    }
+
+    let attachment: Attachment = Attachment {
+        proposal_key: key,
+        name,
+        content_type,
+        size,
+        chunk_count: size.div_ceil(CHUNK_SIZE as u64) as u32,
+        uploader: caller,
+        created_at: ic_cdk::api::time(),
+        sha256: None,
+    };
+
+    let id: u64 = ATTACHMENTS.with(|a| {
+        let id: u64 = a.borrow().last_key_value().map_or(0, |(id, _)| id + 1);
+        a.borrow_mut().insert(id, attachment);
+        id
+    });
+    BY_PROPOSAL.with(|b| b.borrow_mut().insert((key, id), ()));
+
+    Ok(id)
+}
+
+// Every chunk is `CHUNK_SIZE` bytes, only the last one may be shorter.
+#[ic_cdk::update]
+fn upload_chunk(id: u64, index: u32, bytes: Vec<u8>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let attachment: Attachment = match ATTACHMENTS.with(|a| a.borrow().get(&id)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchAttachment),
+    };
+
+    if attachment.uploader != caller {
+        return Err(VoteError::access_rejected(Role::Uploader, caller));
+    }
+
+    if attachment.sha256.is_some()
+        || index >= attachment.chunk_count
+        || bytes.len() != expected_chunk_len(&attachment, index)
+    {
+        return Err(VoteError::InvalidAttachment);
+    }
+
+    CHUNKS.with(|c| c.borrow_mut().insert((id, index), Chunk(bytes)));
+    Ok(())
+}
+
+// Returns the SHA-256 of the whole file, so the uploader can check it's what they sent.
+#[ic_cdk::update]
+fn commit_attachment(id: u64) -> Result<Vec<u8>, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let mut attachment: Attachment = match ATTACHMENTS.with(|a| a.borrow().get(&id)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchAttachment),
+    };
+
+    if attachment.uploader != caller {
+        return Err(VoteError::access_rejected(Role::Uploader, caller));
+    }
+
+    if attachment.sha256.is_some() {
+        return Err(VoteError::InvalidAttachment);
+    }
+
+    let mut hasher = Sha256::new();
+
+    let complete: bool = CHUNKS.with(|c| {
+        let map = c.borrow();
+        (0..attachment.chunk_count).all(|index| match map.get(&(id, index)) {
+            Some(chunk) => {
+                hasher.update(&chunk.0);
+                true
+            }
+            None => false,
+        })
+    });
+
+    if !complete {
+        return Err(VoteError::AttachmentIncomplete);
+    }
+
+    let sha256: Vec<u8> = hasher.finalize().to_vec();
+    attachment.sha256 = Some(sha256.clone());
+    ATTACHMENTS.with(|a| a.borrow_mut().insert(id, attachment));
+
+    Ok(sha256)
+}
+
+#[ic_cdk::update]
+fn delete_attachment(id: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let attachment: Attachment = match ATTACHMENTS.with(|a| a.borrow().get(&id)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchAttachment),
+    };
+
+    if attachment.uploader != caller {
+        return Err(VoteError::access_rejected(Role::Uploader, caller));
+    }
+
+    if !workflow::editing_allowed(attachment.proposal_key) {
+        return Err(VoteError::NotAllowedInCurrentStage);
+    }
+
+    remove_attachment(id);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_attachments(key: u64) -> Vec<(u64, Attachment)> {
+    if !visibility::can_see_key(key, &ic_cdk::caller()) {
+        return vec![];
+    }
+
+    attachments_of(key)
+}
+
+/*
+    Bare bones HTTP interface, only what the download needs.
+    Files larger than one chunk are streamed back chunk by chunk through the callback.
+*/
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) url: String,
+    pub(crate) headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct StreamingToken {
+    id: u64,
+    index: u32,
+}
+
+define_function!(CallbackFunc : (StreamingToken) -> (StreamingCallbackResponse) query);
+
+#[derive(Debug, CandidType, Deserialize)]
+enum StreamingStrategy {
+    Callback {
+        callback: CallbackFunc,
+        token: StreamingToken,
+    },
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    streaming_strategy: Option<StreamingStrategy>,
+    upgrade: Option<bool>, // Asks the gateway to send the request again as an update, see `api_keys`.
+}
+
+impl HttpResponse {
+    pub(crate) fn new(status_code: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code,
+            headers,
+            body,
+            streaming_strategy: None,
+            upgrade: None,
+        }
+    }
+
+    pub(crate) fn upgrade() -> Self {
+        HttpResponse {
+            upgrade: Some(true),
+            ..HttpResponse::new(200, vec![], vec![])
+        }
+    }
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct StreamingCallbackResponse {
+    body: Vec<u8>,
+    token: Option<StreamingToken>,
+}
+
+pub(crate) fn not_found() -> HttpResponse {
+    HttpResponse::new(404, vec![], b"Not found".to_vec())
+}
+
+fn chunk(id: u64, index: u32) -> Vec<u8> {
+    CHUNKS
+        .with(|c| c.borrow().get(&(id, index)))
+        .map_or(vec![], |chunk| chunk.0)
+}
+
+fn next_token(attachment: &Attachment, id: u64, index: u32) -> Option<StreamingToken> {
+    if index + 1 < attachment.chunk_count {
+        Some(StreamingToken {
+            id,
+            index: index + 1,
+        })
+    } else {
+        None
+    }
+}
+
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return not_found();
+    }
+
+    let path: &str = request.url.split('?').next().unwrap_or("");
+
+    if path == "/metrics" {
+        return HttpResponse::new(
+            200,
+            vec![(
+                "Content-Type".to_string(),
+                "text/plain; version=0.0.4".to_string(),
+            )],
+            metrics::render().into_bytes(),
+        );
+    }
+
+    // Counting the key's requests changes state, a query would throw that away.
+    if api_keys::is_api_path(path) {
+        return HttpResponse::upgrade();
+    }
+
+    let id: u64 = match path
+        .strip_prefix("/attachments/")
+        .and_then(|id| id.parse().ok())
+    {
+        Some(value) => value,
+        None => return not_found(),
+    };
+
+    let attachment: Attachment = match ATTACHMENTS.with(|a| a.borrow().get(&id)) {
+        Some(value) if value.sha256.is_some() => value,
+        _ => return not_found(),
+    };
+
+    // Plain HTTP comes without a caller, attachments of restricted proposals are never served this way.
+    let public: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&attachment.proposal_key))
+        .is_some_and(|proposal| visibility::is_public(&proposal));
+
+    if !public {
+        return not_found();
+    }
+
+    let streaming_strategy: Option<StreamingStrategy> =
+        next_token(&attachment, id, 0).map(|token| StreamingStrategy::Callback {
+            callback: CallbackFunc::new(
+                ic_cdk::id(),
+                "http_request_streaming_callback".to_string(),
+            ),
+            token,
+        });
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), attachment.content_type.clone()),
+            ("Content-Length".to_string(), attachment.size.to_string()),
+        ],
+        body: chunk(id, 0),
+        streaming_strategy,
+        upgrade: None,
+    }
+}
+
+#[ic_cdk::query]
+fn http_request_streaming_callback(token: StreamingToken) -> StreamingCallbackResponse {
+    let attachment: Attachment = match ATTACHMENTS.with(|a| a.borrow().get(&token.id)) {
+        Some(value) if value.sha256.is_some() => value,
+        _ => ic_cdk::trap("No such attachment."),
+    };
+
+    StreamingCallbackResponse {
+        body: chunk(token.id, token.index),
+        token: next_token(&attachment, token.id, token.index),
+    }
+}
@@ -0,0 +1,165 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{
+    authenticated_caller, config, get_memory, Memory, Proposal, StorablePrincipal, VoteError,
+    SESSION_MEMORY_ID,
+};
+
+// Signed by every attestation, so a signature can't be reused anywhere else.
+const DOMAIN: &[u8] = b"\x13icp-vote-attestation";
+
+// A session is good for a day at most, the voter comes back through the frontend after that.
+const MAX_SESSION_LENGTH: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/*
+    Proposals created with `frontend_only` only take votes from principals who came through
+    the configured frontend canister. The frontend signs a short lived session attestation
+    for the principal that logged in there, the voter hands it in with `attest_session`
+    from that same principal. Scripts calling the canister straight with an agent have no
+    session, so they can't vote on those proposals.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct AttestationConfig {
+    frontend: Principal, // The frontend canister, every attestation names it.
+    public_key: Vec<u8>, // Ed25519, 32 bytes, the frontend signs sessions with its private half.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct SessionAttestation {
+    voter: Principal,
+    issued_at: u64,
+    expires_at: u64,
+    signature: Vec<u8>, // Over `session_message(voter, issued_at, expires_at)`.
+}
+
+thread_local! {
+    // Voter -> when their session runs out.
+    static SESSIONS: RefCell<StableBTreeMap<StorablePrincipal, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SESSION_MEMORY_ID)));
+}
+
+pub(crate) fn validate(attestation: &AttestationConfig) -> bool {
+    attestation.frontend != Principal::anonymous() && attestation.public_key.len() == 32
+}
+
+fn session_message(
+    frontend: Principal,
+    voter: Principal,
+    issued_at: u64,
+    expires_at: u64,
+) -> Vec<u8> {
+    let canister: Principal = ic_cdk::id();
+
+    let mut message: Vec<u8> = DOMAIN.to_vec();
+    for principal in [canister, frontend, voter] {
+        message.push(principal.as_slice().len() as u8);
+        message.extend_from_slice(principal.as_slice());
+    }
+    message.extend_from_slice(&issued_at.to_be_bytes());
+    message.extend_from_slice(&expires_at.to_be_bytes());
+    message
+}
+
+fn verify(attestation: &SessionAttestation, config: &AttestationConfig) -> Result<(), VoteError> {
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    let now: u64 = ic_cdk::api::time();
+    let fresh: bool = attestation.issued_at <= now
+        && now < attestation.expires_at
+        && attestation.expires_at - attestation.issued_at <= MAX_SESSION_LENGTH;
+
+    if !fresh {
+        return Err(VoteError::InvalidAttestation);
+    }
+
+    let message: Vec<u8> = session_message(
+        config.frontend,
+        attestation.voter,
+        attestation.issued_at,
+        attestation.expires_at,
+    );
+
+    let bytes: [u8; 32] = config
+        .public_key
+        .as_slice()
+        .try_into()
+        .map_err(|_| VoteError::InvalidAttestation)?;
+    let key: VerifyingKey =
+        VerifyingKey::from_bytes(&bytes).map_err(|_| VoteError::InvalidAttestation)?;
+    let signature: Signature =
+        Signature::from_slice(&attestation.signature).map_err(|_| VoteError::InvalidAttestation)?;
+
+    key.verify_strict(&message, &signature)
+        .map_err(|_| VoteError::InvalidAttestation)
+}
+
+// Called on creation, a `frontend_only` proposal needs a frontend to come through.
+pub(crate) fn check_available(frontend_only: bool) -> Result<(), VoteError> {
+    if frontend_only && config::attestation().is_none() {
+        return Err(VoteError::AttestationNotConfigured);
+    }
+
+    Ok(())
+}
+
+// Next to the voter list wherever a ballot is cast.
+pub(crate) fn check(proposal: &Proposal, voter: &Principal) -> Result<(), VoteError> {
+    if !proposal.frontend_only {
+        return Ok(());
+    }
+
+    let expires_at: Option<u64> = SESSIONS.with(|s| s.borrow().get(&StorablePrincipal(*voter)));
+
+    if expires_at.is_none_or(|expires_at| ic_cdk::api::time() >= expires_at) {
+        return Err(VoteError::AttestationRequired);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn forget(voter: Principal) {
+    SESSIONS.with(|s| s.borrow_mut().remove(&StorablePrincipal(voter)));
+}
+
+// The attestation has to come from the principal it was issued for.
+#[ic_cdk::update]
+fn attest_session(attestation: SessionAttestation) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let config: AttestationConfig = match config::attestation() {
+        Some(value) => value,
+        None => return Err(VoteError::AttestationNotConfigured),
+    };
+
+    if attestation.voter != caller {
+        return Err(VoteError::InvalidAttestation);
+    }
+    verify(&attestation, &config)?;
+
+    SESSIONS.with(|s| {
+        s.borrow_mut()
+            .insert(StorablePrincipal(caller), attestation.expires_at)
+    });
+    Ok(attestation.expires_at)
+}
+
+// What the frontend signs, so it doesn't have to rebuild the format.
+#[ic_cdk::query]
+fn get_session_message(voter: Principal, issued_at: u64, expires_at: u64) -> Option<Vec<u8>> {
+    let config: AttestationConfig = config::attestation()?;
+    Some(session_message(
+        config.frontend,
+        voter,
+        issued_at,
+        expires_at,
+    ))
+}
+
+// When the caller's session runs out, `None` without one.
+#[ic_cdk::query]
+fn get_my_session() -> Option<u64> {
+    SESSIONS
+        .with(|s| s.borrow().get(&StorablePrincipal(ic_cdk::caller())))
+        .filter(|expires_at| ic_cdk::api::time() < *expires_at)
+}
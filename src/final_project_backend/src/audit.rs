@@ -0,0 +1,206 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{block_log, config, get_memory, Memory, Proposal, AUDIT_LOG_MEMORY_ID, MAX_VALUE_SIZE};
+
+// Largest page `get_audit_log` returns.
+const MAX_AUDIT_PAGE: u64 = 100;
+// Bytes of the description a trimmed tombstone keeps.
+const TRIMMED_DESCRIPTION_LEN: usize = 1000;
+
+/*
+    The audit log records actions that change or remove history,
+    so anybody can check afterwards who did what and when.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) enum AuditEvent {
+    // `tombstone` is the proposal exactly as it was right before it got removed, unless it had to be trimmed.
+    ProposalDeleted {
+        tombstone: Box<Proposal>,
+        forced: bool,
+        full_hash: Option<Vec<u8>>, // SHA-256 of the untrimmed tombstone, Candid encoded. `None` if nothing was trimmed.
+    },
+    ProposalVetoed {
+        reason: String,
+    },
+    FlaggedAsSpam,
+    ProposalHidden,
+    ProposalRestored,
+    SubmissionApproved,
+    SubmissionRejected {
+        reason: String,
+    },
+    ProposalSuspended, // By the canister, once the reports reached the threshold.
+    ReportsDismissed,
+    // Only the fields that were set, see `config::ConfigUpdate`.
+    ConfigChanged {
+        update: config::ConfigUpdate,
+    },
+    DeadlineExtended {
+        from: u64,
+        to: u64,
+    },
+    VotingSuspended,
+    VotingResumed,
+    // By `update_config` or an install argument. The whole config with its principal lists doesn't fit a record.
+    ConfigReplaced {
+        config_hash: Vec<u8>, // SHA-256 of the new config, Candid encoded, to compare with `get_config`.
+    },
+    OwnerReassignmentRequested {
+        to: Principal,
+        reason: String,
+        effective_at: u64,
+    },
+    OwnerReassignmentCancelled,
+    // Recorded with the admin who asked for it as the actor.
+    OwnerReassigned {
+        from: Principal,
+        to: Principal,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct AuditRecord {
+    seq: u64,
+    timestamp: u64,
+    actor: Principal,
+    proposal_key: u64, // `u64::MAX` for config changes the admin made without a proposal.
+    event: AuditEvent,
+}
+
+impl Storable for AuditRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AuditRecord {
+    // A record can hold a whole proposal plus a bit of bookkeeping.
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE + 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Sequence number -> record. Records are only ever appended.
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditRecord, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(AUDIT_LOG_MEMORY_ID)));
+}
+
+fn fits(tombstone: &Proposal) -> bool {
+    Encode!(tombstone).map_or(0, |bytes| bytes.len()) <= MAX_VALUE_SIZE as usize
+}
+
+/*
+    Proposals have no size bound of their own any more. A tombstone that doesn't fit drops
+    its voters first (they stay counted), then the parts without a bound: the description is
+    cut short, the survey and the payload go. `full_hash` still pins down what was deleted.
+*/
+fn trim(tombstone: &mut Proposal, full_hash: &mut Option<Vec<u8>>) {
+    let bytes: Vec<u8> = match Encode!(&*tombstone) {
+        Ok(value) if value.len() > MAX_VALUE_SIZE as usize => value,
+        _ => return,
+    };
+    *full_hash = Some(Sha256::digest(&bytes).to_vec());
+
+    tombstone.compacted_voters = Some(tombstone.voter_count());
+    tombstone.voted.clear();
+    if fits(tombstone) {
+        return;
+    }
+
+    let mut end: usize = TRIMMED_DESCRIPTION_LEN.min(tombstone.description.len());
+    while !tombstone.description.is_char_boundary(end) {
+        end -= 1;
+    }
+    tombstone.description.truncate(end);
+    tombstone.survey = None;
+    tombstone.execution = None;
+}
+
+pub(crate) fn record(actor: Principal, proposal_key: u64, mut event: AuditEvent) {
+    if let AuditEvent::ProposalDeleted {
+        tombstone,
+        full_hash,
+        ..
+    } = &mut event
+    {
+        trim(tombstone, full_hash);
+    }
+
+    block_log::append_audit(actor, proposal_key, &event);
+
+    AUDIT_LOG.with(|l| {
+        let seq: u64 = l.borrow().last_key_value().map_or(0, |(seq, _)| seq + 1);
+        let record: AuditRecord = AuditRecord {
+            seq,
+            timestamp: ic_cdk::api::time(),
+            actor,
+            proposal_key,
+            event,
+        };
+        l.borrow_mut().insert(seq, record);
+    });
+}
+
+#[ic_cdk::query]
+fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditRecord> {
+    AUDIT_LOG.with(|l| {
+        l.borrow()
+            .range(offset..)
+            .take(limit.min(MAX_AUDIT_PAGE) as usize)
+            .map(|(_, record)| record)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposal_store;
+
+    #[test]
+    fn leaves_a_small_tombstone_alone() {
+        let mut tombstone: Proposal = proposal_store::sample();
+        let mut full_hash: Option<Vec<u8>> = None;
+        trim(&mut tombstone, &mut full_hash);
+
+        assert_eq!(full_hash, None);
+        assert_eq!(tombstone.voted.len(), 3);
+    }
+
+    #[test]
+    fn trims_a_tombstone_until_it_fits() {
+        let mut tombstone: Proposal = proposal_store::sample();
+        tombstone.description = "é".repeat(50_000);
+        tombstone.voted = (0..3_000u32)
+            .map(|n| Principal::from_slice(&n.to_be_bytes()))
+            .collect();
+
+        let mut full_hash: Option<Vec<u8>> = None;
+        trim(&mut tombstone, &mut full_hash);
+
+        assert!(full_hash.is_some());
+        assert!(tombstone.voted.is_empty());
+        assert_eq!(tombstone.compacted_voters, Some(3_000));
+        assert!(tombstone.description.len() <= TRIMMED_DESCRIPTION_LEN);
+
+        let record: AuditRecord = AuditRecord {
+            seq: 0,
+            timestamp: 0,
+            actor: Principal::anonymous(),
+            proposal_key: 0,
+            event: AuditEvent::ProposalDeleted {
+                tombstone: Box::new(tombstone),
+                forced: true,
+                full_hash,
+            },
+        };
+        assert!(record.to_bytes().len() <= AuditRecord::MAX_SIZE as usize);
+    }
+}
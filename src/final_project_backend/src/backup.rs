@@ -0,0 +1,112 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::stable::{stable64_grow, stable64_read, stable64_size, stable64_write};
+use std::cell::Cell;
+
+use crate::{pause, Role, VoteError, PROPOSAL_MAP};
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+// Stays well below the reply size limit.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/*
+    A backup is the raw stable memory, which holds every stable map and cell there is
+    (the memory manager keeps them all in it). Exporting it in chunks and writing it into
+    a fresh canister gives the exact same state over there.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct StateChunk {
+    index: u64,
+    total_size: u64, // Bytes in the whole backup, the same in every chunk.
+    data: Vec<u8>,
+}
+
+thread_local! {
+    // Set by the first imported chunk. Until the canister is upgraded the maps in heap
+    // don't match what's in stable memory anymore, so nothing else may write.
+    static IMPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub(crate) fn is_importing() -> bool {
+    IMPORTING.with(|i| i.get())
+}
+
+// Every update but `import_state` checks this, most of them in `authenticated_caller`.
+pub(crate) fn check() -> Result<(), VoteError> {
+    if is_importing() {
+        return Err(VoteError::ImportInProgress);
+    }
+
+    Ok(())
+}
+
+fn check_controller() -> Result<(), VoteError> {
+    let caller: Principal = ic_cdk::caller();
+
+    if !ic_cdk::api::is_controller(&caller) {
+        return Err(VoteError::access_rejected(Role::Controller, caller));
+    }
+
+    Ok(())
+}
+
+// Every chunk is a call of its own, only a paused canister stays the same from the first to the last.
+#[ic_cdk::query]
+fn export_state(index: u64) -> Result<StateChunk, VoteError> {
+    check_controller()?;
+
+    if pause::check().is_ok() {
+        return Err(VoteError::NotPaused);
+    }
+
+    let total_size: u64 = stable64_size() * WASM_PAGE_SIZE;
+    let offset: u64 = index.saturating_mul(CHUNK_SIZE);
+
+    if offset >= total_size {
+        return Err(VoteError::InvalidChunk);
+    }
+
+    let mut data: Vec<u8> = vec![0; CHUNK_SIZE.min(total_size - offset) as usize];
+    stable64_read(offset, &mut data);
+
+    Ok(StateChunk {
+        index,
+        total_size,
+        data,
+    })
+}
+
+/*
+    Chunks can come in any order. Once all of them are in, upgrade the canister
+    (with the same wasm) so everything is read back from stable memory.
+    Only allowed on a canister without proposals, it overwrites whatever is there.
+*/
+#[ic_cdk::update]
+fn import_state(chunk: StateChunk) -> Result<(), VoteError> {
+    check_controller()?;
+
+    if !is_importing() && !PROPOSAL_MAP.with(|p| p.borrow().is_empty()) {
+        return Err(VoteError::StateNotEmpty);
+    }
+
+    // Every chunk but the last is full, the last one ends exactly at `total_size`.
+    let offset: u64 = chunk.index.saturating_mul(CHUNK_SIZE);
+    let expected: u64 = CHUNK_SIZE.min(chunk.total_size.saturating_sub(offset));
+
+    if offset >= chunk.total_size
+        || chunk.data.len() as u64 != expected
+        || !chunk.total_size.is_multiple_of(WASM_PAGE_SIZE)
+    {
+        return Err(VoteError::InvalidChunk);
+    }
+
+    let pages: u64 = chunk.total_size / WASM_PAGE_SIZE;
+    let current: u64 = stable64_size();
+    if pages > current && stable64_grow(pages - current).is_err() {
+        return Err(VoteError::InvalidChunk);
+    }
+
+    IMPORTING.with(|i| i.set(true));
+    stable64_write(offset, &chunk.data);
+    Ok(())
+}
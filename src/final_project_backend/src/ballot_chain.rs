@@ -0,0 +1,241 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_certified_map::Hash;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    ballots, certification, get_memory, salts, visibility, Choice, Memory, CHAIN_HEAD_MEMORY_ID,
+    CHAIN_LINK_MEMORY_ID, MAX_BATCH_SIZE,
+};
+
+const DOMAIN: &[u8] = b"\x0dicp-vote-chain";
+
+/*
+    Every accepted vote (a changed one too) is appended to its proposal's chain as
+    `H(prev_hash || voter || choice || timestamp || salt)`. The head is certified, so dropping
+    or reordering a ballot later would give a head that doesn't match the certificate.
+    The salt is only shown along with the ballot, see `salts`.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct ChainLink {
+    voter: Principal,
+    choice: Choice,
+    timestamp: u64,
+    hash: Hash,
+    salt: Option<Hash>, // Optional so links stored without one still decode.
+}
+
+impl Storable for ChainLink {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ChainLink {
+    const MAX_SIZE: u32 = 200;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct ChainHead {
+    head: Hash,
+    length: u64,
+}
+
+impl Storable for ChainHead {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ChainHead {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A link as the auditor sees it, who voted what is only filled in where the ballots are public.
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ChainEntry {
+    index: u64,
+    timestamp: u64,
+    hash: Hash,
+    ballot: Option<(Principal, Choice)>, // Always there for the caller's own links.
+    salt: Option<Hash>,                  // Shown with the ballot, the hash can be recomputed then.
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ChainHeadVerification {
+    head: Hash,
+    length: u64,
+    certificate: Option<Vec<u8>>, // IC certificate over the canister's certified data.
+    witness: Vec<u8>,             // CBOR hash tree linking the head to that certified data.
+}
+
+thread_local! {
+    static HEADS: RefCell<StableBTreeMap<u64, ChainHead, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(CHAIN_HEAD_MEMORY_ID)));
+
+    // (proposal key, index) -> link.
+    static LINKS: RefCell<StableBTreeMap<(u64, u64), ChainLink, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(CHAIN_LINK_MEMORY_ID)));
+}
+
+// What the first link builds on, different for every proposal of every canister.
+fn genesis(key: u64) -> Hash {
+    let canister: Principal = ic_cdk::id();
+
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update([canister.as_slice().len() as u8]);
+    hasher.update(canister.as_slice());
+    hasher.update(key.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn link_hash(prev: &Hash, voter: &Principal, choice: Choice, timestamp: u64, salt: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update([voter.as_slice().len() as u8]);
+    hasher.update(voter.as_slice());
+    hasher.update([choice.as_byte()]);
+    hasher.update(timestamp.to_be_bytes());
+    hasher.update(salt);
+    hasher.finalize().into()
+}
+
+/*
+    Called by `cast_vote` for every ballot it accepts, after `salts::check_ready`, and for
+    sealed ballots once they're opened, in the order and with the time they were cast.
+*/
+pub(crate) fn append(key: u64, voter: Principal, choice: Choice, timestamp: u64) {
+    let (prev, length) = match HEADS.with(|h| h.borrow().get(&key)) {
+        Some(head) => (head.head, head.length),
+        None => (genesis(key), 0),
+    };
+
+    let salt: Hash = salts::derive(b"chain", &[key, length]);
+    let hash: Hash = link_hash(&prev, &voter, choice, timestamp, &salt);
+
+    LINKS.with(|l| {
+        l.borrow_mut().insert(
+            (key, length),
+            ChainLink {
+                voter,
+                choice,
+                timestamp,
+                hash,
+                salt: Some(salt),
+            },
+        )
+    });
+    HEADS.with(|h| {
+        h.borrow_mut().insert(
+            key,
+            ChainHead {
+                head: hash,
+                length: length + 1,
+            },
+        )
+    });
+    certification::certify_chain_head(key, hash);
+}
+
+// The links name voters, they go with the rest of the per-voter data. The certified head stays.
+pub(crate) fn remove_links(key: u64) {
+    LINKS.with(|l| {
+        let mut map = l.borrow_mut();
+        let indexes: Vec<(u64, u64)> = map
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in indexes {
+            map.remove(&entry);
+        }
+    });
+}
+
+/*
+    The hashes can't change without breaking the certified head, only the name on the links does.
+    Those links can't be recomputed from their fields anymore, the chain from hash to hash still holds.
+*/
+pub(crate) fn anonymize(key: u64, voter: Principal, tombstone: Principal) {
+    LINKS.with(|l| {
+        let mut map = l.borrow_mut();
+        let links: Vec<((u64, u64), ChainLink)> = map
+            .range((key, 0)..=(key, u64::MAX))
+            .filter(|(_, link)| link.voter == voter)
+            .collect();
+
+        for (entry, mut link) in links {
+            link.voter = tombstone;
+            map.insert(entry, link);
+        }
+    });
+}
+
+pub(crate) fn remove(key: u64) {
+    remove_links(key);
+
+    if HEADS.with(|h| h.borrow_mut().remove(&key)).is_some() {
+        certification::uncertify_chain_head(key);
+    }
+}
+
+pub(crate) fn restore_certification() {
+    HEADS.with(|h| {
+        certification::restore_chain_heads(h.borrow().iter().map(|(key, head)| (key, head.head)))
+    });
+}
+
+#[ic_cdk::query]
+fn get_ballot_chain_head(key: u64) -> Option<ChainHeadVerification> {
+    if !visibility::can_see_key(key, &ic_cdk::caller()) {
+        return None;
+    }
+
+    HEADS
+        .with(|h| h.borrow().get(&key))
+        .map(|head| ChainHeadVerification {
+            head: head.head,
+            length: head.length,
+            certificate: ic_cdk::api::data_certificate(),
+            witness: certification::chain_head_witness(key),
+        })
+}
+
+// Oldest first, `offset` is the index of the first link.
+#[ic_cdk::query]
+fn get_ballot_chain(key: u64, offset: u64, limit: u64) -> Vec<ChainEntry> {
+    let caller: Principal = ic_cdk::caller();
+
+    if !visibility::can_see_key(key, &caller) {
+        return vec![];
+    }
+    let public: bool = ballots::ballots_public(key, &caller);
+
+    LINKS.with(|l| {
+        l.borrow()
+            .range((key, offset)..=(key, u64::MAX))
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|((_, index), link)| {
+                let shown: bool = public || link.voter == caller;
+
+                ChainEntry {
+                    index,
+                    timestamp: link.timestamp,
+                    hash: link.hash,
+                    ballot: shown.then_some((link.voter, link.choice)),
+                    salt: link.salt.filter(|_| shown),
+                }
+            })
+            .collect()
+    })
+}
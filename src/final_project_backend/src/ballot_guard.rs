@@ -0,0 +1,38 @@
+use candid::Principal;
+use std::{cell::RefCell, collections::BTreeSet};
+
+use crate::VoteError;
+
+/*
+    A vote reads the proposal, awaits the ledger (or the SNS, or the NFT collection)
+    and only then counts the ballot in `cast_vote`. `cast_vote` reads the proposal again
+    and writes it back without awaiting, so nothing can interleave there. What this guard adds
+    is that the same voter can't have two ballots for one proposal in flight at once,
+    where both would pass the checks before the await and one of them would be thrown away after it.
+*/
+thread_local! {
+    // (proposal key, voter) of every ballot that is waiting for an outside call.
+    static IN_FLIGHT: RefCell<BTreeSet<(u64, Principal)>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Held across the await, released when the vote is done, failed or trapped.
+pub(crate) struct BallotGuard {
+    key: u64,
+    voter: Principal,
+}
+
+impl Drop for BallotGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|f| f.borrow_mut().remove(&(self.key, self.voter)));
+    }
+}
+
+pub(crate) fn claim(key: u64, voter: Principal) -> Result<BallotGuard, VoteError> {
+    let claimed: bool = IN_FLIGHT.with(|f| f.borrow_mut().insert((key, voter)));
+
+    if !claimed {
+        return Err(VoteError::RequestInProgress);
+    }
+
+    Ok(BallotGuard { key, voter })
+}
@@ -0,0 +1,237 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    embargo, get_memory, visibility, Choice, Memory, StorablePrincipal, BALLOT_MEMORY_ID,
+    PROPOSAL_MAP, VOTER_BALLOT_MEMORY_ID,
+};
+
+// Largest page `get_my_votes`, `list_ballots` and `list_voters` return.
+const MAX_BALLOT_PAGE: u64 = 100;
+
+/*
+    `voted` on the proposal only says who voted. The ballots say how, keyed by proposal
+    so a proposal's ballots can be paged through, and indexed by voter
+    so a wallet can list everything its user ever voted on without scanning all proposals.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct BallotRecord {
+    pub(crate) choice: Choice,
+    pub(crate) weight: u64,
+    pub(crate) timestamp: u64, // When it was cast, or last changed.
+}
+
+impl Storable for BallotRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for BallotRecord {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// What a voter gets back about their own ballots.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Ballot {
+    proposal_key: u64,
+    choice: Choice,
+    weight: u64,
+    cast_at: u64,
+}
+
+impl Ballot {
+    fn new(proposal_key: u64, record: BallotRecord) -> Self {
+        Ballot {
+            proposal_key,
+            choice: record.choice,
+            weight: record.weight,
+            cast_at: record.timestamp,
+        }
+    }
+}
+
+thread_local! {
+    // (proposal key, voter) -> ballot.
+    static BALLOTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), BallotRecord, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(BALLOT_MEMORY_ID)));
+
+    // (voter, proposal key), the same ballots in voter order.
+    static BY_VOTER: RefCell<StableBTreeMap<(StorablePrincipal, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(VOTER_BALLOT_MEMORY_ID)));
+}
+
+// Also replaces the voter's previous ballot when they change their vote.
+// `timestamp` is when the ballot was cast, for a sealed one that's before it's counted.
+pub(crate) fn record(
+    voter: Principal,
+    proposal_key: u64,
+    choice: Choice,
+    weight: u64,
+    timestamp: u64,
+) {
+    let ballot: BallotRecord = BallotRecord {
+        choice,
+        weight,
+        timestamp,
+    };
+    BALLOTS.with(|b| {
+        b.borrow_mut()
+            .insert((proposal_key, StorablePrincipal(voter)), ballot)
+    });
+    BY_VOTER.with(|b| {
+        b.borrow_mut()
+            .insert((StorablePrincipal(voter), proposal_key), ())
+    });
+}
+
+pub(crate) fn get(proposal_key: u64, voter: Principal) -> Option<BallotRecord> {
+    BALLOTS.with(|b| b.borrow().get(&(proposal_key, StorablePrincipal(voter))))
+}
+
+// Drops the ballots of a proposal that is going away.
+pub(crate) fn remove(proposal_key: u64) {
+    BALLOTS.with(|b| {
+        let mut map = b.borrow_mut();
+        let voters: Vec<StorablePrincipal> = map
+            .range((proposal_key, StorablePrincipal::default())..)
+            .take_while(|((key, _), _)| *key == proposal_key)
+            .map(|((_, voter), _)| voter)
+            .collect();
+
+        BY_VOTER.with(|v| {
+            let mut index = v.borrow_mut();
+
+            for voter in voters {
+                map.remove(&(proposal_key, voter));
+                index.remove(&(voter, proposal_key));
+            }
+        });
+    });
+}
+
+// Keys of every proposal the voter has a ballot on.
+pub(crate) fn keys_of(voter: Principal) -> Vec<u64> {
+    let voter: StorablePrincipal = StorablePrincipal(voter);
+
+    BY_VOTER.with(|b| {
+        b.borrow()
+            .range((voter, 0)..=(voter, u64::MAX))
+            .map(|((_, key), _)| key)
+            .collect()
+    })
+}
+
+// Files the ballot under `tombstone` instead, choice and weight stay so the ballots still add up.
+pub(crate) fn anonymize(proposal_key: u64, voter: Principal, tombstone: Principal) {
+    let record: BallotRecord = match BALLOTS.with(|b| {
+        b.borrow_mut()
+            .remove(&(proposal_key, StorablePrincipal(voter)))
+    }) {
+        Some(value) => value,
+        None => return,
+    };
+
+    BALLOTS.with(|b| {
+        b.borrow_mut()
+            .insert((proposal_key, StorablePrincipal(tombstone)), record)
+    });
+    BY_VOTER.with(|b| {
+        b.borrow_mut()
+            .remove(&(StorablePrincipal(voter), proposal_key))
+    });
+}
+
+// Who voted what is only out there for proposals created with public ballots, and never while the results are hidden.
+pub(crate) fn ballots_public(key: u64, caller: &Principal) -> bool {
+    PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| {
+            visibility::can_see(&proposal, caller)
+                && proposal.public_ballots
+                && proposal.results_hidden_until.is_none()
+                && embargo::results_visible(&proposal, caller)
+        })
+}
+
+#[ic_cdk::query]
+fn get_my_vote(key: u64) -> Option<Ballot> {
+    get(key, ic_cdk::caller()).map(|record| Ballot::new(key, record))
+}
+
+// Ordered by proposal key.
+#[ic_cdk::query]
+fn get_my_votes(offset: u64, limit: u64) -> Vec<Ballot> {
+    let caller: StorablePrincipal = StorablePrincipal(ic_cdk::caller());
+
+    let keys: Vec<u64> = BY_VOTER.with(|b| {
+        b.borrow()
+            .range((caller, 0)..=(caller, u64::MAX))
+            .skip(offset as usize)
+            .take(limit.min(MAX_BALLOT_PAGE) as usize)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+
+    keys.into_iter()
+        .filter_map(|key| get(key, caller.0).map(|record| Ballot::new(key, record)))
+        .collect()
+}
+
+#[ic_cdk::query]
+fn get_ballot(key: u64, voter: Principal) -> Option<BallotRecord> {
+    if !ballots_public(key, &ic_cdk::caller()) {
+        return None;
+    }
+
+    get(key, voter)
+}
+
+// Ordered by voter, whoever asks. The callers check `ballots_public` first.
+pub(crate) fn page(key: u64, offset: u64, limit: u64) -> Vec<(Principal, BallotRecord)> {
+    BALLOTS.with(|b| {
+        b.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|((_, voter), ballot)| (voter.0, ballot))
+            .collect()
+    })
+}
+
+pub(crate) fn count(key: u64) -> u64 {
+    BALLOTS.with(|b| {
+        b.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .count() as u64
+    })
+}
+
+// Empty unless the proposal has public ballots.
+fn public_page(key: u64, offset: u64, limit: u64) -> Vec<(Principal, BallotRecord)> {
+    if !ballots_public(key, &ic_cdk::caller()) {
+        return vec![];
+    }
+
+    page(key, offset, limit.min(MAX_BALLOT_PAGE))
+}
+
+#[ic_cdk::query]
+fn list_ballots(key: u64, offset: u64, limit: u64) -> Vec<(Principal, BallotRecord)> {
+    public_page(key, offset, limit)
+}
+
+// The same page without the timestamps, (voter, choice, weight).
+#[ic_cdk::query]
+fn list_voters(key: u64, offset: u64, limit: u64) -> Vec<(Principal, Choice, u64)> {
+    public_page(key, offset, limit)
+        .into_iter()
+        .map(|(voter, ballot)| (voter, ballot.choice, ballot.weight))
+        .collect()
+}
@@ -0,0 +1,96 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, get_memory, Memory, Role, StorablePrincipal, VoteError,
+    BAN_MEMORY_ID, MAX_BATCH_SIZE,
+};
+
+const MAX_REASON_LEN: usize = 500;
+
+/*
+    Banned principals can still read everything, but they can't create proposals or vote anymore.
+    Anything new that lets people post (e.g. comments) should go through `check` as well.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Ban {
+    reason: String,
+    banned_by: Principal,
+    banned_at: u64,
+}
+
+impl Storable for Ban {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Ban {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static BANNED: RefCell<StableBTreeMap<StorablePrincipal, Ban, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(BAN_MEMORY_ID)));
+}
+
+pub(crate) fn check(principal: &Principal) -> Result<(), VoteError> {
+    if BANNED.with(|b| b.borrow().contains_key(&StorablePrincipal(*principal))) {
+        return Err(VoteError::Banned);
+    }
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn ban_principal(principal: Principal, reason: String) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    // An admin banning themselves (or another admin) would only be confusing, it keeps every right anyway.
+    if config::is_admin(&principal) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    let ban: Ban = Ban {
+        reason: reason.chars().take(MAX_REASON_LEN).collect(),
+        banned_by: caller,
+        banned_at: ic_cdk::api::time(),
+    };
+    BANNED.with(|b| b.borrow_mut().insert(StorablePrincipal(principal), ban));
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn unban_principal(principal: Principal) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    BANNED.with(|b| b.borrow_mut().remove(&StorablePrincipal(principal)));
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_banned(offset: u64, limit: u64) -> Vec<(Principal, Ban)> {
+    BANNED.with(|b| {
+        b.borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|(principal, ban)| (principal.0, ban))
+            .collect()
+    })
+}
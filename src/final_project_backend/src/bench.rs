@@ -0,0 +1,201 @@
+use candid::{CandidType, Deserialize, Principal};
+use std::{cell::Cell, time::Duration};
+
+use crate::{
+    authenticated_caller, cast_vote, config, list_proposals, schedule, sorting::SortBy, timers,
+    visibility::Visibility, Choice, ChoiceLabels, CreateProposal, Role, VoteError, PROPOSAL_MAP,
+};
+
+// Created per timer tick, small enough to stay far below the instruction limit of one message.
+const SEED_BATCH: u64 = 500;
+const LIST_PAGE: u64 = 100;
+
+/*
+    Budgets in instructions, set well under the per-message limits (40B for updates, 5B for queries).
+    A release whose numbers come out above them has made a hot path slower and needs a look.
+*/
+const CREATE_BUDGET: u64 = 50_000_000;
+const VOTE_BUDGET: u64 = 20_000_000;
+const LIST_BUDGET: u64 = 300_000_000;
+
+/*
+    Only built with `--features bench`, never deploy that build anywhere that matters:
+    `seed_proposals` fills the canister with as many proposals as it's told to.
+    `scripts/bench.sh` seeds 10k, 100k and 1M proposals and runs `run_benchmarks` at each step,
+    which counts the instructions of creating a proposal, voting on it and listing proposals
+    the way the frontend does. Inter-canister calls would end the measured message early,
+    so the benchmark canister needs a config without ledgers, fees and gates.
+*/
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct Measurement {
+    name: String,
+    instructions: u64,
+    budget: u64,
+    within_budget: bool,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct BenchmarkReport {
+    proposals: u64, // How many there were when the measurements started.
+    measurements: Vec<Measurement>,
+}
+
+thread_local! {
+    // Proposals `seed_proposals` still has to create.
+    static TO_SEED: Cell<u64> = const { Cell::new(0) };
+}
+
+fn check_admin() -> Result<Principal, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+    Ok(caller)
+}
+
+fn synthetic(n: u64) -> CreateProposal {
+    CreateProposal {
+        title: format!("Benchmark proposal {}", n),
+        summary: "Created by seed_proposals.".to_string(),
+        url: None,
+        payload_hash: None,
+        description: "x".repeat(500),
+        is_active: true,
+        results_embargo: None,
+        category: Some("benchmark".to_string()),
+        voting_power: None,
+        nft_gate: None,
+        sns_gate: None,
+        eligibility_root: None,
+        execution: None,
+        visibility: Visibility::Public,
+        decision_rule: None,
+        abstention: None,
+        voting_starts_at: None,
+        voting_ends_at: None,
+        client_nonce: None,
+        depends_on: vec![],
+        eligible_voters: None,
+        save_as_draft: false,
+        public_ballots: false,
+        allow_vote_changes: false,
+        quorum_percent: None,
+        survey: None,
+        choice_labels: ChoiceLabels {
+            approve: None,
+            reject: None,
+            pass: None,
+            abstain: None,
+        },
+        results_visibility: None,
+        role_weighted: false,
+        kind: None,
+        members_only: false,
+        tie_break: None,
+        frontend_only: false,
+        personhood_gated: false,
+        on_pass_create: None,
+        weighting: None,
+        sealed_ballots: false,
+    }
+}
+
+fn seed_batch(owner: Principal) {
+    let batch: u64 = TO_SEED.with(|t| t.get()).min(SEED_BATCH);
+    if batch == 0 {
+        return;
+    }
+
+    ic_cdk::spawn(async move {
+        for _ in 0..batch {
+            let n: u64 = PROPOSAL_MAP.with(|p| p.borrow().len());
+
+            // Without outside calls the creation finishes inside this message.
+            if schedule::create_at_free_key(owner, synthetic(n))
+                .await
+                .is_err()
+            {
+                TO_SEED.with(|t| t.set(0));
+                return;
+            }
+            TO_SEED.with(|t| t.set(t.get() - 1));
+        }
+
+        timers::set_timer(Duration::ZERO, move || seed_batch(owner));
+    });
+}
+
+// Adds `count` proposals in the background, owned by the caller. `get_seeding_remaining` shows the progress.
+#[ic_cdk::update]
+fn seed_proposals(count: u64) -> Result<(), VoteError> {
+    let caller: Principal = check_admin()?;
+
+    if TO_SEED.with(|t| t.get()) > 0 {
+        return Err(VoteError::ImportInProgress);
+    }
+
+    TO_SEED.with(|t| t.set(count));
+    timers::set_timer(Duration::ZERO, move || seed_batch(caller));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_seeding_remaining() -> u64 {
+    TO_SEED.with(|t| t.get())
+}
+
+fn measure(name: &str, budget: u64, start: u64) -> Measurement {
+    let instructions: u64 = ic_cdk::api::performance_counter(0).saturating_sub(start);
+
+    Measurement {
+        name: name.to_string(),
+        instructions,
+        budget,
+        within_budget: instructions <= budget,
+    }
+}
+
+// Leaves the proposal it created behind, it's one more among the seeded ones.
+#[ic_cdk::update]
+async fn run_benchmarks() -> Result<BenchmarkReport, VoteError> {
+    let caller: Principal = check_admin()?;
+
+    if TO_SEED.with(|t| t.get()) > 0 {
+        return Err(VoteError::ImportInProgress);
+    }
+
+    let proposals: u64 = PROPOSAL_MAP.with(|p| p.borrow().len());
+    let mut measurements: Vec<Measurement> = vec![];
+
+    let start: u64 = ic_cdk::api::performance_counter(0);
+    let key: u64 = schedule::create_at_free_key(caller, synthetic(proposals)).await?;
+    measurements.push(measure("create_proposal", CREATE_BUDGET, start));
+
+    let start: u64 = ic_cdk::api::performance_counter(0);
+    cast_vote(caller, key, Choice::Approve, None)?;
+    measurements.push(measure("vote", VOTE_BUDGET, start));
+
+    let start: u64 = ic_cdk::api::performance_counter(0);
+    list_proposals(0, LIST_PAGE, None, None);
+    measurements.push(measure("list_proposals", LIST_BUDGET, start));
+
+    // The last page, with the sort indexes instead of the key order.
+    let start: u64 = ic_cdk::api::performance_counter(0);
+    list_proposals(
+        proposals.saturating_sub(LIST_PAGE),
+        LIST_PAGE,
+        None,
+        Some(SortBy::CreatedDesc),
+    );
+    measurements.push(measure("list_proposals_sorted", LIST_BUDGET, start));
+
+    let start: u64 = ic_cdk::api::performance_counter(0);
+    list_proposals(0, LIST_PAGE, None, Some(SortBy::MostVotes));
+    measurements.push(measure("list_proposals_by_votes", LIST_BUDGET, start));
+
+    Ok(BenchmarkReport {
+        proposals,
+        measurements,
+    })
+}
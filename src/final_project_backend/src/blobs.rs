@@ -0,0 +1,117 @@
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{get_memory, Memory, BLOB_CHUNK_MEMORY_ID, BLOB_REF_MEMORY_ID};
+
+const CHUNK_SIZE: usize = 1024;
+
+// Shorter texts stay inline, the hash and the bookkeeping would take more than they save.
+pub(crate) const MIN_BLOB_SIZE: usize = 256;
+
+pub(crate) type BlobHash = [u8; 32];
+
+/*
+    Content addressed: the same bytes are stored once, however many proposals carry them.
+    Recurring proposals, clones and revisions tend to repeat the same long description.
+    Every holder counts as one reference, the bytes go once the last one lets go.
+*/
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Chunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Hash -> (references, chunks).
+    static REFS: RefCell<StableBTreeMap<BlobHash, (u64, u32), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(BLOB_REF_MEMORY_ID)));
+
+    // (hash, index) -> the bytes.
+    static CHUNKS: RefCell<StableBTreeMap<(BlobHash, u32), Chunk, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(BLOB_CHUNK_MEMORY_ID)));
+}
+
+// Adds a reference, the holder has to `release` it again.
+pub(crate) fn put(bytes: &[u8]) -> BlobHash {
+    let hash: BlobHash = Sha256::digest(bytes).into();
+
+    REFS.with(|r| {
+        let mut refs = r.borrow_mut();
+
+        match refs.get(&hash) {
+            Some((count, chunks)) => {
+                refs.insert(hash, (count + 1, chunks));
+            }
+            None => {
+                let mut chunks: u32 = 0;
+
+                CHUNKS.with(|c| {
+                    let mut map = c.borrow_mut();
+
+                    for piece in bytes.chunks(CHUNK_SIZE) {
+                        map.insert((hash, chunks), Chunk(piece.to_vec()));
+                        chunks += 1;
+                    }
+                });
+                refs.insert(hash, (1, chunks));
+            }
+        }
+    });
+
+    hash
+}
+
+pub(crate) fn get(hash: &BlobHash) -> Option<Vec<u8>> {
+    let (_, chunks) = REFS.with(|r| r.borrow().get(hash))?;
+
+    CHUNKS.with(|c| {
+        Some(
+            c.borrow()
+                .range((*hash, 0)..(*hash, chunks))
+                .flat_map(|(_, chunk)| chunk.0)
+                .collect(),
+        )
+    })
+}
+
+pub(crate) fn put_text(text: &str) -> BlobHash {
+    put(text.as_bytes())
+}
+
+// For hashes from `put_text`, the bytes are always valid UTF-8.
+pub(crate) fn get_text(hash: &BlobHash) -> String {
+    get(hash)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default()
+}
+
+pub(crate) fn release(hash: &BlobHash) {
+    let (count, chunks) = match REFS.with(|r| r.borrow().get(hash)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    if count > 1 {
+        REFS.with(|r| r.borrow_mut().insert(*hash, (count - 1, chunks)));
+        return;
+    }
+
+    REFS.with(|r| r.borrow_mut().remove(hash));
+    CHUNKS.with(|c| {
+        let mut map = c.borrow_mut();
+
+        for index in 0..chunks {
+            map.remove(&(*hash, index));
+        }
+    });
+}
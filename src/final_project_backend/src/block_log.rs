@@ -0,0 +1,414 @@
+use candid::{define_function, CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_certified_map::Hash;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    audit::AuditEvent, certification, get_memory, Memory, BLOCK_LOG_MEMORY_ID, BLOCK_TIP_MEMORY_ID,
+    MAX_BATCH_SIZE,
+};
+
+/*
+    The governance history as an ICRC-3 block log, so explorers and indexers that already
+    speak ICRC-3 can follow it without knowing anything about this canister. Every block
+    carries the hash of the one before it and the newest hash is certified, which makes the
+    whole log verifiable from a single `icrc3_get_tip_certificate`.
+
+    Blocks come from two places: the event feed (only for public proposals, the log has no
+    access control) and the audit log, which is public anyway.
+*/
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+pub(crate) enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(candid::Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    // The representation independent hash from the ICRC-3 spec.
+    fn hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+
+        match self {
+            Value::Blob(bytes) => hasher.update(bytes),
+            Value::Text(text) => hasher.update(text.as_bytes()),
+            Value::Nat(nat) => hasher.update(leb128(nat)),
+            Value::Int(int) => {
+                let mut bytes: Vec<u8> = vec![];
+                int.encode(&mut bytes).unwrap();
+                hasher.update(bytes);
+            }
+            Value::Array(values) => {
+                for value in values {
+                    hasher.update(value.hash());
+                }
+            }
+            Value::Map(entries) => {
+                let mut pairs: Vec<Vec<u8>> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut pair: Vec<u8> = Sha256::digest(key.as_bytes()).to_vec();
+                        pair.extend_from_slice(&value.hash());
+                        pair
+                    })
+                    .collect();
+                pairs.sort();
+
+                for pair in pairs {
+                    hasher.update(pair);
+                }
+            }
+        }
+
+        hasher.finalize().into()
+    }
+}
+
+fn leb128(nat: &Nat) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![];
+    nat.encode(&mut bytes).unwrap();
+    bytes
+}
+
+fn nat(value: u64) -> Value {
+    Value::Nat(Nat::from(value))
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn principal(value: Principal) -> Value {
+    Value::Blob(value.as_slice().to_vec())
+}
+
+struct Block(Value);
+
+impl Storable for Block {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(&self.0).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Block(Decode!(bytes.as_ref(), Value).unwrap())
+    }
+}
+
+impl BoundedStorable for Block {
+    // Reasons are the only free text in a block, and they're capped at 500 bytes.
+    const MAX_SIZE: u32 = 2000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+struct Tip {
+    index: Option<u64>,
+    hash: Vec<u8>,
+}
+
+impl Storable for Tip {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct GetBlocksArgs {
+    start: Nat,
+    length: Nat,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct BlockWithId {
+    id: Nat,
+    block: Value,
+}
+
+define_function!(pub(crate) GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ArchivedBlocks {
+    args: Vec<GetBlocksArgs>,
+    callback: GetBlocksCallback,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct GetBlocksResult {
+    log_length: Nat,
+    blocks: Vec<BlockWithId>,
+    archived_blocks: Vec<ArchivedBlocks>, // Always empty, every block stays here.
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct GetArchivesArgs {
+    from: Option<Principal>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ArchiveInfo {
+    canister_id: Principal,
+    start: Nat,
+    end: Nat,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct DataCertificate {
+    certificate: Vec<u8>,
+    hash_tree: Vec<u8>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct SupportedBlockType {
+    block_type: String,
+    url: String,
+}
+
+thread_local! {
+    // Block index -> block, starting at 0. Blocks are only ever appended.
+    static BLOCKS: RefCell<StableBTreeMap<u64, Block, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(BLOCK_LOG_MEMORY_ID)));
+
+    // The newest block, so appending doesn't have to read and hash it again.
+    static TIP: RefCell<StableCell<Tip, Memory>> = RefCell::new(StableCell::init(get_memory(BLOCK_TIP_MEMORY_ID), Tip::default()).unwrap());
+}
+
+// What the `btype`s in this log stand for.
+const BLOCK_TYPES: [&str; 6] = [
+    "proposal_created",
+    "proposal_edited",
+    "vote",
+    "proposal_finalized",
+    "proposal_deleted",
+    "audit",
+];
+
+const BLOCK_TYPES_URL: &str =
+    "https://github.com/alpha-98/ICP_Vote_Template/blob/main/src/final_project_backend/src/block_log.rs";
+
+fn append(btype: &str, tx: Vec<(String, Value)>) {
+    let tip: Tip = TIP.with(|t| t.borrow().get().clone());
+    let index: u64 = tip.index.map_or(0, |index| index + 1);
+
+    let mut fields: Vec<(String, Value)> = vec![
+        ("btype".to_string(), text(btype)),
+        ("ts".to_string(), nat(ic_cdk::api::time())),
+        ("tx".to_string(), Value::Map(tx)),
+    ];
+    if tip.index.is_some() {
+        fields.push(("phash".to_string(), Value::Blob(tip.hash)));
+    }
+
+    let block: Value = Value::Map(fields);
+    let hash: Hash = block.hash();
+
+    BLOCKS.with(|b| b.borrow_mut().insert(index, Block(block)));
+    TIP.with(|t| {
+        t.borrow_mut()
+            .set(Tip {
+                index: Some(index),
+                hash: hash.to_vec(),
+            })
+            .unwrap()
+    });
+    certification::certify_tip(leb128(&Nat::from(index)), hash);
+}
+
+// Called by `events` for everything that happens to a public proposal.
+pub(crate) fn append_event(btype: &str, key: u64, voter: Option<Principal>) {
+    let mut tx: Vec<(String, Value)> = vec![("key".to_string(), nat(key))];
+
+    if let Some(voter) = voter {
+        tx.push(("voter".to_string(), principal(voter)));
+    }
+
+    append(btype, tx);
+}
+
+// Called by `audit`. The tombstone of a deleted proposal stays in the audit log, it's too large for a block.
+pub(crate) fn append_audit(actor: Principal, key: u64, event: &AuditEvent) {
+    let (name, reason): (&str, Option<&String>) = match event {
+        AuditEvent::ProposalDeleted { forced: true, .. } => ("ProposalForceDeleted", None),
+        AuditEvent::ProposalDeleted { .. } => ("ProposalDeleted", None),
+        AuditEvent::ProposalVetoed { reason } => ("ProposalVetoed", Some(reason)),
+        AuditEvent::FlaggedAsSpam => ("FlaggedAsSpam", None),
+        AuditEvent::ProposalHidden => ("ProposalHidden", None),
+        AuditEvent::ProposalRestored => ("ProposalRestored", None),
+        AuditEvent::SubmissionApproved => ("SubmissionApproved", None),
+        AuditEvent::SubmissionRejected { reason } => ("SubmissionRejected", Some(reason)),
+        AuditEvent::ProposalSuspended => ("ProposalSuspended", None),
+        AuditEvent::ReportsDismissed => ("ReportsDismissed", None),
+        AuditEvent::ConfigChanged { .. } => ("ConfigChanged", None),
+        AuditEvent::DeadlineExtended { .. } => ("DeadlineExtended", None),
+        AuditEvent::VotingSuspended => ("VotingSuspended", None),
+        AuditEvent::VotingResumed => ("VotingResumed", None),
+        AuditEvent::ConfigReplaced { .. } => ("ConfigReplaced", None),
+        AuditEvent::OwnerReassignmentRequested { reason, .. } => {
+            ("OwnerReassignmentRequested", Some(reason))
+        }
+        AuditEvent::OwnerReassignmentCancelled => ("OwnerReassignmentCancelled", None),
+        AuditEvent::OwnerReassigned { reason, .. } => ("OwnerReassigned", Some(reason)),
+    };
+
+    let mut tx: Vec<(String, Value)> = vec![
+        ("key".to_string(), nat(key)),
+        ("actor".to_string(), principal(actor)),
+        ("event".to_string(), text(name)),
+    ];
+
+    if let Some(reason) = reason {
+        tx.push(("reason".to_string(), text(reason)));
+    }
+
+    append("audit", tx);
+}
+
+pub(crate) fn restore_certification() {
+    let tip: Tip = TIP.with(|t| t.borrow().get().clone());
+
+    if let (Some(index), Ok(hash)) = (tip.index, Hash::try_from(tip.hash)) {
+        certification::certify_tip(leb128(&Nat::from(index)), hash);
+    }
+}
+
+fn log_length() -> u64 {
+    TIP.with(|t| t.borrow().get().index.map_or(0, |index| index + 1))
+}
+
+// Ranges past the end are cut short, and all of them together return at most `MAX_BATCH_SIZE` blocks.
+#[ic_cdk::query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let mut blocks: Vec<BlockWithId> = vec![];
+
+    BLOCKS.with(|b| {
+        let map = b.borrow();
+
+        for range in args {
+            let budget: usize = MAX_BATCH_SIZE - blocks.len();
+            let start: u64 = u64::try_from(range.start.0).unwrap_or(u64::MAX);
+            let length: usize = usize::try_from(range.length.0)
+                .unwrap_or(usize::MAX)
+                .min(budget);
+
+            blocks.extend(
+                map.range(start..)
+                    .take(length)
+                    .map(|(id, block)| BlockWithId {
+                        id: Nat::from(id),
+                        block: block.0,
+                    }),
+            );
+        }
+    });
+
+    GetBlocksResult {
+        log_length: Nat::from(log_length()),
+        blocks,
+        archived_blocks: vec![],
+    }
+}
+
+#[ic_cdk::query]
+fn icrc3_get_archives(_args: GetArchivesArgs) -> Vec<ArchiveInfo> {
+    vec![]
+}
+
+#[ic_cdk::query]
+fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    Some(DataCertificate {
+        certificate: ic_cdk::api::data_certificate()?,
+        hash_tree: certification::tip_witness(),
+    })
+}
+
+#[ic_cdk::query]
+fn icrc3_supported_block_types() -> Vec<SupportedBlockType> {
+    BLOCK_TYPES
+        .iter()
+        .map(|block_type| SupportedBlockType {
+            block_type: block_type.to_string(),
+            url: BLOCK_TYPES_URL.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_hex(value: &Value) -> String {
+        hex::encode(value.hash())
+    }
+
+    // The examples of the representation independent hash in the ICRC-3 spec.
+    #[test]
+    fn hashes_values_like_the_spec() {
+        assert_eq!(
+            hash_hex(&nat(42)),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+        );
+        assert_eq!(
+            hash_hex(&Value::Int(candid::Int::from(-42))),
+            "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc"
+        );
+        assert_eq!(
+            hash_hex(&text("Hello, World!")),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(
+            hash_hex(&Value::Blob(vec![1, 2, 3, 4])),
+            "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a"
+        );
+        assert_eq!(
+            hash_hex(&Value::Array(vec![
+                nat(3),
+                text("foo"),
+                Value::Blob(vec![5, 6])
+            ])),
+            "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6"
+        );
+    }
+
+    fn block(fields: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    // A block the way `append_event` writes it, hashed outside this crate.
+    #[test]
+    fn hashes_a_block() {
+        let tx: Value = block(vec![
+            ("key", nat(7)),
+            ("voter", principal(Principal::from_slice(&[1]))),
+        ]);
+        let fields: Vec<(&str, Value)> = vec![
+            ("btype", text("vote")),
+            ("ts", nat(1_700_000_000_000_000_000)),
+            ("tx", tx),
+            ("phash", Value::Blob((0..32).collect())),
+        ];
+        let expected: &str = "43272aafd8fea806b968fc30060291ad3c45a66350da719ad0b2d9e4050d4666";
+
+        assert_eq!(hash_hex(&block(fields.clone())), expected);
+
+        // The fields of a map are hashed sorted, the order they're written in doesn't matter.
+        let mut reversed: Vec<(&str, Value)> = fields.clone();
+        reversed.reverse();
+        assert_eq!(hash_hex(&block(reversed)), expected);
+
+        // Every field counts, the link to the block before included.
+        let mut relinked: Vec<(&str, Value)> = fields;
+        relinked[3] = ("phash", Value::Blob(vec![0; 32]));
+        assert_ne!(hash_hex(&block(relinked)), expected);
+    }
+}
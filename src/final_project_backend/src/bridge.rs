@@ -0,0 +1,447 @@
+use candid::{types::reserved::Reserved, CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use std::{borrow::Cow, cell::Cell, cell::RefCell, time::Duration};
+use tiny_keccak::{Hasher, Keccak};
+
+use crate::{
+    authenticated_caller, config, get_memory, outbox, pause, signed_results, timers, visibility,
+    Memory, Role, VoteError, BRIDGE_MEMORY_ID,
+};
+
+// What the contract has to implement, the canister's address is the only one allowed to call it.
+const RECORD_RESULT: &str = "recordResult(uint256,bytes32)";
+const MAX_RESPONSE_BYTES: u64 = 2000;
+const MAX_ERROR_LEN: usize = 500;
+
+/*
+    When a proposal passes, its result is sent to a contract on an EVM chain as
+    `recordResult(proposalKey, digest)`, where `digest` is the one from `get_signed_result`.
+    The transaction is signed with the same threshold ECDSA key as the results,
+    and goes out through the EVM RPC canister (https://github.com/internet-computer-protocol/evm-rpc-canister).
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct BridgeConfig {
+    evm_rpc: Principal,
+    rpc_url: String, // JSON-RPC endpoint of the chain, e.g. "https://ethereum-sepolia-rpc.publicnode.com".
+    chain_id: u64,
+    contract: String, // 0x prefixed address.
+    gas_limit: u64,
+    max_fee_per_gas: u128,          // Wei.
+    max_priority_fee_per_gas: u128, // Wei.
+    cycles: u128,                   // Attached to every call to the EVM RPC canister.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq)]
+enum SubmissionState {
+    Pending,
+    Sent { tx_hash: String },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Submission {
+    state: SubmissionState,
+    attempts: u32,
+    updated_at: u64,
+}
+
+impl Storable for Submission {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Submission {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// The parts of the EVM RPC canister interface this module needs.
+#[derive(CandidType)]
+struct HttpHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(CandidType)]
+struct RpcApi {
+    url: String,
+    headers: Option<Vec<HttpHeader>>,
+}
+
+#[derive(CandidType)]
+enum RpcService {
+    Custom(RpcApi),
+}
+
+#[derive(CandidType, Deserialize)]
+enum RequestResult {
+    Ok(String),
+    Err(Reserved), // What went wrong isn't needed, only that something did.
+}
+
+thread_local! {
+    // Proposal key -> how far its result got.
+    static SUBMISSIONS: RefCell<StableBTreeMap<u64, Submission, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(BRIDGE_MEMORY_ID)));
+
+    // Submissions go out one at a time, two at once would get the same nonce.
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Clears `RUNNING` even when a submission traps after its await.
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        RUNNING.with(|r| r.set(false));
+    }
+}
+
+pub(crate) fn validate(bridge: &BridgeConfig) -> bool {
+    parse_address(&bridge.contract).is_some()
+        && !bridge.rpc_url.is_empty()
+        && bridge.chain_id != 0
+        && bridge.gas_limit != 0
+        && bridge.max_priority_fee_per_gas <= bridge.max_fee_per_gas
+}
+
+// Called by `decision::finalize` for proposals that passed.
+pub(crate) fn queue(key: u64) {
+    if config::bridge().is_none() {
+        return;
+    }
+
+    set_state(key, SubmissionState::Pending);
+    ic_cdk::spawn(run());
+}
+
+pub(crate) fn remove(key: u64) {
+    SUBMISSIONS.with(|s| s.borrow_mut().remove(&key));
+}
+
+// Calls can't be made from `post_upgrade`, so whatever was left over goes out from a timer.
+pub(crate) fn resume() {
+    timers::set_timer(Duration::ZERO, || ic_cdk::spawn(run()));
+}
+
+fn set_state(key: u64, state: SubmissionState) {
+    SUBMISSIONS.with(|s| {
+        let mut map = s.borrow_mut();
+        let attempts: u32 = map.get(&key).map_or(0, |submission| submission.attempts);
+
+        map.insert(
+            key,
+            Submission {
+                attempts: match state {
+                    SubmissionState::Pending => attempts,
+                    _ => attempts.saturating_add(1),
+                },
+                state,
+                updated_at: ic_cdk::api::time(),
+            },
+        );
+    });
+}
+
+fn next_pending() -> Option<u64> {
+    SUBMISSIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .find(|(_, submission)| submission.state == SubmissionState::Pending)
+            .map(|(key, _)| key)
+    })
+}
+
+async fn run() {
+    if RUNNING.with(|r| r.get()) {
+        return;
+    }
+
+    RUNNING.with(|r| r.set(true));
+    let _guard = RunGuard;
+
+    while let Some(key) = next_pending() {
+        let state: SubmissionState = match submit(key).await {
+            Ok(tx_hash) => SubmissionState::Sent { tx_hash },
+            Err(error) => SubmissionState::Failed {
+                error: error.chars().take(MAX_ERROR_LEN).collect(),
+            },
+        };
+
+        // Deleted or queued again in the meantime.
+        let still_pending: bool = SUBMISSIONS
+            .with(|s| s.borrow().get(&key))
+            .is_some_and(|submission| submission.state == SubmissionState::Pending);
+
+        if still_pending {
+            let target: outbox::CallTarget = outbox::CallTarget::BridgeSubmission(key);
+            match &state {
+                SubmissionState::Failed { error } => outbox::record(target, error),
+                _ => outbox::resolve(&target),
+            }
+            set_state(key, state);
+        }
+    }
+}
+
+/*
+    A failed `eth_sendRawTransaction` can still have reached the chain, a retry then sends
+    a second transaction with the next nonce. The contract should ignore keys it already has.
+*/
+async fn submit(key: u64) -> Result<String, String> {
+    let bridge: BridgeConfig = config::bridge().ok_or("the bridge is not configured")?;
+    let name: String = config::ecdsa_key().ok_or("no threshold ECDSA key is configured")?;
+    let digest: Vec<u8> = signed_results::digest(key).ok_or("the result has no digest")?;
+    let contract: Vec<u8> = parse_address(&bridge.contract).ok_or("invalid contract address")?;
+
+    let public_key: VerifyingKey = public_key(name.clone()).await?;
+    let from: String = format!("0x{}", hex::encode(address(&public_key)));
+
+    let count: String = rpc(
+        &bridge,
+        "eth_getTransactionCount",
+        serde_json::json!([from, "pending"]),
+    )
+    .await?;
+    let nonce: u64 = parse_quantity(&count).ok_or("invalid transaction count")?;
+
+    let mut data: Vec<u8> = keccak(RECORD_RESULT.as_bytes())[..4].to_vec();
+    data.extend_from_slice(&[0; 24]);
+    data.extend_from_slice(&key.to_be_bytes());
+    data.extend_from_slice(&digest);
+
+    let fields: Vec<Vec<u8>> = vec![
+        rlp_bytes(&quantity(bridge.chain_id as u128)),
+        rlp_bytes(&quantity(nonce as u128)),
+        rlp_bytes(&quantity(bridge.max_priority_fee_per_gas)),
+        rlp_bytes(&quantity(bridge.max_fee_per_gas)),
+        rlp_bytes(&quantity(bridge.gas_limit as u128)),
+        rlp_bytes(&contract),
+        rlp_bytes(&[]), // No value.
+        rlp_bytes(&data),
+        rlp_list(&[]), // No access list.
+    ];
+
+    // EIP-1559 transactions are typed 0x02, the type byte is part of what gets signed.
+    let mut unsigned: Vec<u8> = vec![0x02];
+    unsigned.extend(rlp_list(&fields));
+    let hash: [u8; 32] = keccak(&unsigned);
+
+    let response = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: hash.to_vec(),
+        derivation_path: signed_results::derivation_path(),
+        key_id: signed_results::key_id(name),
+    })
+    .await
+    .map_err(|(code, message)| format!("{:?}: {}", code, message))?
+    .0;
+
+    let mut signature: Signature =
+        Signature::from_slice(&response.signature).map_err(|_| "invalid signature")?;
+    // Ethereum only takes the low s form.
+    if let Some(normalized) = signature.normalize_s() {
+        signature = normalized;
+    }
+    let recovery_id: RecoveryId =
+        RecoveryId::trial_recovery_from_prehash(&public_key, &hash, &signature)
+            .map_err(|_| "the signature doesn't match the key")?;
+
+    let (r, s) = signature.split_bytes();
+    let mut signed_fields: Vec<Vec<u8>> = fields;
+    signed_fields.push(rlp_bytes(&quantity(recovery_id.is_y_odd() as u128)));
+    signed_fields.push(rlp_bytes(strip_zeros(&r)));
+    signed_fields.push(rlp_bytes(strip_zeros(&s)));
+
+    let mut raw: Vec<u8> = vec![0x02];
+    raw.extend(rlp_list(&signed_fields));
+
+    rpc(
+        &bridge,
+        "eth_sendRawTransaction",
+        serde_json::json!([format!("0x{}", hex::encode(raw))]),
+    )
+    .await
+}
+
+async fn public_key(name: String) -> Result<VerifyingKey, String> {
+    let response = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: signed_results::derivation_path(),
+        key_id: signed_results::key_id(name),
+    })
+    .await
+    .map_err(|(code, message)| format!("{:?}: {}", code, message))?
+    .0;
+
+    VerifyingKey::from_sec1_bytes(&response.public_key).map_err(|_| "invalid public key".into())
+}
+
+// Sends one JSON-RPC call and hands back its `result`.
+async fn rpc(
+    bridge: &BridgeConfig,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<String, String> {
+    let body: String = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+    .to_string();
+
+    let service: RpcService = RpcService::Custom(RpcApi {
+        url: bridge.rpc_url.clone(),
+        headers: None,
+    });
+
+    let (res,): (RequestResult,) = ic_cdk::api::call::call_with_payment128(
+        bridge.evm_rpc,
+        "request",
+        (service, body, MAX_RESPONSE_BYTES),
+        bridge.cycles,
+    )
+    .await
+    .map_err(|(code, message)| format!("{:?}: {}", code, message))?;
+
+    let text: String = match res {
+        RequestResult::Ok(text) => text,
+        RequestResult::Err(_) => return Err(format!("{} failed at the EVM RPC canister", method)),
+    };
+
+    let json: serde_json::Value = serde_json::from_str(&text).map_err(|_| text.clone())?;
+    match json.get("result").and_then(|result| result.as_str()) {
+        Some(result) => Ok(result.to_string()),
+        None => Err(text),
+    }
+}
+
+fn keccak(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    let mut out: [u8; 32] = [0; 32];
+    hasher.update(bytes);
+    hasher.finalize(&mut out);
+    out
+}
+
+// The last 20 bytes of the hash of the uncompressed key, without its 0x04 prefix.
+fn address(public_key: &VerifyingKey) -> Vec<u8> {
+    let point = public_key.to_encoded_point(false);
+    keccak(&point.as_bytes()[1..])[12..].to_vec()
+}
+
+fn parse_address(address: &str) -> Option<Vec<u8>> {
+    let bytes: Vec<u8> = hex::decode(address.strip_prefix("0x")?).ok()?;
+    (bytes.len() == 20).then_some(bytes)
+}
+
+fn parse_quantity(quantity: &str) -> Option<u64> {
+    u64::from_str_radix(quantity.strip_prefix("0x")?, 16).ok()
+}
+
+fn strip_zeros(bytes: &[u8]) -> &[u8] {
+    let start: usize = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+// RLP integers are big endian without leading zeros, zero is the empty string.
+fn quantity(value: u128) -> Vec<u8> {
+    strip_zeros(&value.to_be_bytes()).to_vec()
+}
+
+fn rlp_prefix(out: &mut Vec<u8>, len: usize, short: u8) {
+    if len < 56 {
+        out.push(short + len as u8);
+    } else {
+        let len_bytes: Vec<u8> = quantity(len as u128);
+        out.push(short + 55 + len_bytes.len() as u8);
+        out.extend(len_bytes);
+    }
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+
+    let mut out: Vec<u8> = vec![];
+    rlp_prefix(&mut out, bytes.len(), 0x80);
+    out.extend_from_slice(bytes);
+    out
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload: Vec<u8> = items.concat();
+
+    let mut out: Vec<u8> = vec![];
+    rlp_prefix(&mut out, payload.len(), 0xc0);
+    out.extend(payload);
+    out
+}
+
+#[ic_cdk::query]
+fn get_bridge_submission(key: u64) -> Option<Submission> {
+    if !visibility::can_see_key(key, &ic_cdk::caller()) {
+        return None;
+    }
+
+    SUBMISSIONS.with(|s| s.borrow().get(&key))
+}
+
+// The address results come from, to be allowed in the contract and funded with gas.
+// For `outbox`, a result that was sent (or queued again) in the meantime counts as done.
+pub(crate) async fn retry(key: u64) -> Result<(), String> {
+    match SUBMISSIONS.with(|s| s.borrow().get(&key)) {
+        Some(submission) if matches!(submission.state, SubmissionState::Failed { .. }) => {}
+        _ => return Ok(()),
+    }
+
+    set_state(key, SubmissionState::Pending);
+    run().await;
+
+    match SUBMISSIONS.with(|s| s.borrow().get(&key)).map(|s| s.state) {
+        Some(SubmissionState::Failed { error }) => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[ic_cdk::update]
+async fn get_bridge_address() -> Result<String, VoteError> {
+    pause::check()?;
+    let name: String = config::ecdsa_key().ok_or(VoteError::InvalidConfig)?;
+    let public_key: VerifyingKey = public_key(name)
+        .await
+        .map_err(|_| VoteError::SigningFailed)?;
+
+    Ok(format!("0x{}", hex::encode(address(&public_key))))
+}
+
+#[ic_cdk::update]
+fn retry_bridge_submission(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    if config::bridge().is_none() {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    match SUBMISSIONS.with(|s| s.borrow().get(&key)) {
+        Some(submission) if matches!(submission.state, SubmissionState::Failed { .. }) => {}
+        _ => return Err(VoteError::NothingToSubmit),
+    }
+
+    set_state(key, SubmissionState::Pending);
+    ic_cdk::spawn(run());
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use ic_certified_map::{
+    fork, fork_hash, labeled, labeled_hash, AsHashTree, Hash, HashTree, RbTree,
+};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+/*
+    Everything the canister certifies ends up under one root hash that goes into
+    the certified data. Each kind of data gets its own labeled subtree, so a witness
+    for one of them can be checked against the IC's certificate on its own.
+*/
+// Labels are in order, the tree is `fork(fork(chains, fork(last hash, last index)), fork(receipts, results))`.
+const CHAINS_LABEL: &[u8] = b"ballot_chains";
+const LAST_BLOCK_HASH_LABEL: &[u8] = b"last_block_hash"; // These two are what ICRC-3 looks for at the top.
+const LAST_BLOCK_INDEX_LABEL: &[u8] = b"last_block_index";
+const RECEIPTS_LABEL: &[u8] = b"receipts";
+const RESULTS_LABEL: &[u8] = b"results";
+
+thread_local! {
+    // Receipt sequence number (big endian) -> receipt hash. Kept on the heap and rebuilt after upgrades.
+    static RECEIPT_TREE: RefCell<RbTree<Vec<u8>, Hash>> = const { RefCell::new(RbTree::new()) };
+
+    // Proposal key (big endian) -> head of its ballot chain, rebuilt the same way.
+    static CHAIN_TREE: RefCell<RbTree<Vec<u8>, Hash>> = const { RefCell::new(RbTree::new()) };
+
+    // Proposal key (big endian) -> hash of its final report, once the results are public.
+    static RESULT_TREE: RefCell<RbTree<Vec<u8>, Hash>> = const { RefCell::new(RbTree::new()) };
+
+    // Index (LEB128) and hash of the newest block in the ICRC-3 log, `None` while it's empty.
+    static TIP: RefCell<Option<(Vec<u8>, Hash)>> = const { RefCell::new(None) };
+}
+
+fn chains_hash() -> Hash {
+    CHAIN_TREE.with(|t| labeled_hash(CHAINS_LABEL, &t.borrow().root_hash()))
+}
+
+fn receipts_hash() -> Hash {
+    RECEIPT_TREE.with(|t| labeled_hash(RECEIPTS_LABEL, &t.borrow().root_hash()))
+}
+
+fn results_hash() -> Hash {
+    RESULT_TREE.with(|t| labeled_hash(RESULTS_LABEL, &t.borrow().root_hash()))
+}
+
+fn tip_tree(tip: &Option<(Vec<u8>, Hash)>) -> HashTree<'_> {
+    match tip {
+        Some((index, hash)) => fork(
+            labeled(
+                LAST_BLOCK_HASH_LABEL,
+                HashTree::Leaf(Cow::Borrowed(&hash[..])),
+            ),
+            labeled(
+                LAST_BLOCK_INDEX_LABEL,
+                HashTree::Leaf(Cow::Borrowed(&index[..])),
+            ),
+        ),
+        None => HashTree::Empty,
+    }
+}
+
+fn tip_hash() -> Hash {
+    TIP.with(|t| tip_tree(&t.borrow()).reconstruct())
+}
+
+fn left_hash() -> Hash {
+    fork_hash(&chains_hash(), &tip_hash())
+}
+
+fn right_hash() -> Hash {
+    fork_hash(&receipts_hash(), &results_hash())
+}
+
+fn update_certified_data() {
+    ic_cdk::api::set_certified_data(&fork_hash(&left_hash(), &right_hash()));
+}
+
+pub(crate) fn certify_receipt(seq: u64, hash: Hash) {
+    RECEIPT_TREE.with(|t| t.borrow_mut().insert(seq.to_be_bytes().to_vec(), hash));
+    update_certified_data();
+}
+
+// Also what restores it after an upgrade.
+pub(crate) fn certify_tip(index: Vec<u8>, hash: Hash) {
+    TIP.with(|t| *t.borrow_mut() = Some((index, hash)));
+    update_certified_data();
+}
+
+pub(crate) fn certify_chain_head(key: u64, head: Hash) {
+    CHAIN_TREE.with(|t| t.borrow_mut().insert(key.to_be_bytes().to_vec(), head));
+    update_certified_data();
+}
+
+pub(crate) fn uncertify_chain_head(key: u64) {
+    CHAIN_TREE.with(|t| t.borrow_mut().delete(&key.to_be_bytes()));
+    update_certified_data();
+}
+
+pub(crate) fn restore_chain_heads(heads: impl Iterator<Item = (u64, Hash)>) {
+    CHAIN_TREE.with(|t| {
+        let mut tree = t.borrow_mut();
+
+        for (key, head) in heads {
+            tree.insert(key.to_be_bytes().to_vec(), head);
+        }
+    });
+    update_certified_data();
+}
+
+pub(crate) fn certify_result(key: u64, hash: Hash) {
+    RESULT_TREE.with(|t| t.borrow_mut().insert(key.to_be_bytes().to_vec(), hash));
+    update_certified_data();
+}
+
+pub(crate) fn uncertify_result(key: u64) {
+    RESULT_TREE.with(|t| t.borrow_mut().delete(&key.to_be_bytes()));
+    update_certified_data();
+}
+
+pub(crate) fn restore_results(results: impl Iterator<Item = (u64, Hash)>) {
+    RESULT_TREE.with(|t| {
+        let mut tree = t.borrow_mut();
+
+        for (key, hash) in results {
+            tree.insert(key.to_be_bytes().to_vec(), hash);
+        }
+    });
+    update_certified_data();
+}
+
+// Certified data doesn't survive an upgrade, so the tree is filled again from stable memory.
+pub(crate) fn restore_receipts(receipts: impl Iterator<Item = (u64, Hash)>) {
+    RECEIPT_TREE.with(|t| {
+        let mut tree = t.borrow_mut();
+
+        for (seq, hash) in receipts {
+            tree.insert(seq.to_be_bytes().to_vec(), hash);
+        }
+    });
+    update_certified_data();
+}
+
+fn encode_witness(tree: HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
+
+// CBOR encoded hash tree proving the receipt `seq` (or its absence), to be checked against `data_certificate()`.
+pub(crate) fn receipt_witness(seq: u64) -> Vec<u8> {
+    RECEIPT_TREE.with(|t| {
+        let tree = t.borrow();
+        encode_witness(fork(
+            HashTree::Pruned(left_hash()),
+            fork(
+                labeled(RECEIPTS_LABEL, tree.witness(&seq.to_be_bytes())),
+                HashTree::Pruned(results_hash()),
+            ),
+        ))
+    })
+}
+
+// Same for the head of a proposal's ballot chain.
+pub(crate) fn chain_head_witness(key: u64) -> Vec<u8> {
+    CHAIN_TREE.with(|t| {
+        let tree = t.borrow();
+        encode_witness(fork(
+            fork(
+                labeled(CHAINS_LABEL, tree.witness(&key.to_be_bytes())),
+                HashTree::Pruned(tip_hash()),
+            ),
+            HashTree::Pruned(right_hash()),
+        ))
+    })
+}
+
+// The tip of the block log, in the shape `icrc3_get_tip_certificate` hands out.
+pub(crate) fn tip_witness() -> Vec<u8> {
+    TIP.with(|t| {
+        let tip = t.borrow();
+        encode_witness(fork(
+            fork(HashTree::Pruned(chains_hash()), tip_tree(&tip)),
+            HashTree::Pruned(right_hash()),
+        ))
+    })
+}
+
+// Same for the result of a proposal, see `reports::get_result_proof`.
+pub(crate) fn result_witness(key: u64) -> Vec<u8> {
+    RESULT_TREE.with(|t| {
+        let tree = t.borrow();
+        encode_witness(fork(
+            HashTree::Pruned(left_hash()),
+            fork(
+                HashTree::Pruned(receipts_hash()),
+                labeled(RESULTS_LABEL, tree.witness(&key.to_be_bytes())),
+            ),
+        ))
+    })
+}
@@ -0,0 +1,123 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    get_memory, sorting, stats, visibility, Memory, ProposalView, CHANGE_INDEX_MEMORY_ID,
+    CHANGE_SEQ_MEMORY_ID, CHANGE_VERSION_MEMORY_ID, PROPOSAL_MAP,
+};
+
+// Upper bound on how many changes one `get_changes` call returns, so a long-offline client can't blow the response size.
+const MAX_CHANGES_PER_CALL: usize = 100;
+
+/*
+    Every proposal carries a version counter that is bumped on each write.
+    Next to the version we keep the global sequence number of the last write
+    so we can answer "what changed after seq N" without scanning every proposal.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct EntityVersion {
+    version: u64,
+    seq: u64,
+}
+
+impl Storable for EntityVersion {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for EntityVersion {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct ProposalChange {
+    key: u64,
+    version: u64,
+    seq: u64,
+    proposal: Option<ProposalView>, // `None` when the proposal no longer exists.
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct Changes {
+    // Pass this back as `last_seen_seq` on the next poll.
+    latest_seq: u64,
+    proposals: Vec<ProposalChange>,
+}
+
+thread_local! {
+    // Last handed out sequence number, 0 means nothing has changed yet.
+    static LAST_SEQ: RefCell<StableCell<u64, Memory>> = RefCell::new(StableCell::init(get_memory(CHANGE_SEQ_MEMORY_ID), 0).unwrap());
+
+    // Proposal key -> current version.
+    static VERSIONS: RefCell<StableBTreeMap<u64, EntityVersion, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(CHANGE_VERSION_MEMORY_ID)));
+
+    // Sequence number -> proposal key. Only the latest seq of each proposal is kept.
+    static SEQ_INDEX: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(CHANGE_INDEX_MEMORY_ID)));
+}
+
+// Has to be called after every write to `PROPOSAL_MAP`.
+pub(crate) fn record_change(key: u64) {
+    let seq: u64 = LAST_SEQ.with(|s| {
+        let next = *s.borrow().get() + 1;
+        s.borrow_mut().set(next).unwrap();
+        next
+    });
+
+    let old: Option<EntityVersion> = VERSIONS.with(|v| v.borrow().get(&key));
+    let version: u64 = match old {
+        Some(old) => {
+            SEQ_INDEX.with(|i| i.borrow_mut().remove(&old.seq));
+            old.version + 1
+        }
+        None => 1,
+    };
+
+    VERSIONS.with(|v| v.borrow_mut().insert(key, EntityVersion { version, seq }));
+    SEQ_INDEX.with(|i| i.borrow_mut().insert(seq, key));
+    stats::refresh(key);
+    sorting::refresh(key);
+}
+
+#[ic_cdk::query]
+fn get_changes(last_seen_seq: u64) -> Changes {
+    let caller: Principal = ic_cdk::caller();
+
+    let changed: Vec<(u64, u64)> = SEQ_INDEX.with(|i| {
+        i.borrow()
+            .range(last_seen_seq.saturating_add(1)..)
+            .take(MAX_CHANGES_PER_CALL)
+            .collect()
+    });
+
+    // When the page is full `latest_seq` tells the client where to continue from.
+    let latest_seq: u64 = match changed.last() {
+        Some((seq, _)) => *seq,
+        None => LAST_SEQ.with(|s| *s.borrow().get()),
+    };
+
+    let proposals: Vec<ProposalChange> = changed
+        .into_iter()
+        .map(|(seq, key)| ProposalChange {
+            key,
+            version: VERSIONS
+                .with(|v| v.borrow().get(&key))
+                .map_or(0, |v| v.version),
+            seq,
+            proposal: PROPOSAL_MAP
+                .with(|p| p.borrow().get(&key))
+                .and_then(|proposal| visibility::present(proposal, &caller)),
+        })
+        .collect();
+
+    Changes {
+        latest_seq,
+        proposals,
+    }
+}
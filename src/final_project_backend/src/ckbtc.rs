@@ -0,0 +1,94 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::{config, icrc, outbox, VoteError};
+
+/*
+    Makes creating a proposal depend on ckBTC, either by owning some or by paying for it.
+    `ledger` is the ckBTC ledger (mxzaz-hqaaa-aaaar-qaada-cai on mainnet), amounts are in satoshi.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) enum CkBtcGate {
+    Hold { ledger: Principal, min_balance: u64 },
+    // Approved beforehand like a deposit (plus the fee), but it goes to the treasury and never comes back.
+    Fee { ledger: Principal, amount: u64 },
+}
+
+// A fee that was taken, in case the creation fails after all.
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+pub(crate) struct PaidFee {
+    ledger: Principal,
+    payer: Principal,
+    amount: u64,
+}
+
+pub(crate) fn validate(gate: &CkBtcGate) -> bool {
+    match gate {
+        CkBtcGate::Hold { min_balance, .. } => *min_balance > 0,
+        CkBtcGate::Fee { amount, .. } => *amount > 0,
+    }
+}
+
+// Checked early, nothing has been taken from the caller yet.
+pub(crate) async fn check_holding(caller: Principal) -> Result<(), VoteError> {
+    let (ledger, min_balance) = match config::ckbtc_gate() {
+        Some(CkBtcGate::Hold {
+            ledger,
+            min_balance,
+        }) => (ledger, min_balance),
+        _ => return Ok(()),
+    };
+
+    let balance: u64 = icrc::balance_of(ledger, caller.into())
+        .await
+        .map_err(|_| VoteError::LedgerCallFailed)?;
+
+    if balance < min_balance {
+        return Err(VoteError::InsufficientCkBtc);
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn collect_fee(caller: Principal) -> Result<Option<PaidFee>, VoteError> {
+    let (ledger, amount) = match config::ckbtc_gate() {
+        Some(CkBtcGate::Fee { ledger, amount }) => (ledger, amount),
+        _ => return Ok(None),
+    };
+
+    icrc::transfer_from(ledger, caller.into(), ic_cdk::id().into(), amount)
+        .await
+        .map_err(|_| VoteError::CkBtcFeeFailed)?;
+
+    Ok(Some(PaidFee {
+        ledger,
+        payer: caller,
+        amount,
+    }))
+}
+
+// Sends the fee back minus the ledger fee, in the background. A failed refund is retried by `outbox`.
+pub(crate) fn refund(fee: PaidFee) {
+    ic_cdk::spawn(async move {
+        if let Err(error) = send_refund(fee.clone()).await {
+            outbox::record(outbox::CallTarget::FeeRefund(fee), &error);
+        }
+    });
+}
+
+pub(crate) async fn send_refund(fee: PaidFee) -> Result<(), String> {
+    let ledger_fee: u64 = icrc::fee(fee.ledger).await?;
+
+    if ledger_fee < fee.amount {
+        let dedup: Option<icrc::Dedup> = outbox::dedup(&outbox::CallTarget::FeeRefund(fee.clone()));
+        icrc::transfer(
+            fee.ledger,
+            None,
+            fee.payer.into(),
+            fee.amount - ledger_fee,
+            dedup,
+        )
+        .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, get_memory, participation, snapshot, visibility, Memory,
+    Proposal, Role, VoteError, COMMITTEE_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const MAX_COMMITTEE_SIZE: u32 = 50;
+
+// Candidates are read in principal order up to here, a draw has to fit into one message.
+const MAX_CANDIDATES: usize = 10_000;
+
+/*
+    A review committee is drawn at random from the voters of a proposal: the ones in its
+    snapshot, or everybody who ever took part when it has none. The randomness comes from
+    the management canister and is stored with the result, so anyone can redo the draw
+    (see `draw`) and check that nobody picked the members by hand.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Committee {
+    members: Vec<Principal>,
+    randomness: Vec<u8>,  // The 32 bytes `raw_rand` returned.
+    candidate_count: u64, // How many candidates the members were drawn from.
+    selected_by: Principal,
+    selected_at: u64,
+}
+
+impl Storable for Committee {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Committee {
+    // Room for the largest allowed committee.
+    const MAX_SIZE: u32 = 2000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> its committee. A committee is drawn once, drawing again could be used to pick a favourite.
+    static COMMITTEES: RefCell<StableBTreeMap<u64, Committee, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(COMMITTEE_MEMORY_ID)));
+}
+
+pub(crate) fn remove(key: u64) {
+    COMMITTEES.with(|c| c.borrow_mut().remove(&key));
+}
+
+/*
+    Partial Fisher-Yates shuffle of `candidates` (in principal order). Draw `i` picks among
+    the candidates not drawn yet using the first 8 bytes of SHA-256(randomness || i).
+*/
+fn draw(mut candidates: Vec<Principal>, randomness: &[u8], size: usize) -> Vec<Principal> {
+    let size: usize = size.min(candidates.len());
+
+    for i in 0..size {
+        let mut hasher = Sha256::new();
+        hasher.update(randomness);
+        hasher.update((i as u64).to_be_bytes());
+        let hash = hasher.finalize();
+
+        let value: u64 = u64::from_be_bytes(hash[..8].try_into().unwrap());
+        let pick: usize = i + (value % (candidates.len() - i) as u64) as usize;
+        candidates.swap(i, pick);
+    }
+
+    candidates.truncate(size);
+    candidates
+}
+
+#[ic_cdk::update]
+async fn select_committee(key: u64, size: u32) -> Result<Committee, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.owner != caller && !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    if size == 0 || size > MAX_COMMITTEE_SIZE {
+        return Err(VoteError::InvalidCommitteeSize);
+    }
+
+    if COMMITTEES.with(|c| c.borrow().contains_key(&key)) {
+        return Err(VoteError::CommitteeAlreadySelected);
+    }
+
+    let randomness: Vec<u8> = match raw_rand().await {
+        Ok((bytes,)) => bytes,
+        Err(_) => return Err(VoteError::RandomnessUnavailable),
+    };
+
+    // Read after the await, so two calls racing each other can't both store a committee.
+    if COMMITTEES.with(|c| c.borrow().contains_key(&key)) {
+        return Err(VoteError::CommitteeAlreadySelected);
+    }
+
+    let candidates: Vec<Principal> = match proposal.snapshot {
+        Some(_) => snapshot::voters(key, MAX_CANDIDATES),
+        None => participation::known_voters(MAX_CANDIDATES),
+    };
+    let candidate_count: u64 = candidates.len() as u64;
+
+    let committee: Committee = Committee {
+        members: draw(candidates, &randomness, size as usize),
+        randomness,
+        candidate_count,
+        selected_by: caller,
+        selected_at: ic_cdk::api::time(),
+    };
+    COMMITTEES.with(|c| c.borrow_mut().insert(key, committee.clone()));
+
+    Ok(committee)
+}
+
+#[ic_cdk::query]
+fn get_committee(key: u64) -> Option<Committee> {
+    if !visibility::can_see_key(key, &ic_cdk::caller()) {
+        return None;
+    }
+
+    COMMITTEES.with(|c| c.borrow().get(&key))
+}
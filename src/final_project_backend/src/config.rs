@@ -0,0 +1,535 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{StableCell, Storable};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    archive, attestation, audit, authenticated_caller, bridge, ckbtc, delegation, deposits, gc,
+    get_memory, personhood, quotas, staking, Memory, Role, VoteError, CONFIG_MEMORY_ID,
+};
+
+// Keeps the config small enough to be read on every call.
+const MAX_COUNCIL_SIZE: usize = 50;
+const MAX_SIGNERS: usize = 50;
+const DEFAULT_EXECUTION_DELAY: u64 = 2 * 24 * 60 * 60 * 1_000_000_000; // Two days.
+const DEFAULT_VETKD_DERIVE_FEE: u128 = 26_153_846_153; // What deriving with `key_1` costs.
+
+/*
+    Policy that used to be hardcoded lives here, so every deployment can pick its own
+    at install time (or on upgrade) and the admin can change it later.
+    It's stored as Candid, which only fills in missing fields that are `opt`:
+    everything added after the first four has to be an `Option`, or the stored config
+    of an older release no longer decodes.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct CanisterConfig {
+    admin: Principal,
+    default_quorum: u32, // Minimum number of votes a new proposal needs for its result to count.
+    max_description_len: u32,
+    allow_public_proposals: bool, // When false only the admin can create proposals.
+    execution_delay: Option<u64>, // Nanoseconds between a proposal passing and its payload becoming executable, two days when not set.
+    council: Option<Vec<Principal>>, // Can veto a passed proposal while it waits in the timelock.
+    deposit: Option<deposits::DepositConfig>, // What creating a proposal costs, `None` for free.
+    signers: Option<Vec<Principal>>, // Co-sign drafts, a draft opens once `signature_threshold` of them did.
+    signature_threshold: Option<u32>,
+    archive: Option<archive::ArchiveConfig>, // Where finalized proposals go once there are too many.
+    default_voting_duration: Option<u64>, // Nanoseconds, sets `voting_ends_at` of proposals that don't set it.
+    ecdsa_key: Option<String>, // Threshold ECDSA key results are signed with (e.g. "key_1"), `None` signs nothing.
+    bridge: Option<bridge::BridgeConfig>, // Where passed results are published, needs `ecdsa_key`.
+    ckbtc_gate: Option<ckbtc::CkBtcGate>, // ckBTC a proposer has to hold or pay, `None` for no requirement.
+    retention: Option<gc::Retention>, // What happens to proposals some time after they closed, `None` keeps them as they are.
+    require_review: Option<bool>, // New proposals wait in `PendingReview` until a moderator approves them.
+    moderators: Option<Vec<Principal>>, // Approve or reject submissions, next to the admin.
+    report_threshold: Option<u32>, // Reports that suspend a proposal, `None` never suspends.
+    governor: Option<Principal>,  // A controller that installs upgrades of this canister for it.
+    router: Option<Principal>, // Set on shard workers, the router may act on behalf of any caller.
+    delegation: Option<delegation::DelegationPolicy>, // `None` turns delegating off.
+    notifier: Option<Principal>, // Gets the voting reminders, see `reminders`.
+    staking: Option<staking::StakingConfig>, // The token that can be staked for voting power, `None` turns staking off.
+    min_voting_period: Option<u64>, // Nanoseconds voting runs before `end_proposal` may close it, `None` for no minimum.
+    attestation: Option<attestation::AttestationConfig>, // The frontend `frontend_only` proposals take votes through.
+    personhood: Option<personhood::PersonhoodConfig>, // Vouches for the voters of `personhood_gated` proposals.
+    quota: Option<quotas::Quota>, // How much one principal may create, `None` for no limit.
+    vetkd_key: Option<String>, // vetKD key sealed ballots are encrypted to (e.g. "key_1"), `None` turns them off.
+    vetkd_derive_fee: Option<u128>, // Cycles sent to derive a vetKey, the fee of `key_1` when not set. What's left over comes back.
+}
+
+/*
+    For tuning a running canister without sending the whole config back:
+    every field that is set replaces the stored value, the rest stays as it is.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct ConfigUpdate {
+    default_quorum: Option<u32>,
+    max_description_len: Option<u32>,
+    allow_public_proposals: Option<bool>,
+    execution_delay: Option<u64>,
+    default_voting_duration: Option<Option<u64>>,
+    deposit: Option<Option<deposits::DepositConfig>>, // The deposit names its ledger.
+    ckbtc_gate: Option<Option<ckbtc::CkBtcGate>>,
+    delegation: Option<Option<delegation::DelegationPolicy>>,
+    require_review: Option<bool>,
+    report_threshold: Option<Option<u32>>,
+    quota: Option<Option<quotas::Quota>>,
+    min_voting_period: Option<Option<u64>>,
+}
+
+impl Default for CanisterConfig {
+    fn default() -> Self {
+        CanisterConfig {
+            // The anonymous principal stands for "no admin yet", it's replaced by whoever installs the canister.
+            admin: Principal::anonymous(),
+            default_quorum: 0,
+            max_description_len: 2000,
+            allow_public_proposals: true,
+            execution_delay: None,
+            council: None,
+            deposit: None,
+            signers: None,
+            signature_threshold: None,
+            archive: None,
+            default_voting_duration: None,
+            ecdsa_key: None,
+            bridge: None,
+            ckbtc_gate: None,
+            retention: None,
+            require_review: None,
+            moderators: None,
+            report_threshold: None,
+            governor: None,
+            router: None,
+            delegation: None,
+            notifier: None,
+            staking: None,
+            min_voting_period: None,
+            attestation: None,
+            personhood: None,
+            quota: None,
+            vetkd_key: None,
+            vetkd_derive_fee: None,
+        }
+    }
+}
+
+impl Storable for CanisterConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static CONFIG: RefCell<StableCell<CanisterConfig, Memory>> = RefCell::new(StableCell::init(get_memory(CONFIG_MEMORY_ID), CanisterConfig::default()).unwrap());
+}
+
+impl CanisterConfig {
+    fn council(&self) -> &[Principal] {
+        self.council.as_deref().unwrap_or_default()
+    }
+
+    fn signers(&self) -> &[Principal] {
+        self.signers.as_deref().unwrap_or_default()
+    }
+
+    fn moderators(&self) -> &[Principal] {
+        self.moderators.as_deref().unwrap_or_default()
+    }
+}
+
+fn get() -> CanisterConfig {
+    CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn set(config: CanisterConfig) {
+    CONFIG.with(|c| c.borrow_mut().set(config).unwrap());
+}
+
+/*
+    Called from `init` and `post_upgrade`. An explicit argument replaces the stored config,
+    otherwise the stored one is kept and only gets an admin if it has none yet.
+    An invalid argument fails the install, the canister keeps running as it was.
+*/
+pub(crate) fn apply_install_arg(arg: Option<CanisterConfig>, installer: Principal) {
+    match arg {
+        Some(config) => {
+            if validate(&config).is_err() {
+                ic_cdk::trap("Invalid config.");
+            }
+            replace(installer, config);
+        }
+        None => {
+            let mut config: CanisterConfig = get();
+
+            if config.admin == Principal::anonymous() {
+                config.admin = installer;
+                set(config);
+            }
+        }
+    }
+}
+
+// Shared by `update_config` and the install argument, both end up in the audit log like `apply_update`.
+fn replace(actor: Principal, config: CanisterConfig) {
+    let config_hash: Vec<u8> = Sha256::digest(Encode!(&config).unwrap()).to_vec();
+    set(config);
+    audit::record(
+        actor,
+        u64::MAX,
+        audit::AuditEvent::ConfigReplaced { config_hash },
+    );
+}
+
+pub(crate) fn is_admin(principal: &Principal) -> bool {
+    *principal != Principal::anonymous() && get().admin == *principal
+}
+
+pub(crate) fn default_quorum() -> u32 {
+    get().default_quorum
+}
+
+pub(crate) fn execution_delay() -> u64 {
+    get().execution_delay.unwrap_or(DEFAULT_EXECUTION_DELAY)
+}
+
+pub(crate) fn archive() -> Option<archive::ArchiveConfig> {
+    get().archive
+}
+
+pub(crate) fn deposit() -> Option<deposits::DepositConfig> {
+    get().deposit
+}
+
+pub(crate) fn is_signer(principal: &Principal) -> bool {
+    *principal != Principal::anonymous() && get().signers().contains(principal)
+}
+
+// `None` while no signer set is configured, drafts can't be proposed then.
+pub(crate) fn signature_threshold() -> Option<u32> {
+    let config: CanisterConfig = get();
+
+    if config.signers().is_empty() {
+        None
+    } else {
+        Some(config.signature_threshold.unwrap_or(0))
+    }
+}
+
+pub(crate) fn is_council_member(principal: &Principal) -> bool {
+    *principal != Principal::anonymous() && get().council().contains(principal)
+}
+
+pub(crate) fn default_voting_duration() -> Option<u64> {
+    get().default_voting_duration
+}
+
+pub(crate) fn ecdsa_key() -> Option<String> {
+    get().ecdsa_key
+}
+
+pub(crate) fn vetkd_key() -> Option<String> {
+    get().vetkd_key
+}
+
+pub(crate) fn vetkd_derive_fee() -> u128 {
+    get().vetkd_derive_fee.unwrap_or(DEFAULT_VETKD_DERIVE_FEE)
+}
+
+pub(crate) fn bridge() -> Option<bridge::BridgeConfig> {
+    get().bridge
+}
+
+pub(crate) fn ckbtc_gate() -> Option<ckbtc::CkBtcGate> {
+    get().ckbtc_gate
+}
+
+pub(crate) fn retention() -> Option<gc::Retention> {
+    get().retention
+}
+
+pub(crate) fn requires_review() -> bool {
+    get().require_review.unwrap_or(false)
+}
+
+pub(crate) fn is_moderator(principal: &Principal) -> bool {
+    is_admin(principal)
+        || (*principal != Principal::anonymous() && get().moderators().contains(principal))
+}
+
+pub(crate) fn report_threshold() -> Option<u32> {
+    get().report_threshold
+}
+
+pub(crate) fn admin() -> Principal {
+    get().admin
+}
+
+pub(crate) fn router() -> Option<Principal> {
+    get().router
+}
+
+// What a worker spawned by `router` starts with: the same admin, the rest is left to them.
+pub(crate) fn worker_config(router: Principal) -> CanisterConfig {
+    CanisterConfig {
+        admin: get().admin,
+        router: Some(router),
+        ..CanisterConfig::default()
+    }
+}
+
+pub(crate) fn delegation() -> Option<delegation::DelegationPolicy> {
+    get().delegation
+}
+
+pub(crate) fn notifier() -> Option<Principal> {
+    get().notifier
+}
+
+pub(crate) fn staking() -> Option<staking::StakingConfig> {
+    get().staking
+}
+
+pub(crate) fn governor() -> Option<Principal> {
+    get().governor
+}
+
+pub(crate) fn attestation() -> Option<attestation::AttestationConfig> {
+    get().attestation
+}
+
+pub(crate) fn personhood() -> Option<personhood::PersonhoodConfig> {
+    get().personhood
+}
+
+pub(crate) fn quota() -> Option<quotas::Quota> {
+    get().quota
+}
+
+pub(crate) fn min_voting_period() -> Option<u64> {
+    get().min_voting_period
+}
+
+pub(crate) fn max_description_len() -> usize {
+    get().max_description_len as usize
+}
+
+pub(crate) fn can_create_proposals(principal: &Principal) -> bool {
+    get().allow_public_proposals || is_admin(principal)
+}
+
+#[ic_cdk::query]
+fn get_config() -> CanisterConfig {
+    get()
+}
+
+// Shared by `update_config`, `set_config` and the install argument.
+fn validate(config: &CanisterConfig) -> Result<(), VoteError> {
+    // Handing the canister to nobody would lock everyone out of the admin endpoints.
+    if config.admin == Principal::anonymous() {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if config.council().len() > MAX_COUNCIL_SIZE || config.moderators().len() > MAX_COUNCIL_SIZE {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if !config.deposit.as_ref().is_none_or(deposits::validate) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    // With signers there has to be a threshold they can actually reach.
+    let threshold: u32 = config.signature_threshold.unwrap_or(0);
+    if config.signers().len() > MAX_SIGNERS
+        || (!config.signers().is_empty()
+            && (threshold == 0 || threshold as usize > config.signers().len()))
+    {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if let Some(value) = &config.bridge {
+        if config.ecdsa_key.is_none() || !bridge::validate(value) {
+            return Err(VoteError::InvalidConfig);
+        }
+    }
+
+    if !config.staking.as_ref().is_none_or(staking::validate) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if !config
+        .attestation
+        .as_ref()
+        .is_none_or(attestation::validate)
+    {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if !config.personhood.as_ref().is_none_or(personhood::validate) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if !config.quota.as_ref().is_none_or(quotas::validate) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if !config.ckbtc_gate.as_ref().is_none_or(ckbtc::validate) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if let Some(retention) = &config.retention {
+        if !gc::validate(retention, config.archive.is_some()) {
+            return Err(VoteError::InvalidConfig);
+        }
+    }
+
+    if !config.delegation.as_ref().is_none_or(delegation::validate) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if config.report_threshold == Some(0) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    if config.default_voting_duration == Some(0) || config.min_voting_period == Some(0) {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn update_config(config: CanisterConfig) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    validate(&config)?;
+    replace(caller, config);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_config(update: ConfigUpdate) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    apply_update(caller, u64::MAX, update)
+}
+
+// The stored config with the update on top, if the result is valid.
+fn updated(update: &ConfigUpdate) -> Result<CanisterConfig, VoteError> {
+    let update: ConfigUpdate = update.clone();
+    let mut config: CanisterConfig = get();
+
+    if let Some(value) = update.default_quorum {
+        config.default_quorum = value;
+    }
+    if let Some(value) = update.max_description_len {
+        config.max_description_len = value;
+    }
+    if let Some(value) = update.allow_public_proposals {
+        config.allow_public_proposals = value;
+    }
+    if let Some(value) = update.execution_delay {
+        config.execution_delay = Some(value);
+    }
+    if let Some(value) = update.default_voting_duration {
+        config.default_voting_duration = value;
+    }
+    if let Some(value) = update.deposit {
+        config.deposit = value;
+    }
+    if let Some(value) = update.ckbtc_gate {
+        config.ckbtc_gate = value;
+    }
+    if let Some(value) = update.require_review {
+        config.require_review = Some(value);
+    }
+    if let Some(value) = update.report_threshold {
+        config.report_threshold = value;
+    }
+    if let Some(value) = update.delegation {
+        config.delegation = value;
+    }
+    if let Some(value) = update.quota {
+        config.quota = value;
+    }
+    if let Some(value) = update.min_voting_period {
+        config.min_voting_period = value;
+    }
+
+    validate(&config)?;
+    Ok(config)
+}
+
+// For a `ConfigChange` proposal at creation, the config may still change before it runs.
+pub(crate) fn check_update(update: &ConfigUpdate) -> Result<(), VoteError> {
+    updated(update).map(|_| ())
+}
+
+// Shared by `set_config` and the execution of a passed `ConfigChange` proposal, both end up in the audit log.
+pub(crate) fn apply_update(
+    actor: Principal,
+    proposal_key: u64,
+    update: ConfigUpdate,
+) -> Result<(), VoteError> {
+    set(updated(&update)?);
+    audit::record(
+        actor,
+        proposal_key,
+        audit::AuditEvent::ConfigChanged { update },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The config as the release that introduced it stored it.
+    #[derive(CandidType)]
+    struct FirstConfig {
+        admin: Principal,
+        default_quorum: u32,
+        max_description_len: u32,
+        allow_public_proposals: bool,
+    }
+
+    #[test]
+    fn reads_the_first_stored_config() {
+        let bytes: Vec<u8> = Encode!(&FirstConfig {
+            admin: Principal::from_slice(&[1]),
+            default_quorum: 5,
+            max_description_len: 1000,
+            allow_public_proposals: false,
+        })
+        .unwrap();
+        let config: CanisterConfig = CanisterConfig::from_bytes(Cow::Owned(bytes));
+
+        assert_eq!(config.admin, Principal::from_slice(&[1]));
+        assert_eq!(config.default_quorum, 5);
+        assert!(config.council().is_empty());
+        assert_eq!(config.execution_delay, None);
+        assert!(validate(&config).is_ok());
+    }
+
+    #[test]
+    fn rejects_an_unreachable_signature_threshold() {
+        let config: CanisterConfig = CanisterConfig {
+            admin: Principal::from_slice(&[1]),
+            signers: Some(vec![Principal::from_slice(&[2])]),
+            signature_threshold: Some(2),
+            ..CanisterConfig::default()
+        };
+
+        assert!(validate(&config).is_err());
+    }
+}
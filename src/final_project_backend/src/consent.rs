@@ -0,0 +1,229 @@
+use candid::{CandidType, Decode, Deserialize, Principal};
+
+use crate::{backup, pause, visibility, Choice, CreateProposal, PROPOSAL_MAP};
+
+const MAX_TITLE_IN_MESSAGE: usize = 80;
+
+/*
+    ICRC-21: before a wallet asks its user to sign a call it fetches a consent message
+    from here and shows it, so people see "Vote Approve on proposal #12: ..." instead of a blob.
+    Only `vote` and `create_proposal` are described, everything else is unsupported.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ConsentMessageMetadata {
+    language: String,
+    utc_offset_minutes: Option<i16>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) enum DisplayMessageType {
+    GenericDisplay,
+    LineDisplay {
+        characters_per_line: u16,
+        lines_per_page: u16,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ConsentMessageSpec {
+    metadata: ConsentMessageMetadata,
+    device_spec: Option<DisplayMessageType>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ConsentMessageRequest {
+    method: String,
+    arg: Vec<u8>,
+    user_preferences: ConsentMessageSpec,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct LineDisplayPage {
+    lines: Vec<String>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) enum ConsentMessage {
+    GenericDisplayMessage(String),
+    LineDisplayMessage { pages: Vec<LineDisplayPage> },
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ConsentInfo {
+    consent_message: ConsentMessage,
+    metadata: ConsentMessageMetadata,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ErrorInfo {
+    description: String,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) enum ConsentError {
+    UnsupportedCanisterCall(ErrorInfo),
+    ConsentMessageUnavailable(ErrorInfo),
+    InsufficientPayment(ErrorInfo),
+    GenericError {
+        error_code: candid::Nat,
+        description: String,
+    },
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct StandardRecord {
+    name: String,
+    url: String,
+}
+
+fn unavailable(description: &str) -> ConsentError {
+    ConsentError::ConsentMessageUnavailable(ErrorInfo {
+        description: description.to_string(),
+    })
+}
+
+fn shorten(title: &str) -> String {
+    if title.chars().count() <= MAX_TITLE_IN_MESSAGE {
+        return title.to_string();
+    }
+
+    let mut short: String = title.chars().take(MAX_TITLE_IN_MESSAGE - 3).collect();
+    short.push_str("...");
+    short
+}
+
+fn describe_vote(arg: &[u8], caller: &Principal) -> Result<String, ConsentError> {
+    let (key, choice): (u64, Choice) =
+        Decode!(arg, u64, Choice).map_err(|_| unavailable("the argument isn't a vote"))?;
+
+    // The title of a proposal the caller can't see stays out of the message.
+    let title: Option<String> = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .filter(|proposal| visibility::can_see(proposal, caller))
+        .map(|proposal| shorten(&proposal.title));
+
+    Ok(match title {
+        Some(title) => format!("Vote {:?} on proposal #{}: {}", choice, key, title),
+        None => format!("Vote {:?} on proposal #{}", choice, key),
+    })
+}
+
+fn describe_create(arg: &[u8]) -> Result<String, ConsentError> {
+    let (_, proposal): (u64, CreateProposal) = Decode!(arg, u64, CreateProposal)
+        .map_err(|_| unavailable("the argument isn't a proposal"))?;
+
+    let mut message: String = format!("Create the proposal: {}", shorten(&proposal.title));
+
+    if proposal.save_as_draft {
+        message.push_str(" (as a draft)");
+    }
+    if proposal.execution.is_some() {
+        message.push_str(". If it passes, it runs a payload after the timelock");
+    }
+
+    Ok(message)
+}
+
+// Greedy word wrap, words longer than a line are split.
+fn paginate(message: &str, characters_per_line: u16, lines_per_page: u16) -> Vec<LineDisplayPage> {
+    let width: usize = characters_per_line.max(1) as usize;
+    let mut lines: Vec<String> = vec![];
+    let mut line: String = String::new();
+
+    for word in message.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+
+        while !word.is_empty() {
+            let used: usize = line.chars().count();
+            let gap: usize = if used == 0 { 0 } else { 1 };
+
+            if used + gap + word.len() <= width {
+                if gap == 1 {
+                    line.push(' ');
+                }
+                line.extend(word.drain(..));
+            } else if used > 0 {
+                lines.push(std::mem::take(&mut line));
+            } else {
+                lines.push(word.drain(..width).collect());
+            }
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+        .chunks(lines_per_page.max(1) as usize)
+        .map(|lines| LineDisplayPage {
+            lines: lines.to_vec(),
+        })
+        .collect()
+}
+
+#[ic_cdk::update]
+fn icrc21_canister_call_consent_message(
+    request: ConsentMessageRequest,
+) -> Result<ConsentInfo, ConsentError> {
+    let caller: Principal = ic_cdk::caller();
+
+    if backup::check().is_err() {
+        return Err(ConsentError::ConsentMessageUnavailable(ErrorInfo {
+            description: "a backup is being restored".to_string(),
+        }));
+    }
+
+    if pause::check().is_err() {
+        return Err(ConsentError::ConsentMessageUnavailable(ErrorInfo {
+            description: "the canister is paused".to_string(),
+        }));
+    }
+
+    let message: String = match request.method.as_str() {
+        "vote" => describe_vote(&request.arg, &caller)?,
+        "create_proposal" => describe_create(&request.arg)?,
+        _ => {
+            return Err(ConsentError::UnsupportedCanisterCall(ErrorInfo {
+                description: format!("no consent message for {}", request.method),
+            }))
+        }
+    };
+
+    let consent_message: ConsentMessage = match request.user_preferences.device_spec {
+        Some(DisplayMessageType::LineDisplay {
+            characters_per_line,
+            lines_per_page,
+        }) => ConsentMessage::LineDisplayMessage {
+            pages: paginate(&message, characters_per_line, lines_per_page),
+        },
+        _ => ConsentMessage::GenericDisplayMessage(message),
+    };
+
+    // The messages only exist in English.
+    Ok(ConsentInfo {
+        consent_message,
+        metadata: ConsentMessageMetadata {
+            language: "en".to_string(),
+            utc_offset_minutes: request.user_preferences.metadata.utc_offset_minutes,
+        },
+    })
+}
+
+#[ic_cdk::query]
+fn icrc10_supported_standards() -> Vec<StandardRecord> {
+    vec![
+        StandardRecord {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-10/ICRC-10.md".to_string(),
+        },
+        StandardRecord {
+            name: "ICRC-3".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+        StandardRecord {
+            name: "ICRC-21".to_string(),
+            url: "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-21/ICRC-21.md".to_string(),
+        },
+    ]
+}
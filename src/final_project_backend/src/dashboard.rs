@@ -0,0 +1,81 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{
+    delegation, embargo, listing, participation, snapshot, stats, visibility, voter_list, Proposal,
+    ProposalView, PROPOSAL_MAP,
+};
+
+// Per list, a home screen shows a handful and links to the full listing.
+const MAX_DASHBOARD_ITEMS: usize = 20;
+
+/*
+    Everything a home screen needs about the caller in one call. The anonymous caller
+    only gets the global counts. Both lists go through every proposal, newest first.
+*/
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct Dashboard {
+    awaiting_vote: Vec<(u64, ProposalView)>, // Open, and the caller may still vote on it.
+    created: Vec<(u64, ProposalView)>,
+    delegation: Option<delegation::Delegation>, // Who the caller delegated to.
+    delegators: u64,                            // How many delegated to the caller.
+    participation: Option<participation::VoterStats>,
+    stats: stats::Stats, // Open proposals and the rest of the global counts.
+}
+
+// Only what can be told without the caller's holdings, gated proposals are listed either way.
+fn awaits_vote(key: u64, proposal: &Proposal, caller: &Principal) -> bool {
+    proposal.is_active
+        && proposal.listing == listing::ListingStatus::Listed
+        && !proposal.voted.contains(caller)
+        && voter_list::check(key, proposal, caller).is_ok()
+        && snapshot::weight_of(key, proposal, caller).is_some()
+}
+
+#[ic_cdk::query]
+fn get_dashboard() -> Dashboard {
+    let caller: Principal = ic_cdk::caller();
+    let mut dashboard: Dashboard = Dashboard {
+        awaiting_vote: vec![],
+        created: vec![],
+        delegation: None,
+        delegators: 0,
+        participation: None,
+        stats: stats::current(),
+    };
+
+    if caller == Principal::anonymous() {
+        return dashboard;
+    }
+
+    let proposals: Vec<(u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .iter()
+            .filter(|(key, proposal)| {
+                (proposal.owner == caller || awaits_vote(*key, proposal, &caller))
+                    && visibility::can_see(proposal, &caller)
+            })
+            .collect()
+    });
+
+    for (key, proposal) in proposals.into_iter().rev() {
+        let created: bool =
+            dashboard.created.len() < MAX_DASHBOARD_ITEMS && proposal.owner == caller;
+        let awaiting: bool = dashboard.awaiting_vote.len() < MAX_DASHBOARD_ITEMS
+            && awaits_vote(key, &proposal, &caller);
+        let view: ProposalView = embargo::redact(proposal, &caller);
+
+        if created {
+            dashboard.created.push((key, view.clone()));
+        }
+
+        if awaiting {
+            dashboard.awaiting_vote.push((key, view));
+        }
+    }
+
+    dashboard.delegation = delegation::delegation_of(caller);
+    dashboard.delegators = delegation::delegator_count(caller);
+    dashboard.participation = participation::stats_of(caller);
+
+    dashboard
+}
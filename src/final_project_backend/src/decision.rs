@@ -0,0 +1,240 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{
+    bridge,
+    delegation::{self, DelegatedWeight},
+    dependencies, events, followups, membership, reports, sealed_ballots, signed_results, ties,
+    watch, Proposal, VoteError,
+};
+
+/*
+    How the counts of a proposal turn into a result. The rule is fixed at creation,
+    and the result is worked out once when voting closes, so every frontend shows the same one.
+    Weights count instead of heads when the proposal is weighted.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) enum DecisionRule {
+    // More approve than reject, passes are ignored. What every proposal used before.
+    SimpleMajority,
+    // Approve has more than half of everything cast, passes included.
+    AbsoluteMajority,
+    // Approve has at least `threshold_percent` of approve and reject together, e.g. 66.
+    Supermajority { threshold_percent: u8 },
+    // Approve is the largest of the three counts on its own.
+    Plurality,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize, Serialize)]
+pub(crate) enum Outcome {
+    Passed,
+    Rejected,
+    QuorumNotMet,
+}
+
+/*
+    Abstaining says "I took part, but I'm neither for nor against". Abstentions never
+    count for or against a proposal, whether they help reach the quorum is up to the proposal.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Abstention {
+    pub(crate) allowed: bool,
+    counts_for_quorum: bool,
+}
+
+impl Default for Abstention {
+    fn default() -> Self {
+        Abstention {
+            allowed: true,
+            counts_for_quorum: true,
+        }
+    }
+}
+
+pub(crate) fn validate(rule: &DecisionRule) -> Result<(), VoteError> {
+    match rule {
+        // Half or less would let both sides win at the same time.
+        DecisionRule::Supermajority { threshold_percent }
+            if !(51..=100).contains(threshold_percent) =>
+        {
+            Err(VoteError::InvalidDecisionRule)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Heads that count for the quorum.
+pub(crate) fn turnout(proposal: &Proposal) -> u64 {
+    let voters: u64 = proposal.voter_count();
+
+    if proposal.abstention.counts_for_quorum {
+        voters
+    } else {
+        voters.saturating_sub(proposal.abstain_voters)
+    }
+}
+
+pub(crate) fn quorum_met(proposal: &Proposal) -> bool {
+    turnout(proposal) >= proposal.quorum as u64
+}
+
+fn rule_met(proposal: &Proposal) -> bool {
+    rule_holds(
+        &proposal.decision_rule,
+        proposal.approve,
+        proposal.reject,
+        proposal.pass,
+    )
+}
+
+fn rule_holds(rule: &DecisionRule, approve: u64, reject: u64, pass: u64) -> bool {
+    // In u128, so large weights can't overflow the products below.
+    let approve: u128 = approve as u128;
+    let reject: u128 = reject as u128;
+    let pass: u128 = pass as u128;
+
+    match rule {
+        DecisionRule::SimpleMajority => approve > reject,
+        DecisionRule::AbsoluteMajority => approve * 2 > approve + reject + pass,
+        DecisionRule::Supermajority { threshold_percent } => {
+            approve > 0 && approve * 100 >= (approve + reject) * *threshold_percent as u128
+        }
+        DecisionRule::Plurality => approve > reject && approve > pass,
+    }
+}
+
+pub(crate) fn outcome(proposal: &Proposal) -> Outcome {
+    if !quorum_met(proposal) {
+        Outcome::QuorumNotMet
+    } else if rule_met(proposal) {
+        Outcome::Passed
+    } else {
+        Outcome::Rejected
+    }
+}
+
+/*
+    The outcome however up to `weight` more, cast by up to `heads` more voters, gets split,
+    counted on top of `direct`. `None` while it could still go more than one way.
+    Every rule only gets easier to meet with more approve and harder with more reject or pass,
+    so putting everything that is still out on one choice is as far as the result can move.
+*/
+pub(crate) fn settled_outcome(
+    proposal: &Proposal,
+    direct: &DelegatedWeight,
+    weight: u64,
+    heads: u64,
+) -> Option<Outcome> {
+    let turnout: u64 = turnout(proposal);
+    let quorum: u64 = proposal.quorum as u64;
+
+    if turnout.saturating_add(heads) < quorum {
+        return Some(Outcome::QuorumNotMet);
+    } else if turnout < quorum {
+        return None;
+    }
+
+    let rule: &DecisionRule = &proposal.decision_rule;
+    let (approve, reject, pass) = (direct.approve, direct.reject, direct.pass);
+
+    if rule_holds(rule, approve, reject.saturating_add(weight), pass)
+        && rule_holds(rule, approve, reject, pass.saturating_add(weight))
+    {
+        Some(Outcome::Passed)
+    } else if !rule_holds(rule, approve.saturating_add(weight), reject, pass) {
+        Some(Outcome::Rejected)
+    } else {
+        None
+    }
+}
+
+// The stored outcome once there is one, a broken tie may have passed.
+pub(crate) fn passed(proposal: &Proposal) -> bool {
+    proposal.outcome.unwrap_or_else(|| outcome(proposal)) == Outcome::Passed
+}
+
+// Called wherever voting closes, before anything that depends on the result.
+pub(crate) fn finalize(key: u64, proposal: &mut Proposal) {
+    // Delegates may have voted sealed too, nothing is applied before every ballot is counted.
+    if sealed_ballots::hold_back(key, proposal) {
+        return;
+    }
+
+    delegation::apply(key, proposal);
+    proposal.outcome = ties::settle(key, proposal, outcome(proposal));
+    events::on_finalized(key, proposal);
+    watch::on_closed(key);
+    conclude(key, proposal);
+}
+
+// Everything that needs the outcome. A tie that is held back gets here once `ties` breaks it.
+pub(crate) fn conclude(key: u64, proposal: &mut Proposal) {
+    if proposal.outcome.is_none() {
+        return;
+    }
+
+    signed_results::request(key, proposal);
+    reports::generate(key, proposal);
+
+    if proposal.outcome == Some(Outcome::Passed) {
+        bridge::queue(key);
+        dependencies::on_passed(key);
+        membership::on_passed(key, proposal);
+        followups::on_passed(key, proposal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposal_store;
+
+    #[test]
+    fn every_rule_decides_as_documented() {
+        assert!(rule_holds(&DecisionRule::SimpleMajority, 3, 2, 10));
+        assert!(!rule_holds(&DecisionRule::SimpleMajority, 2, 2, 0));
+
+        assert!(rule_holds(&DecisionRule::AbsoluteMajority, 6, 2, 3));
+        assert!(!rule_holds(&DecisionRule::AbsoluteMajority, 5, 2, 3));
+
+        let two_thirds: DecisionRule = DecisionRule::Supermajority {
+            threshold_percent: 66,
+        };
+        assert!(rule_holds(&two_thirds, 66, 34, 0));
+        assert!(!rule_holds(&two_thirds, 65, 35, 0));
+        assert!(!rule_holds(&two_thirds, 0, 0, 0));
+
+        assert!(rule_holds(&DecisionRule::Plurality, 4, 3, 3));
+        assert!(!rule_holds(&DecisionRule::Plurality, 4, 3, 4));
+    }
+
+    #[test]
+    fn large_weights_do_not_overflow() {
+        assert!(rule_holds(
+            &DecisionRule::AbsoluteMajority,
+            u64::MAX,
+            u64::MAX / 2,
+            0
+        ));
+    }
+
+    #[test]
+    fn rejects_a_supermajority_both_sides_could_reach() {
+        let rule = |threshold_percent: u8| DecisionRule::Supermajority { threshold_percent };
+
+        assert!(validate(&rule(50)).is_err());
+        assert!(validate(&rule(101)).is_err());
+        assert!(validate(&rule(51)).is_ok());
+    }
+
+    #[test]
+    fn abstentions_count_for_the_quorum_only_when_the_proposal_says_so() {
+        let mut proposal: Proposal = proposal_store::sample();
+        proposal.quorum = 3;
+        proposal.abstain_voters = 1;
+        assert_eq!(outcome(&proposal), Outcome::Passed);
+
+        proposal.abstention.counts_for_quorum = false;
+        assert_eq!(outcome(&proposal), Outcome::QuorumNotMet);
+    }
+}
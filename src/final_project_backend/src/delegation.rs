@@ -0,0 +1,431 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell, collections::HashSet};
+
+use crate::{
+    authenticated_caller, ballots, bans, config, embargo, get_memory, roles, snapshot, visibility,
+    voter_list, weighting, Choice, Memory, Proposal, StorablePrincipal, VoteError,
+    DELEGATE_INDEX_MEMORY_ID, DELEGATION_MEMORY_ID, MAX_BATCH_SIZE, PROPOSAL_MAP,
+};
+
+// Keeps the work of closing a proposal bounded, every delegator of every voting delegate is looked at.
+const MAX_DELEGATORS: usize = 1000;
+// Delegators looked at in all when a proposal closes, those of later voters are left out past it.
+const MAX_RESOLVED: usize = 20_000;
+
+/*
+    Liquid democracy, one level deep: a principal can hand its vote to a delegate. When a
+    proposal closes, every delegator who didn't vote themselves adds their weight to the
+    choice of their delegate, if the delegate voted. Gated proposals (NFT, SNS, Merkle) are
+    left out, their weights can only be worked out while the voter is calling.
+
+    With a decay policy a delegation that isn't renewed loses its weight over time, so
+    forgotten delegations don't keep deciding things. The decay is evaluated on close.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct DelegationPolicy {
+    decay: Option<Decay>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Decay {
+    grace_period: u64, // Nanoseconds after a renewal at full weight.
+    decay_period: u64, // Nanoseconds after that over which the weight falls linearly to nothing.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Delegation {
+    delegate: Principal,
+    renewed_at: u64,
+}
+
+impl Storable for Delegation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Delegation {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// What delegations added to a closed proposal, so closing it again can take it back out first.
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize)]
+pub(crate) struct DelegatedWeight<C = u64> {
+    pub(crate) approve: C,
+    pub(crate) reject: C,
+    pub(crate) pass: C,
+    pub(crate) abstain: C,
+}
+
+impl<C> DelegatedWeight<C> {
+    pub(crate) fn map<D>(self, f: impl Fn(C) -> D) -> DelegatedWeight<D> {
+        DelegatedWeight {
+            approve: f(self.approve),
+            reject: f(self.reject),
+            pass: f(self.pass),
+            abstain: f(self.abstain),
+        }
+    }
+}
+
+thread_local! {
+    // Delegator -> who they delegated to.
+    static DELEGATIONS: RefCell<StableBTreeMap<StorablePrincipal, Delegation, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(DELEGATION_MEMORY_ID)));
+
+    // (delegate, delegator), to find the delegators of a delegate.
+    static BY_DELEGATE: RefCell<StableBTreeMap<(StorablePrincipal, StorablePrincipal), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(DELEGATE_INDEX_MEMORY_ID)));
+}
+
+pub(crate) fn validate(policy: &DelegationPolicy) -> bool {
+    policy
+        .decay
+        .as_ref()
+        .is_none_or(|decay| decay.decay_period > 0)
+}
+
+fn delegators_of(delegate: Principal) -> Vec<(Principal, Delegation)> {
+    let ids: Vec<Principal> = BY_DELEGATE.with(|b| {
+        b.borrow()
+            .range((StorablePrincipal(delegate), StorablePrincipal::default())..)
+            .take_while(|((d, _), _)| d.0 == delegate)
+            .map(|((_, delegator), _)| delegator.0)
+            .collect()
+    });
+
+    DELEGATIONS.with(|d| {
+        let map = d.borrow();
+        ids.into_iter()
+            .filter_map(|id| {
+                map.get(&StorablePrincipal(id))
+                    .map(|delegation| (id, delegation))
+            })
+            .collect()
+    })
+}
+
+// Share of the weight that's left, in basis points.
+fn remaining_share(decay: &Option<Decay>, delegation: &Delegation, now: u64) -> u64 {
+    let decay: &Decay = match decay {
+        Some(value) => value,
+        None => return 10_000,
+    };
+
+    let age: u64 = now.saturating_sub(delegation.renewed_at);
+    let decayed: u64 = age.saturating_sub(decay.grace_period);
+
+    if decayed >= decay.decay_period {
+        return 0;
+    }
+
+    ((decay.decay_period - decayed) as u128 * 10_000 / decay.decay_period as u128) as u64
+}
+
+fn add(proposal: &mut Proposal, choice: Choice, weight: u64, sign: bool) {
+    let count: &mut u64 = match choice {
+        Choice::Approve => &mut proposal.approve,
+        Choice::Reject => &mut proposal.reject,
+        Choice::Pass => &mut proposal.pass,
+        Choice::Abstain => &mut proposal.abstain,
+    };
+
+    *count = if sign {
+        count.saturating_add(weight)
+    } else {
+        count.saturating_sub(weight)
+    };
+}
+
+impl DelegatedWeight {
+    fn add(&mut self, choice: Choice, weight: u64) {
+        let total: &mut u64 = match choice {
+            Choice::Approve => &mut self.approve,
+            Choice::Reject => &mut self.reject,
+            Choice::Pass => &mut self.pass,
+            Choice::Abstain => &mut self.abstain,
+        };
+        *total = total.saturating_add(weight);
+    }
+
+    fn entries(&self) -> [(Choice, u64); 4] {
+        [
+            (Choice::Approve, self.approve),
+            (Choice::Reject, self.reject),
+            (Choice::Pass, self.pass),
+            (Choice::Abstain, self.abstain),
+        ]
+    }
+}
+
+// Where the counts of a proposal come from. Hidden like the counts themselves.
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct VoteBreakdown {
+    direct: DelegatedWeight, // Cast by the voters themselves, same shape as `delegated`.
+    delegated: DelegatedWeight, // Added for delegators by the vote of their delegate.
+    delegators: u64,         // Delegators whose weight went to their delegate's choice.
+    overridden: u64, // Delegators who voted themselves, their delegate's choice didn't count for them.
+    projected: bool, // Voting is open, `delegated` is what closing now would add.
+}
+
+// What the delegations of a proposal resolve to right now.
+struct Resolution {
+    delegated: DelegatedWeight,
+    delegators: u64,
+    overridden: u64,
+}
+
+// The weight a delegator brings along, before any decay.
+fn base_weight(key: u64, proposal: &Proposal, delegator: &Principal) -> Option<u64> {
+    match &proposal.role_weights {
+        Some(schedule) => Some(roles::weight_of(schedule, delegator)),
+        None => snapshot::weight_of(key, proposal, delegator)
+            .map(|weight| weighting::effective(proposal, weight)),
+    }
+}
+
+/*
+    Every delegator of every delegate who voted follows the delegate's choice, unless they
+    cast a ballot of their own: a direct vote always overrides the delegation for that proposal,
+    whichever of the two came first. Each delegator counts once, either way.
+    Delegates are taken in the order they voted, until `MAX_RESOLVED` delegators were looked at.
+*/
+fn resolve(key: u64, proposal: &Proposal) -> Resolution {
+    let mut resolution: Resolution = Resolution {
+        delegated: DelegatedWeight::default(),
+        delegators: 0,
+        overridden: 0,
+    };
+
+    let policy: DelegationPolicy = match config::delegation() {
+        Some(value) => value,
+        None => return resolution,
+    };
+
+    let gated: bool = proposal.nft_gate.is_some()
+        || proposal.sns_gate.is_some()
+        || proposal.eligibility_root.is_some();
+    if gated {
+        return resolution;
+    }
+
+    let now: u64 = ic_cdk::api::time();
+    let voted: HashSet<Principal> = proposal.voted.iter().copied().collect();
+    let mut looked_at: usize = 0;
+
+    for delegate in &proposal.voted {
+        let delegators: Vec<(Principal, Delegation)> = delegators_of(*delegate);
+        if delegators.is_empty() {
+            continue;
+        }
+
+        looked_at += delegators.len();
+        if looked_at > MAX_RESOLVED {
+            break;
+        }
+
+        let choice: Choice = match ballots::get(key, *delegate) {
+            Some(ballot) => ballot.choice,
+            None => continue,
+        };
+
+        for (delegator, delegation) in delegators {
+            if voted.contains(&delegator) {
+                resolution.overridden += 1;
+                continue;
+            }
+
+            let eligible: bool = bans::check(&delegator).is_ok()
+                && visibility::can_see(proposal, &delegator)
+                && voter_list::check(key, proposal, &delegator).is_ok();
+            if !eligible {
+                continue;
+            }
+
+            let base: u64 = match base_weight(key, proposal, &delegator) {
+                Some(value) => value,
+                None => continue,
+            };
+            let share: u64 = remaining_share(&policy.decay, &delegation, now);
+            let weight: u64 = (base as u128 * share as u128 / 10_000) as u64;
+
+            resolution.delegated.add(choice, weight);
+            resolution.delegators += 1;
+        }
+    }
+
+    resolution
+}
+
+/*
+    Called from `decision::finalize`, before the outcome is worked out. Whatever the last close
+    added is taken out first, a workflow can reopen the proposal and more people may have voted since.
+*/
+pub(crate) fn apply(key: u64, proposal: &mut Proposal) {
+    let previous: DelegatedWeight = std::mem::take(&mut proposal.delegated);
+    for (choice, weight) in previous.entries() {
+        add(proposal, choice, weight, false);
+    }
+
+    let delegated: DelegatedWeight = resolve(key, proposal).delegated;
+    for (choice, weight) in delegated.entries() {
+        add(proposal, choice, weight, true);
+    }
+
+    proposal.delegated = delegated;
+}
+
+pub(crate) fn delegation_of(delegator: Principal) -> Option<Delegation> {
+    DELEGATIONS.with(|d| d.borrow().get(&StorablePrincipal(delegator)))
+}
+
+fn unlink(delegator: Principal) {
+    if let Some(old) = DELEGATIONS.with(|d| d.borrow_mut().remove(&StorablePrincipal(delegator))) {
+        BY_DELEGATE.with(|b| {
+            b.borrow_mut().remove(&(
+                StorablePrincipal(old.delegate),
+                StorablePrincipal(delegator),
+            ))
+        });
+    }
+}
+
+// Drops the principal's own delegation and every delegation made to them.
+pub(crate) fn forget(principal: Principal) {
+    unlink(principal);
+
+    for (delegator, _) in delegators_of(principal) {
+        unlink(delegator);
+    }
+}
+
+// Replaces an earlier delegation. Delegates can't delegate themselves, so votes never travel further than one hop.
+#[ic_cdk::update]
+fn delegate_to(delegate: Principal) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if config::delegation().is_none() {
+        return Err(VoteError::DelegationDisabled);
+    }
+
+    let chained: bool = delegation_of(delegate).is_some() || !delegators_of(caller).is_empty();
+
+    if delegate == caller || delegate == Principal::anonymous() || chained {
+        return Err(VoteError::InvalidDelegate);
+    }
+
+    if delegators_of(delegate).len() >= MAX_DELEGATORS {
+        return Err(VoteError::TooManyDelegators);
+    }
+
+    unlink(caller);
+    let delegation: Delegation = Delegation {
+        delegate,
+        renewed_at: ic_cdk::api::time(),
+    };
+    DELEGATIONS.with(|d| d.borrow_mut().insert(StorablePrincipal(caller), delegation));
+    BY_DELEGATE.with(|b| {
+        b.borrow_mut()
+            .insert((StorablePrincipal(delegate), StorablePrincipal(caller)), ())
+    });
+
+    Ok(())
+}
+
+// Resets the decay, the delegation counts in full again.
+#[ic_cdk::update]
+fn renew_delegation() -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let mut delegation: Delegation = match delegation_of(caller) {
+        Some(value) => value,
+        None => return Err(VoteError::NoDelegation),
+    };
+
+    delegation.renewed_at = ic_cdk::api::time();
+    DELEGATIONS.with(|d| d.borrow_mut().insert(StorablePrincipal(caller), delegation));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn revoke_delegation() -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if delegation_of(caller).is_none() {
+        return Err(VoteError::NoDelegation);
+    }
+
+    unlink(caller);
+    Ok(())
+}
+
+// What the voters cast themselves. Once closed the counts include what the last close added, that part isn't direct.
+pub(crate) fn direct(proposal: &Proposal) -> DelegatedWeight {
+    let applied: &DelegatedWeight = &proposal.delegated;
+
+    DelegatedWeight {
+        approve: proposal.approve.saturating_sub(applied.approve),
+        reject: proposal.reject.saturating_sub(applied.reject),
+        pass: proposal.pass.saturating_sub(applied.pass),
+        abstain: proposal.abstain.saturating_sub(applied.abstain),
+    }
+}
+
+#[ic_cdk::query]
+fn get_vote_breakdown(key: u64) -> Option<VoteBreakdown> {
+    let caller: Principal = ic_cdk::caller();
+    let proposal: Proposal = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .filter(|proposal| visibility::can_see(proposal, &caller))?;
+
+    if proposal.results_hidden_until.is_some() || !embargo::results_visible(&proposal, &caller) {
+        return None;
+    }
+
+    let resolution: Resolution = resolve(key, &proposal);
+    let projected: bool = proposal.is_active;
+
+    let direct: DelegatedWeight = direct(&proposal);
+    let delegated: DelegatedWeight = if projected {
+        resolution.delegated
+    } else {
+        proposal.delegated.clone()
+    };
+
+    Some(VoteBreakdown {
+        direct,
+        delegated,
+        delegators: resolution.delegators,
+        overridden: resolution.overridden,
+        projected,
+    })
+}
+
+#[ic_cdk::query]
+fn get_my_delegation() -> Option<Delegation> {
+    delegation_of(ic_cdk::caller())
+}
+
+pub(crate) fn delegator_count(delegate: Principal) -> u64 {
+    BY_DELEGATE.with(|b| {
+        b.borrow()
+            .range((StorablePrincipal(delegate), StorablePrincipal::default())..)
+            .take_while(|((d, _), _)| d.0 == delegate)
+            .count() as u64
+    })
+}
+
+// Only the delegate sees who delegated to them.
+#[ic_cdk::query]
+fn get_my_delegators(offset: u64, limit: u64) -> Vec<(Principal, Delegation)> {
+    delegators_of(ic_cdk::caller())
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+        .collect()
+}
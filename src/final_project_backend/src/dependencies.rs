@@ -0,0 +1,150 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, time::Duration};
+
+use crate::{
+    changes, decision, get_memory, open, timers, Memory, Proposal, VoteError, DEPENDENT_MEMORY_ID,
+    PROPOSAL_MAP, WAITING_MEMORY_ID,
+};
+
+const MAX_DEPENDENCIES: usize = 10;
+
+// Proposals one cycle check looks at before giving up, a deeper graph is refused.
+const MAX_GRAPH_SIZE: usize = 1000;
+
+/*
+    A proposal can depend on others (`depends_on`). It doesn't open, and its payload
+    doesn't run, before every one of them has passed. Dependencies have to exist
+    when the proposal is created, which together with the cycle check keeps the graph acyclic.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct Waiting {
+    requested_active: bool, // What the proposal would have been without dependencies.
+}
+
+impl Storable for Waiting {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Waiting {
+    const MAX_SIZE: u32 = 50;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // (dependency, dependent), to find what might open once a proposal passes.
+    static DEPENDENTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(DEPENDENT_MEMORY_ID)));
+
+    // Proposal key -> held back by its dependencies.
+    static WAITING: RefCell<StableBTreeMap<u64, Waiting, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(WAITING_MEMORY_ID)));
+}
+
+pub(crate) fn validate(key: u64, depends_on: &[u64]) -> Result<(), VoteError> {
+    if depends_on.len() > MAX_DEPENDENCIES || depends_on.contains(&key) {
+        return Err(VoteError::InvalidDependencies);
+    }
+
+    // Walks everything the new proposal would depend on, finding `key` there closes a cycle.
+    let mut seen: BTreeSet<u64> = BTreeSet::new();
+    let mut stack: Vec<u64> = depends_on.to_vec();
+
+    while let Some(next) = stack.pop() {
+        if next == key {
+            return Err(VoteError::DependencyCycle);
+        }
+        if !seen.insert(next) {
+            continue;
+        }
+        if seen.len() > MAX_GRAPH_SIZE {
+            return Err(VoteError::InvalidDependencies);
+        }
+
+        match PROPOSAL_MAP.with(|p| p.borrow().get(&next)) {
+            Some(proposal) => stack.extend(proposal.depends_on),
+            None => return Err(VoteError::InvalidDependencies),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn all_passed(depends_on: &[u64]) -> bool {
+    depends_on.iter().all(|dependency| {
+        PROPOSAL_MAP
+            .with(|p| p.borrow().get(dependency))
+            .is_some_and(|proposal| decision::passed(&proposal))
+    })
+}
+
+pub(crate) fn register(key: u64, depends_on: &[u64]) {
+    DEPENDENTS.with(|d| {
+        let mut map = d.borrow_mut();
+        for dependency in depends_on {
+            map.insert((*dependency, key), ());
+        }
+    });
+}
+
+// Called by `open` instead of opening.
+pub(crate) fn wait(key: u64, requested_active: bool) {
+    WAITING.with(|w| w.borrow_mut().insert(key, Waiting { requested_active }));
+}
+
+pub(crate) fn is_waiting(key: u64) -> bool {
+    WAITING.with(|w| w.borrow().contains_key(&key))
+}
+
+pub(crate) fn remove(key: u64, depends_on: &[u64]) {
+    DEPENDENTS.with(|d| {
+        let mut map = d.borrow_mut();
+        for dependency in depends_on {
+            map.remove(&(*dependency, key));
+        }
+    });
+    WAITING.with(|w| w.borrow_mut().remove(&key));
+}
+
+/*
+    Called by `decision::finalize` when `key` passed. The proposal isn't written back yet
+    at that point, so the dependents are looked at from a timer right after.
+*/
+pub(crate) fn on_passed(key: u64) {
+    timers::set_timer(Duration::ZERO, move || release(key));
+}
+
+fn release(key: u64) {
+    let dependents: Vec<u64> = DEPENDENTS.with(|d| {
+        d.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, dependent), _)| dependent)
+            .collect()
+    });
+
+    for dependent in dependents {
+        let waiting: Waiting = match WAITING.with(|w| w.borrow().get(&dependent)) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&dependent)) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if !all_passed(&proposal.depends_on) {
+            continue;
+        }
+
+        WAITING.with(|w| w.borrow_mut().remove(&dependent));
+        proposal.is_active = waiting.requested_active;
+        open(dependent, &mut proposal);
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(dependent, proposal));
+        changes::record_change(dependent);
+    }
+}
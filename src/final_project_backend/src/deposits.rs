@@ -0,0 +1,279 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    audit, authenticated_caller, config, decision, get_memory, icrc, outbox, sealed_ballots,
+    Memory, Proposal, Role, VoteError, DEPOSIT_MEMORY_ID, PROPOSAL_DEPOSIT_MEMORY_ID,
+};
+
+// Deposits wait here until they are refunded or forfeited, apart from the treasury's own funds.
+const ESCROW_SUBACCOUNT: [u8; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+];
+const MAX_REASON_LEN: usize = 500;
+
+/*
+    Creating a proposal can cost a deposit, which makes spamming expensive.
+    The proposer approves the canister for `amount` (plus the fee) on the ledger beforehand,
+    then `create_proposal` pulls it in with `icrc2_transfer_from`.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct DepositConfig {
+    ledger: Principal,
+    amount: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum DepositStatus {
+    Held,
+    Settling { refund: bool },       // The transfer is in flight.
+    Refunded { block: Option<u64> }, // No block when the fee ate the whole deposit.
+    Forfeited { block: Option<u64> },
+    Failed { refund: bool, reason: String }, // The admin can retry it.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Deposit {
+    proposal_key: u64,
+    depositor: Principal,
+    ledger: Principal,
+    amount: u64,
+    status: DepositStatus,
+}
+
+impl Storable for Deposit {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Deposit {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Deposit id -> deposit. Settled deposits are kept as a record of where the money went.
+    static DEPOSITS: RefCell<StableBTreeMap<u64, Deposit, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(DEPOSIT_MEMORY_ID)));
+
+    // Proposal key -> id of its latest deposit.
+    static PROPOSAL_DEPOSITS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PROPOSAL_DEPOSIT_MEMORY_ID)));
+}
+
+pub(crate) fn validate(deposit: &DepositConfig) -> bool {
+    deposit.amount > 0
+}
+
+fn escrow() -> icrc::Account {
+    icrc::Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(ESCROW_SUBACCOUNT.to_vec()),
+    }
+}
+
+/*
+    Takes the deposit the config asks for, before the proposal is written.
+    Returns `None` when deposits are turned off.
+*/
+pub(crate) async fn collect(depositor: Principal) -> Result<Option<Deposit>, VoteError> {
+    let deposit: DepositConfig = match config::deposit() {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    icrc::transfer_from(deposit.ledger, depositor.into(), escrow(), deposit.amount)
+        .await
+        .map_err(|_| VoteError::DepositFailed)?;
+
+    Ok(Some(Deposit {
+        proposal_key: 0,
+        depositor,
+        ledger: deposit.ledger,
+        amount: deposit.amount,
+        status: DepositStatus::Held,
+    }))
+}
+
+// Ties a collected deposit to the proposal, after any earlier deposit of `key` has been released.
+pub(crate) fn hold(key: u64, mut deposit: Deposit) {
+    deposit.proposal_key = key;
+    let id: u64 = DEPOSITS.with(|d| {
+        let id: u64 = d.borrow().last_key_value().map_or(0, |(id, _)| id + 1);
+        d.borrow_mut().insert(id, deposit);
+        id
+    });
+    PROPOSAL_DEPOSITS.with(|p| p.borrow_mut().insert(key, id));
+}
+
+// Once the quorum is there the proposal was worth making, no need to wait for the end.
+pub(crate) fn on_vote(key: u64, proposal: &Proposal) {
+    if decision::quorum_met(proposal) {
+        release(key, true);
+    }
+}
+
+pub(crate) fn on_close(key: u64, proposal: &Proposal) {
+    // Who abstained is sealed as well, `sealed_ballots` calls this again once the ballots are counted.
+    if sealed_ballots::is_pending(key) {
+        return;
+    }
+    release(key, decision::quorum_met(proposal));
+}
+
+/*
+    Starts refunding (or forfeiting) the deposit of `key` if it's still held.
+    The transfer runs in the background, the caller doesn't wait for the ledger.
+*/
+pub(crate) fn release(key: u64, refund: bool) {
+    let id: u64 = match PROPOSAL_DEPOSITS.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    let held: bool = DEPOSITS
+        .with(|d| d.borrow().get(&id))
+        .is_some_and(|deposit| matches!(deposit.status, DepositStatus::Held));
+
+    if held {
+        set_status(id, DepositStatus::Settling { refund });
+        ic_cdk::spawn(settle(id, refund));
+    }
+}
+
+// True when the proposal has no deposit or its deposit has gone where it belongs.
+pub(crate) fn is_settled(key: u64) -> bool {
+    get_deposit(key).is_none_or(|(_, deposit)| {
+        matches!(
+            deposit.status,
+            DepositStatus::Refunded { .. } | DepositStatus::Forfeited { .. }
+        )
+    })
+}
+
+fn set_status(id: u64, status: DepositStatus) {
+    DEPOSITS.with(|d| {
+        let deposit_opt: Option<Deposit> = d.borrow().get(&id);
+
+        if let Some(mut deposit) = deposit_opt {
+            deposit.status = status;
+            d.borrow_mut().insert(id, deposit);
+        }
+    });
+}
+
+async fn settle(id: u64, refund: bool) {
+    let deposit: Deposit = match DEPOSITS.with(|d| d.borrow().get(&id)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    // Forfeited deposits go to the canister's main account, that's the treasury.
+    let to: icrc::Account = if refund {
+        deposit.depositor.into()
+    } else {
+        ic_cdk::id().into()
+    };
+
+    let target: outbox::CallTarget = outbox::CallTarget::DepositSettlement(id);
+    let res: Result<Option<u64>, String> = match icrc::fee(deposit.ledger).await {
+        Ok(fee) if fee >= deposit.amount => Ok(None),
+        Ok(fee) => icrc::transfer(
+            deposit.ledger,
+            Some(ESCROW_SUBACCOUNT.to_vec()),
+            to,
+            deposit.amount - fee,
+            outbox::dedup(&target),
+        )
+        .await
+        .map(Some),
+        Err(err) => Err(err),
+    };
+
+    let status: DepositStatus = match res {
+        Ok(block) => {
+            outbox::resolve(&target);
+            if refund {
+                DepositStatus::Refunded { block }
+            } else {
+                DepositStatus::Forfeited { block }
+            }
+        }
+        Err(reason) => {
+            outbox::record(target, &reason);
+            DepositStatus::Failed {
+                refund,
+                reason: reason.chars().take(MAX_REASON_LEN).collect(),
+            }
+        }
+    };
+    set_status(id, status);
+}
+
+// For `outbox`, a deposit that isn't failed (anymore) counts as done.
+pub(crate) async fn retry(id: u64) -> Result<(), String> {
+    let refund: bool = match DEPOSITS.with(|d| d.borrow().get(&id)).map(|d| d.status) {
+        Some(DepositStatus::Failed { refund, .. }) => refund,
+        _ => return Ok(()),
+    };
+
+    set_status(id, DepositStatus::Settling { refund });
+    settle(id, refund).await;
+
+    match DEPOSITS.with(|d| d.borrow().get(&id)).map(|d| d.status) {
+        Some(DepositStatus::Failed { reason, .. }) => Err(reason),
+        _ => Ok(()),
+    }
+}
+
+#[ic_cdk::query]
+fn get_deposit(key: u64) -> Option<(u64, Deposit)> {
+    let id: u64 = PROPOSAL_DEPOSITS.with(|p| p.borrow().get(&key))?;
+    DEPOSITS
+        .with(|d| d.borrow().get(&id))
+        .map(|deposit| (id, deposit))
+}
+
+// The admin marks a proposal as spam, its deposit goes to the treasury.
+#[ic_cdk::update]
+fn flag_as_spam(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    match get_deposit(key) {
+        Some((_, deposit)) if matches!(deposit.status, DepositStatus::Held) => {}
+        _ => return Err(VoteError::NoDeposit),
+    }
+
+    release(key, false);
+    audit::record(caller, key, audit::AuditEvent::FlaggedAsSpam);
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn retry_deposit_settlement(id: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    let refund: bool = match DEPOSITS.with(|d| d.borrow().get(&id)).map(|d| d.status) {
+        Some(DepositStatus::Failed { refund, .. }) => refund,
+        _ => return Err(VoteError::NoDeposit),
+    };
+
+    set_status(id, DepositStatus::Settling { refund });
+    ic_cdk::spawn(settle(id, refund));
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, changes, config, create, get_memory, open, CreateProposal, Memory,
+    Proposal, Role, VoteError, DRAFT_MEMORY_ID, PROPOSAL_MAP,
+};
+
+/*
+    Some proposals need backing before they go out: a draft is stored like any other
+    proposal, but nobody can vote on it until enough of the configured signers co-signed it.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Draft {
+    proposer: Principal,
+    threshold: u32,         // Copied from the config, like the quorum.
+    requested_active: bool, // What `is_active` becomes once the draft opens.
+    signatures: Vec<Principal>,
+}
+
+impl Storable for Draft {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Draft {
+    // Room for every signer of the largest allowed signer set.
+    const MAX_SIZE: u32 = 2000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> its signatures so far. The entry goes away when the draft opens.
+    static DRAFTS: RefCell<StableBTreeMap<u64, Draft, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(DRAFT_MEMORY_ID)));
+}
+
+pub(crate) fn start(key: u64, proposer: Principal, requested_active: bool) {
+    let draft: Draft = Draft {
+        proposer,
+        threshold: config::signature_threshold().unwrap_or(0),
+        requested_active,
+        signatures: vec![],
+    };
+    DRAFTS.with(|d| d.borrow_mut().insert(key, draft));
+}
+
+pub(crate) fn is_pending(key: u64) -> bool {
+    DRAFTS.with(|d| d.borrow().contains_key(&key))
+}
+
+pub(crate) fn remove(key: u64) {
+    DRAFTS.with(|d| d.borrow_mut().remove(&key));
+}
+
+#[ic_cdk::update]
+async fn propose_draft(key: u64, proposal: CreateProposal) -> Result<u64, VoteError> {
+    // Without signers nobody could ever open the draft.
+    if config::signature_threshold().is_none() {
+        return Err(VoteError::InvalidConfig);
+    }
+
+    create(key, proposal, true).await
+}
+
+// Returns whether this signature opened the draft.
+#[ic_cdk::update]
+fn co_sign(key: u64) -> Result<bool, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_signer(&caller) {
+        return Err(VoteError::access_rejected(Role::Signer, caller));
+    }
+
+    let mut draft: Draft = match DRAFTS.with(|d| d.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchDraft),
+    };
+
+    if draft.signatures.contains(&caller) {
+        return Err(VoteError::AlreadySigned);
+    }
+    draft.signatures.push(caller);
+
+    if (draft.signatures.len() as u64) < draft.threshold as u64 {
+        DRAFTS.with(|d| d.borrow_mut().insert(key, draft));
+        return Ok(false);
+    }
+
+    DRAFTS.with(|d| d.borrow_mut().remove(&key));
+
+    PROPOSAL_MAP.with(|p| {
+        let proposal_opt: Option<Proposal> = p.borrow().get(&key);
+
+        if let Some(mut proposal) = proposal_opt {
+            proposal.is_active = draft.requested_active;
+            open(key, &mut proposal);
+            p.borrow_mut().insert(key, proposal);
+            changes::record_change(key);
+        }
+    });
+
+    Ok(true)
+}
+
+#[ic_cdk::query]
+fn get_draft(key: u64) -> Option<Draft> {
+    DRAFTS.with(|d| d.borrow().get(&key))
+}
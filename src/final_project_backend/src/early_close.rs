@@ -0,0 +1,80 @@
+use candid::Principal;
+use std::collections::HashSet;
+
+use crate::{
+    decision::{self, Outcome},
+    delegation::{self, DelegatedWeight},
+    invites, ties, voter_list, window, Proposal,
+};
+
+/*
+    When everybody who can vote is known up front, through a snapshot or a voter list,
+    a proposal doesn't have to wait for its deadline once the votes still out can't change
+    its outcome. Checked after every vote, the deadline is then moved up to now and
+    the deadline timer closes the proposal like it would have at the end.
+*/
+
+// The weight and the heads that could still come in. `None` when they aren't known.
+fn uncast(key: u64, proposal: &Proposal, direct: &DelegatedWeight) -> Option<(u64, u64)> {
+    // Weights worked out while the voter calls, and changed votes, can move the counts any which way.
+    let open_ended: bool = proposal.nft_gate.is_some()
+        || proposal.sns_gate.is_some()
+        || proposal.eligibility_root.is_some()
+        || proposal.role_weights.is_some()
+        || proposal.allow_vote_changes;
+
+    if open_ended {
+        return None;
+    }
+
+    if let Some(snapshot) = &proposal.snapshot {
+        let cast: u64 = direct
+            .approve
+            .saturating_add(direct.reject)
+            .saturating_add(direct.pass)
+            .saturating_add(direct.abstain);
+
+        return Some((
+            snapshot.total_weight.saturating_sub(cast),
+            snapshot.voter_count.saturating_sub(proposal.voter_count()),
+        ));
+    }
+
+    if proposal.voter_list {
+        // Counted name by name, a purge takes voters off the list who are still counted in `voted`.
+        let voted: HashSet<&Principal> = proposal.voted.iter().collect();
+        let missing: u64 = voter_list::members(key)
+            .iter()
+            .filter(|voter| !voted.contains(voter))
+            .count() as u64
+            + invites::pending(key);
+
+        return Some((missing, missing));
+    }
+
+    None
+}
+
+fn settled(key: u64, proposal: &Proposal) -> Option<Outcome> {
+    let direct: DelegatedWeight = delegation::direct(proposal);
+    let (weight, heads) = uncast(key, proposal, &direct)?;
+    let outcome: Outcome = decision::settled_outcome(proposal, &direct, weight, heads)?;
+
+    // A tie that is broken some other way than failing isn't settled while one is still possible.
+    let tie_possible: bool = outcome != Outcome::QuorumNotMet
+        && direct.approve.abs_diff(direct.reject) <= weight
+        && !matches!(proposal.tie_break, ties::TieBreak::Fail);
+
+    (!tie_possible).then_some(outcome)
+}
+
+// Called by `cast_vote` with the counts the vote left, the caller writes the proposal back.
+pub(crate) fn check(key: u64, proposal: &mut Proposal) {
+    if !proposal.is_active || settled(key, proposal).is_none() {
+        return;
+    }
+
+    let now: u64 = ic_cdk::api::time();
+    proposal.voting_ends_at = Some(now);
+    window::schedule_close(key, now);
+}
@@ -0,0 +1,56 @@
+use candid::Principal;
+
+use crate::{authenticated_caller, changes, Proposal, Role, VoteError, PROPOSAL_MAP};
+
+const MAX_EDITORS: usize = 10;
+
+/*
+    Editors can do what the owner does with `edit_proposal` and `end_proposal`,
+    so a team doesn't have to share one identity. Only the owner picks them.
+*/
+pub(crate) fn can_edit(proposal: &Proposal, caller: &Principal) -> bool {
+    proposal.owner == *caller || proposal.editors.contains(caller)
+}
+
+fn update_editors(
+    key: u64,
+    f: impl FnOnce(&mut Vec<Principal>) -> Result<(), VoteError>,
+) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.owner != caller {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    f(&mut proposal.editors)?;
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn add_editor(key: u64, editor: Principal) -> Result<(), VoteError> {
+    update_editors(key, |editors| {
+        if editor == Principal::anonymous() || editors.len() >= MAX_EDITORS {
+            return Err(VoteError::InvalidEditor);
+        }
+
+        if !editors.contains(&editor) {
+            editors.push(editor);
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn remove_editor(key: u64, editor: Principal) -> Result<(), VoteError> {
+    update_editors(key, |editors| {
+        editors.retain(|e| *e != editor);
+        Ok(())
+    })
+}
@@ -0,0 +1,150 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde::Serialize;
+use std::{cell::RefCell, time::Duration};
+
+use crate::{
+    changes, config, editors, get_memory, reports, timers, Memory, Proposal, ProposalView,
+    EMBARGO_MEMORY_ID, PROPOSAL_MAP,
+};
+
+/*
+    Whether the counts can be seen while voting still runs. Early numbers make people follow
+    the crowd, so a proposal can keep them to itself until it closes. On top of that it can
+    keep them hidden for a while after it's ended (e.g. until an official announcement),
+    a timer publishes them at the embargo time. The owner sees them through the embargo,
+    admins always see them.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default, CandidType, Deserialize, Serialize)]
+pub(crate) enum ResultsVisibility {
+    #[default]
+    Live,
+    AfterClose,
+    CreatorOnly, // The owner and the editors see them live.
+}
+
+thread_local! {
+    // Proposal key -> time the results get published. Needed to re-arm the timers after an upgrade.
+    static PENDING_PUBLICATIONS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(EMBARGO_MEMORY_ID)));
+}
+
+pub(crate) fn schedule_publication(key: u64, until: u64) {
+    PENDING_PUBLICATIONS.with(|p| p.borrow_mut().insert(key, until));
+    arm_timer(key, until);
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = PENDING_PUBLICATIONS.with(|p| p.borrow().iter().collect());
+
+    for (key, until) in pending {
+        arm_timer(key, until);
+    }
+}
+
+fn arm_timer(key: u64, until: u64) {
+    let delay: u64 = until.saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || publish(key));
+}
+
+fn publish(key: u64) {
+    PENDING_PUBLICATIONS.with(|p| p.borrow_mut().remove(&key));
+
+    PROPOSAL_MAP.with(|p| {
+        let proposal_opt: Option<Proposal> = p.borrow().get(&key);
+
+        // The proposal could have been deleted in the meantime.
+        if let Some(mut proposal) = proposal_opt {
+            proposal.results_hidden_until = None;
+            p.borrow_mut().insert(key, proposal);
+            changes::record_change(key);
+            reports::on_published(key);
+        }
+    });
+}
+
+fn results_hidden(proposal: &Proposal) -> bool {
+    match proposal.results_hidden_until {
+        Some(until) => ic_cdk::api::time() < until,
+        None => false,
+    }
+}
+
+fn hidden_while_open(proposal: &Proposal, caller: &Principal) -> bool {
+    if proposal.closed_at.is_some() {
+        return false;
+    }
+
+    match proposal.results_visibility {
+        ResultsVisibility::Live => false,
+        ResultsVisibility::AfterClose => true,
+        ResultsVisibility::CreatorOnly => !editors::can_edit(proposal, caller),
+    }
+}
+
+pub(crate) fn results_visible(proposal: &Proposal, caller: &Principal) -> bool {
+    if config::is_admin(caller) {
+        return true;
+    }
+
+    if results_hidden(proposal) && proposal.owner != *caller {
+        return false;
+    }
+
+    !hidden_while_open(proposal, caller)
+}
+
+// Hidden counts come back as `None`, a zero would read as nobody having voted.
+pub(crate) fn redact(proposal: Proposal, caller: &Principal) -> ProposalView {
+    if results_visible(&proposal, caller) {
+        return proposal.map_counts(Some);
+    }
+
+    let mut view: ProposalView = proposal.map_counts(|_| None);
+    view.outcome = None;
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposal_store;
+
+    fn open(results_visibility: ResultsVisibility) -> Proposal {
+        let mut proposal: Proposal = proposal_store::sample();
+        proposal.is_active = true;
+        proposal.closed_at = None;
+        proposal.results_visibility = results_visibility;
+        proposal
+    }
+
+    #[test]
+    fn hidden_counts_are_none_rather_than_zero() {
+        let view: ProposalView = redact(
+            open(ResultsVisibility::AfterClose),
+            &Principal::from_slice(&[1]),
+        );
+
+        assert_eq!(view.approve, None);
+        assert_eq!(view.abstain_voters, None);
+        assert_eq!(view.delegated.reject, None);
+        assert!(view.outcome.is_none());
+    }
+
+    #[test]
+    fn only_editors_see_creator_only_counts() {
+        let proposal: Proposal = open(ResultsVisibility::CreatorOnly);
+        let owner: Principal = proposal.owner;
+
+        assert_eq!(redact(proposal.clone(), &owner).approve, Some(2));
+        assert_eq!(redact(proposal, &Principal::from_slice(&[1])).approve, None);
+    }
+
+    #[test]
+    fn shows_the_counts_once_closed() {
+        let view: ProposalView = redact(proposal_store::sample(), &Principal::from_slice(&[1]));
+
+        assert_eq!(view.approve, Some(2));
+        assert_eq!(view.reject, Some(1));
+        assert_eq!(view.pass, Some(0));
+    }
+}
@@ -0,0 +1,132 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{block_log, get_memory, visibility, Memory, Proposal, EVENT_MEMORY_ID, MAX_BATCH_SIZE};
+
+/*
+    Unlike `get_changes`, which only knows the latest state of each proposal,
+    the event feed keeps every event in order. Indexers keep the last `seq`
+    they saw and ask for what came after it.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum EventKind {
+    Created,
+    Edited,
+    Voted { voter: Principal }, // How isn't part of it, the tally has the counts once they're public.
+    Finalized,
+    Deleted,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Event {
+    seq: u64,
+    timestamp: u64,
+    proposal_key: u64,
+    kind: EventKind,
+    public: bool, // Events of restricted proposals only go to whoever can see the proposal.
+}
+
+impl Storable for Event {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Event {
+    const MAX_SIZE: u32 = 200;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Sequence number -> event, starting at 1.
+    static EVENTS: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(EVENT_MEMORY_ID)));
+}
+
+fn record(proposal_key: u64, proposal: &Proposal, kind: EventKind) {
+    let public: bool = visibility::is_public(proposal);
+
+    if public {
+        let (btype, voter): (&str, Option<Principal>) = match &kind {
+            EventKind::Created => ("proposal_created", None),
+            EventKind::Edited => ("proposal_edited", None),
+            EventKind::Voted { voter } => ("vote", Some(*voter)),
+            EventKind::Finalized => ("proposal_finalized", None),
+            EventKind::Deleted => ("proposal_deleted", None),
+        };
+        block_log::append_event(btype, proposal_key, voter);
+    }
+
+    EVENTS.with(|e| {
+        let seq: u64 = e.borrow().last_key_value().map_or(1, |(seq, _)| seq + 1);
+        let event: Event = Event {
+            seq,
+            timestamp: ic_cdk::api::time(),
+            proposal_key,
+            kind,
+            public,
+        };
+        e.borrow_mut().insert(seq, event);
+    });
+}
+
+pub(crate) fn on_created(key: u64, proposal: &Proposal) {
+    record(key, proposal, EventKind::Created);
+}
+
+pub(crate) fn on_edited(key: u64, proposal: &Proposal) {
+    record(key, proposal, EventKind::Edited);
+}
+
+pub(crate) fn on_voted(key: u64, proposal: &Proposal, voter: Principal) {
+    record(key, proposal, EventKind::Voted { voter });
+}
+
+pub(crate) fn on_finalized(key: u64, proposal: &Proposal) {
+    record(key, proposal, EventKind::Finalized);
+}
+
+pub(crate) fn on_deleted(key: u64, proposal: &Proposal) {
+    record(key, proposal, EventKind::Deleted);
+}
+
+// Looks back from the newest event, so it only reads the ones that are recent enough.
+pub(crate) fn votes_since(timestamp: u64) -> u64 {
+    EVENTS.with(|e| {
+        let map = e.borrow();
+        let mut seq: u64 = map.last_key_value().map_or(0, |(seq, _)| seq);
+        let mut votes: u64 = 0;
+
+        // Sequence numbers have no gaps, so stepping down one at a time finds every event.
+        while let Some(event) = map.get(&seq) {
+            if event.timestamp < timestamp {
+                break;
+            }
+            if matches!(event.kind, EventKind::Voted { .. }) {
+                votes += 1;
+            }
+            seq -= 1;
+        }
+
+        votes
+    })
+}
+
+// Everything after `seq`, pass 0 to start from the beginning.
+#[ic_cdk::query]
+fn get_events_since(seq: u64, limit: u64) -> Vec<Event> {
+    let caller: Principal = ic_cdk::caller();
+
+    EVENTS.with(|e| {
+        e.borrow()
+            .range(seq.saturating_add(1)..)
+            .map(|(_, event)| event)
+            .filter(|event| event.public || visibility::can_see_key(event.proposal_key, &caller))
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .collect()
+    })
+}
@@ -0,0 +1,333 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    audit, authenticated_caller, config, decision, dependencies, get_memory, membership, outbox,
+    stats, treasury, upgrade, watch, Memory, Proposal, Role, VoteError, EXECUTION_MEMORY_ID,
+    PROPOSAL_MAP,
+};
+
+const MAX_METHOD_LEN: usize = 100;
+const MAX_ARG_SIZE: usize = 2000;
+const MAX_REASON_LEN: usize = 500; // Reject messages get cut, the state has to fit its bound.
+const MAX_VETO_REASON_LEN: usize = 500;
+
+/*
+    A proposal can carry a call that runs once it has passed. The call is never made
+    right away: it's queued for the configured `execution_delay`, which gives everybody
+    a window to react (or to get out) before something irreversible happens.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) enum ExecutionPayload {
+    Call {
+        canister: Principal,
+        method: String,
+        arg: Vec<u8>, // Candid encoded argument of `method`.
+    },
+    // Pays out of the treasury.
+    Transfer(treasury::TransferProposal),
+    // Installs a new module, see `upgrade`.
+    Upgrade(upgrade::CanisterUpgrade),
+    // Applied on top of the config as it is when the payload runs.
+    Config(config::ConfigUpdate),
+    // Applied right when the proposal passes, see `membership`. Runs here only as a fallback.
+    Membership(Vec<membership::MembershipChange>),
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) enum ExecutionOutcome {
+    Reply(Vec<u8>),
+    Transfer { block: u64 },
+    Upgrade { canister: Principal },
+    Applied, // A config or membership change, nothing to reply.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum ExecutionStatus {
+    Queued,
+    // The call is in flight, nobody can start it a second time.
+    Executing,
+    Executed {
+        at: u64,
+    },
+    // Can be retried with another `execute_proposal`.
+    Failed {
+        at: u64,
+        reason: String,
+    },
+    // Final, the payload never runs.
+    Vetoed {
+        at: u64,
+        by: Principal,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ExecutionState {
+    executable_at: u64,
+    status: ExecutionStatus,
+}
+
+impl Storable for ExecutionState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ExecutionState {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> where its payload is in the timelock.
+    static EXECUTIONS: RefCell<StableBTreeMap<u64, ExecutionState, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(EXECUTION_MEMORY_ID)));
+}
+
+pub(crate) fn validate(payload: &ExecutionPayload) -> Result<(), VoteError> {
+    match payload {
+        ExecutionPayload::Call { method, arg, .. } => {
+            if method.is_empty() || method.len() > MAX_METHOD_LEN || arg.len() > MAX_ARG_SIZE {
+                return Err(VoteError::InvalidExecutionPayload);
+            }
+
+            Ok(())
+        }
+        ExecutionPayload::Transfer(transfer) => treasury::validate(transfer),
+        ExecutionPayload::Upgrade(upgrade) => upgrade::validate(upgrade),
+        ExecutionPayload::Config(update) => config::check_update(update),
+        ExecutionPayload::Membership(changes) => membership::validate(changes),
+    }
+}
+
+/*
+    Called whenever voting on a proposal closes. A passed proposal with a payload
+    starts its timelock, the delay is read from the config at this moment.
+*/
+pub(crate) fn queue(key: u64, proposal: &Proposal) {
+    if proposal.execution.is_none() || !decision::passed(proposal) {
+        return;
+    }
+
+    // Voting can close more than once (a workflow can reopen it), the first timelock stays.
+    if EXECUTIONS.with(|e| e.borrow().contains_key(&key)) {
+        return;
+    }
+
+    let state: ExecutionState = ExecutionState {
+        executable_at: ic_cdk::api::time().saturating_add(config::execution_delay()),
+        status: ExecutionStatus::Queued,
+    };
+    EXECUTIONS.with(|e| e.borrow_mut().insert(key, state));
+}
+
+// True when there is nothing (left) to execute.
+pub(crate) fn is_settled(key: u64) -> bool {
+    EXECUTIONS
+        .with(|e| e.borrow().get(&key))
+        .is_none_or(|state| {
+            matches!(
+                state.status,
+                ExecutionStatus::Executed { .. } | ExecutionStatus::Vetoed { .. }
+            )
+        })
+}
+
+// Executed, vetoed or running: too late to check the payload beforehand.
+pub(crate) fn has_run(key: u64) -> bool {
+    EXECUTIONS
+        .with(|e| e.borrow().get(&key))
+        .is_some_and(|state| {
+            matches!(
+                state.status,
+                ExecutionStatus::Executing
+                    | ExecutionStatus::Executed { .. }
+                    | ExecutionStatus::Vetoed { .. }
+            )
+        })
+}
+
+pub(crate) fn remove(key: u64) {
+    EXECUTIONS.with(|e| e.borrow_mut().remove(&key));
+}
+
+/*
+    The council is the last line of defence: while a passed proposal sits in its timelock
+    any member can stop it for good. The reason goes into the audit log for everybody to see.
+*/
+#[ic_cdk::update]
+fn veto_proposal(key: u64, reason: String) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_council_member(&caller) {
+        return Err(VoteError::access_rejected(Role::Council, caller));
+    }
+
+    if reason.is_empty() || reason.len() > MAX_VETO_REASON_LEN {
+        return Err(VoteError::InvalidVetoReason);
+    }
+
+    let mut state: ExecutionState = match EXECUTIONS.with(|e| e.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NotExecutable),
+    };
+
+    // A call that is already running (or done) can't be taken back anymore.
+    match state.status {
+        ExecutionStatus::Queued | ExecutionStatus::Failed { .. } => {}
+        _ => return Err(VoteError::NotExecutable),
+    }
+
+    let at: u64 = ic_cdk::api::time();
+
+    if at >= state.executable_at {
+        return Err(VoteError::VetoWindowClosed);
+    }
+
+    state.status = ExecutionStatus::Vetoed {
+        at,
+        by: caller,
+        reason: reason.clone(),
+    };
+    EXECUTIONS.with(|e| e.borrow_mut().insert(key, state));
+    audit::record(caller, key, audit::AuditEvent::ProposalVetoed { reason });
+
+    Ok(())
+}
+
+// For payloads applied without a timelock, the proposal then never shows up as queued.
+pub(crate) fn record_applied(key: u64) {
+    let at: u64 = ic_cdk::api::time();
+    let state: ExecutionState = ExecutionState {
+        executable_at: at,
+        status: ExecutionStatus::Executed { at },
+    };
+    EXECUTIONS.with(|e| e.borrow_mut().insert(key, state));
+    stats::on_executed();
+    watch::on_executed(key, true);
+}
+
+#[ic_cdk::query]
+fn get_execution(key: u64) -> Option<ExecutionState> {
+    EXECUTIONS.with(|e| e.borrow().get(&key))
+}
+
+/*
+    Anybody can run a queued payload once its timelock is over, the delay is the protection.
+    Returns the raw reply of the call, the block of the treasury transfer or the upgraded canister.
+*/
+#[ic_cdk::update]
+async fn execute_proposal(key: u64) -> Result<ExecutionOutcome, VoteError> {
+    authenticated_caller()?;
+    run(key).await
+}
+
+// For `outbox`, a payload that ran (or was vetoed) in the meantime counts as done.
+pub(crate) async fn retry(key: u64) -> Result<(), String> {
+    match run(key).await {
+        Ok(_) | Err(VoteError::NotExecutable) => Ok(()),
+        Err(err) => match EXECUTIONS
+            .with(|e| e.borrow().get(&key))
+            .map(|state| state.status)
+        {
+            Some(ExecutionStatus::Failed { reason, .. }) => Err(reason),
+            _ => Err(format!("{:?}", err)),
+        },
+    }
+}
+
+async fn run(key: u64) -> Result<ExecutionOutcome, VoteError> {
+    let mut state: ExecutionState = match EXECUTIONS.with(|e| e.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NotExecutable),
+    };
+
+    match state.status {
+        ExecutionStatus::Queued | ExecutionStatus::Failed { .. } => {}
+        _ => return Err(VoteError::NotExecutable),
+    }
+
+    if ic_cdk::api::time() < state.executable_at {
+        return Err(VoteError::TimelockNotExpired);
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NotExecutable),
+    };
+
+    // A dependency can lose its result again, when a workflow reopens it.
+    if !dependencies::all_passed(&proposal.depends_on) {
+        return Err(VoteError::DependenciesNotPassed);
+    }
+
+    let payload: ExecutionPayload = match proposal.execution {
+        Some(value) => value,
+        None => return Err(VoteError::NotExecutable),
+    };
+
+    // Written before the await, so a second caller sees the call is already running.
+    state.status = ExecutionStatus::Executing;
+    EXECUTIONS.with(|e| e.borrow_mut().insert(key, state.clone()));
+
+    let res: Result<ExecutionOutcome, String> = match payload {
+        ExecutionPayload::Call {
+            canister,
+            method,
+            arg,
+        } => ic_cdk::api::call::call_raw(canister, &method, &arg, 0)
+            .await
+            .map(ExecutionOutcome::Reply)
+            .map_err(|(code, message)| format!("{:?}: {}", code, message)),
+        ExecutionPayload::Transfer(transfer) => treasury::execute(key, &transfer)
+            .await
+            .map(|block| ExecutionOutcome::Transfer { block }),
+        ExecutionPayload::Upgrade(upgrade) => upgrade::execute(key, &upgrade)
+            .await
+            .map(|canister| ExecutionOutcome::Upgrade { canister }),
+        ExecutionPayload::Config(update) => config::apply_update(ic_cdk::id(), key, update)
+            .map(|_| ExecutionOutcome::Applied)
+            .map_err(|_| "the change no longer makes a valid config".to_string()),
+        ExecutionPayload::Membership(changes) => {
+            membership::apply(key, &changes);
+            Ok(ExecutionOutcome::Applied)
+        }
+    };
+
+    let at: u64 = ic_cdk::api::time();
+    let (status, outcome) = match res {
+        Ok(outcome) => (ExecutionStatus::Executed { at }, Ok(outcome)),
+        Err(reason) => (
+            ExecutionStatus::Failed {
+                at,
+                reason: reason.chars().take(MAX_REASON_LEN).collect(),
+            },
+            Err(VoteError::ExecutionFailed),
+        ),
+    };
+
+    // The proposal may have been deleted while the call was running.
+    if EXECUTIONS.with(|e| e.borrow().contains_key(&key)) {
+        let succeeded: bool = matches!(status, ExecutionStatus::Executed { .. });
+
+        if succeeded {
+            stats::on_executed();
+            outbox::resolve(&outbox::CallTarget::Execution(key));
+        }
+        if let ExecutionStatus::Failed { reason, .. } = &status {
+            outbox::record(outbox::CallTarget::Execution(key), reason);
+        }
+        watch::on_executed(key, succeeded);
+        state.status = status;
+        EXECUTIONS.with(|e| e.borrow_mut().insert(key, state));
+    }
+
+    outcome
+}
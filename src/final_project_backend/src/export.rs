@@ -0,0 +1,112 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde_json::{json, Value};
+
+use crate::{ballots, tally, visibility, Proposal, PROPOSAL_MAP};
+
+// Ballots per chunk, a chunk of either format stays well below the reply limit.
+const EXPORT_CHUNK_SIZE: u64 = 1000;
+
+/*
+    Results for offline records, as text an organizer can save straight to a file.
+    The first chunk starts with the tally, every chunk then has the ballots in voter order.
+    Both follow the same rules as the queries: the counts are empty while the caller
+    may not see them, and the ballots are only there for proposals with public ballots.
+    Concatenated, the CSV chunks are one file. Every JSON chunk is a document of its own.
+*/
+#[derive(Debug, Clone, Copy, CandidType, Deserialize)]
+pub(crate) enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ExportChunk {
+    data: String,
+    chunk: u32,
+    chunk_count: u32, // At least one, the tally is always there.
+}
+
+fn csv(
+    tally: &tally::Tally,
+    ballots: &[(Principal, ballots::BallotRecord)],
+    first: bool,
+) -> String {
+    let mut data: String = String::new();
+
+    if first {
+        data.push_str("choice,weight\n");
+        for (choice, weight) in tally.counts() {
+            let weight: String = weight.map_or(String::new(), |weight| weight.to_string());
+            data.push_str(&format!("{:?},{}\n", choice, weight));
+        }
+        data.push_str("\nvoter,choice,weight,cast_at\n");
+    }
+
+    for (voter, ballot) in ballots {
+        data.push_str(&format!(
+            "{},{:?},{},{}\n",
+            voter, ballot.choice, ballot.weight, ballot.timestamp
+        ));
+    }
+
+    data
+}
+
+fn json(
+    key: u64,
+    tally: &tally::Tally,
+    ballots: &[(Principal, ballots::BallotRecord)],
+    first: bool,
+) -> String {
+    let ballots: Vec<Value> = ballots
+        .iter()
+        .map(|(voter, ballot)| {
+            json!({
+                "voter": voter.to_text(),
+                "choice": ballot.choice,
+                "weight": ballot.weight,
+                "cast_at": ballot.timestamp,
+            })
+        })
+        .collect();
+
+    let tally: Value = if first { json!(tally) } else { Value::Null };
+
+    json!({ "key": key, "tally": tally, "ballots": ballots }).to_string()
+}
+
+// `None` for a proposal the caller can't see and for chunks past the end.
+#[ic_cdk::query]
+fn export_results(key: u64, format: ExportFormat, chunk: u32) -> Option<ExportChunk> {
+    let caller: Principal = ic_cdk::caller();
+    let proposal: Proposal = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .filter(|proposal| visibility::can_see(proposal, &caller))?;
+
+    let public: bool = ballots::ballots_public(key, &caller);
+    let ballot_count: u64 = if public { ballots::count(key) } else { 0 };
+    let chunk_count: u32 = ballot_count.div_ceil(EXPORT_CHUNK_SIZE).max(1) as u32;
+
+    if chunk >= chunk_count {
+        return None;
+    }
+
+    let page: Vec<(Principal, ballots::BallotRecord)> = if public {
+        ballots::page(key, chunk as u64 * EXPORT_CHUNK_SIZE, EXPORT_CHUNK_SIZE)
+    } else {
+        vec![]
+    };
+    let tally: tally::Tally = tally::present(&proposal, &caller);
+    let first: bool = chunk == 0;
+
+    let data: String = match format {
+        ExportFormat::Csv => csv(&tally, &page, first),
+        ExportFormat::Json => json(key, &tally, &page, first),
+    };
+
+    Some(ExportChunk {
+        data,
+        chunk,
+        chunk_count,
+    })
+}
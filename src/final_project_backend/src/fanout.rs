@@ -0,0 +1,54 @@
+use candid::Principal;
+
+use crate::{archive, sharding, tally, visibility, ProposalView, MAX_BATCH_SIZE, PROPOSAL_MAP};
+
+/*
+    Reads that don't care where a proposal lives: here, in the archive or on a shard worker.
+    The frontend makes one call and the canister does the fan-out, the other canisters are
+    only asked for the keys that aren't here.
+*/
+async fn lookup(key: u64, caller: Principal) -> Option<ProposalView> {
+    if let Some(proposal) = PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        return visibility::present(proposal, &caller);
+    }
+
+    if let Some(proposal) = archive::fetch(key, &caller).await {
+        return Some(proposal);
+    }
+
+    sharding::fetch(key, caller).await
+}
+
+// The result at index `i` belongs to the key at index `i`, `None` for what isn't found or visible.
+#[ic_cdk::query(composite = true)]
+async fn find_proposals(keys: Vec<u64>) -> Vec<Option<ProposalView>> {
+    let caller: Principal = ic_cdk::caller();
+    let mut proposals: Vec<Option<ProposalView>> = vec![];
+
+    for key in keys.into_iter().take(MAX_BATCH_SIZE) {
+        proposals.push(lookup(key, caller).await);
+    }
+
+    proposals
+}
+
+/*
+    `get_tally` for proposals wherever they are, the tally is worked out here from the proposal.
+    Counts hidden from the caller come in as `None` and stay hidden, `tally::present` looks at
+    the same fields `embargo::redact` did.
+*/
+#[ic_cdk::query(composite = true)]
+async fn find_tallies(keys: Vec<u64>) -> Vec<Option<tally::Tally>> {
+    let caller: Principal = ic_cdk::caller();
+    let mut tallies: Vec<Option<tally::Tally>> = vec![];
+
+    for key in keys.into_iter().take(MAX_BATCH_SIZE) {
+        tallies.push(
+            lookup(key, caller)
+                .await
+                .map(|view| tally::present(&view.map_counts(|count| count.unwrap_or(0)), &caller)),
+        );
+    }
+
+    tallies
+}
@@ -0,0 +1,163 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, time::Duration};
+
+use crate::{
+    authenticated_caller, changes, config, get_memory, rate_limit, schedule, timers, visibility,
+    CreateProposal, Memory, Proposal, Role, VoteError, FOLLOW_UP_MEMORY_ID, MAX_VALUE_SIZE,
+    PROPOSAL_MAP,
+};
+
+/*
+    A signal vote can carry the binding vote that follows it (a ratification round, the actual budget).
+    Once the signal vote passes the follow-up is created for the same owner, under the next free key,
+    with `follows` pointing back. It's created at most once, even if the signal vote is reopened and passes again.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct FollowUp {
+    proposal: CreateProposal,
+    opened: Option<Result<u64, VoteError>>, // Key of the follow-up, or why it couldn't be created.
+}
+
+impl Storable for FollowUp {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for FollowUp {
+    // Same as a scheduled proposal, the template is a whole proposal.
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> the proposal to open once it passes.
+    static FOLLOW_UPS: RefCell<StableBTreeMap<u64, FollowUp, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(FOLLOW_UP_MEMORY_ID)));
+
+    // Keys whose follow-up is being created right now, a retry can't race the first attempt.
+    static OPENING: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+pub(crate) fn store(key: u64, proposal: CreateProposal) {
+    FOLLOW_UPS.with(|f| {
+        f.borrow_mut().insert(
+            key,
+            FollowUp {
+                proposal,
+                opened: None,
+            },
+        )
+    });
+}
+
+pub(crate) fn remove(key: u64) {
+    FOLLOW_UPS.with(|f| f.borrow_mut().remove(&key));
+}
+
+// Carried over to the next round, unless this round already opened its follow-up.
+pub(crate) fn pending(key: u64) -> Option<CreateProposal> {
+    FOLLOW_UPS
+        .with(|f| f.borrow().get(&key))
+        .filter(|follow_up| follow_up.opened.is_none())
+        .map(|follow_up| follow_up.proposal)
+}
+
+/*
+    Called by `decision::conclude` when `key` passed. Creating awaits the ledger and the fee,
+    and the passed proposal isn't written back yet, so it runs from a timer right after.
+*/
+pub(crate) fn on_passed(key: u64, proposal: &Proposal) {
+    let due: bool = FOLLOW_UPS
+        .with(|f| f.borrow().get(&key))
+        .is_some_and(|follow_up| follow_up.opened.is_none());
+
+    if due {
+        let owner: Principal = proposal.owner;
+        timers::set_timer(Duration::ZERO, move || {
+            ic_cdk::spawn(async move {
+                // The result is kept with the follow-up, `get_follow_up` shows it.
+                let _ = open(key, owner).await;
+            })
+        });
+    }
+}
+
+async fn open(key: u64, owner: Principal) -> Result<u64, VoteError> {
+    let follow_up: FollowUp = match FOLLOW_UPS.with(|f| f.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !OPENING.with(|o| o.borrow_mut().insert(key)) {
+        return Err(VoteError::NoFollowUpToRetry);
+    }
+    let res: Result<u64, VoteError> = schedule::create_at_free_key(owner, follow_up.proposal).await;
+    OPENING.with(|o| o.borrow_mut().remove(&key));
+
+    if let Ok(new_key) = res {
+        // Written after the await, like `rounds` does for the round it clones.
+        PROPOSAL_MAP.with(|p| {
+            let mut map = p.borrow_mut();
+
+            if let Some(mut value) = map.get(&new_key) {
+                value.follows = Some(key);
+                map.insert(new_key, value);
+            }
+        });
+        changes::record_change(new_key);
+    }
+
+    // Re-read, the proposal could have been deleted while the follow-up was created.
+    FOLLOW_UPS.with(|f| {
+        let mut map = f.borrow_mut();
+
+        if let Some(mut value) = map.get(&key) {
+            value.opened = Some(res.clone());
+            map.insert(key, value);
+        }
+    });
+
+    res
+}
+
+// The template and what became of it. Only the owner and admins see it, the template may list restricted voters.
+#[ic_cdk::query]
+fn get_follow_up(key: u64) -> Option<FollowUp> {
+    let caller: Principal = ic_cdk::caller();
+
+    match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if value.owner == caller || config::is_admin(&caller) => {
+            FOLLOW_UPS.with(|f| f.borrow().get(&key))
+        }
+        _ => None,
+    }
+}
+
+// When the follow-up couldn't be created (a quota, an empty ledger), the owner can try again once that's fixed.
+#[ic_cdk::update]
+async fn retry_follow_up(key: u64) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if visibility::can_see(&value, &caller) => value,
+        _ => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.owner != caller && !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    match FOLLOW_UPS.with(|f| f.borrow().get(&key)) {
+        Some(FollowUp {
+            opened: Some(Err(_)),
+            ..
+        }) => open(key, proposal.owner).await,
+        _ => Err(VoteError::NoFollowUpToRetry),
+    }
+}
@@ -0,0 +1,133 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::StableBTreeMap;
+use std::{cell::RefCell, time::Duration};
+
+use crate::{
+    archive, ballot_chain, ballots, changes, config, get_memory, nft_gate, snapshot, sns, timers,
+    Memory, Proposal, CLOSED_AT_MEMORY_ID, EXPIRY_MEMORY_ID, MAX_BATCH_SIZE, PROPOSAL_MAP,
+};
+
+const GC_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Entries one run looks at, finished ones that aren't settled yet are skipped over.
+const MAX_SCAN: usize = 1000;
+
+/*
+    Proposals that have been closed for longer than `period` are cleaned up by an hourly job.
+    `Compact` keeps the proposal with its final counts but drops everything kept per voter,
+    `Archive` moves it to the archive canister right away instead of waiting for the threshold.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Retention {
+    period: u64, // Nanoseconds after closing.
+    action: GcAction,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize)]
+enum GcAction {
+    Compact,
+    Archive,
+}
+
+thread_local! {
+    // Proposal key -> when voting closed.
+    static CLOSED_AT: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(CLOSED_AT_MEMORY_ID)));
+
+    // (closed at, proposal key), oldest first. Entries leave once their proposal has been dealt with.
+    static EXPIRY: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(EXPIRY_MEMORY_ID)));
+}
+
+// Archiving needs an archive canister to go to.
+pub(crate) fn validate(retention: &Retention, has_archive: bool) -> bool {
+    retention.period > 0 && (has_archive || matches!(retention.action, GcAction::Compact))
+}
+
+pub(crate) fn start_collecting() {
+    timers::set_timer_interval(GC_INTERVAL, || ic_cdk::spawn(run()));
+}
+
+// Called wherever voting closes, a proposal that closes again starts over.
+pub(crate) fn on_close(key: u64) {
+    remove(key);
+
+    let now: u64 = ic_cdk::api::time();
+    CLOSED_AT.with(|c| c.borrow_mut().insert(key, now));
+    EXPIRY.with(|e| e.borrow_mut().insert((now, key), ()));
+}
+
+pub(crate) fn remove(key: u64) {
+    if let Some(closed_at) = CLOSED_AT.with(|c| c.borrow_mut().remove(&key)) {
+        EXPIRY.with(|e| e.borrow_mut().remove(&(closed_at, key)));
+    }
+}
+
+async fn run() {
+    let retention: Retention = match config::retention() {
+        Some(value) => value,
+        None => return,
+    };
+    let cutoff: u64 = ic_cdk::api::time().saturating_sub(retention.period);
+
+    let expired: Vec<u64> = EXPIRY.with(|e| {
+        e.borrow()
+            .range(..(cutoff, u64::MAX))
+            .take(MAX_SCAN)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+
+    let mut batch: Vec<u64> = vec![];
+    for key in expired {
+        let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+            Some(value) => value,
+            // Archived by the threshold in the meantime.
+            None => {
+                remove(key);
+                continue;
+            }
+        };
+
+        if !archive::finalized(key, &proposal) {
+            continue;
+        }
+
+        batch.push(key);
+        if batch.len() >= MAX_BATCH_SIZE {
+            break;
+        }
+    }
+
+    match retention.action {
+        GcAction::Compact => {
+            for key in batch {
+                compact(key);
+            }
+        }
+        // Whatever the archive took is gone from `PROPOSAL_MAP`, the next run drops it from the index.
+        GcAction::Archive => archive::move_to_archive(batch).await,
+    }
+}
+
+/*
+    The counts, the outcome and the texts stay, who voted and how doesn't.
+    `compacted_voters` keeps the number of voters for the tally.
+*/
+fn compact(key: u64) {
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    ballots::remove(key);
+    ballot_chain::remove_links(key);
+    snapshot::remove(key);
+    nft_gate::remove(key);
+    sns::remove(key);
+
+    proposal.compacted_voters = Some(proposal.voter_count());
+    proposal.voted = vec![];
+
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+    remove(key);
+}
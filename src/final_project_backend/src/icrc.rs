@@ -0,0 +1,191 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde::Serialize;
+
+/*
+    The bits of the ICRC-1 ledger interface we talk to.
+    https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Account {
+    pub(crate) owner: Principal,
+    pub(crate) subaccount: Option<Vec<u8>>,
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Account {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+// Ledger amounts are `nat`, we work with u64 and cap anything bigger.
+pub(crate) fn nat_to_u64(nat: &Nat) -> u64 {
+    let digits: Vec<u64> = nat.0.to_u64_digits();
+
+    match digits.len() {
+        0 => 0,
+        1 => digits[0],
+        _ => u64::MAX,
+    }
+}
+
+pub(crate) async fn balance_of(ledger: Principal, account: Account) -> Result<u64, String> {
+    let res: Result<(Nat,), _> = ic_cdk::call(ledger, "icrc1_balance_of", (account,)).await;
+
+    match res {
+        Ok((balance,)) => Ok(nat_to_u64(&balance)),
+        Err((code, msg)) => Err(format!("icrc1_balance_of failed: {:?} {}", code, msg)),
+    }
+}
+
+/*
+    ICRC-7 (NFT collections) only needs the tokens an account owns.
+    https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7
+*/
+const TOKENS_PAGE_SIZE: u64 = 100;
+
+pub(crate) async fn tokens_of(
+    collection: Principal,
+    account: Account,
+    max_tokens: usize,
+) -> Result<Vec<Nat>, String> {
+    let mut tokens: Vec<Nat> = vec![];
+
+    loop {
+        let prev: Option<Nat> = tokens.last().cloned();
+        let res: Result<(Vec<Nat>,), _> = ic_cdk::call(
+            collection,
+            "icrc7_tokens_of",
+            (account.clone(), prev, Some(Nat::from(TOKENS_PAGE_SIZE))),
+        )
+        .await;
+
+        let page: Vec<Nat> = match res {
+            Ok((page,)) => page,
+            Err((code, msg)) => return Err(format!("icrc7_tokens_of failed: {:?} {}", code, msg)),
+        };
+        let last_page: bool = (page.len() as u64) < TOKENS_PAGE_SIZE;
+
+        tokens.extend(page);
+
+        if last_page || tokens.len() >= max_tokens {
+            tokens.truncate(max_tokens);
+            return Ok(tokens);
+        }
+    }
+}
+
+/*
+    Transfers (ICRC-1) and transfers on behalf of somebody who approved us (ICRC-2).
+    https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2
+    Both ledgers answer with the same errors, except that only ICRC-2 has `InsufficientAllowance`.
+*/
+
+/*
+    A transfer sent again with the same `created_at_time` and memo is one the ledger
+    already knows, it answers `Duplicate` with the block of the first one instead of paying twice.
+    That only holds within the ledger's window of a day, after that it answers `TooOld`.
+*/
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+pub(crate) struct Dedup {
+    pub(crate) created_at_time: u64,
+    pub(crate) memo: Vec<u8>,
+}
+#[derive(Debug, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+pub(crate) async fn fee(ledger: Principal) -> Result<u64, String> {
+    let res: Result<(Nat,), _> = ic_cdk::call(ledger, "icrc1_fee", ()).await;
+
+    match res {
+        Ok((fee,)) => Ok(nat_to_u64(&fee)),
+        Err((code, msg)) => Err(format!("icrc1_fee failed: {:?} {}", code, msg)),
+    }
+}
+
+// Sends `amount` from one of the canister's own subaccounts. Returns the block index.
+pub(crate) async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: u64,
+    dedup: Option<Dedup>,
+) -> Result<u64, String> {
+    let arg: TransferArg = TransferArg {
+        from_subaccount,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: dedup.as_ref().map(|d| d.memo.clone()),
+        created_at_time: dedup.map(|d| d.created_at_time),
+    };
+    let res: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(ledger, "icrc1_transfer", (arg,)).await;
+
+    match res {
+        Ok((Ok(block),)) => Ok(nat_to_u64(&block)),
+        Ok((Err(TransferError::Duplicate { duplicate_of }),)) => Ok(nat_to_u64(&duplicate_of)),
+        Ok((Err(err),)) => Err(format!("icrc1_transfer rejected: {:?}", err)),
+        Err((code, msg)) => Err(format!("icrc1_transfer failed: {:?} {}", code, msg)),
+    }
+}
+
+// Pulls `amount` out of `from`, which must have approved the canister for it (plus the fee).
+pub(crate) async fn transfer_from(
+    ledger: Principal,
+    from: Account,
+    to: Account,
+    amount: u64,
+) -> Result<u64, String> {
+    let arg: TransferFromArgs = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let res: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (arg,)).await;
+
+    match res {
+        Ok((Ok(block),)) => Ok(nat_to_u64(&block)),
+        Ok((Err(TransferError::Duplicate { duplicate_of }),)) => Ok(nat_to_u64(&duplicate_of)),
+        Ok((Err(err),)) => Err(format!("icrc2_transfer_from rejected: {:?}", err)),
+        Err((code, msg)) => Err(format!("icrc2_transfer_from failed: {:?} {}", code, msg)),
+    }
+}
@@ -0,0 +1,92 @@
+use candid::Principal;
+
+use crate::{
+    audit, authenticated_caller, changes, config, reminders, window, Proposal, Role, VoteError,
+    PROPOSAL_MAP,
+};
+
+/*
+    For incidents on a single proposal, like a frontend that was down for part of the vote:
+    moderators (and the admin) can give it more time, or hold the voting while things get
+    sorted out, without ending it. A suspended proposal turns voters away, its deadline keeps
+    running, so the time lost is given back with `extend_deadline`. Everything lands in the audit log.
+*/
+fn moderated(key: u64, caller: &Principal) -> Result<Proposal, VoteError> {
+    if !config::is_moderator(caller) {
+        return Err(VoteError::access_rejected(Role::Moderator, *caller));
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !proposal.is_active {
+        return Err(VoteError::not_active(&proposal));
+    }
+
+    Ok(proposal)
+}
+
+fn save(key: u64, proposal: Proposal) {
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+}
+
+// Only for proposals with a deadline, the reminder moves along with it.
+#[ic_cdk::update]
+fn extend_deadline(key: u64, extra_ns: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    let mut proposal: Proposal = moderated(key, &caller)?;
+
+    let from: u64 = match proposal.voting_ends_at {
+        Some(value) if extra_ns > 0 => value,
+        _ => return Err(VoteError::InvalidVotingWindow),
+    };
+    let to: u64 = from.saturating_add(extra_ns);
+
+    proposal.voting_ends_at = Some(to);
+    window::schedule_close(key, to);
+    reminders::remove(key);
+    reminders::schedule(key, to);
+    save(key, proposal);
+
+    audit::record(
+        caller,
+        key,
+        audit::AuditEvent::DeadlineExtended { from, to },
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn suspend_voting(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    let mut proposal: Proposal = moderated(key, &caller)?;
+
+    if proposal.voting_suspended_at.is_some() {
+        return Ok(());
+    }
+
+    proposal.voting_suspended_at = Some(ic_cdk::api::time());
+    save(key, proposal);
+
+    audit::record(caller, key, audit::AuditEvent::VotingSuspended);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn resume_voting(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    let mut proposal: Proposal = moderated(key, &caller)?;
+
+    if proposal.voting_suspended_at.is_none() {
+        return Ok(());
+    }
+
+    proposal.voting_suspended_at = None;
+    save(key, proposal);
+
+    audit::record(caller, key, audit::AuditEvent::VotingResumed);
+    Ok(())
+}
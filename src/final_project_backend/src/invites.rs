@@ -0,0 +1,173 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, bans, config, get_memory, rate_limit, visibility, voter_list, Memory,
+    Proposal, Role, VoteError, INVITE_INDEX_MEMORY_ID, INVITE_MEMORY_ID, MAX_BATCH_SIZE,
+    PROPOSAL_MAP,
+};
+
+// Random bytes in a code, hex encoded that's 32 characters.
+const CODE_LEN: usize = 16;
+
+/*
+    Invite codes put somebody on a proposal's voter list without the organizer knowing
+    their principal up front: the code goes out by mail or chat, and whoever redeems it
+    first is added. Only hashes are stored, the codes are handed out once by `generate_invites`.
+    The proposal needs a voter list, the rest of its rules (visibility included) still apply.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct Invite {
+    proposal_key: u64,
+    redeemed_by: Option<Principal>,
+}
+
+impl Storable for Invite {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Invite {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+type CodeHash = [u8; 32];
+
+thread_local! {
+    // SHA-256 of the code -> invite.
+    static INVITES: RefCell<StableBTreeMap<CodeHash, Invite, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(INVITE_MEMORY_ID)));
+
+    // (proposal key, code hash), to drop the invites of a proposal that goes away.
+    static BY_PROPOSAL: RefCell<StableBTreeMap<(u64, CodeHash), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(INVITE_INDEX_MEMORY_ID)));
+}
+
+fn hash(code: &str) -> CodeHash {
+    Sha256::digest(code.as_bytes()).into()
+}
+
+pub(crate) fn remove(key: u64) {
+    let hashes: Vec<CodeHash> = BY_PROPOSAL.with(|b| {
+        b.borrow()
+            .range((key, [0; 32])..=(key, [u8::MAX; 32]))
+            .map(|((_, hash), _)| hash)
+            .collect()
+    });
+
+    for hash in hashes {
+        INVITES.with(|i| i.borrow_mut().remove(&hash));
+        BY_PROPOSAL.with(|b| b.borrow_mut().remove(&(key, hash)));
+    }
+}
+
+// Invites for proposal `key` nobody has redeemed yet, each one is a voter that may still join.
+pub(crate) fn pending(key: u64) -> u64 {
+    BY_PROPOSAL.with(|b| {
+        b.borrow()
+            .range((key, [0; 32])..=(key, [u8::MAX; 32]))
+            .filter(|((_, hash), _)| {
+                INVITES
+                    .with(|i| i.borrow().get(hash))
+                    .is_some_and(|invite| invite.redeemed_by.is_none())
+            })
+            .count() as u64
+    })
+}
+
+// Owner or admin. The codes are only ever returned here, they can't be looked up later.
+#[ic_cdk::update]
+async fn generate_invites(key: u64, count: u32) -> Result<Vec<String>, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    if count == 0 || count as usize > MAX_BATCH_SIZE {
+        return Err(VoteError::InvalidInviteCount);
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.owner != caller && !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    if !proposal.voter_list {
+        return Err(VoteError::NoVoterList);
+    }
+
+    let randomness: Vec<u8> = match raw_rand().await {
+        Ok((bytes,)) => bytes,
+        Err(_) => return Err(VoteError::RandomnessUnavailable),
+    };
+
+    // The proposal could have been deleted or replaced during the await.
+    if !PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| proposal.voter_list)
+    {
+        return Err(VoteError::NoSuchProposal);
+    }
+
+    let codes: Vec<String> = (0..count)
+        .map(|i| {
+            let mut hasher = Sha256::new();
+            hasher.update(&randomness);
+            hasher.update(i.to_be_bytes());
+            hex::encode(&hasher.finalize()[..CODE_LEN])
+        })
+        .collect();
+
+    for code in &codes {
+        let hash: CodeHash = hash(code);
+        INVITES.with(|i| {
+            i.borrow_mut().insert(
+                hash,
+                Invite {
+                    proposal_key: key,
+                    redeemed_by: None,
+                },
+            )
+        });
+        BY_PROPOSAL.with(|b| b.borrow_mut().insert((key, hash), ()));
+    }
+
+    Ok(codes)
+}
+
+// Returns the key of the proposal the caller can now vote on.
+#[ic_cdk::update]
+fn redeem_invite(code: String) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+    bans::check(&caller)?;
+
+    let hash: CodeHash = hash(code.trim());
+    let mut invite: Invite = match INVITES.with(|i| i.borrow().get(&hash)) {
+        Some(value) if value.redeemed_by.is_none() => value,
+        _ => return Err(VoteError::InvalidInvite),
+    };
+
+    let key: u64 = invite.proposal_key;
+    let visible: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| visibility::can_see(&proposal, &caller));
+
+    if !visible {
+        return Err(VoteError::InvalidInvite);
+    }
+
+    voter_list::add(key, caller);
+    invite.redeemed_by = Some(caller);
+    INVITES.with(|i| i.borrow_mut().insert(hash, invite));
+    Ok(key)
+}
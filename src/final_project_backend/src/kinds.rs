@@ -0,0 +1,76 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::{config, execution::ExecutionPayload, membership, treasury, VoteError};
+
+/*
+    What a proposal is about, picked by its creator. Every kind but `Motion` carries a typed
+    payload that is checked when the proposal is created and run by the execution engine
+    once the proposal has passed its timelock (membership changes apply as soon as it passes).
+    A bare `execution` payload still works, the kind is then read off the payload.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) enum ProposalKind {
+    Motion, // Nothing runs, the result is the point.
+    ConfigChange(config::ConfigUpdate),
+    Transfer(treasury::TransferProposal),
+    CanisterCall {
+        canister: Principal,
+        method: String,
+        arg: Vec<u8>, // Candid encoded argument of `method`.
+    },
+    MembershipChange(Vec<membership::MembershipChange>),
+}
+
+// Stored on the proposal, for listings and frontends. The payload itself is in `execution`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, CandidType, Deserialize, Serialize)]
+pub(crate) enum Kind {
+    #[default]
+    Motion,
+    ConfigChange,
+    Transfer,
+    CanisterCall,
+    MembershipChange,
+    Upgrade, // Only through `execution`, see `upgrade`.
+}
+
+fn kind_of(payload: &ExecutionPayload) -> Kind {
+    match payload {
+        ExecutionPayload::Call { .. } => Kind::CanisterCall,
+        ExecutionPayload::Transfer(_) => Kind::Transfer,
+        ExecutionPayload::Upgrade(_) => Kind::Upgrade,
+        ExecutionPayload::Config(_) => Kind::ConfigChange,
+        ExecutionPayload::Membership(_) => Kind::MembershipChange,
+    }
+}
+
+// The kind and the payload to store. Setting both a kind and a payload is ambiguous.
+pub(crate) fn resolve(
+    kind: Option<ProposalKind>,
+    execution: Option<ExecutionPayload>,
+) -> Result<(Kind, Option<ExecutionPayload>), VoteError> {
+    let kind: ProposalKind = match (kind, execution) {
+        (Some(_), Some(_)) => return Err(VoteError::ConflictingKind),
+        (None, Some(payload)) => return Ok((kind_of(&payload), Some(payload))),
+        (None, None) => return Ok((Kind::Motion, None)),
+        (Some(kind), None) => kind,
+    };
+
+    let payload: Option<ExecutionPayload> = match kind {
+        ProposalKind::Motion => None,
+        ProposalKind::ConfigChange(update) => Some(ExecutionPayload::Config(update)),
+        ProposalKind::Transfer(transfer) => Some(ExecutionPayload::Transfer(transfer)),
+        ProposalKind::CanisterCall {
+            canister,
+            method,
+            arg,
+        } => Some(ExecutionPayload::Call {
+            canister,
+            method,
+            arg,
+        }),
+        ProposalKind::MembershipChange(changes) => Some(ExecutionPayload::Membership(changes)),
+    };
+
+    Ok((payload.as_ref().map_or(Kind::Motion, kind_of), payload))
+}
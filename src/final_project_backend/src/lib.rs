@@ -1,12 +1,208 @@
-use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet};
+
+mod abuse;
+mod api_keys;
+mod archive;
+mod attachments;
+mod attestation;
+mod audit;
+mod backup;
+mod ballot_chain;
+mod ballot_guard;
+mod ballots;
+mod bans;
+#[cfg(feature = "bench")]
+mod bench;
+mod blobs;
+mod block_log;
+mod bridge;
+mod certification;
+mod changes;
+mod ckbtc;
+mod committee;
+mod config;
+mod consent;
+mod dashboard;
+mod decision;
+mod delegation;
+mod dependencies;
+mod deposits;
+mod drafts;
+mod early_close;
+mod editors;
+mod embargo;
+mod events;
+mod execution;
+mod export;
+mod fanout;
+mod followups;
+mod gc;
+mod icrc;
+mod interventions;
+mod invites;
+mod kinds;
+mod listing;
+mod membership;
+mod merkle;
+mod metrics;
+mod moderation;
+mod nft_gate;
+mod nonces;
+mod outbox;
+mod ownership;
+mod participation;
+mod pause;
+mod personhood;
+mod preflight;
+mod privacy;
+mod proposal_store;
+mod quotas;
+mod rate_limit;
+mod receipts;
+mod reminders;
+mod reports;
+mod revisions;
+mod roles;
+mod rounds;
+mod salts;
+mod schedule;
+mod sealed_ballots;
+mod sharding;
+mod signed_ballots;
+mod signed_results;
+mod snapshot;
+mod sns;
+mod sorting;
+mod staking;
+mod stats;
+mod survey;
+mod tally;
+mod tally_history;
+mod ties;
+mod timers;
+mod treasury;
+mod upgrade;
+mod validation;
+mod visibility;
+mod voter_list;
+mod watch;
+mod weighting;
+mod window;
+mod workflow;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 const MAX_VALUE_SIZE: u32 = 5000;
 
+// Most keys/ballots a single batch call may carry, so one message can't run out of instructions.
+const MAX_BATCH_SIZE: usize = 100;
+
+/*
+    Every stable structure lives in its own virtual memory.
+    Keep all of the ids in one place so two structures never share a memory by accident.
+*/
+const PROPOSAL_MEMORY_ID: MemoryId = MemoryId::new(0);
+const CHANGE_SEQ_MEMORY_ID: MemoryId = MemoryId::new(1);
+const CHANGE_VERSION_MEMORY_ID: MemoryId = MemoryId::new(2);
+const CHANGE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(3);
+// MemoryId 4 held the admin before it moved into the config, don't reuse it.
+const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(5);
+const EMBARGO_MEMORY_ID: MemoryId = MemoryId::new(6);
+const WORKFLOW_MEMORY_ID: MemoryId = MemoryId::new(7);
+const WORKFLOW_PROGRESS_MEMORY_ID: MemoryId = MemoryId::new(8);
+const WORKFLOW_PENDING_MEMORY_ID: MemoryId = MemoryId::new(9);
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(10);
+const SNAPSHOT_MEMORY_ID: MemoryId = MemoryId::new(11);
+const NFT_VOTES_MEMORY_ID: MemoryId = MemoryId::new(12);
+const SNS_VOTES_MEMORY_ID: MemoryId = MemoryId::new(13);
+const RECEIPT_MEMORY_ID: MemoryId = MemoryId::new(14);
+const EXECUTION_MEMORY_ID: MemoryId = MemoryId::new(15);
+const DEPOSIT_MEMORY_ID: MemoryId = MemoryId::new(16);
+const PROPOSAL_DEPOSIT_MEMORY_ID: MemoryId = MemoryId::new(17);
+const TREASURY_MEMORY_ID: MemoryId = MemoryId::new(18);
+const DRAFT_MEMORY_ID: MemoryId = MemoryId::new(19);
+const REVISION_MEMORY_ID: MemoryId = MemoryId::new(20);
+const ARCHIVE_MEMORY_ID: MemoryId = MemoryId::new(21);
+const BALLOT_MEMORY_ID: MemoryId = MemoryId::new(22);
+const ATTACHMENT_MEMORY_ID: MemoryId = MemoryId::new(23);
+const ATTACHMENT_CHUNK_MEMORY_ID: MemoryId = MemoryId::new(24);
+const ATTACHMENT_INDEX_MEMORY_ID: MemoryId = MemoryId::new(25);
+const SCHEDULE_MEMORY_ID: MemoryId = MemoryId::new(26);
+const VOTING_WINDOW_MEMORY_ID: MemoryId = MemoryId::new(27);
+const BAN_MEMORY_ID: MemoryId = MemoryId::new(28);
+const NONCE_MEMORY_ID: MemoryId = MemoryId::new(29);
+const STATS_MEMORY_ID: MemoryId = MemoryId::new(30);
+const STATS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(31);
+const PARTICIPATION_MEMORY_ID: MemoryId = MemoryId::new(32);
+const PARTICIPATION_RANK_MEMORY_ID: MemoryId = MemoryId::new(33);
+const COMMITTEE_MEMORY_ID: MemoryId = MemoryId::new(34);
+const SIGNED_RESULT_MEMORY_ID: MemoryId = MemoryId::new(35);
+const BRIDGE_MEMORY_ID: MemoryId = MemoryId::new(36);
+const CLOSED_AT_MEMORY_ID: MemoryId = MemoryId::new(37);
+const EXPIRY_MEMORY_ID: MemoryId = MemoryId::new(38);
+const EVENT_MEMORY_ID: MemoryId = MemoryId::new(39);
+const TALLY_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(40);
+const TALLY_COUNT_MEMORY_ID: MemoryId = MemoryId::new(41);
+const DEPENDENT_MEMORY_ID: MemoryId = MemoryId::new(42);
+const WAITING_MEMORY_ID: MemoryId = MemoryId::new(43);
+const VOTER_LIST_MEMORY_ID: MemoryId = MemoryId::new(44);
+const INVITE_MEMORY_ID: MemoryId = MemoryId::new(45);
+const INVITE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(46);
+const VOTER_BALLOT_MEMORY_ID: MemoryId = MemoryId::new(49);
+const SORT_KEYS_MEMORY_ID: MemoryId = MemoryId::new(50);
+const SORT_CREATED_MEMORY_ID: MemoryId = MemoryId::new(51);
+const SORT_RECENT_MEMORY_ID: MemoryId = MemoryId::new(52);
+const SORT_VOTES_MEMORY_ID: MemoryId = MemoryId::new(53);
+const SORT_ENDING_MEMORY_ID: MemoryId = MemoryId::new(54);
+const FINAL_REPORT_MEMORY_ID: MemoryId = MemoryId::new(55);
+const CHAIN_HEAD_MEMORY_ID: MemoryId = MemoryId::new(56);
+const CHAIN_LINK_MEMORY_ID: MemoryId = MemoryId::new(57);
+const ABUSE_REPORT_MEMORY_ID: MemoryId = MemoryId::new(58);
+const ABUSE_COUNT_MEMORY_ID: MemoryId = MemoryId::new(59);
+const PAUSE_MEMORY_ID: MemoryId = MemoryId::new(60);
+const WASM_CHUNK_MEMORY_ID: MemoryId = MemoryId::new(61);
+const BLOCK_LOG_MEMORY_ID: MemoryId = MemoryId::new(62);
+const BLOCK_TIP_MEMORY_ID: MemoryId = MemoryId::new(63);
+const SHARD_MEMORY_ID: MemoryId = MemoryId::new(64);
+const SHARD_ROUTE_MEMORY_ID: MemoryId = MemoryId::new(65);
+const SHARD_WASM_MEMORY_ID: MemoryId = MemoryId::new(66);
+const DELEGATION_MEMORY_ID: MemoryId = MemoryId::new(67);
+const DELEGATE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(68);
+const REMINDER_MEMORY_ID: MemoryId = MemoryId::new(69);
+const SURVEY_TALLY_MEMORY_ID: MemoryId = MemoryId::new(70);
+const SURVEY_TEXT_MEMORY_ID: MemoryId = MemoryId::new(71);
+const API_KEY_MEMORY_ID: MemoryId = MemoryId::new(72);
+const API_KEY_HASH_MEMORY_ID: MemoryId = MemoryId::new(73);
+const BLOB_REF_MEMORY_ID: MemoryId = MemoryId::new(74);
+const BLOB_CHUNK_MEMORY_ID: MemoryId = MemoryId::new(75);
+const PREFLIGHT_MEMORY_ID: MemoryId = MemoryId::new(76);
+const ROLE_WEIGHT_MEMORY_ID: MemoryId = MemoryId::new(77);
+const ROLE_ASSIGNMENT_MEMORY_ID: MemoryId = MemoryId::new(78);
+const STAKE_MEMORY_ID: MemoryId = MemoryId::new(79);
+const MEMBER_MEMORY_ID: MemoryId = MemoryId::new(80);
+const WATCHER_MEMORY_ID: MemoryId = MemoryId::new(81);
+const INBOX_MEMORY_ID: MemoryId = MemoryId::new(82);
+const INBOX_SEQ_MEMORY_ID: MemoryId = MemoryId::new(83);
+const TIE_MEMORY_ID: MemoryId = MemoryId::new(84);
+const SESSION_MEMORY_ID: MemoryId = MemoryId::new(85);
+const OPEN_QUOTA_MEMORY_ID: MemoryId = MemoryId::new(86);
+const RECENT_QUOTA_MEMORY_ID: MemoryId = MemoryId::new(87);
+const FOLLOW_UP_MEMORY_ID: MemoryId = MemoryId::new(88);
+const OUTBOX_MEMORY_ID: MemoryId = MemoryId::new(89);
+const SEALED_BALLOT_MEMORY_ID: MemoryId = MemoryId::new(90);
+const UNSEALING_MEMORY_ID: MemoryId = MemoryId::new(91);
+const REASSIGNMENT_MEMORY_ID: MemoryId = MemoryId::new(92);
+const BALLOT_SECRET_MEMORY_ID: MemoryId = MemoryId::new(93);
+const PAYOUT_TIME_MEMORY_ID: MemoryId = MemoryId::new(94);
+const PURGED_VOTER_MEMORY_ID: MemoryId = MemoryId::new(95);
+const SIGNED_BALLOT_NONCE_MEMORY_ID: MemoryId = MemoryId::new(96);
+const PROPOSAL_INDEX_MEMORY_ID: MemoryId = MemoryId::new(47);
+const PROPOSAL_CHUNK_MEMORY_ID: MemoryId = MemoryId::new(48);
+
 /*
     First thing to do in any smart contract is defining the types that
     we need to store in our state. OR
@@ -21,12 +217,58 @@ const MAX_VALUE_SIZE: u32 = 5000;
 
 // enums are only for return_types
 
-#[derive(Debug, CandidType, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize, Serialize)]
 
 enum Choice {
     Approve,
     Reject,
     Pass,
+    Abstain, // Counts as taking part, never for or against. See `decision::Abstention`.
+}
+
+impl Choice {
+    // How a choice goes into everything that gets hashed or signed. Never renumber these.
+    fn as_byte(self) -> u8 {
+        match self {
+            Choice::Approve => 0,
+            Choice::Reject => 1,
+            Choice::Pass => 2,
+            Choice::Abstain => 3,
+        }
+    }
+}
+
+/*
+    Only a hint for frontends, the choices themselves stay the same underneath.
+    A choice without a style is shown under its own name.
+*/
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize, Serialize)]
+struct ChoiceStyle {
+    label: String,
+    color: Option<String>, // `#rrggbb`.
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize, Serialize)]
+struct ChoiceLabels {
+    approve: Option<ChoiceStyle>, // E.g. "For".
+    reject: Option<ChoiceStyle>,
+    pass: Option<ChoiceStyle>,
+    abstain: Option<ChoiceStyle>,
+}
+
+// Who would have been let through, so a frontend can tell the caller what they're missing.
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize)]
+enum Role {
+    Owner, // Admins get through as well where the owner does.
+    Editor,
+    Admin,
+    Moderator,
+    Council,
+    Signer,
+    Controller,
+    Creator, // Allowed to create proposals, see `config::can_create_proposals`.
+    Uploader,
+    Router,
 }
 
 /*
@@ -34,59 +276,401 @@ enum Choice {
     We have VoteError so front-end know what went wrong
     in case we have face any problem.
 */
-#[derive(Debug, CandidType, Deserialize)]
+#[derive(Debug, Clone, CandidType, Deserialize)]
 
 enum VoteError {
-    AlreadyVoted,
-    ProposalIsNotActive,
+    AlreadyVoted {
+        at: Option<u64>, // When the ballot was cast, `None` where that isn't kept (surveys).
+    },
+    ProposalNotActive {
+        status: listing::ListingStatus,
+        closed_at: Option<u64>, // Set once voting has closed, `None` while it hasn't started.
+    },
     NoSuchProposal,
-    AccessRejected,
+    AccessRejected {
+        required: Role,
+        caller: Principal,
+    },
     UpdateError,
+    ProposalHasVotes,
+    InvalidWorkflow,
+    NotAllowedInCurrentStage,
+    RateLimited,
+    AnonymousNotAllowed,
+    InvalidConfig,
+    LedgerCallFailed,
+    NotEligible,
+    NoImportedSnapshot,
+    CollectionCallFailed,
+    ConflictingVotingPower,
+    GovernanceCallFailed,
+    InvalidExecutionPayload,
+    InvalidWasmChunk,
+    ShardUnavailable,
+    ShardSpawnFailed,
+    TooManyShards,
+    DelegationDisabled,
+    InvalidDelegate,
+    TooManyDelegators,
+    NoDelegation,
+    NotExecutable,
+    TimelockNotExpired,
+    ExecutionFailed,
+    InvalidVetoReason,
+    VetoWindowClosed,
+    DepositFailed,
+    NoDeposit,
+    NoSuchDraft,
+    AlreadySigned,
+    ProposalArchived,
+    InvalidAttachment,
+    NoSuchAttachment,
+    AttachmentQuotaExceeded,
+    AttachmentIncomplete,
+    AccessListTooLong,
+    InvalidSchedule,
+    NoSuchSchedule,
+    InvalidDecisionRule,
+    AbstainNotAllowed,
+    InvalidVotingWindow,
+    VotingNotStarted,
+    VotingEnded,
+    Banned,
+    RequestInProgress,
+    InvalidCommitteeSize,
+    CommitteeAlreadySelected,
+    RandomnessUnavailable,
+    InvalidSignature,
+    BallotExpired,
+    InvalidProof,
+    SigningFailed,
+    NothingToSign,
+    NothingToSubmit,
+    InsufficientCkBtc,
+    CkBtcFeeFailed,
+    InvalidChunk,
+    StateNotEmpty,
+    ImportInProgress,
+    CanisterPaused,
+    NotPaused, // `export_state` only reads a paused canister.
+    InvalidDependencies,
+    DependencyCycle,
+    DependenciesNotPassed,
+    InvalidVoterList,
+    InvalidInviteCount,
+    NoVoterList,
+    InvalidInvite,
+    InvalidEditor,
+    NotADraft,
+    ProposalIsDraft,
+    ProposalLocked,
+    NotPendingReview,
+    NotApproved,
+    InvalidReason,
+    AlreadyReported,
+    ProposalSuspended,
+    VotesStillOpen,
+    InvalidSurvey,
+    NotASurvey,
+    SurveyProposal,
+    IncompleteSurvey,
+    InvalidAnswer,
+    InvalidApiKey,
+    ProposalStillOpen,
+    InvalidRoleWeight,
+    NoRoleWeights,
+    StakingDisabled,
+    InvalidStake,
+    StakeLocked {
+        until: u64,
+    },
+    ConflictingKind,
+    RegistryAlreadySeeded,
+    TooManyWatchers,
+    InvalidTieBreak,
+    NoTieToBreak,
+    VotingSuspended,
+    AttestationNotConfigured,
+    InvalidAttestation,
+    AttestationRequired,
+    PersonhoodNotConfigured,
+    PersonhoodCallFailed,
+    NotAHuman,
+    QuotaExceeded {
+        limit: u32,
+        window: Option<u64>, // Nanoseconds the limit counts over, `None` for proposals open at the same time.
+    },
+    VotingPeriodNotElapsed {
+        until: u64, // The earliest the proposal can be ended.
+    },
+    NoFollowUpToRetry,
+    InvalidWeighting,
+    NoSuchCall,
+    CallInFlight,
+    CallFailed,
+    VetKdNotConfigured,
+    VetKdCallFailed,
+    BallotsAreSealed,  // Votes on the proposal go through `cast_sealed_vote`.
+    ProposalNotSealed, // Votes on the proposal go through `vote`.
+    InvalidSealedBallot,
+    PublicSealedBallots,
+    NothingToUnseal,
+    InvalidNewOwner,
+    ReassignmentPending,
+    NoReassignmentPending,
+    AlreadyExists, // Another proposal has the key, `delete_proposal` frees it.
+    SaltsNotReady, // Right after the install, try again in a few seconds.
+    TooManySnapshotVoters,
+    ValidationFailed(validation::ValidationError),
+    ArchiveCallFailed,   // Nothing was purged, try again.
+    ProposalsAreSharded, // The router has workers, new proposals go through `routed_create_proposal`.
+    StaleBallot, // The voter submitted a signed ballot with this nonce or a higher one already.
+}
+
+impl VoteError {
+    fn access_rejected(required: Role, caller: Principal) -> Self {
+        VoteError::AccessRejected { required, caller }
+    }
+
+    fn not_active(proposal: &Proposal) -> Self {
+        VoteError::ProposalNotActive {
+            status: proposal.listing,
+            closed_at: proposal.closed_at,
+        }
+    }
 }
 
 /*
     Create actual Propsal itself.
     Principal is what stands as a wallet address in ICP.
+    Stored by field position, see `impl Storable for Proposal` before adding a field.
+    The counts are `None` in what a caller gets while the results are hidden from them, see `embargo::redact`.
 */
-#[derive(Debug, CandidType, Deserialize)]
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
 
-struct Proposal {
+struct Proposal<C = u64> {
+    title: String,
+    summary: String, // Short text for proposal cards, `description` is the full text.
+    url: Option<String>, // Link to the discussion or the full document.
+    payload_hash: Option<Vec<u8>>, // SHA-256 of whatever off-chain payload the proposal is about.
     description: String,
-    approve: u32,
-    reject: u32,
-    pass: u32,
+    approve: C,
+    reject: C,
+    pass: C,
+    abstain: C,
+    abstain_voters: C, // Heads behind `abstain`, the quorum counts heads.
     is_active: bool,
     voted: Vec<candid::Principal>, // Vector of the user who have voted for this proposal.
     owner: candid::Principal, // Owner of propsal and candid principal and SYNTAX of accessing principal.
+    results_embargo: Option<u64>, // How long (in nanoseconds) the results stay hidden after the proposal is ended.
+    results_hidden_until: Option<u64>, // Set when the proposal is ended with an embargo, cleared once results are published.
+    category: Option<String>, // Proposals of a category with a workflow move through its stages on their own.
+    quorum: u32, // Copied from the config at creation, so changing the default doesn't affect running proposals.
+    snapshot: Option<snapshot::Snapshot>, // Set for token-weighted votes, the counts above are then weights instead of heads.
+    nft_gate: Option<nft_gate::NftGate>,  // Only holders of the collection's NFTs may vote.
+    sns_gate: Option<sns::SnsGate>,       // Votes are weighted by the voter's neurons in this SNS.
+    eligibility_root: Option<merkle::EligibilityRoot>, // Voters prove their weight against this root.
+    execution: Option<execution::ExecutionPayload>, // Runs after the timelock once the proposal has passed.
+    visibility: visibility::Visibility, // Restricted proposals only exist for the principals on the list.
+    decision_rule: decision::DecisionRule, // Fixed at creation, changing it mid-vote would change the result.
+    outcome: Option<decision::Outcome>,    // Set when voting closes.
+    abstention: decision::Abstention,
+    voting_starts_at: Option<u64>, // Votes before this time are turned away.
+    voting_ends_at: Option<u64>,   // The proposal closes on its own at this time.
+    compacted_voters: Option<u64>, // Voters dropped from `voted` by the garbage collector or a purge, still counted.
+    listing: listing::ListingStatus, // Hidden proposals are left out of listings.
+    depends_on: Vec<u64>,          // Keys that have to pass before this one opens or executes.
+    voter_list: bool,              // Only the principals on its list in `voter_list` may vote.
+    editors: Vec<Principal>,       // Can edit and end the proposal like the owner, see `editors`.
+    public_ballots: bool,          // Anybody who can see the proposal can see who voted what.
+    allow_vote_changes: bool,      // Voters may change their choice while voting is open.
+    created_at: u64,
+    updated_at: u64,        // Stamped by `PROPOSAL_MAP` on every write.
+    closed_at: Option<u64>, // When voting last closed, cleared if it opens again.
+    delegated: delegation::DelegatedWeight<C>, // Included in the counts above since the last close.
+    quorum_percent: Option<u32>, // Of `registered_voters`, replaces `quorum` once the proposal opens.
+    registered_voters: Option<u64>, // How long the voter list was when the proposal opened.
+    survey: Option<survey::Survey>, // Answered with `submit_survey`, the counts above stay at zero.
+    choice_labels: ChoiceLabels,
+    results_visibility: embargo::ResultsVisibility, // Counts stay hidden while voting runs, unless `Live`.
+    description_hash: Option<blobs::BlobHash>, // Set by `PROPOSAL_MAP` when the description is kept in `blobs`.
+    previous_round: Option<u64>, // The proposal this one was cloned from, see `rounds`.
+    role_weights: Option<Vec<roles::RoleWeight>>, // The schedule at creation, for role weighted proposals.
+    kind: kinds::Kind,
+    members_only: bool,
+    tie_break: ties::TieBreak,
+    voting_suspended_at: Option<u64>, // Set by `suspend_voting`, votes are turned away until `resume_voting`.
+    frontend_only: bool, // Only voters with a session from the frontend may vote, see `attestation`.
+    personhood_gated: bool, // Only voters the proof-of-personhood provider vouches for may vote.
+    follows: Option<u64>, // The proposal whose passing opened this one, see `followups`.
+    weighting: Option<weighting::Weighting>, // Applied to every snapshot balance as it's counted.
+    sealed_ballots: bool, // Ballots are encrypted until voting closes, see `sealed_ballots`.
 }
 
-#[derive(Debug, CandidType, Deserialize)]
+// What callers get, the counts are `None` while the results are hidden from them.
+type ProposalView = Proposal<Option<u64>>;
+
+impl Proposal {
+    fn voter_count(&self) -> u64 {
+        self.voted.len() as u64 + self.compacted_voters.unwrap_or(0)
+    }
+}
+
+impl<C> Proposal<C> {
+    // The same proposal with every count passed through `f`, see `embargo::redact`.
+    fn map_counts<D>(self, f: impl Fn(C) -> D) -> Proposal<D> {
+        Proposal {
+            title: self.title,
+            summary: self.summary,
+            url: self.url,
+            payload_hash: self.payload_hash,
+            description: self.description,
+            approve: f(self.approve),
+            reject: f(self.reject),
+            pass: f(self.pass),
+            abstain: f(self.abstain),
+            abstain_voters: f(self.abstain_voters),
+            is_active: self.is_active,
+            voted: self.voted,
+            owner: self.owner,
+            results_embargo: self.results_embargo,
+            results_hidden_until: self.results_hidden_until,
+            category: self.category,
+            quorum: self.quorum,
+            snapshot: self.snapshot,
+            nft_gate: self.nft_gate,
+            sns_gate: self.sns_gate,
+            eligibility_root: self.eligibility_root,
+            execution: self.execution,
+            visibility: self.visibility,
+            decision_rule: self.decision_rule,
+            outcome: self.outcome,
+            abstention: self.abstention,
+            voting_starts_at: self.voting_starts_at,
+            voting_ends_at: self.voting_ends_at,
+            compacted_voters: self.compacted_voters,
+            listing: self.listing,
+            depends_on: self.depends_on,
+            voter_list: self.voter_list,
+            editors: self.editors,
+            public_ballots: self.public_ballots,
+            allow_vote_changes: self.allow_vote_changes,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            closed_at: self.closed_at,
+            delegated: self.delegated.map(&f),
+            quorum_percent: self.quorum_percent,
+            registered_voters: self.registered_voters,
+            survey: self.survey,
+            choice_labels: self.choice_labels,
+            results_visibility: self.results_visibility,
+            description_hash: self.description_hash,
+            previous_round: self.previous_round,
+            role_weights: self.role_weights,
+            kind: self.kind,
+            members_only: self.members_only,
+            tie_break: self.tie_break,
+            voting_suspended_at: self.voting_suspended_at,
+            frontend_only: self.frontend_only,
+            personhood_gated: self.personhood_gated,
+            follows: self.follows,
+            weighting: self.weighting,
+            sealed_ballots: self.sealed_ballots,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
 /*
     create propsal is justfor an argument type. SO
     We don't need to store it in Storable.
 */
 struct CreateProposal {
+    title: String,
+    summary: String,
+    url: Option<String>,
+    payload_hash: Option<Vec<u8>>,
     description: String,
     is_active: bool,
+    results_embargo: Option<u64>,
+    category: Option<String>,
+    voting_power: Option<snapshot::SnapshotSource>, // Only read on creation, the snapshot can't change afterwards.
+    nft_gate: Option<nft_gate::NftGate>,
+    sns_gate: Option<sns::SnsGate>, // At most one of `voting_power`, `nft_gate`, `sns_gate` and `eligibility_root` can be set.
+    eligibility_root: Option<merkle::EligibilityRoot>,
+    execution: Option<execution::ExecutionPayload>, // Only read on creation, like the voting power.
+    visibility: visibility::Visibility,
+    decision_rule: Option<decision::DecisionRule>, // Only read on creation, `SimpleMajority` when not set.
+    abstention: Option<decision::Abstention>, // Only read on creation, abstaining is allowed and counts for the quorum when not set.
+    voting_starts_at: Option<u64>, // Only read on creation, like the rest of the voting rules.
+    voting_ends_at: Option<u64>,
+    client_nonce: Option<[u8; 32]>, // Retrying with the same nonce returns the first key instead of creating again.
+    depends_on: Vec<u64>,           // Only read on creation.
+    eligible_voters: Option<Vec<Principal>>, // Only read on creation, stored next to the proposal.
+    save_as_draft: bool, // Only read on creation, the proposal stays a `Draft` until `publish_proposal`.
+    public_ballots: bool, // Only read on creation, voters are told up front whether their choice is public.
+    allow_vote_changes: bool, // Only read on creation.
+    quorum_percent: Option<u32>, // Only read on creation, needs `eligible_voters`.
+    survey: Option<survey::Survey>, // Only read on creation, can't be combined with weighted or gated voting.
+    choice_labels: ChoiceLabels,    // Locked once published, like the title.
+    results_visibility: Option<embargo::ResultsVisibility>, // Only read on creation, `Live` when not set.
+    role_weighted: bool, // Only read on creation, votes count times the multiplier of the voter's role.
+    kind: Option<kinds::ProposalKind>, // Only read on creation, the payload goes into `execution`. Can't be set next to `execution`.
+    members_only: bool, // Only read on creation, only members of the registry in `membership` may vote.
+    tie_break: Option<ties::TieBreak>, // Only read on creation, a tie fails when not set.
+    frontend_only: bool, // Only read on creation, needs `attestation` in the config.
+    personhood_gated: bool, // Only read on creation, needs `personhood` in the config. Can't be set on surveys.
+    on_pass_create: Option<Box<CreateProposal>>, // Only read on creation, opened for the same owner once this one passes.
+    weighting: Option<weighting::Weighting>,     // Only read on creation, needs `voting_power`.
+    sealed_ballots: bool, // Only read on creation, needs `vetkd_key` in the config. Ballots can't be public.
 }
 
 /*
     We are implementing the storable for the state we are going to store.
     Inside our state we are going to hold Proposal struct.
 */
+/*
+    Postcard instead of Candid: fields are written by position rather than by name,
+    which roughly halves what a proposal takes. New fields go at the end of the struct,
+    and only with a new layout version that reads the old one, see `proposal_store`.
+*/
 impl Storable for Proposal {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(proposal_store::encode(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        proposal_store::decode(bytes.as_ref())
     }
 }
 
-impl BoundedStorable for Proposal {
-    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
-    const IS_FIXED_SIZE: bool = false; // for stable binary tree where we are going to store the proposal object.
+/*
+    Principal doesn't implement Storable on its own, so we wrap it.
+    A principal is never longer than 29 bytes.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct StorablePrincipal(Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_slice().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(bytes.as_ref()))
+    }
+}
+
+impl BoundedStorable for StorablePrincipal {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Needed to use it in tuple keys. The empty principal also sorts before every other one.
+impl Default for StorablePrincipal {
+    fn default() -> Self {
+        StorablePrincipal(Principal::management_canister())
+    }
 }
 
 /*
@@ -99,61 +683,596 @@ thread_local! {
 
     // For Storing the Proposal map.
     // It's enusre that our state is going to be preserved among updates.
-    static PROPOSAL_MAP: RefCell<StableBTreeMap<u64,Proposal,Memory>> = RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)))));
+    static PROPOSAL_MAP: RefCell<proposal_store::ProposalStore> = RefCell::new(proposal_store::ProposalStore::init(get_memory(PROPOSAL_INDEX_MEMORY_ID), get_memory(PROPOSAL_CHUNK_MEMORY_ID)));
+
+    // Keys of proposals being created right now, see `CreationGuard`.
+    static CREATING: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+
+}
 
+fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/*
+    Every update method starts with this guard. The anonymous principal is the same
+    for everybody who isn't logged in, so letting it create or vote would make results meaningless.
+*/
+fn authenticated_caller() -> Result<Principal, VoteError> {
+    let caller: Principal = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err(VoteError::AnonymousNotAllowed);
+    }
+
+    // Every update goes through here, that keeps them away from a half imported state.
+    backup::check()?;
+    pause::check()?;
+
+    Ok(caller)
+}
+
+// Without an explicit config, whoever installs the canister becomes its admin.
+#[ic_cdk::init]
+fn init(config: Option<config::CanisterConfig>) {
+    config::apply_install_arg(config, ic_cdk::caller());
+    rate_limit::start_pruning();
+    nonces::start_pruning();
+    archive::start_archiving();
+    gc::start_collecting();
+    tally_history::start_sampling();
+    salts::start();
+    receipts::restore_certification();
+    ballot_chain::restore_certification();
+    block_log::restore_certification();
+    reports::restore_certification();
+}
+
+// An upgrade can replace the config, otherwise the stored one is kept.
+#[ic_cdk::post_upgrade]
+fn post_upgrade(config: Option<config::CanisterConfig>) {
+    proposal_store::migrate();
+    sorting::backfill();
+    stats::backfill();
+    config::apply_install_arg(config, ic_cdk::caller());
+    // Timers don't survive upgrades, so the pending ones have to be armed again.
+    embargo::rearm_timers();
+    workflow::rearm_timers();
+    schedule::rearm_timers();
+    window::rearm_timers();
+    reminders::rearm_timers();
+    outbox::rearm_timers();
+    ownership::rearm_timers();
+    sealed_ballots::resume();
+    rate_limit::start_pruning();
+    nonces::start_pruning();
+    archive::start_archiving();
+    gc::start_collecting();
+    tally_history::start_sampling();
+    salts::start();
+    receipts::restore_certification();
+    ballot_chain::restore_certification();
+    block_log::restore_certification();
+    reports::restore_certification();
+    bridge::resume();
+}
+
+#[ic_cdk::query]
+fn get_proposal(key: u64) -> Option<ProposalView> {
+    let caller: Principal = ic_cdk::caller();
+    PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .and_then(|proposal| visibility::present(proposal, &caller))
 }
 
 #[ic_cdk::query]
-fn get_proposal(key: u64) -> Option<Proposal> {
-    PROPOSAL_MAP.with(|p| p.borrow().get(&key))
+fn get_proposals(keys: Vec<u64>) -> Vec<Option<ProposalView>> {
+    if keys.len() > MAX_BATCH_SIZE {
+        ic_cdk::trap("Too many keys in one batch.");
+    }
+
+    let caller: Principal = ic_cdk::caller();
+
+    PROPOSAL_MAP.with(|p| {
+        let map = p.borrow();
+        keys.iter()
+            .map(|key| {
+                map.get(key)
+                    .and_then(|proposal| visibility::present(proposal, &caller))
+            })
+            .collect()
+    })
 }
 
+/*
+    Pages through the proposals, skipping the ones the caller can't see or the filter leaves out.
+    Without a sort they come in key order and `offset` is the first key,
+    with one `offset` is how many of the matching proposals to skip.
+*/
 #[ic_cdk::query]
-fn get_proposal_count() -> u64 {
-    PROPOSAL_MAP.with(|p| p.borrow().len())
+fn list_proposals(
+    offset: u64,
+    limit: u64,
+    filter: Option<ListFilter>,
+    sort: Option<SortBy>,
+) -> Vec<(u64, ProposalView)> {
+    let caller: Principal = ic_cdk::caller();
+    let filter: ListFilter = filter.unwrap_or_default();
+    let limit: usize = limit.min(MAX_BATCH_SIZE as u64) as usize;
+
+    let shown = |(key, proposal): (u64, Proposal)| -> Option<(u64, ProposalView)> {
+        if !listing::matches(&filter, &proposal) {
+            return None;
+        }
+        visibility::present(proposal, &caller).map(|proposal| (key, proposal))
+    };
+
+    match sort {
+        None => PROPOSAL_MAP.with(|p| {
+            p.borrow()
+                .range(offset..)
+                .filter_map(shown)
+                .take(limit)
+                .collect()
+        }),
+        Some(sort) => sorting::with_keys(sort, |keys| {
+            keys.filter_map(|key| {
+                PROPOSAL_MAP
+                    .with(|p| p.borrow().get(&key))
+                    .map(|proposal| (key, proposal))
+            })
+            .filter_map(shown)
+            .skip(offset as usize)
+            .take(limit)
+            .collect()
+        }),
+    }
 }
 
 #[ic_cdk::update]
-fn create_proposal(key: u64, proposal: CreateProposal) -> Option<Proposal> {
-    let value: Proposal = Proposal {
+async fn create_proposal(key: u64, proposal: CreateProposal) -> Result<u64, VoteError> {
+    create(key, proposal, false).await
+}
+
+/*
+    Shared by `create_proposal` and `drafts::propose_draft`. A draft stays closed until the signers co-signed it.
+    Returns the key of the proposal, which is the one the nonce created first on a retry.
+*/
+async fn create(key: u64, proposal: CreateProposal, draft: bool) -> Result<u64, VoteError> {
+    create_for(authenticated_caller()?, key, proposal, draft).await
+}
+
+// Past the authentication, `sharding` comes in here with the caller the router names.
+async fn create_for(
+    caller: Principal,
+    key: u64,
+    proposal: CreateProposal,
+    draft: bool,
+) -> Result<u64, VoteError> {
+    rate_limit::consume(caller, 1)?;
+
+    let nonce: Option<[u8; 32]> = proposal.client_nonce;
+    if let Some(key) = nonce.and_then(|nonce| nonces::replayed(caller, &nonce)) {
+        return Ok(key);
+    }
+    let _guard: Option<nonces::NonceGuard> = nonce
+        .map(|nonce| nonces::claim(caller, nonce))
+        .transpose()?;
+
+    create_as(caller, key, proposal, draft).await?;
+
+    if let Some(nonce) = nonce {
+        nonces::record(caller, nonce, key);
+    }
+
+    Ok(key)
+}
+
+// Releases the key of a creation once it's done, failed or trapped.
+struct CreationGuard(u64);
+
+impl CreationGuard {
+    fn claim(key: u64) -> Result<Self, VoteError> {
+        if PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key))
+            || !CREATING.with(|c| c.borrow_mut().insert(key))
+        {
+            return Err(VoteError::AlreadyExists);
+        }
+
+        Ok(CreationGuard(key))
+    }
+}
+
+impl Drop for CreationGuard {
+    fn drop(&mut self) {
+        CREATING.with(|c| c.borrow_mut().remove(&self.0));
+    }
+}
+
+// Everything after the caller checks, `schedule` also goes through here when a scheduled proposal opens.
+async fn create_as(
+    caller: Principal,
+    key: u64,
+    proposal: CreateProposal,
+    draft: bool,
+) -> Result<(), VoteError> {
+    bans::check(&caller)?;
+
+    if !config::can_create_proposals(&caller) {
+        return Err(VoteError::access_rejected(Role::Creator, caller));
+    }
+    quotas::check(caller)?;
+    sharding::check_local_creation()?;
+
+    // The key still belongs to the archived proposal, `find_proposal` would be ambiguous otherwise.
+    if archive::is_archived(key) {
+        return Err(VoteError::ProposalArchived);
+    }
+
+    // Taking over a key would get rid of somebody's proposal without `delete_proposal`.
+    // Held until the creation is done, another one can't take the key while this one awaits.
+    let _guard: CreationGuard = CreationGuard::claim(key)?;
+
+    validation::validate(&proposal)?;
+
+    // A co-signed draft opens with its signatures, it can't be published on top of that.
+    if draft && proposal.save_as_draft {
+        return Err(VoteError::ProposalIsDraft);
+    }
+
+    let power_sources: usize = [
+        proposal.voting_power.is_some(),
+        proposal.nft_gate.is_some(),
+        proposal.sns_gate.is_some(),
+        proposal.eligibility_root.is_some(),
+        proposal.role_weighted,
+    ]
+    .iter()
+    .filter(|set| **set)
+    .count();
+
+    if power_sources > 1 {
+        return Err(VoteError::ConflictingVotingPower);
+    }
+
+    let (kind, execution): (kinds::Kind, Option<execution::ExecutionPayload>) =
+        kinds::resolve(proposal.kind.clone(), proposal.execution.clone())?;
+
+    if let Some(payload) = &execution {
+        execution::validate(payload)?;
+    }
+
+    if let Some(survey) = &proposal.survey {
+        if power_sources > 0 {
+            return Err(VoteError::ConflictingVotingPower);
+        }
+        // Answers are taken without awaiting anything, there is no asking the provider.
+        // They're counted as they come in, there is nothing to seal either.
+        if proposal.personhood_gated || proposal.sealed_ballots {
+            return Err(VoteError::SurveyProposal);
+        }
+        survey::validate(survey)?;
+    }
+
+    dependencies::validate(key, &proposal.depends_on)?;
+
+    if let Some(voters) = &proposal.eligible_voters {
+        voter_list::validate(voters)?;
+    }
+
+    if let Some(source) = &proposal.voting_power {
+        snapshot::validate(source)?;
+    }
+
+    if let Some(root) = &proposal.eligibility_root {
+        merkle::validate(root)?;
+    }
+
+    let decision_rule: decision::DecisionRule = proposal
+        .decision_rule
+        .unwrap_or(decision::DecisionRule::SimpleMajority);
+    decision::validate(&decision_rule)?;
+
+    let tie_break: ties::TieBreak = proposal.tie_break.unwrap_or_default();
+    ties::validate(&tie_break)?;
+    attestation::check_available(proposal.frontend_only)?;
+    personhood::check_available(proposal.personhood_gated)?;
+
+    if let Some(weighting) = &proposal.weighting {
+        weighting::validate(weighting, proposal.voting_power.is_some())?;
+    }
+
+    if proposal.sealed_ballots {
+        sealed_ballots::validate(proposal.public_ballots, &tie_break)?;
+    }
+
+    // Without an end of its own, voting runs for the configured default from when it starts.
+    let voting_ends_at: Option<u64> = proposal.voting_ends_at.or_else(|| {
+        config::default_voting_duration().map(|duration| {
+            proposal
+                .voting_starts_at
+                .unwrap_or_else(ic_cdk::api::time)
+                .saturating_add(duration)
+        })
+    });
+    window::validate(proposal.voting_starts_at, voting_ends_at)?;
+
+    let role_weights: Option<Vec<roles::RoleWeight>> = if proposal.role_weighted {
+        Some(roles::schedule().ok_or(VoteError::NoRoleWeights)?)
+    } else {
+        None
+    };
+
+    ckbtc::check_holding(caller).await?;
+
+    // The snapshot is taken before anything is written, a failed ledger call leaves no half-created proposal behind.
+    let (snapshot, balances) = match &proposal.voting_power {
+        Some(source) => {
+            let (snapshot, balances) = snapshot::take(source).await?;
+            (Some(snapshot), balances)
+        }
+        None => (None, vec![]),
+    };
+
+    let mut value: Proposal = Proposal {
+        title: proposal.title,
+        summary: proposal.summary,
+        url: proposal.url,
+        payload_hash: proposal.payload_hash,
         description: proposal.description,
-        approve: 0u32,
-        reject: 0u32,
-        pass: 0u32,
+        approve: 0u64,
+        reject: 0u64,
+        pass: 0u64,
+        abstain: 0u64,
+        abstain_voters: 0u64,
         is_active: proposal.is_active,
         voted: vec![],
-        owner: ic_cdk::caller(),
+        owner: caller,
+        results_embargo: proposal.results_embargo,
+        results_hidden_until: None,
+        category: proposal.category,
+        quorum: config::default_quorum(),
+        snapshot,
+        nft_gate: proposal.nft_gate,
+        sns_gate: proposal.sns_gate,
+        eligibility_root: proposal.eligibility_root,
+        execution,
+        visibility: proposal.visibility,
+        decision_rule,
+        outcome: None,
+        abstention: proposal.abstention.unwrap_or_default(),
+        voting_starts_at: proposal.voting_starts_at,
+        voting_ends_at,
+        compacted_voters: None,
+        listing: if proposal.save_as_draft {
+            listing::ListingStatus::Draft
+        } else if !draft && moderation::needs_review(&caller) {
+            listing::ListingStatus::PendingReview
+        } else {
+            listing::ListingStatus::Listed
+        },
+        depends_on: proposal.depends_on,
+        voter_list: proposal.eligible_voters.is_some(),
+        editors: vec![],
+        public_ballots: proposal.public_ballots,
+        allow_vote_changes: proposal.allow_vote_changes,
+        created_at: ic_cdk::api::time(),
+        updated_at: ic_cdk::api::time(),
+        closed_at: None,
+        delegated: delegation::DelegatedWeight::default(),
+        quorum_percent: proposal.quorum_percent,
+        registered_voters: None,
+        survey: proposal.survey,
+        choice_labels: proposal.choice_labels,
+        results_visibility: proposal.results_visibility.unwrap_or_default(),
+        description_hash: None,
+        previous_round: None,
+        role_weights,
+        kind,
+        members_only: proposal.members_only,
+        tie_break,
+        voting_suspended_at: None,
+        frontend_only: proposal.frontend_only,
+        personhood_gated: proposal.personhood_gated,
+        follows: None,
+        weighting: proposal.weighting,
+        sealed_ballots: proposal.sealed_ballots,
     };
 
-    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, value))
+    // Taken last, so nothing after them can fail and leave a payment without a proposal.
+    let fee: Option<ckbtc::PaidFee> = ckbtc::collect_fee(caller).await?;
+    let deposit: Option<deposits::Deposit> = match deposits::collect(caller).await {
+        Ok(value) => value,
+        Err(err) => {
+            if let Some(fee) = fee {
+                ckbtc::refund(fee);
+            }
+            return Err(err);
+        }
+    };
+
+    snapshot::store(key, balances);
+    if let Some(follow_up) = proposal.on_pass_create {
+        followups::store(key, *follow_up);
+    }
+    dependencies::register(key, &value.depends_on);
+    if let Some(voters) = &proposal.eligible_voters {
+        voter_list::store(key, voters);
+    }
+
+    if draft {
+        // Remembered for when the draft opens, its workflow only starts then as well.
+        drafts::start(key, caller, value.is_active);
+        value.is_active = false;
+    } else if listing::is_unpublished(value.listing) {
+        // Opened by `publish_proposal` or `approve_submission`, along with its workflow.
+        value.is_active = false;
+    } else {
+        open(key, &mut value);
+    }
+
+    if let Some(ends_at) = value.voting_ends_at {
+        window::schedule_close(key, ends_at);
+        reminders::schedule(key, ends_at);
+    }
+
+    if let Some(deposit) = deposit {
+        deposits::hold(key, deposit);
+    }
+
+    events::on_created(key, &value);
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, value));
+    changes::record_change(key);
+    participation::on_proposal_created(caller);
+    quotas::on_created(caller, key);
+
+    Ok(())
+}
+
+// With a workflow, the first stage decides whether voting is open, not the caller.
+fn open(key: u64, proposal: &mut Proposal) {
+    // Held back until the dependencies passed, `dependencies::on_passed` comes back here then.
+    if !dependencies::all_passed(&proposal.depends_on) {
+        dependencies::wait(key, proposal.is_active);
+        proposal.is_active = false;
+        return;
+    }
+
+    // The bar is set once, invites redeemed later don't move it.
+    if let (Some(percent), None) = (proposal.quorum_percent, proposal.registered_voters) {
+        let registered: u64 = voter_list::count(key);
+        proposal.quorum = (registered * percent as u64)
+            .div_ceil(100)
+            .min(u32::MAX as u64) as u32;
+        proposal.registered_voters = Some(registered);
+    }
+
+    if let Some(category) = &proposal.category {
+        if let Some(allows_voting) = workflow::start(key, category) {
+            proposal.is_active = allows_voting;
+        }
+    }
 }
 
 #[ic_cdk::update]
 fn edit_proposal(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
     PROPOSAL_MAP.with(|p| {
         let old_proposal_opt = p.borrow().get(&key);
-        let old_proposal: Proposal;
-
-        match old_proposal_opt {
-            Some(value) => old_proposal = value,
+        let old_proposal: Proposal = match old_proposal_opt {
+            Some(value) => value,
             None => return Err(VoteError::NoSuchProposal),
+        };
+
+        if !editors::can_edit(&old_proposal, &caller) {
+            return Err(VoteError::access_rejected(Role::Editor, caller));
+        }
+
+        validation::validate(&proposal)?;
+
+        if !workflow::editing_allowed(key) {
+            return Err(VoteError::NotAllowedInCurrentStage);
         }
 
-        if old_proposal.owner != ic_cdk::caller() {
-            return Err(VoteError::AccessRejected);
+        // Once published, what voters are voting on stays as it was.
+        let is_draft: bool = listing::is_unpublished(old_proposal.listing);
+        if !is_draft
+            && (proposal.title != old_proposal.title
+                || proposal.description != old_proposal.description
+                || proposal.url != old_proposal.url
+                || proposal.payload_hash != old_proposal.payload_hash
+                || proposal.choice_labels != old_proposal.choice_labels)
+        {
+            return Err(VoteError::ProposalLocked);
         }
 
+        // Proposals inside a workflow open and close with their stages, drafts with their signatures
+        // (or their publication).
+        let is_active: bool = if workflow::is_managed(key)
+            || drafts::is_pending(key)
+            || dependencies::is_waiting(key)
+            || is_draft
+            || old_proposal.listing == listing::ListingStatus::Rejected
+        {
+            old_proposal.is_active
+        } else {
+            proposal.is_active
+        };
+
+        revisions::record(key, &old_proposal);
+
         let value: Proposal = Proposal {
+            title: proposal.title,
+            summary: proposal.summary,
+            url: proposal.url,
+            payload_hash: proposal.payload_hash,
             description: proposal.description,
             approve: old_proposal.approve,
             reject: old_proposal.reject,
             pass: old_proposal.pass,
-            is_active: proposal.is_active,
+            abstain: old_proposal.abstain,
+            abstain_voters: old_proposal.abstain_voters,
+            is_active,
             voted: old_proposal.voted,
             owner: old_proposal.owner,
+            results_embargo: proposal.results_embargo,
+            results_hidden_until: old_proposal.results_hidden_until,
+            category: old_proposal.category,
+            quorum: old_proposal.quorum,
+            snapshot: old_proposal.snapshot,
+            nft_gate: old_proposal.nft_gate,
+            sns_gate: old_proposal.sns_gate,
+            eligibility_root: old_proposal.eligibility_root,
+            execution: old_proposal.execution,
+            visibility: proposal.visibility,
+            decision_rule: old_proposal.decision_rule,
+            // Reopening voting makes the old result meaningless.
+            outcome: if is_active {
+                None
+            } else {
+                old_proposal.outcome
+            },
+            abstention: old_proposal.abstention,
+            voting_starts_at: old_proposal.voting_starts_at,
+            voting_ends_at: old_proposal.voting_ends_at,
+            compacted_voters: old_proposal.compacted_voters,
+            listing: old_proposal.listing,
+            depends_on: old_proposal.depends_on,
+            voter_list: old_proposal.voter_list,
+            editors: old_proposal.editors,
+            public_ballots: old_proposal.public_ballots,
+            allow_vote_changes: old_proposal.allow_vote_changes,
+            created_at: old_proposal.created_at,
+            updated_at: old_proposal.updated_at,
+            closed_at: if is_active {
+                None
+            } else {
+                old_proposal.closed_at
+            },
+            delegated: old_proposal.delegated,
+            quorum_percent: old_proposal.quorum_percent,
+            registered_voters: old_proposal.registered_voters,
+            survey: old_proposal.survey,
+            choice_labels: proposal.choice_labels,
+            results_visibility: old_proposal.results_visibility,
+            description_hash: None,
+            previous_round: old_proposal.previous_round,
+            role_weights: old_proposal.role_weights,
+            kind: old_proposal.kind,
+            members_only: old_proposal.members_only,
+            tie_break: old_proposal.tie_break,
+            voting_suspended_at: old_proposal.voting_suspended_at,
+            frontend_only: old_proposal.frontend_only,
+            personhood_gated: old_proposal.personhood_gated,
+            follows: old_proposal.follows,
+            weighting: old_proposal.weighting,
+            sealed_ballots: old_proposal.sealed_ballots,
         };
 
+        events::on_edited(key, &value);
         let res: Option<Proposal> = p.borrow_mut().insert(key, value);
+        changes::record_change(key);
 
         match res {
             Some(_) => Ok(()),
@@ -164,22 +1283,37 @@ fn edit_proposal(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
 
 #[ic_cdk::update]
 fn end_proposal(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
     PROPOSAL_MAP.with(|p| {
         let old_proposal_opt = p.borrow().get(&key);
-        let mut old_proposal: Proposal;
-
-        match old_proposal_opt {
-            Some(value) => old_proposal = value,
+        let mut old_proposal: Proposal = match old_proposal_opt {
+            Some(value) => value,
             None => return Err(VoteError::NoSuchProposal),
+        };
+
+        if !editors::can_edit(&old_proposal, &caller) {
+            return Err(VoteError::access_rejected(Role::Editor, caller));
         }
 
-        if old_proposal.owner != ic_cdk::caller() {
-            return Err(VoteError::AccessRejected);
+        // Voting opens at creation, or at the start of its window when that comes later.
+        if let Some(period) = config::min_voting_period() {
+            let opened_at: u64 = old_proposal
+                .voting_starts_at
+                .map_or(old_proposal.created_at, |start| {
+                    start.max(old_proposal.created_at)
+                });
+            let until: u64 = opened_at.saturating_add(period);
+
+            if ic_cdk::api::time() < until {
+                return Err(VoteError::VotingPeriodNotElapsed { until });
+            }
         }
 
-        old_proposal.is_active = false;
+        close(key, &mut old_proposal);
 
         let res: Option<Proposal> = p.borrow_mut().insert(key, old_proposal);
+        changes::record_change(key);
 
         match res {
             Some(_) => Ok(()),
@@ -188,37 +1322,421 @@ fn end_proposal(key: u64) -> Result<(), VoteError> {
     })
 }
 
+// Shared by `end_proposal` and the end of a voting window.
+fn close(key: u64, proposal: &mut Proposal) {
+    if ties::extend(key, proposal) {
+        return;
+    }
+
+    proposal.is_active = false;
+    proposal.closed_at = Some(ic_cdk::api::time());
+    workflow::stop(key);
+    window::remove(key);
+    reminders::remove(key);
+
+    if let Some(embargo) = proposal.results_embargo {
+        let until: u64 = ic_cdk::api::time().saturating_add(embargo);
+        proposal.results_hidden_until = Some(until);
+        embargo::schedule_publication(key, until);
+    }
+    decision::finalize(key, proposal);
+    gc::on_close(key);
+    execution::queue(key, proposal);
+    deposits::on_close(key, proposal);
+}
+
 #[ic_cdk::update]
-fn vote(key: u64, choice: Choice) -> Result<(), VoteError> {
+async fn vote(key: u64, choice: Choice) -> Result<receipts::VoteReceipt, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    vote_as(caller, key, choice).await
+}
+
+// Shared by every vote that has to ask another canister first.
+async fn vote_as(
+    caller: Principal,
+    key: u64,
+    choice: Choice,
+) -> Result<receipts::VoteReceipt, VoteError> {
+    let _guard = ballot_guard::claim(key, caller)?;
+
+    let holdings: Option<Holdings> = fetch_holdings(key, caller).await?;
+    cast_vote(caller, key, choice, holdings)
+}
+
+/*
+    Ballots are applied one by one, a failing ballot doesn't stop the others.
+    The result at index `i` belongs to the ballot at index `i`.
+*/
+#[ic_cdk::update]
+async fn vote_many(ballots: Vec<(u64, Choice)>) -> Vec<Result<receipts::VoteReceipt, VoteError>> {
+    if ballots.len() > MAX_BATCH_SIZE {
+        ic_cdk::trap("Too many ballots in one batch.");
+    }
+
+    // Every ballot costs a token, otherwise batching would be a way around the limit.
+    let checked: Result<Principal, VoteError> = authenticated_caller().and_then(|caller| {
+        rate_limit::consume(caller, ballots.len() as u64)?;
+        Ok(caller)
+    });
+
+    let caller: Principal = match checked {
+        Ok(caller) => caller,
+        Err(err) => return ballots.iter().map(|_| Err(err.clone())).collect(),
+    };
+
+    let mut results: Vec<Result<receipts::VoteReceipt, VoteError>> =
+        Vec::with_capacity(ballots.len());
+
+    for (key, choice) in ballots {
+        results.push(vote_as(caller, key, choice).await);
+    }
+
+    results
+}
+
+/*
+    Voting power that lives in another canister (NFTs, SNS neurons) has to be looked up
+    before the ballot can be applied. `None` means the proposal doesn't need anything from outside.
+*/
+enum Holdings {
+    Nfts(Vec<Nat>),
+    Neurons(Vec<sns::Neuron>),
+    // Brought by the voter, see `merkle::vote_with_proof`.
+    Proven { weight: u64, proof: Vec<Vec<u8>> },
+}
+
+async fn fetch_holdings(key: u64, caller: Principal) -> Result<Option<Holdings>, VoteError> {
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    // Checked before any outside call, so nobody learns anything about a proposal they can't see.
+    if !visibility::can_see(&proposal, &caller) {
+        return Err(VoteError::NoSuchProposal);
+    }
+    voter_list::check(key, &proposal, &caller)?;
+
+    if proposal.personhood_gated {
+        personhood::verify(caller).await?;
+    }
+
+    if let Some(gate) = &proposal.nft_gate {
+        let tokens: Vec<Nat> = nft_gate::owned_tokens(gate, caller).await?;
+        return Ok(Some(Holdings::Nfts(tokens)));
+    }
+
+    if let Some(gate) = &proposal.sns_gate {
+        let neurons: Vec<sns::Neuron> = sns::neurons_of(gate, caller).await?;
+        return Ok(Some(Holdings::Neurons(neurons)));
+    }
+
+    Ok(None)
+}
+
+// Who may vote on the proposal at all, for plain and sealed ballots alike.
+fn check_voter(key: u64, proposal: &Proposal, caller: &Principal) -> Result<(), VoteError> {
+    bans::check(caller)?;
+
+    if proposal.survey.is_some() {
+        return Err(VoteError::SurveyProposal);
+    }
+
+    // The access list may have changed while the holdings were fetched.
+    if !visibility::can_see(proposal, caller) {
+        return Err(VoteError::NoSuchProposal);
+    }
+    voter_list::check(key, proposal, caller)?;
+    privacy::check(key, caller)?;
+    attestation::check(proposal, caller)
+}
+
+fn check_open(key: u64, proposal: &Proposal) -> Result<(), VoteError> {
+    if !proposal.is_active || !workflow::voting_allowed(key) || drafts::is_pending(key) {
+        return Err(VoteError::not_active(proposal));
+    }
+
+    if proposal.listing == listing::ListingStatus::Suspended {
+        return Err(VoteError::ProposalSuspended);
+    }
+    window::check(proposal)
+}
+
+// The weight of a first ballot. Claims the NFTs or neurons behind it, so it's only called once the ballot is going in.
+fn weight_of(
+    key: u64,
+    proposal: &Proposal,
+    caller: Principal,
+    holdings: &Option<Holdings>,
+) -> Result<u64, VoteError> {
+    if proposal.nft_gate.is_some() {
+        let tokens: &[Nat] = match holdings {
+            Some(Holdings::Nfts(tokens)) => tokens,
+            _ => &[],
+        };
+        nft_gate::claim(key, proposal, caller, tokens)
+    } else if proposal.sns_gate.is_some() {
+        let neurons: &[sns::Neuron] = match holdings {
+            Some(Holdings::Neurons(neurons)) => neurons,
+            _ => &[],
+        };
+        sns::claim(key, proposal, caller, neurons)
+    } else if let Some(root) = &proposal.eligibility_root {
+        match holdings {
+            Some(Holdings::Proven { weight, proof }) => {
+                merkle::verify(root, &caller, *weight, proof)?;
+                Ok(*weight)
+            }
+            _ => Err(VoteError::NotEligible),
+        }
+    } else if let Some(schedule) = &proposal.role_weights {
+        Ok(roles::weight_of(schedule, &caller))
+    } else {
+        match snapshot::weight_of(key, proposal, &caller) {
+            Some(weight) => Ok(weighting::effective(proposal, weight)),
+            None => Err(VoteError::NotEligible),
+        }
+    }
+}
+
+fn add_to_counts(proposal: &mut Proposal, choice: Choice, weight: u64) {
+    match choice {
+        Choice::Approve => proposal.approve = proposal.approve.saturating_add(weight),
+        Choice::Reject => proposal.reject = proposal.reject.saturating_add(weight),
+        Choice::Pass => proposal.pass = proposal.pass.saturating_add(weight),
+        Choice::Abstain => {
+            proposal.abstain = proposal.abstain.saturating_add(weight);
+            proposal.abstain_voters += 1;
+        }
+    }
+}
+
+/*
+    `holdings` is what `fetch_holdings` found for the caller.
+    Everything from here on is synchronous, so the checks and the tally update can't interleave with other calls.
+*/
+fn cast_vote(
+    caller: Principal,
+    key: u64,
+    choice: Choice,
+    holdings: Option<Holdings>,
+) -> Result<receipts::VoteReceipt, VoteError> {
     PROPOSAL_MAP.with(|p| {
         let proposal_opt: Option<Proposal> = p.borrow().get(&key);
-        let mut proposal: Proposal;
-
-        match proposal_opt {
-            Some(value) => proposal = value,
+        let mut proposal: Proposal = match proposal_opt {
+            Some(value) => value,
             None => return Err(VoteError::NoSuchProposal),
+        };
+
+        check_voter(key, &proposal, &caller)?;
+        salts::check_ready()?;
+
+        if proposal.sealed_ballots {
+            return Err(VoteError::BallotsAreSealed);
         }
 
-        let caller: Principal = ic_cdk::caller();
+        // A changed vote keeps the weight it was cast with, the holdings behind it are already used.
+        let previous: Option<ballots::BallotRecord> = if proposal.voted.contains(&caller) {
+            match ballots::get(key, caller) {
+                Some(ballot) if proposal.allow_vote_changes && ballot.choice != choice => {
+                    Some(ballot)
+                }
+                ballot => {
+                    return Err(VoteError::AlreadyVoted {
+                        at: ballot.map(|ballot| ballot.timestamp),
+                    })
+                }
+            }
+        } else {
+            None
+        };
 
-        if proposal.voted.contains(&caller) {
-            return Err(VoteError::AlreadyVoted);
-        } else if proposal.is_active != true {
-            return Err(VoteError::ProposalIsNotActive);
+        if matches!(choice, Choice::Abstain) && !proposal.abstention.allowed {
+            return Err(VoteError::AbstainNotAllowed);
         }
+        check_open(key, &proposal)?;
 
-        match choice {
-            Choice::Approve => proposal.approve += 1,
-            Choice::Pass => proposal.pass -= 1,
-            Choice::Reject => proposal.reject += 1,
+        let weight: u64 = match &previous {
+            Some(ballot) => ballot.weight,
+            None => weight_of(key, &proposal, caller, &holdings)?,
+        };
+
+        if let Some(ballot) = &previous {
+            match ballot.choice {
+                Choice::Approve => proposal.approve = proposal.approve.saturating_sub(weight),
+                Choice::Reject => proposal.reject = proposal.reject.saturating_sub(weight),
+                Choice::Pass => proposal.pass = proposal.pass.saturating_sub(weight),
+                Choice::Abstain => {
+                    proposal.abstain = proposal.abstain.saturating_sub(weight);
+                    proposal.abstain_voters = proposal.abstain_voters.saturating_sub(1);
+                }
+            }
         }
 
-        proposal.voted.push(caller);
+        add_to_counts(&mut proposal, choice, weight);
+
+        if previous.is_none() {
+            proposal.voted.push(caller);
+            participation::on_vote(caller);
+        }
+        let now: u64 = ic_cdk::api::time();
+        ballots::record(caller, key, choice, weight, now);
+        ballot_chain::append(key, caller, choice, now);
+        deposits::on_vote(key, &proposal);
+        events::on_voted(key, &proposal, caller);
+        early_close::check(key, &mut proposal);
         let res: Option<Proposal> = p.borrow_mut().insert(key, proposal);
+        changes::record_change(key);
 
         match res {
-            Some(_) => Ok(()),
+            Some(_) => Ok(receipts::issue(key, caller, choice)),
             None => Err(VoteError::UpdateError),
         }
     })
 }
+
+/*
+    Deleting is only allowed while nobody has voted yet, otherwise we would be
+    throwing away somebody's ballot. The admin can still force it (e.g. for spam).
+    Either way the removed proposal is kept as a tombstone in the audit log.
+*/
+#[ic_cdk::update]
+fn delete_proposal(key: u64, force: bool) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    let caller_is_admin: bool = config::is_admin(&caller);
+
+    if proposal.owner != caller && !caller_is_admin {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    if proposal.voter_count() != 0 {
+        if !force {
+            return Err(VoteError::ProposalHasVotes);
+        } else if !caller_is_admin {
+            return Err(VoteError::access_rejected(Role::Admin, caller));
+        }
+    }
+
+    PROPOSAL_MAP.with(|p| p.borrow_mut().remove(&key));
+    events::on_deleted(key, &proposal);
+    snapshot::remove(key);
+    nft_gate::remove(key);
+    sns::remove(key);
+    execution::remove(key);
+    upgrade::remove(key);
+    // Deleting your own proposal before anybody voted is fine, a forced delete is for spam.
+    deposits::release(key, !force);
+    drafts::remove(key);
+    revisions::remove(key);
+    attachments::remove(key);
+    window::remove(key);
+    reminders::remove(key);
+    workflow::remove(key);
+    committee::remove(key);
+    signed_results::remove(key);
+    reports::remove(key);
+    ballot_chain::remove(key);
+    survey::remove(key);
+    preflight::remove(key);
+    watch::remove(key);
+    ties::remove(key);
+    abuse::remove(key);
+    bridge::remove(key);
+    gc::remove(key);
+    tally_history::remove(key);
+    followups::remove(key);
+    sealed_ballots::remove(key);
+    ownership::remove(key);
+    dependencies::remove(key, &proposal.depends_on);
+    voter_list::remove(key);
+    invites::remove(key);
+    ballots::remove(key);
+    privacy::remove(key);
+    changes::record_change(key);
+    audit::record(
+        caller,
+        key,
+        audit::AuditEvent::ProposalDeleted {
+            tombstone: Box::new(proposal),
+            forced: force,
+            full_hash: None,
+        },
+    );
+
+    Ok(())
+}
+
+/*
+    Generates the candid interface from the endpoint signatures, see `scripts/generate-candid.sh`.
+    The macro looks every type up by name from here, so the ones the modules use unqualified are imported.
+*/
+use abuse::{AbuseReport, ReportedProposal};
+use api_keys::{ApiKey, IssuedApiKey};
+use attachments::{
+    Attachment, HttpRequest, HttpResponse, StreamingCallbackResponse, StreamingToken,
+};
+use attestation::SessionAttestation;
+use audit::AuditRecord;
+use backup::StateChunk;
+use ballot_chain::{ChainEntry, ChainHeadVerification};
+use ballots::{Ballot, BallotRecord};
+use bans::Ban;
+#[cfg(feature = "bench")]
+use bench::BenchmarkReport;
+use block_log::{
+    ArchiveInfo, DataCertificate, GetArchivesArgs, GetBlocksArgs, GetBlocksResult,
+    SupportedBlockType,
+};
+use bridge::Submission;
+use changes::Changes;
+use committee::Committee;
+use config::{CanisterConfig, ConfigUpdate};
+use consent::{ConsentError, ConsentInfo, ConsentMessageRequest, StandardRecord};
+use dashboard::Dashboard;
+use delegation::{Delegation, VoteBreakdown};
+use deposits::Deposit;
+use drafts::Draft;
+use events::Event;
+use execution::{ExecutionOutcome, ExecutionState};
+use export::{ExportChunk, ExportFormat};
+use followups::FollowUp;
+use listing::ListFilter;
+use membership::Member;
+use outbox::PendingCall;
+use ownership::Reassignment;
+use participation::VoterStats;
+use pause::PauseState;
+use preflight::SimulationResult;
+use privacy::PurgeSummary;
+use receipts::{ReceiptVerification, VoteReceipt};
+use reports::{FinalReport, ResultProof};
+use revisions::Revision;
+use roles::RoleWeight;
+use rounds::NewWindow;
+use schedule::{Recurrence, ScheduledProposal};
+use sealed_ballots::Unsealing;
+use sharding::Shard;
+use signed_ballots::SignedBallot;
+use signed_results::SignedResult;
+use sorting::SortBy;
+use staking::Stake;
+use stats::Stats;
+use survey::{Answer, SurveyResults};
+use tally::Tally;
+use tally_history::TallyPoint;
+use ties::TieBreakPath;
+use treasury::TransferRecord;
+use upgrade::WasmUpload;
+use watch::Notification;
+use window::VotingWindow;
+use workflow::{StageStatus, WorkflowStage};
+
+ic_cdk::export_candid!();
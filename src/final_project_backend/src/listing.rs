@@ -0,0 +1,132 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::{
+    audit, authenticated_caller, changes, config, editors, moderation, open, Proposal, Role,
+    VoteError, PROPOSAL_MAP,
+};
+
+/*
+    Hiding is a soft delete: the proposal keeps its votes and can still be fetched by key,
+    it just doesn't show up in `list_proposals` unless the filter asks for hidden ones.
+    Drafts are left out the same way until they're published, voting is closed until then.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default, CandidType, Deserialize, Serialize)]
+pub(crate) enum ListingStatus {
+    #[default]
+    Listed,
+    Hidden,
+    Draft,
+    PendingReview, // Waiting for a moderator, see `moderation`.
+    Rejected,      // Turned down by a moderator, it never opens.
+    Suspended,     // Reported too often, see `abuse`.
+}
+
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub(crate) struct ListFilter {
+    status: Option<ListingStatus>, // Listed when not set.
+    created_after: Option<u64>,    // Nanoseconds, like the timestamps on the proposal.
+    created_before: Option<u64>,
+    updated_after: Option<u64>,
+}
+
+// Not open to voters yet, the content can still change.
+pub(crate) fn is_unpublished(status: ListingStatus) -> bool {
+    matches!(status, ListingStatus::Draft | ListingStatus::PendingReview)
+}
+
+pub(crate) fn matches(filter: &ListFilter, proposal: &Proposal) -> bool {
+    proposal.listing == filter.status.unwrap_or_default()
+        && filter.created_after.is_none_or(|t| proposal.created_at > t)
+        && filter
+            .created_before
+            .is_none_or(|t| proposal.created_at < t)
+        && filter.updated_after.is_none_or(|t| proposal.updated_at > t)
+}
+
+fn set_status(key: u64, status: ListingStatus) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.owner != caller && !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    // Nothing to do, and nothing to put in the audit log either.
+    if proposal.listing == status {
+        return Ok(());
+    }
+
+    // Restoring a draft would skip `publish_proposal`, restoring a submission the review.
+    match proposal.listing {
+        ListingStatus::Draft => return Err(VoteError::ProposalIsDraft),
+        ListingStatus::PendingReview | ListingStatus::Rejected => {
+            return Err(VoteError::NotApproved)
+        }
+        ListingStatus::Suspended => return Err(VoteError::ProposalSuspended),
+        _ => {}
+    }
+
+    proposal.listing = status;
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+
+    let event: audit::AuditEvent = if status == ListingStatus::Hidden {
+        audit::AuditEvent::ProposalHidden
+    } else {
+        audit::AuditEvent::ProposalRestored
+    };
+    audit::record(caller, key, event);
+    Ok(())
+}
+
+/*
+    Opens voting on a draft (workflows, dependencies and the voting window still have their say).
+    From here on the title, description, url and payload hash can't be edited anymore.
+*/
+#[ic_cdk::update]
+fn publish_proposal(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !editors::can_edit(&proposal, &caller) {
+        return Err(VoteError::access_rejected(Role::Editor, caller));
+    }
+
+    if proposal.listing != ListingStatus::Draft {
+        return Err(VoteError::NotADraft);
+    }
+
+    // With reviews on, publishing hands the draft to the moderators instead.
+    if moderation::needs_review(&caller) {
+        proposal.listing = ListingStatus::PendingReview;
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+        changes::record_change(key);
+        return Ok(());
+    }
+
+    proposal.listing = ListingStatus::Listed;
+    proposal.is_active = true;
+    open(key, &mut proposal);
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn archive_proposal(key: u64) -> Result<(), VoteError> {
+    set_status(key, ListingStatus::Hidden)
+}
+
+#[ic_cdk::update]
+fn restore_proposal(key: u64) -> Result<(), VoteError> {
+    set_status(key, ListingStatus::Listed)
+}
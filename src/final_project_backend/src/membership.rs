@@ -0,0 +1,177 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, dependencies, execution, execution::ExecutionPayload, get_memory,
+    roles, Memory, Proposal, Role, StorablePrincipal, VoteError, MAX_BATCH_SIZE, MEMBER_MEMORY_ID,
+};
+
+const MAX_MEMBERSHIP_CHANGES: usize = 100;
+
+/*
+    The voter registry. Proposals created with `members_only` take votes from members only,
+    and who is a member is decided by `MembershipChange` proposals: once one passes its
+    changes apply right away, there is no timelock on who may vote. The admin only seeds
+    the registry while it's empty, after that it's governed by votes alone.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) enum MembershipChange {
+    Add {
+        member: Principal,
+        role: Option<String>,
+    },
+    Remove {
+        member: Principal, // Loses their role as well.
+    },
+    SetRole {
+        member: Principal,
+        role: Option<String>, // `None` takes the role away.
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Member {
+    joined_at: u64,
+    proposal_key: Option<u64>, // The proposal that added them, `None` for the seed members.
+}
+
+impl Storable for Member {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Member {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static MEMBERS: RefCell<StableBTreeMap<StorablePrincipal, Member, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(MEMBER_MEMORY_ID)));
+}
+
+pub(crate) fn is_member(principal: &Principal) -> bool {
+    MEMBERS.with(|m| m.borrow().contains_key(&StorablePrincipal(*principal)))
+}
+
+pub(crate) fn validate(changes: &[MembershipChange]) -> Result<(), VoteError> {
+    if changes.is_empty() || changes.len() > MAX_MEMBERSHIP_CHANGES {
+        return Err(VoteError::InvalidExecutionPayload);
+    }
+
+    for change in changes {
+        match change {
+            MembershipChange::Add {
+                role: Some(role), ..
+            }
+            | MembershipChange::SetRole {
+                role: Some(role), ..
+            } => roles::check_role(role)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// In the order given. Changing the role of somebody who isn't a member makes them one.
+pub(crate) fn apply(key: u64, changes: &[MembershipChange]) {
+    let joined = || Member {
+        joined_at: ic_cdk::api::time(),
+        proposal_key: Some(key),
+    };
+
+    for change in changes {
+        match change {
+            MembershipChange::Add { member, role } | MembershipChange::SetRole { member, role } => {
+                if !is_member(member) {
+                    MEMBERS.with(|m| m.borrow_mut().insert(StorablePrincipal(*member), joined()));
+                }
+                roles::assign(*member, role.clone());
+            }
+            MembershipChange::Remove { member } => {
+                MEMBERS.with(|m| m.borrow_mut().remove(&StorablePrincipal(*member)));
+                roles::assign(*member, None);
+            }
+        }
+    }
+}
+
+/*
+    Called by `decision::finalize` for proposals that passed. A proposal that closes again
+    doesn't apply twice, and one whose dependencies haven't passed waits in the timelock
+    like any other payload.
+*/
+pub(crate) fn on_passed(key: u64, proposal: &Proposal) {
+    let changes: &[MembershipChange] = match &proposal.execution {
+        Some(ExecutionPayload::Membership(changes)) => changes,
+        _ => return,
+    };
+
+    if execution::has_run(key) || !dependencies::all_passed(&proposal.depends_on) {
+        return;
+    }
+
+    apply(key, changes);
+    execution::record_applied(key);
+}
+
+// Only while the registry is empty, to get the first members in.
+#[ic_cdk::update]
+fn seed_members(members: Vec<Principal>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    if MEMBERS.with(|m| !m.borrow().is_empty()) {
+        return Err(VoteError::RegistryAlreadySeeded);
+    }
+
+    if members.is_empty() || members.len() > MAX_MEMBERSHIP_CHANGES {
+        return Err(VoteError::InvalidExecutionPayload);
+    }
+
+    MEMBERS.with(|m| {
+        let mut map = m.borrow_mut();
+
+        for member in members {
+            let seeded: Member = Member {
+                joined_at: ic_cdk::api::time(),
+                proposal_key: None,
+            };
+            map.insert(StorablePrincipal(member), seeded);
+        }
+    });
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_member(principal: Principal) -> Option<Member> {
+    MEMBERS.with(|m| m.borrow().get(&StorablePrincipal(principal)))
+}
+
+// In principal order, `start` excluded so the last principal of a page can be passed on.
+#[ic_cdk::query]
+fn list_members(start: Option<Principal>, limit: u64) -> Vec<(Principal, Member)> {
+    MEMBERS.with(|m| {
+        let map = m.borrow();
+        let page = match start {
+            Some(start) => map.range(StorablePrincipal(start)..),
+            None => map.range(..),
+        };
+
+        page.filter(|(member, _)| Some(member.0) != start)
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|(member, details)| (member.0, details))
+            .collect()
+    })
+}
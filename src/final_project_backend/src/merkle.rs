@@ -0,0 +1,210 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    authenticated_caller, ballot_guard, cast_vote, personhood, rate_limit, receipts, visibility,
+    Choice, Holdings, VoteError, PROPOSAL_MAP,
+};
+
+// A tree this deep already holds more voters than there are principals in use.
+const MAX_PROOF_LEN: usize = 64;
+
+/*
+    Instead of uploading every eligible voter, the owner commits to a Merkle tree of
+    (voter, weight) leaves and each voter brings their own inclusion proof.
+    Leaves are SHA-256(0x00 || principal length || principal || weight as big endian u64),
+    inner nodes SHA-256(0x01 || smaller child || larger child), so a proof is just the siblings.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct EligibilityRoot {
+    root: Vec<u8>,
+    // Only for the results page, the canister can't check them against the tree.
+    pub(crate) voter_count: u64,
+    total_weight: u64,
+}
+
+pub(crate) fn validate(root: &EligibilityRoot) -> Result<(), VoteError> {
+    if root.root.len() != 32 {
+        return Err(VoteError::InvalidProof);
+    }
+
+    Ok(())
+}
+
+fn leaf(voter: &Principal, weight: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update([voter.as_slice().len() as u8]);
+    hasher.update(voter.as_slice());
+    hasher.update(weight.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn node(a: &[u8], b: &[u8]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(low);
+    hasher.update(high);
+    hasher.finalize().into()
+}
+
+pub(crate) fn verify(
+    root: &EligibilityRoot,
+    voter: &Principal,
+    weight: u64,
+    proof: &[Vec<u8>],
+) -> Result<(), VoteError> {
+    if proof.len() > MAX_PROOF_LEN || proof.iter().any(|sibling| sibling.len() != 32) {
+        return Err(VoteError::InvalidProof);
+    }
+
+    let computed: [u8; 32] = proof
+        .iter()
+        .fold(leaf(voter, weight), |acc, sibling| node(&acc, sibling));
+
+    if computed.as_slice() != root.root.as_slice() || weight == 0 {
+        return Err(VoteError::NotEligible);
+    }
+
+    Ok(())
+}
+
+// The vote of proposals with an eligibility root. `proof` are the siblings from the leaf up.
+#[ic_cdk::update]
+async fn vote_with_proof(
+    key: u64,
+    choice: Choice,
+    weight: u64,
+    proof: Vec<Vec<u8>>,
+) -> Result<receipts::VoteReceipt, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+    let _guard = ballot_guard::claim(key, caller)?;
+
+    // The proof needs nothing from outside, a personhood gate on top of it does.
+    let gated: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| {
+            proposal.personhood_gated && visibility::can_see(&proposal, &caller)
+        });
+    if gated {
+        personhood::verify(caller).await?;
+    }
+
+    cast_vote(
+        caller,
+        key,
+        choice,
+        Some(Holdings::Proven { weight, proof }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+        A tree of four voters, the principals [1] to [4] with the weights 10 to 40,
+        hashed outside this crate as described above:
+
+                      root
+                 n01        n23
+               l0   l1    l2   l3
+    */
+    const LEAF_0: &str = "e6e1846bb72ca167302cb5f783c6a7c68a2617eccd027ee5b8cac3219195efdc";
+    const LEAF_1: &str = "bba9ae762e426ef630579bd60068a9fe7095cb1133435b9ee78e4539564af93b";
+    const LEAF_2: &str = "53021338a6d688262461d152aa3d9328a57ad8fab5798bdf584fc1a808653503";
+    const LEAF_3: &str = "5e7fd2c4d1ca6b3efc70bc5bfd0084d2577321f025d53b56a45b2ccf06b8ace3";
+    const NODE_01: &str = "4163b13f55e437ccd5a798b0b16e13c2a3d27f2b8d411dcb9ff27d86eb632d49";
+    const NODE_23: &str = "fea9b2509efcb1e983156b31400f76f256b3dc57e6004e51ef319dac78659869";
+    const ROOT: &str = "8c2fb0a21cc18b96f8c5b03bf9b812a34eafe3efa48e83dc99c4e8f534fefd30";
+
+    fn root() -> EligibilityRoot {
+        EligibilityRoot {
+            root: hex::decode(ROOT).unwrap(),
+            voter_count: 4,
+            total_weight: 100,
+        }
+    }
+
+    fn voter(index: u8) -> Principal {
+        Principal::from_slice(&[index + 1])
+    }
+
+    fn proof(siblings: &[&str]) -> Vec<Vec<u8>> {
+        siblings.iter().map(|s| hex::decode(s).unwrap()).collect()
+    }
+
+    #[test]
+    fn hashes_leaves_and_nodes() {
+        assert_eq!(hex::encode(leaf(&voter(0), 10)), LEAF_0);
+        assert_eq!(hex::encode(leaf(&voter(3), 40)), LEAF_3);
+
+        let (l0, l1) = (hex::decode(LEAF_0).unwrap(), hex::decode(LEAF_1).unwrap());
+        assert_eq!(hex::encode(node(&l0, &l1)), NODE_01);
+        // Children are sorted before hashing, which side a sibling is on doesn't matter.
+        assert_eq!(hex::encode(node(&l1, &l0)), NODE_01);
+    }
+
+    #[test]
+    fn accepts_valid_proofs() {
+        let cases: [(u8, u64, [&str; 2]); 4] = [
+            (0, 10, [LEAF_1, NODE_23]),
+            (1, 20, [LEAF_0, NODE_23]),
+            (2, 30, [LEAF_3, NODE_01]),
+            (3, 40, [LEAF_2, NODE_01]),
+        ];
+
+        for (index, weight, siblings) in cases {
+            assert!(verify(&root(), &voter(index), weight, &proof(&siblings)).is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_proofs() {
+        let siblings: Vec<Vec<u8>> = proof(&[LEAF_1, NODE_23]);
+
+        // Another weight, another voter, siblings from the top down, a level missing.
+        assert!(matches!(
+            verify(&root(), &voter(0), 11, &siblings),
+            Err(VoteError::NotEligible)
+        ));
+        assert!(matches!(
+            verify(&root(), &voter(1), 10, &siblings),
+            Err(VoteError::NotEligible)
+        ));
+        assert!(matches!(
+            verify(&root(), &voter(0), 10, &proof(&[NODE_23, LEAF_1])),
+            Err(VoteError::NotEligible)
+        ));
+        assert!(matches!(
+            verify(&root(), &voter(0), 10, &proof(&[LEAF_1])),
+            Err(VoteError::NotEligible)
+        ));
+
+        let mut short: Vec<Vec<u8>> = siblings.clone();
+        short[1].pop();
+        assert!(matches!(
+            verify(&root(), &voter(0), 10, &short),
+            Err(VoteError::InvalidProof)
+        ));
+
+        let too_long: Vec<Vec<u8>> = vec![vec![0; 32]; MAX_PROOF_LEN + 1];
+        assert!(matches!(
+            verify(&root(), &voter(0), 10, &too_long),
+            Err(VoteError::InvalidProof)
+        ));
+    }
+
+    #[test]
+    fn root_must_be_a_hash() {
+        assert!(validate(&root()).is_ok());
+
+        let mut truncated: EligibilityRoot = root();
+        truncated.root.pop();
+        assert!(validate(&truncated).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt::Write;
+
+use crate::{events, stats};
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const MINUTE: u64 = 60 * 1_000_000_000;
+
+/*
+    `/metrics` in the Prometheus text format (https://prometheus.io/docs/instrumenting/exposition_formats/),
+    served by `http_request` so any scraper pointed at the canister's raw URL can read it.
+    Only counts, nothing that a restricted proposal would give away.
+*/
+pub(crate) fn render() -> String {
+    let mut out: String = String::new();
+
+    gauge(
+        &mut out,
+        "icp_vote_cycles_balance",
+        "Cycles held by the canister.",
+        &[("", ic_cdk::api::canister_balance128())],
+    );
+    gauge(
+        &mut out,
+        "icp_vote_stable_memory_bytes",
+        "Size of the stable memory.",
+        &[(
+            "",
+            (ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE) as u128,
+        )],
+    );
+
+    let by_status: Vec<(&str, u128)> = stats::by_status()
+        .into_iter()
+        .map(|(status, count)| (status, count as u128))
+        .collect();
+    gauge(
+        &mut out,
+        "icp_vote_proposals",
+        "Proposals by status, closed includes passed and rejected.",
+        &by_status,
+    );
+
+    let since: u64 = ic_cdk::api::time().saturating_sub(MINUTE);
+    gauge(
+        &mut out,
+        "icp_vote_votes_per_minute",
+        "Votes cast during the last minute.",
+        &[("", events::votes_since(since) as u128)],
+    );
+
+    out
+}
+
+// An empty label leaves the sample without labels.
+fn gauge(out: &mut String, name: &str, help: &str, samples: &[(&str, u128)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+
+    for (status, value) in samples {
+        if status.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{status=\"{}\"}} {}", name, status, value);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use candid::Principal;
+
+use crate::{
+    audit, authenticated_caller, changes, config, deposits, listing::ListingStatus, open, Proposal,
+    Role, VoteError, PROPOSAL_MAP,
+};
+
+const MAX_REASON_LEN: usize = 500;
+
+/*
+    With `require_review` set, a new proposal sits in `PendingReview` until a moderator
+    (or the admin) approves it. Proposals from moderators, and co-signed drafts
+    which the signers already looked at, don't wait.
+*/
+pub(crate) fn needs_review(author: &Principal) -> bool {
+    config::requires_review() && !config::is_moderator(author)
+}
+
+fn pending(key: u64, caller: &Principal) -> Result<Proposal, VoteError> {
+    if !config::is_moderator(caller) {
+        return Err(VoteError::access_rejected(Role::Moderator, *caller));
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.listing != ListingStatus::PendingReview {
+        return Err(VoteError::NotPendingReview);
+    }
+
+    Ok(proposal)
+}
+
+// Opens voting like `publish_proposal` does, workflows, dependencies and the voting window still have their say.
+#[ic_cdk::update]
+fn approve_submission(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    let mut proposal: Proposal = pending(key, &caller)?;
+
+    proposal.listing = ListingStatus::Listed;
+    proposal.is_active = true;
+    open(key, &mut proposal);
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+
+    audit::record(caller, key, audit::AuditEvent::SubmissionApproved);
+    Ok(())
+}
+
+// The reason goes into the audit log. The deposit is kept, keeping spam out is what it's for.
+#[ic_cdk::update]
+fn reject_submission(key: u64, reason: String) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if reason.is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(VoteError::InvalidReason);
+    }
+
+    let mut proposal: Proposal = pending(key, &caller)?;
+
+    proposal.listing = ListingStatus::Rejected;
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+    deposits::release(key, false);
+
+    audit::record(
+        caller,
+        key,
+        audit::AuditEvent::SubmissionRejected { reason },
+    );
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_stable_structures::{storable::Blob, StableBTreeMap};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::{
+    get_memory, icrc, Memory, Proposal, StorablePrincipal, VoteError, NFT_VOTES_MEMORY_ID,
+};
+
+// Nobody gets more than this many tokens counted, it also bounds the number of calls to the collection.
+const MAX_TOKENS_PER_VOTER: usize = 500;
+
+/*
+    NFT-gated proposals only accept votes from holders of a token in `collection`.
+    Every token can only be used once per proposal, otherwise one NFT could be
+    passed from wallet to wallet and vote again each time.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct NftGate {
+    collection: Principal,
+    one_vote_per_token: bool, // Weight the vote by the number of unused tokens instead of one vote per holder.
+}
+
+// Token ids are `nat`, 32 bytes are plenty for any id a collection hands out.
+type TokenKey = Blob<32>;
+
+thread_local! {
+    // (proposal key, token id) -> whoever voted with it.
+    static USED_TOKENS: RefCell<StableBTreeMap<(u64, TokenKey), StorablePrincipal, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(NFT_VOTES_MEMORY_ID)));
+}
+
+fn token_key(token: &Nat) -> Option<TokenKey> {
+    TokenKey::try_from(token.0.to_bytes_be().as_slice()).ok()
+}
+
+// The tokens `voter` holds in the gate's collection. This awaits, `claim` re-checks afterwards.
+pub(crate) async fn owned_tokens(gate: &NftGate, voter: Principal) -> Result<Vec<Nat>, VoteError> {
+    match icrc::tokens_of(
+        gate.collection,
+        icrc::Account::from(voter),
+        MAX_TOKENS_PER_VOTER,
+    )
+    .await
+    {
+        Ok(tokens) => Ok(tokens),
+        Err(_) => Err(VoteError::CollectionCallFailed),
+    }
+}
+
+/*
+    Marks every token of the voter that hasn't voted on this proposal yet as used
+    and returns the weight those tokens give. Has to run in the same message as the tally update.
+*/
+pub(crate) fn claim(
+    key: u64,
+    proposal: &Proposal,
+    voter: Principal,
+    tokens: &[Nat],
+) -> Result<u64, VoteError> {
+    let gate: &NftGate = match &proposal.nft_gate {
+        Some(gate) => gate,
+        None => return Ok(1),
+    };
+
+    USED_TOKENS.with(|u| {
+        let mut used = u.borrow_mut();
+
+        let unused: Vec<TokenKey> = tokens
+            .iter()
+            .filter_map(token_key)
+            .filter(|token| !used.contains_key(&(key, *token)))
+            .collect();
+
+        if unused.is_empty() {
+            return Err(VoteError::NotEligible);
+        }
+
+        let weight: u64 = if gate.one_vote_per_token {
+            unused.len() as u64
+        } else {
+            1
+        };
+
+        for token in unused {
+            used.insert((key, token), StorablePrincipal(voter));
+        }
+
+        Ok(weight)
+    })
+}
+
+pub(crate) fn remove(key: u64) {
+    USED_TOKENS.with(|u| {
+        let tokens: Vec<(u64, TokenKey)> = u
+            .borrow()
+            .range((key, TokenKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(k, _)| k)
+            .collect();
+
+        let mut used = u.borrow_mut();
+        for token in tokens {
+            used.remove(&token);
+        }
+    });
+}
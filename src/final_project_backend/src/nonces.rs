@@ -0,0 +1,89 @@
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use std::{cell::RefCell, collections::BTreeSet, time::Duration};
+
+use crate::{get_memory, timers, Memory, StorablePrincipal, VoteError, NONCE_MEMORY_ID};
+
+// Agents give up on a call after a few minutes, a day leaves plenty of room for retries.
+const NONCE_TTL: u64 = 24 * 3600 * 1_000_000_000;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+type Nonce = [u8; 32];
+type NonceId = (StorablePrincipal, Nonce);
+
+/*
+    A call that timed out in the agent may still have gone through. Creating with a
+    `client_nonce` makes the retry safe: the same caller with the same nonce gets the key
+    of the proposal the first call created, instead of a second proposal.
+*/
+thread_local! {
+    // (caller, nonce) -> (proposal key, created at).
+    static SEEN: RefCell<StableBTreeMap<NonceId, (u64, u64), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(NONCE_MEMORY_ID)));
+
+    // Nonces of creations that are still waiting for a ledger or another canister.
+    static IN_FLIGHT: RefCell<BTreeSet<(Principal, Nonce)>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Releases the nonce of a creation once it's done, failed or trapped.
+pub(crate) struct NonceGuard {
+    caller: Principal,
+    nonce: Nonce,
+}
+
+impl Drop for NonceGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|f| f.borrow_mut().remove(&(self.caller, self.nonce)));
+    }
+}
+
+// The key the nonce already created, if it did within the last day.
+pub(crate) fn replayed(caller: Principal, nonce: &Nonce) -> Option<u64> {
+    let (key, created_at) = SEEN.with(|s| s.borrow().get(&(StorablePrincipal(caller), *nonce)))?;
+
+    if ic_cdk::api::time().saturating_sub(created_at) >= NONCE_TTL {
+        return None;
+    }
+
+    Some(key)
+}
+
+// A retry that arrives while the first call is still running has nothing to return yet.
+pub(crate) fn claim(caller: Principal, nonce: Nonce) -> Result<NonceGuard, VoteError> {
+    if !IN_FLIGHT.with(|f| f.borrow_mut().insert((caller, nonce))) {
+        return Err(VoteError::RequestInProgress);
+    }
+
+    Ok(NonceGuard { caller, nonce })
+}
+
+pub(crate) fn record(caller: Principal, nonce: Nonce, key: u64) {
+    SEEN.with(|s| {
+        s.borrow_mut().insert(
+            (StorablePrincipal(caller), nonce),
+            (key, ic_cdk::api::time()),
+        )
+    });
+}
+
+fn prune() {
+    let now: u64 = ic_cdk::api::time();
+
+    let expired: Vec<NonceId> = SEEN.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, (_, created_at))| now.saturating_sub(*created_at) >= NONCE_TTL)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    SEEN.with(|s| {
+        let mut seen = s.borrow_mut();
+        for id in expired {
+            seen.remove(&id);
+        }
+    });
+}
+
+pub(crate) fn start_pruning() {
+    timers::set_timer_interval(PRUNE_INTERVAL, prune);
+}
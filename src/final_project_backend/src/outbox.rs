@@ -0,0 +1,261 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, time::Duration};
+
+use crate::{
+    authenticated_caller, bridge, ckbtc, config, deposits, execution, get_memory, icrc, timers,
+    Memory, Role, VoteError, OUTBOX_MEMORY_ID,
+};
+
+const FIRST_BACKOFF: u64 = 60 * 1_000_000_000; // A minute, doubled after every failed attempt.
+const MAX_BACKOFF: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_ATTEMPTS: u32 = 10; // Automatic ones, `retry_call` works after that as well.
+const MAX_ERROR_LEN: usize = 500;
+const MAX_NOTIFICATION_SIZE: usize = 2000; // Bigger notifications are dropped instead of kept.
+
+/*
+    Calls to other canisters that run in the background (executing a payload, settling a deposit,
+    refunding a fee, the bridge, notifications) end up here when they fail. A timer tries them again,
+    first after a minute, then with a doubling delay up to a day, `MAX_ATTEMPTS` times in all.
+    Each integration keeps its own state, a retry goes through the same code as the first attempt,
+    so anything that was settled by hand in the meantime counts as done.
+    Calls made while somebody waits for the answer (ledger balances, proofs of personhood)
+    aren't kept, the caller sees the error and tries again themselves.
+    A retried ledger transfer is sent with the `created_at_time` of the call and a memo
+    made of its id, so one whose reply was lost isn't paid a second time.
+*/
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+pub(crate) enum CallTarget {
+    Execution(u64),         // The payload of the proposal with this key.
+    DepositSettlement(u64), // The deposit with this id.
+    FeeRefund(ckbtc::PaidFee),
+    BridgeSubmission(u64), // The result of the proposal with this key.
+    // One way calls like `notify_reminder`, nothing is done with the reply.
+    Notification {
+        canister: Principal,
+        method: String,
+        arg: Vec<u8>, // Candid encoded.
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct PendingCall {
+    target: CallTarget,
+    attempts: u32,
+    last_error: String,
+    first_failed_at: u64,
+    next_attempt_at: Option<u64>, // `None` once the automatic retries ran out.
+    created_at_time: Option<u64>, // Sent with ledger transfers, `None` for calls kept before it.
+}
+
+impl Storable for PendingCall {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PendingCall {
+    const MAX_SIZE: u32 = 3000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Call id -> the failed call and when it's tried next.
+    static OUTBOX: RefCell<StableBTreeMap<u64, PendingCall, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(OUTBOX_MEMORY_ID)));
+
+    // Calls being tried right now, the timer and `retry_call` can't run one twice.
+    static IN_FLIGHT: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+fn backoff(attempts: u32) -> u64 {
+    FIRST_BACKOFF
+        .saturating_mul(
+            1u64.checked_shl(attempts.saturating_sub(1))
+                .unwrap_or(u64::MAX),
+        )
+        .min(MAX_BACKOFF)
+}
+
+fn find(target: &CallTarget) -> Option<u64> {
+    OUTBOX.with(|o| {
+        o.borrow()
+            .iter()
+            .find(|(_, call)| call.target == *target)
+            .map(|(id, _)| id)
+    })
+}
+
+/*
+    Called by the integrations when a background call fails. A call the outbox already has
+    is left alone, its retries update it themselves.
+*/
+pub(crate) fn record(target: CallTarget, error: &str) {
+    if let CallTarget::Notification { arg, .. } = &target {
+        if arg.len() > MAX_NOTIFICATION_SIZE {
+            return;
+        }
+    }
+
+    if find(&target).is_some() {
+        return;
+    }
+
+    let now: u64 = ic_cdk::api::time();
+    let next_attempt_at: u64 = now.saturating_add(backoff(1));
+    let call: PendingCall = PendingCall {
+        target,
+        attempts: 1,
+        last_error: error.chars().take(MAX_ERROR_LEN).collect(),
+        first_failed_at: now,
+        next_attempt_at: Some(next_attempt_at),
+        created_at_time: Some(now),
+    };
+
+    let id: u64 = OUTBOX.with(|o| {
+        let id: u64 = o.borrow().last_key_value().map_or(0, |(id, _)| id + 1);
+        o.borrow_mut().insert(id, call);
+        id
+    });
+    arm_timer(id, next_attempt_at);
+}
+
+// Called when a call the outbox may hold went through some other way, e.g. `execute_proposal`.
+pub(crate) fn resolve(target: &CallTarget) {
+    if let Some(id) = find(target) {
+        OUTBOX.with(|o| o.borrow_mut().remove(&id));
+    }
+}
+
+fn memo(id: u64) -> Vec<u8> {
+    [b"outbox:".as_slice(), &id.to_be_bytes()].concat()
+}
+
+/*
+    What a ledger transfer for `target` is sent with, the same on every retry.
+    `None` while the outbox doesn't hold the call, the first attempt goes out without.
+*/
+pub(crate) fn dedup(target: &CallTarget) -> Option<icrc::Dedup> {
+    let id: u64 = find(target)?;
+
+    OUTBOX.with(|o| {
+        let mut call: PendingCall = o.borrow().get(&id)?;
+        let created_at_time: u64 = match call.created_at_time {
+            Some(at) => at,
+            None => {
+                let now: u64 = ic_cdk::api::time();
+                call.created_at_time = Some(now);
+                o.borrow_mut().insert(id, call);
+                now
+            }
+        };
+
+        Some(icrc::Dedup {
+            created_at_time,
+            memo: memo(id),
+        })
+    })
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = OUTBOX.with(|o| {
+        o.borrow()
+            .iter()
+            .filter_map(|(id, call)| call.next_attempt_at.map(|at| (id, at)))
+            .collect()
+    });
+
+    for (id, at) in pending {
+        arm_timer(id, at);
+    }
+}
+
+fn arm_timer(id: u64, at: u64) {
+    let delay: u64 = at.saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || {
+        ic_cdk::spawn(async move {
+            // What became of it is in the outbox, nobody waits for it here.
+            let _ = attempt(id, Some(at)).await;
+        })
+    });
+}
+
+async fn dispatch(target: CallTarget) -> Result<(), String> {
+    match target {
+        CallTarget::Execution(key) => execution::retry(key).await,
+        CallTarget::DepositSettlement(id) => deposits::retry(id).await,
+        CallTarget::FeeRefund(fee) => ckbtc::send_refund(fee).await,
+        CallTarget::BridgeSubmission(key) => bridge::retry(key).await,
+        CallTarget::Notification {
+            canister,
+            method,
+            arg,
+        } => ic_cdk::api::call::call_raw(canister, &method, &arg, 0)
+            .await
+            .map(|_| ())
+            .map_err(|(code, message)| format!("{:?}: {}", code, message)),
+    }
+}
+
+// `due` is when the timer was armed for, a timer left over from an earlier attempt does nothing.
+async fn attempt(id: u64, due: Option<u64>) -> Result<(), VoteError> {
+    let call: PendingCall = match OUTBOX.with(|o| o.borrow().get(&id)) {
+        Some(value) if due.is_none() || value.next_attempt_at == due => value,
+        _ => return Err(VoteError::NoSuchCall), // Resolved or tried again in the meantime.
+    };
+
+    if !IN_FLIGHT.with(|i| i.borrow_mut().insert(id)) {
+        return Err(VoteError::CallInFlight);
+    }
+    let res: Result<(), String> = dispatch(call.target).await;
+    IN_FLIGHT.with(|i| i.borrow_mut().remove(&id));
+
+    // Re-read, it may have been resolved while the call was out.
+    let mut call: PendingCall = match OUTBOX.with(|o| o.borrow().get(&id)) {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    match res {
+        Ok(()) => {
+            OUTBOX.with(|o| o.borrow_mut().remove(&id));
+            Ok(())
+        }
+        Err(error) => {
+            call.attempts = call.attempts.saturating_add(1);
+            call.last_error = error.chars().take(MAX_ERROR_LEN).collect();
+            call.next_attempt_at = (call.attempts < MAX_ATTEMPTS)
+                .then(|| ic_cdk::api::time().saturating_add(backoff(call.attempts)));
+
+            if let Some(at) = call.next_attempt_at {
+                arm_timer(id, at);
+            }
+            OUTBOX.with(|o| o.borrow_mut().insert(id, call));
+            Err(VoteError::CallFailed)
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn list_pending_calls() -> Vec<(u64, PendingCall)> {
+    if !config::is_admin(&ic_cdk::caller()) {
+        return vec![];
+    }
+
+    OUTBOX.with(|o| o.borrow().iter().collect())
+}
+
+// Tries the call right away, also once the automatic retries have given up. The error is in `list_pending_calls`.
+#[ic_cdk::update]
+async fn retry_call(id: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    attempt(id, None).await
+}
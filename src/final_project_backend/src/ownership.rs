@@ -0,0 +1,187 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+use crate::{
+    audit, authenticated_caller, changes, config, get_memory, timers, visibility, Memory, Proposal,
+    Role, VoteError, PROPOSAL_MAP, REASSIGNMENT_MEMORY_ID,
+};
+
+const MAX_REASON_LEN: usize = 500;
+// Long enough for an owner who still has their key to notice and cancel.
+const TIMELOCK: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/*
+    An owner who lost their key leaves the proposal stuck, nobody else can end, edit or delete it.
+    The admin can hand it to somebody else, but not on the spot: the request says why,
+    goes into the audit log and takes effect after `TIMELOCK`. Until then the owner
+    (which shows the key isn't lost after all), the admin or a council member can cancel it.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Reassignment {
+    from: Principal,
+    to: Principal,
+    reason: String,
+    requested_by: Principal,
+    requested_at: u64,
+    effective_at: u64,
+}
+
+impl Storable for Reassignment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Reassignment {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> the pending reassignment. Needed to re-arm the timers after an upgrade.
+    static PENDING: RefCell<StableBTreeMap<u64, Reassignment, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(REASSIGNMENT_MEMORY_ID)));
+}
+
+pub(crate) fn remove(key: u64) {
+    PENDING.with(|p| p.borrow_mut().remove(&key));
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = PENDING.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(key, reassignment)| (key, reassignment.effective_at))
+            .collect()
+    });
+
+    for (key, at) in pending {
+        arm_timer(key, at);
+    }
+}
+
+fn arm_timer(key: u64, at: u64) {
+    let delay: u64 = at.saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || apply(key, at));
+}
+
+fn apply(key: u64, at: u64) {
+    // Cancelled, or cancelled and requested again with a timer of its own.
+    let reassignment: Reassignment = match PENDING.with(|p| p.borrow().get(&key)) {
+        Some(value) if value.effective_at == at => value,
+        _ => return,
+    };
+    remove(key);
+
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if value.owner == reassignment.from => value,
+        _ => return,
+    };
+
+    proposal.owner = reassignment.to;
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+
+    audit::record(
+        reassignment.requested_by,
+        key,
+        audit::AuditEvent::OwnerReassigned {
+            from: reassignment.from,
+            to: reassignment.to,
+            reason: reassignment.reason,
+        },
+    );
+}
+
+// Returns when the new owner takes over.
+#[ic_cdk::update]
+fn reassign_owner(key: u64, new_owner: Principal, reason: String) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    if reason.trim().is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(VoteError::InvalidReason);
+    }
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if new_owner == Principal::anonymous() || new_owner == proposal.owner {
+        return Err(VoteError::InvalidNewOwner);
+    }
+
+    if PENDING.with(|p| p.borrow().contains_key(&key)) {
+        return Err(VoteError::ReassignmentPending);
+    }
+
+    let now: u64 = ic_cdk::api::time();
+    let effective_at: u64 = now.saturating_add(TIMELOCK);
+
+    PENDING.with(|p| {
+        p.borrow_mut().insert(
+            key,
+            Reassignment {
+                from: proposal.owner,
+                to: new_owner,
+                reason: reason.clone(),
+                requested_by: caller,
+                requested_at: now,
+                effective_at,
+            },
+        )
+    });
+    arm_timer(key, effective_at);
+
+    audit::record(
+        caller,
+        key,
+        audit::AuditEvent::OwnerReassignmentRequested {
+            to: new_owner,
+            reason,
+            effective_at,
+        },
+    );
+    Ok(effective_at)
+}
+
+#[ic_cdk::update]
+fn cancel_reassignment(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let reassignment: Reassignment = match PENDING.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoReassignmentPending),
+    };
+
+    if caller != reassignment.from
+        && !config::is_admin(&caller)
+        && !config::is_council_member(&caller)
+    {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    remove(key);
+    audit::record(caller, key, audit::AuditEvent::OwnerReassignmentCancelled);
+    Ok(())
+}
+
+// Shown to everybody who can see the proposal, the owner is the one who has to notice it.
+#[ic_cdk::query]
+fn get_reassignment(key: u64) -> Option<Reassignment> {
+    let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+
+    if !visibility::can_see(&proposal, &ic_cdk::caller()) {
+        return None;
+    }
+
+    PENDING.with(|p| p.borrow().get(&key))
+}
@@ -0,0 +1,114 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    get_memory, Memory, StorablePrincipal, MAX_BATCH_SIZE, PARTICIPATION_MEMORY_ID,
+    PARTICIPATION_RANK_MEMORY_ID,
+};
+
+/*
+    How active everybody has been, for engagement dashboards. These are counts of what
+    happened: a vote on a proposal that got deleted later still counts.
+*/
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub(crate) struct VoterStats {
+    proposals_created: u64,
+    votes_cast: u64,
+    last_active: u64,
+}
+
+impl Storable for VoterStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for VoterStats {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static STATS: RefCell<StableBTreeMap<StorablePrincipal, VoterStats, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PARTICIPATION_MEMORY_ID)));
+
+    // (u64::MAX - votes cast, voter) -> (), so iterating in key order gives the most active voters first.
+    static RANKING: RefCell<StableBTreeMap<(u64, StorablePrincipal), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PARTICIPATION_RANK_MEMORY_ID)));
+}
+
+fn update(principal: Principal, f: impl FnOnce(&mut VoterStats)) {
+    let key: StorablePrincipal = StorablePrincipal(principal);
+    let mut stats: VoterStats = STATS.with(|s| s.borrow().get(&key)).unwrap_or_default();
+    let old_votes: u64 = stats.votes_cast;
+
+    f(&mut stats);
+    stats.last_active = ic_cdk::api::time();
+
+    if stats.votes_cast != old_votes {
+        RANKING.with(|r| {
+            let mut ranking = r.borrow_mut();
+            ranking.remove(&(u64::MAX - old_votes, key));
+            ranking.insert((u64::MAX - stats.votes_cast, key), ());
+        });
+    }
+    STATS.with(|s| s.borrow_mut().insert(key, stats));
+}
+
+pub(crate) fn on_proposal_created(principal: Principal) {
+    update(principal, |stats| stats.proposals_created += 1);
+}
+
+pub(crate) fn on_vote(principal: Principal) {
+    update(principal, |stats| stats.votes_cast += 1);
+}
+
+pub(crate) fn remove(principal: Principal) {
+    let key: StorablePrincipal = StorablePrincipal(principal);
+
+    if let Some(stats) = STATS.with(|s| s.borrow_mut().remove(&key)) {
+        RANKING.with(|r| r.borrow_mut().remove(&(u64::MAX - stats.votes_cast, key)));
+    }
+}
+
+// Everybody who ever created a proposal or voted, in principal order.
+pub(crate) fn known_voters(limit: usize) -> Vec<Principal> {
+    STATS.with(|s| {
+        s.borrow()
+            .iter()
+            .take(limit)
+            .map(|(voter, _)| voter.0)
+            .collect()
+    })
+}
+
+pub(crate) fn stats_of(principal: Principal) -> Option<VoterStats> {
+    STATS.with(|s| s.borrow().get(&StorablePrincipal(principal)))
+}
+
+#[ic_cdk::query]
+fn get_voter_stats(principal: Principal) -> Option<VoterStats> {
+    stats_of(principal)
+}
+
+// Most votes first, ties in principal order.
+#[ic_cdk::query]
+fn get_top_voters(limit: u64) -> Vec<(Principal, VoterStats)> {
+    let top: Vec<StorablePrincipal> = RANKING.with(|r| {
+        r.borrow()
+            .iter()
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|((_, voter), _)| voter)
+            .collect()
+    });
+
+    STATS.with(|s| {
+        let stats = s.borrow();
+        top.into_iter()
+            .filter_map(|voter| stats.get(&voter).map(|value| (voter.0, value)))
+            .collect()
+    })
+}
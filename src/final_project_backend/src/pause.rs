@@ -0,0 +1,83 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{backup, config, get_memory, Memory, Role, VoteError, PAUSE_MEMORY_ID};
+
+/*
+    A circuit breaker for incidents: while paused every update but `resume` is turned away
+    (most of them by `authenticated_caller`) and the timers wait, queries keep working.
+    `import_state` is left to the controllers, restoring a backup can be the way out of an
+    incident. It lives in stable memory, so an upgrade that ships the fix doesn't unpause
+    on its own.
+*/
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub(crate) struct PauseState {
+    paused_at: Option<u64>,
+    paused_by: Option<Principal>,
+}
+
+impl Storable for PauseState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static PAUSE: RefCell<StableCell<PauseState, Memory>> = RefCell::new(StableCell::init(get_memory(PAUSE_MEMORY_ID), PauseState::default()).unwrap());
+}
+
+pub(crate) fn check() -> Result<(), VoteError> {
+    if PAUSE.with(|p| p.borrow().get().paused_at.is_some()) {
+        return Err(VoteError::CanisterPaused);
+    }
+
+    Ok(())
+}
+
+// Can't go through `authenticated_caller`, that would lock the admin out of `resume`.
+fn check_admin() -> Result<Principal, VoteError> {
+    backup::check()?;
+    let caller: Principal = ic_cdk::caller();
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    Ok(caller)
+}
+
+fn set(state: PauseState) {
+    PAUSE.with(|p| p.borrow_mut().set(state).unwrap());
+}
+
+#[ic_cdk::update]
+fn pause() -> Result<(), VoteError> {
+    let caller: Principal = check_admin()?;
+
+    if check().is_err() {
+        return Ok(());
+    }
+
+    set(PauseState {
+        paused_at: Some(ic_cdk::api::time()),
+        paused_by: Some(caller),
+    });
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn resume() -> Result<(), VoteError> {
+    check_admin()?;
+    set(PauseState::default());
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_pause_state() -> PauseState {
+    PAUSE.with(|p| p.borrow().get().clone())
+}
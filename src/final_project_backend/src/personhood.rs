@@ -0,0 +1,50 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{config, VoteError};
+
+// Keeps a misconfigured method name from growing the config without bound.
+const MAX_METHOD_LEN: usize = 100;
+
+/*
+    Proposals created with `personhood_gated` only count ballots from principals a
+    proof-of-personhood provider vouches for, one human one vote. The provider is whatever
+    canister the config names, asked through `method : (principal) -> (bool)` on every such vote,
+    so a credential that was revoked stops counting right away.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct PersonhoodConfig {
+    provider: Principal,
+    method: String, // e.g. "has_valid_credential".
+}
+
+pub(crate) fn validate(personhood: &PersonhoodConfig) -> bool {
+    personhood.provider != Principal::anonymous()
+        && !personhood.method.is_empty()
+        && personhood.method.len() <= MAX_METHOD_LEN
+}
+
+// Called on creation, a gated proposal needs a provider to ask.
+pub(crate) fn check_available(gated: bool) -> Result<(), VoteError> {
+    if gated && config::personhood().is_none() {
+        return Err(VoteError::PersonhoodNotConfigured);
+    }
+
+    Ok(())
+}
+
+// Asks the provider about `voter`. This awaits, it runs before the ballot is applied.
+pub(crate) async fn verify(voter: Principal) -> Result<(), VoteError> {
+    let personhood: PersonhoodConfig = match config::personhood() {
+        Some(value) => value,
+        None => return Err(VoteError::PersonhoodNotConfigured),
+    };
+
+    let res: Result<(bool,), _> =
+        ic_cdk::call(personhood.provider, &personhood.method, (voter,)).await;
+
+    match res {
+        Ok((true,)) => Ok(()),
+        Ok((false,)) => Err(VoteError::NotAHuman),
+        Err(_) => Err(VoteError::PersonhoodCallFailed),
+    }
+}
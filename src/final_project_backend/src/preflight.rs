@@ -0,0 +1,169 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, execution, execution::ExecutionPayload, get_memory, rate_limit,
+    treasury, upgrade, visibility, Memory, Proposal, VoteError, PREFLIGHT_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const MAX_REASON_LEN: usize = 500;
+
+/*
+    A dry run of the payload, so voters know before it passes whether it would execute.
+    The call itself can't be made without its effects, so it's checked as far as that goes:
+    the argument has to be valid Candid and the target has to offer the method in its interface.
+    A transfer checks the treasury's balance, an upgrade the uploaded module.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) enum Preflight {
+    WouldExecute,
+    WouldFail { reason: String },
+    // The target doesn't publish its interface, the method couldn't be looked up.
+    Unverified { reason: String },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct SimulationResult {
+    simulated_at: u64,
+    simulated_by: Principal,
+    result: Preflight,
+}
+
+impl Storable for SimulationResult {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SimulationResult {
+    const MAX_SIZE: u32 = 2200; // A reason of `MAX_REASON_LEN` characters can take four bytes each.
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> the latest dry run of its payload.
+    static SIMULATIONS: RefCell<StableBTreeMap<u64, SimulationResult, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PREFLIGHT_MEMORY_ID)));
+}
+
+pub(crate) fn remove(key: u64) {
+    SIMULATIONS.with(|s| s.borrow_mut().remove(&key));
+}
+
+// Whether the `service` of a Candid interface has a method with this name.
+fn offers_method(interface: &str, method: &str) -> bool {
+    let service: &str = match interface.find("service") {
+        Some(start) => &interface[start..],
+        None => return false,
+    };
+    let quoted: String = format!("\"{}\"", method);
+
+    service.split([';', '{']).any(|entry| {
+        let entry: &str = entry.trim_start();
+
+        [method, quoted.as_str()].iter().any(|name| {
+            entry
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.trim_start().starts_with(':'))
+        })
+    })
+}
+
+async fn check_call(canister: Principal, method: &str, arg: &[u8]) -> Preflight {
+    if candid::de::IDLDeserialize::new(arg).is_err() {
+        return Preflight::WouldFail {
+            reason: "the argument isn't valid Candid".to_string(),
+        };
+    }
+
+    // Only a query on the target, nothing is changed by asking.
+    let res: Result<(String,), _> =
+        ic_cdk::call(canister, "__get_candid_interface_tmp_hack", ()).await;
+
+    match res {
+        Ok((interface,)) if offers_method(&interface, method) => Preflight::WouldExecute,
+        Ok(_) => Preflight::WouldFail {
+            reason: format!("the target has no method `{}`", method),
+        },
+        Err((code, message)) => Preflight::Unverified {
+            reason: format!("{:?}: {}", code, message),
+        },
+    }
+}
+
+fn from_check(res: Result<(), String>) -> Preflight {
+    match res {
+        Ok(()) => Preflight::WouldExecute,
+        Err(reason) => Preflight::WouldFail { reason },
+    }
+}
+
+// Anybody can run it again, the latest result replaces the one before.
+#[ic_cdk::update]
+async fn simulate_execution(key: u64) -> Result<SimulationResult, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if visibility::can_see(&value, &caller) => value,
+        _ => return Err(VoteError::NoSuchProposal),
+    };
+
+    let payload: ExecutionPayload = match proposal.execution {
+        Some(value) if !execution::has_run(key) => value,
+        _ => return Err(VoteError::NotExecutable),
+    };
+
+    let result: Preflight = match &payload {
+        ExecutionPayload::Call {
+            canister,
+            method,
+            arg,
+        } => check_call(*canister, method, arg).await,
+        ExecutionPayload::Transfer(transfer) => from_check(treasury::preflight(transfer).await),
+        ExecutionPayload::Upgrade(upgrade) => from_check(upgrade::preflight(key, upgrade)),
+        ExecutionPayload::Config(update) => from_check(
+            config::check_update(update)
+                .map_err(|_| "the change wouldn't make a valid config".to_string()),
+        ),
+        ExecutionPayload::Membership(_) => Preflight::WouldExecute,
+    };
+
+    let result: Preflight = match result {
+        Preflight::WouldFail { reason } => Preflight::WouldFail {
+            reason: reason.chars().take(MAX_REASON_LEN).collect(),
+        },
+        Preflight::Unverified { reason } => Preflight::Unverified {
+            reason: reason.chars().take(MAX_REASON_LEN).collect(),
+        },
+        other => other,
+    };
+
+    let simulation: SimulationResult = SimulationResult {
+        simulated_at: ic_cdk::api::time(),
+        simulated_by: caller,
+        result,
+    };
+
+    // The proposal may have been deleted while the checks were running.
+    if PROPOSAL_MAP.with(|p| p.borrow().get(&key)).is_some() {
+        SIMULATIONS.with(|s| s.borrow_mut().insert(key, simulation.clone()));
+    }
+
+    Ok(simulation)
+}
+
+#[ic_cdk::query]
+fn get_simulation(key: u64) -> Option<SimulationResult> {
+    let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+
+    if !visibility::can_see(&proposal, &ic_cdk::caller()) {
+        return None;
+    }
+
+    SIMULATIONS.with(|s| s.borrow().get(&key))
+}
@@ -0,0 +1,176 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_certified_map::Hash;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeSet};
+
+use crate::{
+    archive, attestation, authenticated_caller, ballot_chain, ballots, changes, delegation,
+    get_memory, participation, salts, voter_list, Memory, Proposal, VoteError, PROPOSAL_MAP,
+    PURGED_VOTER_MEMORY_ID,
+};
+
+const DOMAIN: &[u8] = b"\x0eicp-vote-purge";
+
+// Type byte of the reserved class, no canister or user ever gets a principal ending in it.
+const TOMBSTONE_CLASS: u8 = 0x7f;
+
+thread_local! {
+    // (proposal key, salted hash of a purged voter), so a proposal that is reopened later doesn't take their vote again.
+    static PURGED: RefCell<StableBTreeMap<(u64, Hash), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PURGED_VOTER_MEMORY_ID)));
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct PurgeSummary {
+    ballots: u64,     // Ballots now filed under a tombstone.
+    voter_lists: u64, // Lists the caller was taken off.
+}
+
+/*
+    Every ballot gets its own tombstone out of fresh randomness that is thrown away afterwards,
+    so neither the caller nor anybody else can link the tombstones to them or to each other.
+*/
+fn tombstone(randomness: &[u8], key: u64) -> Principal {
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update(randomness);
+    hasher.update(key.to_be_bytes());
+    let hash: [u8; 32] = hasher.finalize().into();
+
+    let mut bytes: Vec<u8> = hash[..28].to_vec();
+    bytes.push(TOMBSTONE_CLASS);
+    Principal::from_slice(&bytes)
+}
+
+// Salted with the ballot secret, the principal can't be found again by hashing candidates.
+fn purged_hash(key: u64, voter: &Principal) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(salts::derive(b"purged", &[key]));
+    hasher.update(voter.as_slice());
+    hasher.finalize().into()
+}
+
+fn has_purged(key: u64) -> bool {
+    PURGED.with(|p| {
+        p.borrow()
+            .range((key, [0; 32])..)
+            .next()
+            .is_some_and(|((k, _), _)| k == key)
+    })
+}
+
+// A voter who purged their ballot on `key` can't vote on it again, it is still counted.
+pub(crate) fn check(key: u64, voter: &Principal) -> Result<(), VoteError> {
+    if has_purged(key) && PURGED.with(|p| p.borrow().contains_key(&(key, purged_hash(key, voter))))
+    {
+        return Err(VoteError::AlreadyVoted { at: None });
+    }
+    Ok(())
+}
+
+pub(crate) fn remove(key: u64) {
+    PURGED.with(|p| {
+        let mut map = p.borrow_mut();
+        let entries: Vec<(u64, Hash)> = map
+            .range((key, [0; 32])..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in entries {
+            map.remove(&entry);
+        }
+    });
+}
+
+fn has_open_votes(keys: &[u64]) -> bool {
+    keys.iter().any(|key| {
+        PROPOSAL_MAP
+            .with(|p| p.borrow().get(key))
+            .is_some_and(|proposal| proposal.is_active)
+    })
+}
+
+/*
+    For deployments with privacy requirements: takes the caller's principal out of the voter
+    lists, the ballots, the participation stats and the delegations. Ballots are filed under
+    anonymous tombstones instead of being dropped, so the tallies and the voter counts stay right.
+
+    Proposals the caller voted on have to be closed first, an open one would let them vote again.
+    A salted hash of the caller is kept per proposal, so one that is reopened later turns them away.
+    Archived proposals are changed in the archive, before anything here, so a failed call
+    leaves everything as it was. The audit log, the event feed and the ICRC-3 blocks are
+    append-only and keep what they have.
+*/
+#[ic_cdk::update]
+async fn purge_my_data() -> Result<PurgeSummary, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if has_open_votes(&ballots::keys_of(caller)) {
+        return Err(VoteError::VotesStillOpen);
+    }
+    salts::check_ready()?;
+
+    let randomness: Vec<u8> = match raw_rand().await {
+        Ok((bytes,)) => bytes,
+        Err(_) => return Err(VoteError::RandomnessUnavailable),
+    };
+
+    // Until no await is left, a proposal can be archived while the archive is called.
+    let mut in_archive: BTreeSet<u64> = BTreeSet::new();
+    loop {
+        let pending: Vec<u64> = ballots::keys_of(caller)
+            .into_iter()
+            .filter(|key| archive::is_archived(*key) && !in_archive.contains(key))
+            .collect();
+
+        if pending.is_empty() {
+            break;
+        }
+        for key in pending {
+            archive::forget_voter(key, caller)
+                .await
+                .map_err(|_| VoteError::ArchiveCallFailed)?;
+            in_archive.insert(key);
+        }
+    }
+
+    // Read after the awaits, a proposal may have been reopened in the meantime.
+    let keys: Vec<u64> = ballots::keys_of(caller);
+    if has_open_votes(&keys) {
+        return Err(VoteError::VotesStillOpen);
+    }
+
+    for key in &keys {
+        let anonymous: Principal = tombstone(&randomness, *key);
+        ballots::anonymize(*key, caller, anonymous);
+        ballot_chain::anonymize(*key, caller, anonymous);
+        PURGED.with(|p| {
+            p.borrow_mut()
+                .insert((*key, purged_hash(*key, &caller)), ())
+        });
+
+        let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(key)) {
+            Some(value) => value,
+            None => continue, // Archived, its copy there was changed above.
+        };
+
+        if proposal.voted.contains(&caller) {
+            proposal.voted.retain(|voter| *voter != caller);
+            proposal.compacted_voters = Some(proposal.compacted_voters.unwrap_or(0) + 1);
+            PROPOSAL_MAP.with(|p| p.borrow_mut().insert(*key, proposal));
+            changes::record_change(*key);
+        }
+    }
+
+    let voter_lists: u64 = voter_list::remove_voter(caller);
+    participation::remove(caller);
+    delegation::forget(caller);
+    attestation::forget(caller);
+
+    Ok(PurgeSummary {
+        ballots: keys.len() as u64,
+        voter_lists,
+    })
+}
@@ -0,0 +1,461 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    ops::RangeBounds,
+};
+
+use crate::{
+    blobs, decision, delegation, embargo, get_memory, kinds, listing, ties, validation, visibility,
+    ChoiceLabels, Memory, Proposal, MAX_VALUE_SIZE, PROPOSAL_MAP, PROPOSAL_MEMORY_ID,
+};
+
+/*
+    The btree of ic-stable-structures 0.5 only stores values with a bound, so a proposal is cut
+    into pieces of this size. Unbounded values need 0.6, and moving to it means rewriting the
+    `BoundedStorable` impls of every other map in the crate, for what the chunks already give:
+    no ceiling on a proposal's size, and small ones taking little space.
+*/
+const CHUNK_SIZE: usize = 1024;
+
+/*
+    Written in front of every stored proposal. Postcard has no field names, so a struct
+    that changes can't read what the old one wrote: copy the old struct in here as
+    `ProposalV<n>`, bump the version and let `decode` turn the old layout into the new one.
+    Archived proposals come back in the layout they left in, old versions stay readable.
+*/
+const LAYOUT_VERSION: u8 = 1;
+
+pub(crate) fn encode(proposal: &Proposal) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![LAYOUT_VERSION];
+    bytes.extend(postcard::to_allocvec(proposal).unwrap());
+    bytes
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Proposal {
+    match bytes.split_first() {
+        Some((&LAYOUT_VERSION, rest)) => postcard::from_bytes(rest).unwrap(),
+        Some((version, _)) => panic!("unknown proposal layout {}", version),
+        None => panic!("empty proposal"),
+    }
+}
+
+// Open proposals kept decoded on the heap, the ones people are voting on right now.
+const CACHE_CAPACITY: usize = 100;
+
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Chunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/*
+    Least recently used goes first once it's full. Only active proposals are kept, closed
+    ones are hardly read again. It's on the heap, so an upgrade starts with an empty cache
+    and can never serve a proposal in its old layout.
+*/
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<u64, (Proposal, u64)>, // Key -> proposal and when it was last used.
+    by_use: BTreeMap<u64, u64>,             // Last use -> key, the oldest comes first.
+    clock: u64,
+}
+
+impl Cache {
+    fn touch(&mut self, key: u64) -> Option<&Proposal> {
+        let (proposal, used) = self.entries.get_mut(&key)?;
+
+        self.clock += 1;
+        self.by_use.remove(used);
+        self.by_use.insert(self.clock, key);
+        *used = self.clock;
+
+        Some(proposal)
+    }
+
+    fn put(&mut self, key: u64, proposal: &Proposal) {
+        if !proposal.is_active {
+            self.evict(key);
+            return;
+        }
+
+        self.clock += 1;
+        if let Some((_, used)) = self.entries.insert(key, (proposal.clone(), self.clock)) {
+            self.by_use.remove(&used);
+        }
+        self.by_use.insert(self.clock, key);
+
+        if self.entries.len() > CACHE_CAPACITY {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn evict(&mut self, key: u64) {
+        if let Some((_, used)) = self.entries.remove(&key) {
+            self.by_use.remove(&used);
+        }
+    }
+}
+
+/*
+    Holds proposals of any size. Small ones take a single chunk instead of a 5000 byte slot,
+    big ones (thousands of voters) no longer trap on insert.
+    It has the bits of `StableBTreeMap` the canister uses, so `PROPOSAL_MAP` reads like any other map.
+    Writes go through the cache to stable memory right away, `get` is the only read that uses it.
+    Long descriptions live in `blobs`, the stored proposal only has their hash.
+*/
+pub(crate) struct ProposalStore {
+    index: StableBTreeMap<u64, u32, Memory>, // Proposal key -> how many chunks it has.
+    chunks: StableBTreeMap<(u64, u32), Chunk, Memory>,
+    cache: RefCell<Cache>,
+}
+
+impl ProposalStore {
+    pub(crate) fn init(index: Memory, chunks: Memory) -> Self {
+        ProposalStore {
+            index: StableBTreeMap::init(index),
+            chunks: StableBTreeMap::init(chunks),
+            cache: RefCell::new(Cache::default()),
+        }
+    }
+
+    fn read(&self, key: u64, count: u32) -> Proposal {
+        let bytes: Vec<u8> = self
+            .chunks
+            .range((key, 0)..(key, count))
+            .flat_map(|(_, chunk)| chunk.0)
+            .collect();
+
+        let mut proposal: Proposal = Proposal::from_bytes(Cow::Owned(bytes));
+        if let Some(hash) = &proposal.description_hash {
+            proposal.description = blobs::get_text(hash);
+        }
+        proposal
+    }
+
+    pub(crate) fn get(&self, key: &u64) -> Option<Proposal> {
+        if let Some(proposal) = self.cache.borrow_mut().touch(*key) {
+            return Some(proposal.clone());
+        }
+
+        let proposal: Proposal = self.read(*key, self.index.get(key)?);
+        self.cache.borrow_mut().put(*key, &proposal);
+        Some(proposal)
+    }
+
+    // Overwrites the chunks in place, only the ones the new value doesn't need anymore are removed.
+    pub(crate) fn insert(&mut self, key: u64, mut value: Proposal) -> Option<Proposal> {
+        let old: Option<Proposal> = self.get(&key);
+        let old_count: u32 = self.index.get(&key).unwrap_or(0);
+        value.updated_at = ic_cdk::api::time();
+
+        // The new reference is taken before the old one goes, an unchanged text is never dropped in between.
+        value.description_hash = (value.description.len() >= blobs::MIN_BLOB_SIZE)
+            .then(|| blobs::put_text(&value.description));
+        if let Some(hash) = old.as_ref().and_then(|old| old.description_hash) {
+            blobs::release(&hash);
+        }
+
+        // Stored without the description, the cache keeps the whole proposal.
+        let bytes: Vec<u8> = match value.description_hash {
+            Some(_) => {
+                let description: String = std::mem::take(&mut value.description);
+                let bytes: Vec<u8> = value.to_bytes().into_owned();
+                value.description = description;
+                bytes
+            }
+            None => value.to_bytes().into_owned(),
+        };
+        let mut count: u32 = 0;
+
+        for piece in bytes.chunks(CHUNK_SIZE) {
+            self.chunks.insert((key, count), Chunk(piece.to_vec()));
+            count += 1;
+        }
+        for chunk in count..old_count {
+            self.chunks.remove(&(key, chunk));
+        }
+        self.index.insert(key, count);
+        self.cache.get_mut().put(key, &value);
+
+        old
+    }
+
+    pub(crate) fn remove(&mut self, key: &u64) -> Option<Proposal> {
+        let old: Proposal = self.get(key)?;
+        let count: u32 = self.index.remove(key)?;
+
+        for chunk in 0..count {
+            self.chunks.remove(&(*key, chunk));
+        }
+        self.cache.get_mut().evict(*key);
+
+        if let Some(hash) = &old.description_hash {
+            blobs::release(hash);
+        }
+
+        Some(old)
+    }
+
+    pub(crate) fn contains_key(&self, key: &u64) -> bool {
+        self.index.contains_key(key)
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.index.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub(crate) fn last_key_value(&self) -> Option<(u64, Proposal)> {
+        self.index
+            .last_key_value()
+            .map(|(key, count)| (key, self.read(key, count)))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u64, Proposal)> + '_ {
+        self.index
+            .iter()
+            .map(|(key, count)| (key, self.read(key, count)))
+    }
+
+    pub(crate) fn range(
+        &self,
+        key_range: impl RangeBounds<u64>,
+    ) -> impl Iterator<Item = (u64, Proposal)> + '_ {
+        self.index
+            .range(key_range)
+            .map(|(key, count)| (key, self.read(key, count)))
+    }
+}
+
+/*
+    How the first release stored proposals, Candid in a map with a 5000 byte bound.
+    Frozen, it has to read what that release wrote, not what `Proposal` looks like today.
+*/
+#[derive(Debug, CandidType, Deserialize)]
+struct BaselineProposal {
+    description: String,
+    approve: u32,
+    reject: u32,
+    pass: u32,
+    is_active: bool,
+    voted: Vec<Principal>,
+    owner: Principal,
+}
+
+impl Storable for BaselineProposal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for BaselineProposal {
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Everything the baseline didn't have gets what an unrestricted, unweighted proposal would have.
+fn from_baseline(old: BaselineProposal, migrated_at: u64) -> Proposal {
+    // There were no titles, the description's first line stands in.
+    let title: String = old
+        .description
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take(validation::MAX_TITLE_LEN)
+        .collect();
+
+    Proposal {
+        title,
+        summary: String::new(),
+        url: None,
+        payload_hash: None,
+        description: old.description,
+        approve: old.approve as u64,
+        reject: old.reject as u64,
+        // The baseline counted a pass with `pass -= 1`, which wrapped. Negating undoes it.
+        pass: old.pass.wrapping_neg() as u64,
+        abstain: 0,
+        abstain_voters: 0,
+        is_active: old.is_active,
+        voted: old.voted,
+        owner: old.owner,
+        results_embargo: None,
+        results_hidden_until: None,
+        category: None,
+        quorum: 0, // The baseline had none, the result stays what it was.
+        snapshot: None,
+        nft_gate: None,
+        sns_gate: None,
+        eligibility_root: None,
+        execution: None,
+        visibility: visibility::Visibility::Public,
+        decision_rule: decision::DecisionRule::SimpleMajority,
+        outcome: None,
+        abstention: decision::Abstention::default(),
+        voting_starts_at: None,
+        voting_ends_at: None,
+        compacted_voters: None,
+        listing: listing::ListingStatus::Listed,
+        depends_on: vec![],
+        voter_list: false,
+        editors: vec![],
+        public_ballots: false,
+        allow_vote_changes: false,
+        // The real times were never recorded, the migration is the earliest one known.
+        created_at: migrated_at,
+        updated_at: migrated_at,
+        closed_at: (!old.is_active).then_some(migrated_at),
+        delegated: delegation::DelegatedWeight::default(),
+        quorum_percent: None,
+        registered_voters: None,
+        survey: None,
+        choice_labels: ChoiceLabels {
+            approve: None,
+            reject: None,
+            pass: None,
+            abstain: None,
+        },
+        results_visibility: embargo::ResultsVisibility::default(),
+        description_hash: None,
+        previous_round: None,
+        role_weights: None,
+        kind: kinds::Kind::default(),
+        members_only: false,
+        tie_break: ties::TieBreak::default(),
+        voting_suspended_at: None,
+        frontend_only: false,
+        personhood_gated: false,
+        follows: None,
+        weighting: None,
+        sealed_ballots: false,
+    }
+}
+
+// Called first thing in `post_upgrade`, moves whatever is left in the old map over to the store.
+pub(crate) fn migrate() {
+    let legacy: StableBTreeMap<u64, BaselineProposal, Memory> =
+        StableBTreeMap::init(get_memory(PROPOSAL_MEMORY_ID));
+
+    if legacy.is_empty() {
+        return;
+    }
+
+    let now: u64 = ic_cdk::api::time();
+
+    PROPOSAL_MAP.with(|p| {
+        let mut store = p.borrow_mut();
+
+        for (key, proposal) in legacy.iter() {
+            store.insert(key, from_baseline(proposal, now));
+        }
+    });
+
+    legacy.clear();
+}
+
+// A closed proposal with three votes, for the tests of other modules.
+#[cfg(test)]
+pub(crate) fn sample() -> Proposal {
+    from_baseline(
+        BaselineProposal {
+            description: "A sample proposal".to_string(),
+            approve: 2,
+            reject: 1,
+            pass: 0,
+            is_active: false,
+            voted: (1..=3).map(|n: u8| Principal::from_slice(&[n])).collect(),
+            owner: Principal::from_slice(&[9]),
+        },
+        0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn baseline() -> BaselineProposal {
+        BaselineProposal {
+            description: "Fund the meetup\nDetails follow.".to_string(),
+            approve: 3,
+            reject: 1,
+            pass: 2u32.wrapping_neg(), // Two passes, as the baseline counted them.
+            is_active: false,
+            voted: vec![Principal::from_slice(&[1]), Principal::from_slice(&[2])],
+            owner: Principal::from_slice(&[7]),
+        }
+    }
+
+    #[test]
+    fn reads_what_the_baseline_wrote() {
+        let bytes: Vec<u8> = Encode!(&baseline()).unwrap();
+        let proposal: Proposal = from_baseline(BaselineProposal::from_bytes(Cow::Owned(bytes)), 42);
+
+        assert_eq!(proposal.title, "Fund the meetup");
+        assert_eq!(proposal.description, "Fund the meetup\nDetails follow.");
+        assert_eq!(
+            (proposal.approve, proposal.reject, proposal.pass),
+            (3, 1, 2)
+        );
+        assert_eq!(proposal.voted.len(), 2);
+        assert_eq!(proposal.owner, Principal::from_slice(&[7]));
+        assert_eq!(proposal.quorum, 0);
+        assert_eq!(proposal.created_at, 42);
+        assert_eq!(proposal.closed_at, Some(42));
+    }
+
+    #[test]
+    fn repairs_the_wrapped_pass_count() {
+        let mut old: BaselineProposal = baseline();
+        old.pass = 0;
+        assert_eq!(from_baseline(old, 42).pass, 0);
+
+        let mut old: BaselineProposal = baseline();
+        old.pass = u32::MAX;
+        assert_eq!(from_baseline(old, 42).pass, 1);
+    }
+
+    #[test]
+    fn round_trips_with_the_layout_version() {
+        let proposal: Proposal = from_baseline(baseline(), 42);
+        let bytes: Vec<u8> = encode(&proposal);
+        assert_eq!(bytes[0], LAYOUT_VERSION);
+
+        let decoded: Proposal = decode(&bytes);
+        assert_eq!(decoded.title, proposal.title);
+        assert_eq!(decoded.voted, proposal.voted);
+        assert_eq!(decoded.created_at, 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown proposal layout")]
+    fn refuses_a_layout_it_does_not_know() {
+        let mut bytes: Vec<u8> = encode(&from_baseline(baseline(), 42));
+        bytes[0] = LAYOUT_VERSION + 1;
+        decode(&bytes);
+    }
+}
@@ -0,0 +1,130 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::{
+    config, get_memory, listing::ListingStatus, Memory, StorablePrincipal, VoteError,
+    OPEN_QUOTA_MEMORY_ID, PROPOSAL_MAP, RECENT_QUOTA_MEMORY_ID,
+};
+
+const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/*
+    Caps how much a single principal can create, so one account can't flood the listing.
+    Open proposals are everything that hasn't closed yet, drafts and submissions in review
+    included. The daily limit counts creations in the day before the call, not per calendar day.
+    The admin isn't limited.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Quota {
+    max_open: Option<u32>,
+    max_per_day: Option<u32>,
+}
+
+thread_local! {
+    // (owner, proposal key) of proposals that were open when last looked at.
+    static OPEN: RefCell<StableBTreeMap<(StorablePrincipal, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(OPEN_QUOTA_MEMORY_ID)));
+
+    // (owner, created at) -> proposal key, for the last day of creations.
+    static RECENT: RefCell<StableBTreeMap<(StorablePrincipal, u64), u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(RECENT_QUOTA_MEMORY_ID)));
+}
+
+pub(crate) fn validate(quota: &Quota) -> bool {
+    quota.max_open != Some(0) && quota.max_per_day != Some(0)
+}
+
+fn is_open(key: u64, owner: &Principal) -> bool {
+    PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| {
+            proposal.owner == *owner
+                && proposal.closed_at.is_none()
+                && proposal.listing != ListingStatus::Rejected
+        })
+}
+
+// Proposals are only recorded here, whatever closed, moved or went away since is dropped on the next count.
+fn open_count(owner: Principal) -> u64 {
+    let keys: Vec<u64> = OPEN.with(|o| {
+        o.borrow()
+            .range((StorablePrincipal(owner), 0)..=(StorablePrincipal(owner), u64::MAX))
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+
+    let mut count: u64 = 0;
+    for key in keys {
+        if is_open(key, &owner) {
+            count += 1;
+        } else {
+            OPEN.with(|o| o.borrow_mut().remove(&(StorablePrincipal(owner), key)));
+        }
+    }
+    count
+}
+
+// Creations older than a day are dropped on the way.
+fn recent_count(owner: Principal, now: u64) -> u64 {
+    let since: u64 = now.saturating_sub(DAY);
+
+    let expired: Vec<u64> = RECENT.with(|r| {
+        r.borrow()
+            .range((StorablePrincipal(owner), 0)..(StorablePrincipal(owner), since))
+            .map(|((_, created_at), _)| created_at)
+            .collect()
+    });
+    for created_at in expired {
+        RECENT.with(|r| {
+            r.borrow_mut()
+                .remove(&(StorablePrincipal(owner), created_at))
+        });
+    }
+
+    RECENT.with(|r| {
+        r.borrow()
+            .range((StorablePrincipal(owner), since)..=(StorablePrincipal(owner), u64::MAX))
+            .count() as u64
+    })
+}
+
+// Called by `create_as` before anything is taken from the caller.
+pub(crate) fn check(owner: Principal) -> Result<(), VoteError> {
+    let quota: Quota = match config::quota() {
+        Some(value) if !config::is_admin(&owner) => value,
+        _ => return Ok(()),
+    };
+
+    if let Some(limit) = quota.max_open {
+        if open_count(owner) >= limit as u64 {
+            return Err(VoteError::QuotaExceeded {
+                limit,
+                window: None,
+            });
+        }
+    }
+
+    if let Some(limit) = quota.max_per_day {
+        if recent_count(owner, ic_cdk::api::time()) >= limit as u64 {
+            return Err(VoteError::QuotaExceeded {
+                limit,
+                window: Some(DAY),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// Called by `create_as` once the proposal is stored. Recorded without a quota too, one set later counts from the start.
+pub(crate) fn on_created(owner: Principal, key: u64) {
+    let mut now: u64 = ic_cdk::api::time();
+
+    // Two creations in one message share a timestamp, the second one goes a nanosecond later.
+    while RECENT.with(|r| r.borrow().contains_key(&(StorablePrincipal(owner), now))) {
+        now += 1;
+    }
+
+    OPEN.with(|o| o.borrow_mut().insert((StorablePrincipal(owner), key), ()));
+    RECENT.with(|r| r.borrow_mut().insert((StorablePrincipal(owner), now), key));
+}
@@ -0,0 +1,72 @@
+use candid::Principal;
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+use crate::{timers, VoteError};
+
+/*
+    Token bucket per principal. Every rate limited call takes a token,
+    and tokens trickle back in over time up to `BUCKET_CAPACITY`.
+    The buckets live on the heap on purpose: losing them on upgrade only means
+    everybody starts with a full bucket again.
+*/
+const BUCKET_CAPACITY: u64 = 20;
+const REFILL_INTERVAL_NS: u64 = 3_000_000_000; // One token back every 3 seconds.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
+struct Bucket {
+    tokens: u64,
+    last_refill: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: u64) {
+        let refilled: u64 = now.saturating_sub(self.last_refill) / REFILL_INTERVAL_NS;
+
+        if refilled > 0 {
+            self.tokens = (self.tokens + refilled).min(BUCKET_CAPACITY);
+            self.last_refill += refilled * REFILL_INTERVAL_NS;
+        }
+    }
+}
+
+thread_local! {
+    static BUCKETS: RefCell<HashMap<Principal, Bucket>> = RefCell::new(HashMap::new());
+}
+
+// Takes `cost` tokens from the caller's bucket, or none at all if there aren't enough.
+pub(crate) fn consume(caller: Principal, cost: u64) -> Result<(), VoteError> {
+    let now: u64 = ic_cdk::api::time();
+
+    BUCKETS.with(|b| {
+        let mut buckets = b.borrow_mut();
+        let bucket: &mut Bucket = buckets.entry(caller).or_insert(Bucket {
+            tokens: BUCKET_CAPACITY,
+            last_refill: now,
+        });
+
+        bucket.refill(now);
+
+        if bucket.tokens < cost {
+            return Err(VoteError::RateLimited);
+        }
+
+        bucket.tokens -= cost;
+        Ok(())
+    })
+}
+
+// A full bucket carries no information, so it can be dropped and recreated on the next call.
+fn prune() {
+    let now: u64 = ic_cdk::api::time();
+
+    BUCKETS.with(|b| {
+        b.borrow_mut().retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < BUCKET_CAPACITY
+        })
+    });
+}
+
+pub(crate) fn start_pruning() {
+    timers::set_timer_interval(PRUNE_INTERVAL, prune);
+}
@@ -0,0 +1,87 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_certified_map::Hash;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::{certification, get_memory, salts, Choice, Memory, RECEIPT_MEMORY_ID};
+
+/*
+    Every accepted vote gets a receipt. Its hash is stored and certified,
+    so the voter can later prove how and when they voted, even to someone
+    who doesn't trust the frontend that showed it to them. Without the salt on the receipt
+    nobody else can check a guess of how somebody voted against the stored hash.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct VoteReceipt {
+    proposal_id: u64,
+    voter: Principal,
+    choice: Choice,
+    timestamp: u64,
+    sequence: u64,
+    salt: Hash, // Only the voter gets it, see `salts`.
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ReceiptVerification {
+    valid: bool,
+    certificate: Option<Vec<u8>>, // IC certificate over the canister's certified data.
+    witness: Vec<u8>,             // CBOR hash tree linking the receipt hash to that certified data.
+}
+
+thread_local! {
+    // Sequence number -> hash of the receipt.
+    static RECEIPTS: RefCell<StableBTreeMap<u64, Hash, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(RECEIPT_MEMORY_ID)));
+}
+
+impl VoteReceipt {
+    fn hash(&self) -> Hash {
+        let choice: u8 = self.choice.as_byte();
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.sequence.to_be_bytes());
+        hasher.update(self.proposal_id.to_be_bytes());
+        hasher.update([self.voter.as_slice().len() as u8]);
+        hasher.update(self.voter.as_slice());
+        hasher.update([choice]);
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update(self.salt);
+        hasher.finalize().into()
+    }
+}
+
+// Only called after `salts::check_ready`.
+pub(crate) fn issue(proposal_id: u64, voter: Principal, choice: Choice) -> VoteReceipt {
+    let sequence: u64 =
+        RECEIPTS.with(|r| r.borrow().last_key_value().map_or(0, |(seq, _)| seq + 1));
+
+    let receipt: VoteReceipt = VoteReceipt {
+        proposal_id,
+        voter,
+        choice,
+        timestamp: ic_cdk::api::time(),
+        sequence,
+        salt: salts::derive(b"receipt", &[sequence]),
+    };
+    let hash: Hash = receipt.hash();
+
+    RECEIPTS.with(|r| r.borrow_mut().insert(sequence, hash));
+    certification::certify_receipt(sequence, hash);
+
+    receipt
+}
+
+pub(crate) fn restore_certification() {
+    RECEIPTS.with(|r| certification::restore_receipts(r.borrow().iter()));
+}
+
+#[ic_cdk::query]
+fn verify_receipt(receipt: VoteReceipt) -> ReceiptVerification {
+    let stored: Option<Hash> = RECEIPTS.with(|r| r.borrow().get(&receipt.sequence));
+
+    ReceiptVerification {
+        valid: stored == Some(receipt.hash()),
+        certificate: ic_cdk::api::data_certificate(),
+        witness: certification::receipt_witness(receipt.sequence),
+    }
+}
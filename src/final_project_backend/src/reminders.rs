@@ -0,0 +1,107 @@
+use candid::{CandidType, Deserialize, Encode, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::{cell::RefCell, collections::HashSet, time::Duration};
+
+use crate::{
+    config, get_memory, outbox, timers, voter_list, Memory, Proposal, MAX_BATCH_SIZE, PROPOSAL_MAP,
+    REMINDER_MEMORY_ID,
+};
+
+const REMINDER_LEAD: u64 = 24 * 60 * 60 * 1_000_000_000; // A day before voting ends.
+
+/*
+    A day before a proposal with a voter list closes, the configured notifier canister is told
+    who on the list hasn't voted yet, so it can remind them (by mail, push, whatever it does).
+    The notifier gets `notify_reminder` calls with at most `MAX_BATCH_SIZE` voters each.
+    Proposals that close in less than a day when they're created get no reminder.
+*/
+#[derive(Debug, CandidType, Deserialize)]
+struct Reminder {
+    proposal_key: u64,
+    title: String,
+    ends_at: u64,
+    voters: Vec<Principal>,
+}
+
+thread_local! {
+    // Proposal key -> when its voting ends, for the reminders still to send.
+    static PENDING_REMINDERS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(REMINDER_MEMORY_ID)));
+}
+
+pub(crate) fn schedule(key: u64, ends_at: u64) {
+    if ends_at.saturating_sub(ic_cdk::api::time()) < REMINDER_LEAD {
+        return;
+    }
+
+    PENDING_REMINDERS.with(|p| p.borrow_mut().insert(key, ends_at));
+    arm_timer(key, ends_at);
+}
+
+pub(crate) fn remove(key: u64) {
+    PENDING_REMINDERS.with(|p| p.borrow_mut().remove(&key));
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = PENDING_REMINDERS.with(|p| p.borrow().iter().collect());
+
+    for (key, ends_at) in pending {
+        arm_timer(key, ends_at);
+    }
+}
+
+fn arm_timer(key: u64, ends_at: u64) {
+    let delay: u64 = (ends_at - REMINDER_LEAD).saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || {
+        ic_cdk::spawn(send(key, ends_at))
+    });
+}
+
+// Everybody on the list who hasn't voted, in principal order.
+fn missing_voters(key: u64, proposal: &Proposal) -> Vec<Principal> {
+    let voted: HashSet<&Principal> = proposal.voted.iter().collect();
+
+    voter_list::members(key)
+        .into_iter()
+        .filter(|voter| !voted.contains(voter))
+        .collect()
+}
+
+async fn send(key: u64, ends_at: u64) {
+    // A proposal deleted and created again in the meantime has a reminder of its own.
+    if PENDING_REMINDERS.with(|p| p.borrow().get(&key)) != Some(ends_at) {
+        return;
+    }
+    remove(key);
+
+    let notifier: Principal = match config::notifier() {
+        Some(value) => value,
+        None => return,
+    };
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if value.is_active && value.voter_list => value,
+        _ => return,
+    };
+
+    // A notifier that is down gets the batches it missed from `outbox`.
+    for voters in missing_voters(key, &proposal).chunks(MAX_BATCH_SIZE) {
+        let reminder: Reminder = Reminder {
+            proposal_key: key,
+            title: proposal.title.clone(),
+            ends_at,
+            voters: voters.to_vec(),
+        };
+        let arg: Vec<u8> = Encode!(&reminder).unwrap();
+
+        if let Err((code, message)) =
+            ic_cdk::api::call::call_raw(notifier, "notify_reminder", &arg, 0).await
+        {
+            let target: outbox::CallTarget = outbox::CallTarget::Notification {
+                canister: notifier,
+                method: "notify_reminder".to_string(),
+                arg,
+            };
+            outbox::record(target, &format!("{:?}: {}", code, message));
+        }
+    }
+}
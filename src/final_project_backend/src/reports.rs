@@ -0,0 +1,190 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_certified_map::Hash;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    certification,
+    decision::{self, Outcome},
+    get_memory, roles, tally, ties, visibility, weighting, Memory, Proposal,
+    FINAL_REPORT_MEMORY_ID, PROPOSAL_MAP,
+};
+
+const DOMAIN: &[u8] = b"\x0eicp-vote-report";
+
+/*
+    The result of a proposal as it was worked out when voting closed. It's stored as is
+    and never recomputed, so a later change to the tally or the decision rules
+    can't change what an old proposal's result was.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct FinalReport {
+    tally: tally::Tally,
+    turnout: u64, // Heads that counted for the quorum.
+    quorum_met: bool,
+    outcome: Outcome,
+    closed_at: u64,
+    role_weights: Option<Vec<roles::RoleWeight>>, // The multipliers the votes were counted with.
+    tie_break: Vec<ties::TieBreakPath>,           // How a tie was broken, empty without one.
+    weighting: Option<weighting::WeightingReport>, // The cap and curve the votes were counted with.
+    result_hash: Vec<u8>,                         // SHA-256 over the fields above, see `hash_of`.
+}
+
+impl Storable for FinalReport {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for FinalReport {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/*
+    Everything an auditor or another chain's light client needs to check a result without trusting
+    whoever passes it on:
+    1. verify `certificate` against the IC root key, through the subnet delegation it carries if there is one,
+    2. check that `canister/<canister id>/certified_data` in it is the root hash of `witness`,
+    3. look up `results/<key, 8 bytes big endian>` in `witness`, it has to be `report.result_hash`,
+    4. hash the report again (see `hash_of`) to tie its fields to that hash.
+    The certificate is the newest one, the hash under it was certified when voting closed
+    (or the embargo ended) and only changes if the proposal closes again.
+*/
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct ResultProof {
+    key: u64,
+    report: FinalReport,
+    certificate: Option<Vec<u8>>, // CBOR, only queries get one.
+    witness: Vec<u8>,             // CBOR hash tree from the result hash to the certified data.
+}
+
+thread_local! {
+    // Proposal key -> report. Kept when the proposal is archived, it's the part that is history.
+    static REPORTS: RefCell<StableBTreeMap<u64, FinalReport, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(FINAL_REPORT_MEMORY_ID)));
+}
+
+fn hash_of(key: u64, report: &FinalReport) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update(key.to_be_bytes());
+    hasher.update(
+        Encode!(
+            &report.tally,
+            &report.turnout,
+            &report.quorum_met,
+            &report.outcome,
+            &report.closed_at,
+            &report.role_weights,
+            &report.tie_break,
+            &report.weighting
+        )
+        .unwrap(),
+    );
+    hasher.finalize().to_vec()
+}
+
+/*
+    Called by `decision::finalize`. A proposal that opens again (a workflow stage, an edit)
+    gets a new report the next time it closes, nothing else writes here.
+*/
+pub(crate) fn generate(key: u64, proposal: &Proposal) {
+    let outcome: Outcome = match proposal.outcome {
+        Some(value) => value,
+        None => return,
+    };
+
+    let mut report: FinalReport = FinalReport {
+        tally: tally::tally_of(proposal),
+        turnout: decision::turnout(proposal),
+        quorum_met: decision::quorum_met(proposal),
+        outcome,
+        closed_at: proposal.closed_at.unwrap_or_else(ic_cdk::api::time),
+        role_weights: proposal.role_weights.clone(),
+        tie_break: ties::path_of(key),
+        weighting: weighting::report(key, proposal),
+        result_hash: vec![],
+    };
+    report.result_hash = hash_of(key, &report);
+
+    // Under embargo the hash would give small tallies away, it's certified once they're published.
+    if proposal.results_hidden_until.is_none() {
+        certify(key, &report);
+    } else {
+        certification::uncertify_result(key);
+    }
+
+    REPORTS.with(|r| r.borrow_mut().insert(key, report));
+}
+
+fn certify(key: u64, report: &FinalReport) {
+    if let Ok(hash) = Hash::try_from(report.result_hash.as_slice()) {
+        certification::certify_result(key, hash);
+    }
+}
+
+// Called by `embargo` when the results come out.
+pub(crate) fn on_published(key: u64) {
+    if let Some(report) = REPORTS.with(|r| r.borrow().get(&key)) {
+        certify(key, &report);
+    }
+}
+
+pub(crate) fn remove(key: u64) {
+    REPORTS.with(|r| r.borrow_mut().remove(&key));
+    certification::uncertify_result(key);
+}
+
+// Certified data doesn't survive an upgrade. Reports of archived proposals were public, they're certified as well.
+pub(crate) fn restore_certification() {
+    let published: Vec<(u64, Hash)> = REPORTS.with(|r| {
+        r.borrow()
+            .iter()
+            .filter(|(key, _)| {
+                PROPOSAL_MAP
+                    .with(|p| p.borrow().get(key))
+                    .is_none_or(|proposal| proposal.results_hidden_until.is_none())
+            })
+            .filter_map(|(key, report)| {
+                Hash::try_from(report.result_hash.as_slice())
+                    .ok()
+                    .map(|hash| (key, hash))
+            })
+            .collect()
+    });
+
+    certification::restore_results(published.into_iter());
+}
+
+// Like the tally, nothing while the results are under embargo.
+#[ic_cdk::query]
+fn get_final_report(key: u64) -> Option<FinalReport> {
+    let caller: Principal = ic_cdk::caller();
+
+    // An archived proposal isn't here to check against anymore, only public ones keep their report.
+    if let Some(proposal) = PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        if !visibility::can_see(&proposal, &caller) || proposal.results_hidden_until.is_some() {
+            return None;
+        }
+    }
+
+    REPORTS.with(|r| r.borrow().get(&key))
+}
+
+// The report with what proves it, `None` whenever `get_final_report` has nothing.
+#[ic_cdk::query]
+fn get_result_proof(key: u64) -> Option<ResultProof> {
+    let report: FinalReport = get_final_report(key)?;
+
+    Some(ResultProof {
+        key,
+        report,
+        certificate: ic_cdk::api::data_certificate(),
+        witness: certification::result_witness(key),
+    })
+}
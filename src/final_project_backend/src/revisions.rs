@@ -0,0 +1,125 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{blobs, get_memory, visibility, Memory, Proposal, MAX_VALUE_SIZE, REVISION_MEMORY_ID};
+
+// Most revisions `get_proposal_history` returns, the newest ones win.
+const MAX_HISTORY: usize = 100;
+
+/*
+    Editing a proposal replaces it, so everything it said before is kept here.
+    `voter_count` tells who voted on which text: everybody counted there saw this revision.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Revision {
+    revision: u64,
+    replaced_at: u64,
+    voter_count: u64,
+    title: String,
+    summary: String,
+    url: Option<String>,
+    payload_hash: Option<Vec<u8>>,
+    description: String, // Empty in stable memory when the text is in `blobs`.
+    is_active: bool,
+    results_embargo: Option<u64>,
+    description_hash: Option<blobs::BlobHash>,
+}
+
+impl Storable for Revision {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Revision {
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // (proposal key, revision number) -> the proposal's settings before that edit.
+    static REVISIONS: RefCell<StableBTreeMap<(u64, u64), Revision, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(REVISION_MEMORY_ID)));
+}
+
+// Called by `edit_proposal` with the proposal as it was before the edit.
+pub(crate) fn record(key: u64, old: &Proposal) {
+    REVISIONS.with(|r| {
+        let revision: u64 = r
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .last()
+            .map_or(0, |((_, revision), _)| revision + 1);
+
+        // Edits mostly leave the description alone, every revision would otherwise keep a copy.
+        let description_hash: Option<blobs::BlobHash> = (old.description.len()
+            >= blobs::MIN_BLOB_SIZE)
+            .then(|| blobs::put_text(&old.description));
+
+        let value: Revision = Revision {
+            revision,
+            replaced_at: ic_cdk::api::time(),
+            voter_count: old.voter_count(),
+            title: old.title.clone(),
+            summary: old.summary.clone(),
+            url: old.url.clone(),
+            payload_hash: old.payload_hash.clone(),
+            description: match description_hash {
+                Some(_) => String::new(),
+                None => old.description.clone(),
+            },
+            is_active: old.is_active,
+            results_embargo: old.results_embargo,
+            description_hash,
+        };
+        r.borrow_mut().insert((key, revision), value);
+    });
+}
+
+pub(crate) fn remove(key: u64) {
+    let revisions: Vec<((u64, u64), Revision)> =
+        REVISIONS.with(|r| r.borrow().range((key, 0)..=(key, u64::MAX)).collect());
+
+    REVISIONS.with(|r| {
+        let mut map = r.borrow_mut();
+
+        for (k, revision) in revisions {
+            map.remove(&k);
+
+            if let Some(hash) = &revision.description_hash {
+                blobs::release(hash);
+            }
+        }
+    });
+}
+
+// Oldest first. The current text is the proposal itself, it's not part of the history.
+#[ic_cdk::query]
+fn get_proposal_history(key: u64) -> Vec<Revision> {
+    if !visibility::can_see_key(key, &ic_cdk::caller()) {
+        return vec![];
+    }
+
+    let mut history: Vec<Revision> = REVISIONS.with(|r| {
+        r.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|(_, revision)| revision)
+            .collect()
+    });
+
+    if history.len() > MAX_HISTORY {
+        history.drain(..history.len() - MAX_HISTORY);
+    }
+
+    for revision in &mut history {
+        if let Some(hash) = &revision.description_hash {
+            revision.description = blobs::get_text(hash);
+        }
+    }
+
+    history
+}
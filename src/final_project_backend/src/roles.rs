@@ -0,0 +1,163 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, get_memory, Memory, Role, StorablePrincipal, VoteError,
+    ROLE_ASSIGNMENT_MEMORY_ID, ROLE_WEIGHT_MEMORY_ID,
+};
+
+// The schedule is copied onto every role weighted proposal and into its final report.
+const MAX_ROLES: usize = 8;
+const MAX_ROLE_LEN: usize = 32;
+const MAX_MULTIPLIER: u32 = 100;
+
+/*
+    Voter roles, next to the access roles in the config: the admin names roles
+    (founder, member, ...), gives each a multiplier and assigns them to principals.
+    A proposal that opts in with `role_weighted` counts every vote times the multiplier
+    of the voter's role. The schedule is read once at creation, later changes leave
+    running proposals alone; which role a voter has is read when they vote.
+*/
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize, Serialize)]
+pub(crate) struct RoleWeight {
+    role: String,
+    multiplier: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RoleName(String);
+
+impl Storable for RoleName {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        RoleName(String::from_utf8(bytes.into_owned()).unwrap())
+    }
+}
+
+impl BoundedStorable for RoleName {
+    const MAX_SIZE: u32 = MAX_ROLE_LEN as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Role -> multiplier.
+    static WEIGHTS: RefCell<StableBTreeMap<RoleName, u32, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ROLE_WEIGHT_MEMORY_ID)));
+
+    // Voter -> their role, a voter has at most one.
+    static ASSIGNMENTS: RefCell<StableBTreeMap<StorablePrincipal, RoleName, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(ROLE_ASSIGNMENT_MEMORY_ID)));
+}
+
+// What a role weighted proposal is created with, `None` while no role has a weight.
+pub(crate) fn schedule() -> Option<Vec<RoleWeight>> {
+    let schedule: Vec<RoleWeight> = WEIGHTS.with(|w| {
+        w.borrow()
+            .iter()
+            .map(|(role, multiplier)| RoleWeight {
+                role: role.0,
+                multiplier,
+            })
+            .collect()
+    });
+
+    (!schedule.is_empty()).then_some(schedule)
+}
+
+// Voters without a role, or with one the schedule doesn't know, count once.
+pub(crate) fn weight_of(schedule: &[RoleWeight], voter: &Principal) -> u64 {
+    let role: RoleName = match ASSIGNMENTS.with(|a| a.borrow().get(&StorablePrincipal(*voter))) {
+        Some(value) => value,
+        None => return 1,
+    };
+
+    schedule
+        .iter()
+        .find(|weight| weight.role == role.0)
+        .map_or(1, |weight| weight.multiplier as u64)
+}
+
+pub(crate) fn check_role(role: &str) -> Result<(), VoteError> {
+    if role.is_empty() || role.len() > MAX_ROLE_LEN {
+        return Err(VoteError::InvalidRoleWeight);
+    }
+
+    Ok(())
+}
+
+// `None` takes the role out of the schedule, principals keep it but count once again.
+#[ic_cdk::update]
+fn set_role_weight(role: String, multiplier: Option<u32>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    check_role(&role)?;
+
+    match multiplier {
+        Some(multiplier) => {
+            let known: bool = WEIGHTS.with(|w| w.borrow().contains_key(&RoleName(role.clone())));
+            let full: bool = WEIGHTS.with(|w| w.borrow().len() as usize >= MAX_ROLES);
+
+            if multiplier == 0 || multiplier > MAX_MULTIPLIER || (!known && full) {
+                return Err(VoteError::InvalidRoleWeight);
+            }
+
+            WEIGHTS.with(|w| w.borrow_mut().insert(RoleName(role), multiplier));
+        }
+        None => {
+            WEIGHTS.with(|w| w.borrow_mut().remove(&RoleName(role)));
+        }
+    }
+
+    Ok(())
+}
+
+// Also used by `membership` for passed membership changes.
+pub(crate) fn assign(voter: Principal, role: Option<String>) {
+    match role {
+        Some(role) => {
+            ASSIGNMENTS.with(|a| {
+                a.borrow_mut()
+                    .insert(StorablePrincipal(voter), RoleName(role))
+            });
+        }
+        None => {
+            ASSIGNMENTS.with(|a| a.borrow_mut().remove(&StorablePrincipal(voter)));
+        }
+    }
+}
+
+// `None` takes the role away.
+#[ic_cdk::update]
+fn assign_role(voter: Principal, role: Option<String>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    if let Some(role) = &role {
+        check_role(role)?;
+    }
+
+    assign(voter, role);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_role_weights() -> Vec<RoleWeight> {
+    schedule().unwrap_or_default()
+}
+
+#[ic_cdk::query]
+fn get_role(voter: Principal) -> Option<String> {
+    ASSIGNMENTS
+        .with(|a| a.borrow().get(&StorablePrincipal(voter)))
+        .map(|role| role.0)
+}
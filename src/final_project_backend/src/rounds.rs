@@ -0,0 +1,124 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{
+    authenticated_caller, changes, editors, followups, rate_limit, schedule, snapshot, visibility,
+    voter_list, CreateProposal, Proposal, Role, VoteError, PROPOSAL_MAP,
+};
+
+// A chain longer than this is cut off in `get_rounds`.
+const MAX_ROUNDS: usize = 100;
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct NewWindow {
+    starts_at: Option<u64>, // Opens right away when not set.
+    ends_at: Option<u64>,
+}
+
+/*
+    A vote that has to be held again (a runoff, a second reading) is cloned from the last round.
+    The clone starts with zeroed tallies and a fresh snapshot of the same ledger and voters,
+    everything else is copied. `previous_round` keeps the link back to the round it came from.
+*/
+fn next_round(key: u64, proposal: &Proposal, window: NewWindow) -> CreateProposal {
+    CreateProposal {
+        title: proposal.title.clone(),
+        summary: proposal.summary.clone(),
+        url: proposal.url.clone(),
+        payload_hash: proposal.payload_hash.clone(),
+        description: proposal.description.clone(),
+        is_active: true,
+        results_embargo: proposal.results_embargo,
+        category: proposal.category.clone(),
+        voting_power: proposal
+            .snapshot
+            .as_ref()
+            .map(|value| snapshot::source_of(key, value)),
+        nft_gate: proposal.nft_gate.clone(),
+        sns_gate: proposal.sns_gate.clone(),
+        eligibility_root: proposal.eligibility_root.clone(),
+        execution: proposal.execution.clone(),
+        visibility: proposal.visibility.clone(),
+        decision_rule: Some(proposal.decision_rule.clone()),
+        abstention: Some(proposal.abstention.clone()),
+        voting_starts_at: window.starts_at,
+        voting_ends_at: window.ends_at,
+        client_nonce: None,
+        depends_on: proposal.depends_on.clone(),
+        eligible_voters: proposal.voter_list.then(|| voter_list::members(key)),
+        save_as_draft: false,
+        public_ballots: proposal.public_ballots,
+        allow_vote_changes: proposal.allow_vote_changes,
+        quorum_percent: proposal.quorum_percent,
+        survey: proposal.survey.clone(),
+        choice_labels: proposal.choice_labels.clone(),
+        results_visibility: Some(proposal.results_visibility),
+        role_weighted: proposal.role_weights.is_some(),
+        kind: None, // Read off `execution` again.
+        members_only: proposal.members_only,
+        tie_break: Some(proposal.tie_break.clone()),
+        frontend_only: proposal.frontend_only,
+        personhood_gated: proposal.personhood_gated,
+        on_pass_create: followups::pending(key).map(Box::new),
+        weighting: proposal.weighting.clone(),
+        sealed_ballots: proposal.sealed_ballots,
+    }
+}
+
+// Only a closed round can be cloned, the clone would compete with it otherwise.
+#[ic_cdk::update]
+async fn clone_proposal(key: u64, new_window: NewWindow) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if visibility::can_see(&value, &caller) => value,
+        _ => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !editors::can_edit(&proposal, &caller) {
+        return Err(VoteError::access_rejected(Role::Editor, caller));
+    }
+
+    if proposal.is_active || proposal.closed_at.is_none() {
+        return Err(VoteError::ProposalStillOpen);
+    }
+
+    let new_key: u64 =
+        schedule::create_at_free_key(caller, next_round(key, &proposal, new_window)).await?;
+
+    // Written after the await, nothing else touches a proposal this young in the meantime.
+    PROPOSAL_MAP.with(|p| {
+        let mut map = p.borrow_mut();
+
+        if let Some(mut value) = map.get(&new_key) {
+            value.previous_round = Some(key);
+            map.insert(new_key, value);
+        }
+    });
+    changes::record_change(new_key);
+
+    Ok(new_key)
+}
+
+// The keys of the earlier rounds, the newest first. Rounds that were removed end the chain.
+#[ic_cdk::query]
+fn get_rounds(key: u64) -> Vec<u64> {
+    let caller: Principal = ic_cdk::caller();
+    let mut rounds: Vec<u64> = vec![];
+    let mut current: u64 = key;
+
+    while rounds.len() < MAX_ROUNDS {
+        let previous: u64 = match PROPOSAL_MAP.with(|p| p.borrow().get(&current)) {
+            Some(value) if visibility::can_see(&value, &caller) => match value.previous_round {
+                Some(previous) => previous,
+                None => break,
+            },
+            _ => break,
+        };
+
+        rounds.push(previous);
+        current = previous;
+    }
+
+    rounds
+}
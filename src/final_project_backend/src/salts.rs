@@ -0,0 +1,63 @@
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_certified_map::Hash;
+use ic_stable_structures::StableCell;
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, time::Duration};
+
+use crate::{get_memory, timers, Memory, VoteError, BALLOT_SECRET_MEMORY_ID};
+
+const DOMAIN: &[u8] = b"\x0eicp-vote-salts";
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/*
+    Chain links and receipts hash who voted what, and anybody can see those hashes.
+    With the voter known there are only four choices to try, so every such hash also takes
+    a salt only the voter (or everybody, where ballots are public) gets to see. The salts
+    come from a secret the canister draws from `raw_rand` once, right after it's installed.
+*/
+thread_local! {
+    // Empty until `raw_rand` answered.
+    static SECRET: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(StableCell::init(get_memory(BALLOT_SECRET_MEMORY_ID), vec![]).unwrap());
+}
+
+// Called from `init` and `post_upgrade`, does nothing once there is a secret.
+pub(crate) fn start() {
+    if SECRET.with(|s| s.borrow().get().is_empty()) {
+        timers::set_timer(Duration::ZERO, || ic_cdk::spawn(draw()));
+    }
+}
+
+async fn draw() {
+    match raw_rand().await {
+        Ok((bytes,)) => {
+            SECRET.with(|s| s.borrow_mut().set(bytes).unwrap());
+        }
+        Err(_) => {
+            timers::set_timer(RETRY_DELAY, || ic_cdk::spawn(draw()));
+        }
+    }
+}
+
+// Votes are turned away for the few seconds between the install and the secret being drawn.
+pub(crate) fn check_ready() -> Result<(), VoteError> {
+    if SECRET.with(|s| s.borrow().get().is_empty()) {
+        return Err(VoteError::SaltsNotReady);
+    }
+    Ok(())
+}
+
+// `purpose` keeps the salts of chain links and receipts apart. Only called after `check_ready`.
+pub(crate) fn derive(purpose: &[u8], id: &[u64]) -> Hash {
+    let secret: Vec<u8> = SECRET.with(|s| s.borrow().get().clone());
+    assert!(!secret.is_empty(), "no ballot secret yet");
+
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update(&secret);
+    hasher.update([purpose.len() as u8]);
+    hasher.update(purpose);
+    for part in id {
+        hasher.update(part.to_be_bytes());
+    }
+    hasher.finalize().into()
+}
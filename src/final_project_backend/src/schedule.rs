@@ -0,0 +1,228 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, time::Duration};
+
+use crate::{
+    archive, authenticated_caller, bans, config, create_as, get_memory, rate_limit, timers,
+    validation, CreateProposal, Memory, Role, VoteError, MAX_VALUE_SIZE, PROPOSAL_MAP,
+    SCHEDULE_MEMORY_ID,
+};
+
+// Anything shorter would mostly be a way to flood the canister with proposals.
+const MIN_INTERVAL: u64 = 3600 * 1_000_000_000;
+
+/*
+    A scheduled proposal opens on its own at `start_time`. With a recurrence it's
+    created again every `interval` (e.g. a monthly budget vote) until `remaining` runs out.
+    Every occurrence is a new proposal with the next free key, the field values are copied from `proposal`.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Recurrence {
+    interval: u64,          // In nanoseconds.
+    remaining: Option<u32>, // Occurrences left after the next one, `None` repeats forever.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ScheduledProposal {
+    owner: Principal,
+    proposal: CreateProposal,
+    start_time: u64, // When the next occurrence opens.
+    recurrence: Option<Recurrence>,
+    last_opened: Option<Result<u64, VoteError>>, // Key of the last occurrence, or why it couldn't be created.
+}
+
+impl Storable for ScheduledProposal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ScheduledProposal {
+    // The template is everything a proposal is made of, so it gets the same bound.
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Schedule id -> what to open next. Needed to re-arm the timers after an upgrade.
+    static SCHEDULES: RefCell<StableBTreeMap<u64, ScheduledProposal, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SCHEDULE_MEMORY_ID)));
+
+    // Keys handed to occurrences that are still being created, so two of them can't get the same one.
+    static RESERVED_KEYS: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = SCHEDULES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(id, schedule)| (id, schedule.start_time))
+            .collect()
+    });
+
+    for (id, start_time) in pending {
+        arm_timer(id, start_time);
+    }
+}
+
+fn arm_timer(id: u64, start_time: u64) {
+    let delay: u64 = start_time.saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || ic_cdk::spawn(open(id)));
+}
+
+// The first key after every proposal that exists, was archived or is about to be created. `None` once `u64::MAX` is taken.
+fn next_free_key() -> Option<u64> {
+    let last: Option<u64> = [
+        PROPOSAL_MAP.with(|p| p.borrow().last_key_value().map(|(key, _)| key)),
+        archive::last_key(),
+        RESERVED_KEYS.with(|r| r.borrow().last().copied()),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+
+    match last {
+        Some(key) => key.checked_add(1),
+        None => Some(0),
+    }
+}
+
+// Creates the proposal under a key nobody else can take while the creation awaits. Also used by `rounds`.
+pub(crate) async fn create_at_free_key(
+    owner: Principal,
+    proposal: CreateProposal,
+) -> Result<u64, VoteError> {
+    let key: u64 = match next_free_key() {
+        Some(value) => value,
+        None => return Err(VoteError::InvalidSchedule),
+    };
+
+    RESERVED_KEYS.with(|r| r.borrow_mut().insert(key));
+    let res = create_as(owner, key, proposal, false).await;
+    RESERVED_KEYS.with(|r| r.borrow_mut().remove(&key));
+    res.map(|_| key)
+}
+
+async fn open(id: u64) {
+    let schedule: ScheduledProposal = match SCHEDULES.with(|s| s.borrow().get(&id)) {
+        Some(value) => value,
+        None => return, // Cancelled in the meantime.
+    };
+
+    let res: Result<u64, VoteError> = create_at_free_key(schedule.owner, schedule.proposal).await;
+
+    // Re-read, the schedule could have been cancelled while the proposal was created.
+    let mut schedule: ScheduledProposal = match SCHEDULES.with(|s| s.borrow().get(&id)) {
+        Some(value) => value,
+        None => return,
+    };
+    schedule.last_opened = Some(res);
+
+    let next: Option<Recurrence> =
+        schedule
+            .recurrence
+            .take()
+            .and_then(|recurrence| match recurrence.remaining {
+                Some(0) => None,
+                remaining => Some(Recurrence {
+                    interval: recurrence.interval,
+                    remaining: remaining.map(|n| n - 1),
+                }),
+            });
+
+    match next {
+        Some(recurrence) => {
+            // Occurrences missed while the canister was stopped are skipped, not opened all at once.
+            let now: u64 = ic_cdk::api::time();
+            while schedule.start_time <= now {
+                schedule.start_time = schedule.start_time.saturating_add(recurrence.interval);
+            }
+            schedule.recurrence = Some(recurrence);
+
+            arm_timer(id, schedule.start_time);
+            SCHEDULES.with(|s| s.borrow_mut().insert(id, schedule));
+        }
+        None => {
+            SCHEDULES.with(|s| s.borrow_mut().remove(&id));
+        }
+    }
+}
+
+// Returns the schedule id. The proposal is checked now and once more when it opens.
+#[ic_cdk::update]
+fn schedule_proposal(
+    proposal: CreateProposal,
+    start_time: u64,
+    recurrence: Option<Recurrence>,
+) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+    bans::check(&caller)?;
+
+    if !config::can_create_proposals(&caller) {
+        return Err(VoteError::access_rejected(Role::Creator, caller));
+    }
+
+    validation::validate(&proposal)?;
+
+    let interval_ok: bool = recurrence
+        .as_ref()
+        .is_none_or(|recurrence| recurrence.interval >= MIN_INTERVAL);
+
+    if start_time <= ic_cdk::api::time() || !interval_ok {
+        return Err(VoteError::InvalidSchedule);
+    }
+
+    let schedule: ScheduledProposal = ScheduledProposal {
+        owner: caller,
+        proposal,
+        start_time,
+        recurrence,
+        last_opened: None,
+    };
+
+    let id: u64 = SCHEDULES.with(|s| {
+        let id: u64 = s.borrow().last_key_value().map_or(0, |(id, _)| id + 1);
+        s.borrow_mut().insert(id, schedule);
+        id
+    });
+    arm_timer(id, start_time);
+
+    Ok(id)
+}
+
+// Proposals that already opened stay, only the coming occurrences are dropped.
+#[ic_cdk::update]
+fn cancel_schedule(id: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let schedule: ScheduledProposal = match SCHEDULES.with(|s| s.borrow().get(&id)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchSchedule),
+    };
+
+    if schedule.owner != caller && !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    // The armed timer finds nothing and does nothing.
+    SCHEDULES.with(|s| s.borrow_mut().remove(&id));
+
+    Ok(())
+}
+
+// Schedules carry the full proposal, restricted ones included, so everybody only sees their own.
+#[ic_cdk::query]
+fn get_my_schedules() -> Vec<(u64, ScheduledProposal)> {
+    let caller: Principal = ic_cdk::caller();
+
+    SCHEDULES.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, schedule)| schedule.owner == caller)
+            .collect()
+    })
+}
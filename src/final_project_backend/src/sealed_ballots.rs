@@ -0,0 +1,631 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::{
+    call::{call, call_with_payment128},
+    management_canister::main::raw_rand,
+};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use ic_vetkeys::{
+    vetkd_api_types::{
+        VetKDCurve, VetKDDeriveKeyReply, VetKDDeriveKeyRequest, VetKDKeyId, VetKDPublicKeyReply,
+        VetKDPublicKeyRequest,
+    },
+    DerivedPublicKey, EncryptedVetKey, IbeCiphertext, TransportSecretKey, VetKey,
+};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap, time::Duration};
+
+use crate::{
+    add_to_counts, authenticated_caller, backup, ballot_chain, ballot_guard, ballots, changes,
+    check_open, check_voter, config, decision, deposits, events, execution, fetch_holdings,
+    get_memory, participation, pause, rate_limit, salts, ties::TieBreak, timers, visibility,
+    weight_of, Choice, Holdings, Memory, Proposal, StorablePrincipal, VoteError, PROPOSAL_MAP,
+    SEALED_BALLOT_MEMORY_ID, UNSEALING_MEMORY_ID,
+};
+
+const CONTEXT: &[u8] = b"icp-vote-sealed-ballots";
+const MAX_CIPHERTEXT_LEN: usize = 200; // Header, seed and masks, the choice and a principal.
+const MAX_ERROR_LEN: usize = 500;
+
+// Every ballot is a pairing and a scalar multiplication, this keeps a batch far below the instruction limit.
+const UNSEAL_BATCH: usize = 100;
+
+/*
+    Ballots of a `sealed_ballots` proposal are encrypted by the voter's frontend with vetKeys IBE,
+    to the key from `get_ballot_encryption_key` and the proposal key (8 bytes, big endian) as the identity.
+    The plaintext is the choice (0 approve, 1 reject, 2 pass, 3 abstain) followed by the voter's principal,
+    so a ballot copied from somebody else doesn't count.
+    Nobody, this canister included, can read them before voting closes: only then does the canister
+    ask for the proposal's vetKey, decrypt the ballots, count them and finalize as usual.
+    Until then the counts stay at zero and only who voted is known. A ballot that doesn't decrypt
+    (or isn't a choice the proposal takes) is spoiled, its voter still counts for the quorum.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct SealedBallot {
+    ciphertext: Vec<u8>,
+    weight: u64, // Worked out when it was cast, like for any other ballot.
+    cast_at: u64,
+}
+
+impl Storable for SealedBallot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SealedBallot {
+    const MAX_SIZE: u32 = 300;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Unsealing {
+    started_at: u64,
+    counted: u64,
+    spoiled: u64,
+    finished_at: Option<u64>,
+    error: Option<String>, // Why the last attempt stopped, `unseal_ballots` tries again.
+}
+
+impl Storable for Unsealing {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Unsealing {
+    const MAX_SIZE: u32 = 700;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // (proposal key, voter) -> the ballot, until it's counted.
+    static SEALED: RefCell<StableBTreeMap<(u64, StorablePrincipal), SealedBallot, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SEALED_BALLOT_MEMORY_ID)));
+
+    // Proposal key -> how opening its ballots went, the last time voting closed.
+    static UNSEALINGS: RefCell<StableBTreeMap<u64, Unsealing, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(UNSEALING_MEMORY_ID)));
+
+    // Keys being unsealed right now, with their vetKey once it's there. Lost on upgrade, `resume` starts over.
+    static UNSEALING: RefCell<BTreeMap<u64, Option<VetKey>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Called on creation. The results can't be public ballot by ballot, and a tie can't be seen in time to extend.
+pub(crate) fn validate(public_ballots: bool, tie_break: &TieBreak) -> Result<(), VoteError> {
+    if config::vetkd_key().is_none() {
+        return Err(VoteError::VetKdNotConfigured);
+    }
+
+    if public_ballots {
+        return Err(VoteError::PublicSealedBallots);
+    }
+
+    if matches!(tie_break, TieBreak::ExtendVoting(_)) {
+        return Err(VoteError::InvalidTieBreak);
+    }
+
+    Ok(())
+}
+
+fn key_id(name: String) -> VetKDKeyId {
+    VetKDKeyId {
+        curve: VetKDCurve::Bls12_381_G2,
+        name,
+    }
+}
+
+fn sealed_of(key: u64) -> Vec<(Principal, SealedBallot)> {
+    SEALED.with(|s| {
+        s.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), ballot)| (voter.0, ballot))
+            .collect()
+    })
+}
+
+// True while the proposal has ballots nobody has read yet.
+pub(crate) fn is_pending(key: u64) -> bool {
+    SEALED.with(|s| {
+        s.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .next()
+            .is_some_and(|((k, _), _)| k == key)
+    })
+}
+
+pub(crate) fn remove(key: u64) {
+    for (voter, _) in sealed_of(key) {
+        SEALED.with(|s| s.borrow_mut().remove(&(key, StorablePrincipal(voter))));
+    }
+    UNSEALINGS.with(|u| u.borrow_mut().remove(&key));
+}
+
+fn update(key: u64, f: impl FnOnce(&mut Unsealing)) {
+    UNSEALINGS.with(|u| {
+        let mut map = u.borrow_mut();
+
+        if let Some(mut unsealing) = map.get(&key) {
+            f(&mut unsealing);
+            map.insert(key, unsealing);
+        }
+    });
+}
+
+fn fail(key: u64, error: String) {
+    UNSEALING.with(|u| u.borrow_mut().remove(&key));
+    update(key, |unsealing| {
+        unsealing.error = Some(error.chars().take(MAX_ERROR_LEN).collect())
+    });
+}
+
+/*
+    Called by `decision::finalize` before anything else. A proposal with sealed ballots
+    keeps its outcome back until they're counted, then `finish` finalizes it again.
+*/
+pub(crate) fn hold_back(key: u64, proposal: &Proposal) -> bool {
+    if !proposal.sealed_ballots || !is_pending(key) {
+        return false;
+    }
+
+    UNSEALINGS.with(|u| {
+        u.borrow_mut().insert(
+            key,
+            Unsealing {
+                started_at: ic_cdk::api::time(),
+                counted: 0,
+                spoiled: 0,
+                finished_at: None,
+                error: None,
+            },
+        )
+    });
+    start(key);
+    true
+}
+
+// Runs from its own message, the caller of `hold_back` still has to write the closed proposal back.
+fn start(key: u64) {
+    if UNSEALING.with(|u| u.borrow().contains_key(&key)) {
+        return;
+    }
+    UNSEALING.with(|u| u.borrow_mut().insert(key, None));
+
+    timers::set_timer(Duration::ZERO, move || ic_cdk::spawn(unseal(key)));
+}
+
+// Timers don't survive upgrades, unsealings that were cut short start over.
+pub(crate) fn resume() {
+    let unfinished: Vec<u64> = UNSEALINGS.with(|u| {
+        u.borrow()
+            .iter()
+            .filter(|(_, unsealing)| unsealing.finished_at.is_none() && unsealing.error.is_none())
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    for key in unfinished {
+        start(key);
+    }
+}
+
+async fn derive_vetkey(key: u64) -> Result<VetKey, String> {
+    let name: String = config::vetkd_key().ok_or("no vetKD key is configured")?;
+    let input: Vec<u8> = key.to_be_bytes().to_vec();
+
+    let (seed,) = raw_rand()
+        .await
+        .map_err(|(code, message)| format!("raw_rand: {:?}: {}", code, message))?;
+    let transport: TransportSecretKey = TransportSecretKey::from_seed(seed)?;
+
+    let (public,): (VetKDPublicKeyReply,) = call(
+        Principal::management_canister(),
+        "vetkd_public_key",
+        (VetKDPublicKeyRequest {
+            canister_id: None,
+            context: CONTEXT.to_vec(),
+            key_id: key_id(name.clone()),
+        },),
+    )
+    .await
+    .map_err(|(code, message)| format!("vetkd_public_key: {:?}: {}", code, message))?;
+
+    let (derived,): (VetKDDeriveKeyReply,) = call_with_payment128(
+        Principal::management_canister(),
+        "vetkd_derive_key",
+        (VetKDDeriveKeyRequest {
+            input: input.clone(),
+            context: CONTEXT.to_vec(),
+            transport_public_key: transport.public_key(),
+            key_id: key_id(name),
+        },),
+        config::vetkd_derive_fee(),
+    )
+    .await
+    .map_err(|(code, message)| format!("vetkd_derive_key: {:?}: {}", code, message))?;
+
+    let public_key: DerivedPublicKey = DerivedPublicKey::deserialize(&public.public_key)
+        .map_err(|err| format!("public key: {:?}", err))?;
+    EncryptedVetKey::deserialize(&derived.encrypted_key)?.decrypt_and_verify(
+        &transport,
+        &public_key,
+        &input,
+    )
+}
+
+async fn unseal(key: u64) {
+    match derive_vetkey(key).await {
+        Ok(vetkey) => {
+            UNSEALING.with(|u| u.borrow_mut().insert(key, Some(vetkey)));
+            unseal_batch(key);
+        }
+        Err(error) => fail(key, error),
+    }
+}
+
+fn open(vetkey: &VetKey, voter: &Principal, ciphertext: &[u8]) -> Option<Choice> {
+    let plaintext: Vec<u8> = IbeCiphertext::deserialize(ciphertext)
+        .ok()?
+        .decrypt(vetkey)
+        .ok()?;
+    let (choice, bound_to) = plaintext.split_first()?;
+
+    if bound_to != voter.as_slice() {
+        return None;
+    }
+
+    match choice {
+        0 => Some(Choice::Approve),
+        1 => Some(Choice::Reject),
+        2 => Some(Choice::Pass),
+        3 => Some(Choice::Abstain),
+        _ => None,
+    }
+}
+
+// The `count` ballots cast first, they're counted in the order they came in.
+fn first_cast(
+    mut sealed: Vec<(Principal, SealedBallot)>,
+    count: usize,
+) -> Vec<(Principal, SealedBallot)> {
+    sealed.sort_by_key(|(voter, ballot)| (ballot.cast_at, *voter));
+    sealed.truncate(count);
+    sealed
+}
+
+/*
+    Counts up to `UNSEAL_BATCH` ballots, and schedules the next batch or `finish`.
+    The ballots and the chain get the time each ballot was cast, not the time it was opened.
+*/
+fn unseal_batch(key: u64) {
+    let vetkey: VetKey = match UNSEALING.with(|u| u.borrow().get(&key).cloned().flatten()) {
+        Some(value) => value,
+        None => return,
+    };
+
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => {
+            UNSEALING.with(|u| u.borrow_mut().remove(&key));
+            return;
+        }
+    };
+
+    // Opened again meanwhile, the next close starts over with every ballot still sealed.
+    if proposal.is_active {
+        fail(key, "voting opened again".to_string());
+        return;
+    }
+
+    let (mut counted, mut spoiled) = (0u64, 0u64);
+
+    for (voter, ballot) in first_cast(sealed_of(key), UNSEAL_BATCH) {
+        match open(&vetkey, &voter, &ballot.ciphertext) {
+            Some(Choice::Abstain) if !proposal.abstention.allowed => spoiled += 1,
+            Some(choice) => {
+                add_to_counts(&mut proposal, choice, ballot.weight);
+                ballots::record(voter, key, choice, ballot.weight, ballot.cast_at);
+                ballot_chain::append(key, voter, choice, ballot.cast_at);
+                counted += 1;
+            }
+            None => spoiled += 1,
+        }
+        SEALED.with(|s| s.borrow_mut().remove(&(key, StorablePrincipal(voter))));
+    }
+
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    update(key, |unsealing| {
+        unsealing.counted += counted;
+        unsealing.spoiled += spoiled;
+    });
+
+    if is_pending(key) {
+        timers::set_timer(Duration::ZERO, move || unseal_batch(key));
+    } else {
+        finish(key);
+    }
+}
+
+// Runs what `close` skipped while the ballots were sealed.
+fn finish(key: u64) {
+    UNSEALING.with(|u| u.borrow_mut().remove(&key));
+
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal: Proposal = match p.borrow().get(&key) {
+            Some(value) => value,
+            None => return,
+        };
+
+        decision::finalize(key, &mut proposal);
+        execution::queue(key, &proposal);
+        deposits::on_close(key, &proposal);
+
+        p.borrow_mut().insert(key, proposal);
+        changes::record_change(key);
+    });
+
+    update(key, |unsealing| {
+        unsealing.finished_at = Some(ic_cdk::api::time());
+        unsealing.error = None;
+    });
+}
+
+/*
+    The same checks as `vote`, the choice is the only thing left out.
+    A voter who may change their vote replaces their sealed ballot, it keeps its weight.
+*/
+fn cast(
+    caller: Principal,
+    key: u64,
+    ciphertext: Vec<u8>,
+    holdings: Option<Holdings>,
+) -> Result<(), VoteError> {
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal: Proposal = match p.borrow().get(&key) {
+            Some(value) => value,
+            None => return Err(VoteError::NoSuchProposal),
+        };
+
+        check_voter(key, &proposal, &caller)?;
+        salts::check_ready()?; // Needed once the ballot is unsealed.
+
+        if !proposal.sealed_ballots {
+            return Err(VoteError::ProposalNotSealed);
+        }
+
+        let sealed_key: (u64, StorablePrincipal) = (key, StorablePrincipal(caller));
+        let previous: Option<SealedBallot> = if proposal.voted.contains(&caller) {
+            match SEALED.with(|s| s.borrow().get(&sealed_key)) {
+                Some(ballot) if proposal.allow_vote_changes => Some(ballot),
+                ballot => {
+                    return Err(VoteError::AlreadyVoted {
+                        at: ballot.map(|ballot| ballot.cast_at),
+                    })
+                }
+            }
+        } else {
+            None
+        };
+
+        check_open(key, &proposal)?;
+
+        // Whether it decrypts can only be known after the close, it has to be a ciphertext at least.
+        if ciphertext.len() > MAX_CIPHERTEXT_LEN || IbeCiphertext::deserialize(&ciphertext).is_err()
+        {
+            return Err(VoteError::InvalidSealedBallot);
+        }
+
+        let weight: u64 = match &previous {
+            Some(ballot) => ballot.weight,
+            None => weight_of(key, &proposal, caller, &holdings)?,
+        };
+
+        SEALED.with(|s| {
+            s.borrow_mut().insert(
+                sealed_key,
+                SealedBallot {
+                    ciphertext,
+                    weight,
+                    cast_at: ic_cdk::api::time(),
+                },
+            )
+        });
+
+        // The counts don't move, so there is nothing for `early_close` to look at.
+        if previous.is_none() {
+            proposal.voted.push(caller);
+            participation::on_vote(caller);
+            deposits::on_vote(key, &proposal);
+            events::on_voted(key, &proposal, caller);
+            p.borrow_mut().insert(key, proposal);
+            changes::record_change(key);
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+async fn cast_sealed_vote(key: u64, ciphertext: Vec<u8>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    let _guard = ballot_guard::claim(key, caller)?;
+    let holdings: Option<Holdings> = fetch_holdings(key, caller).await?;
+    cast(caller, key, ciphertext, holdings)
+}
+
+// The derived public key ballots of `key` are encrypted to, the same for every proposal of this canister.
+#[ic_cdk::update]
+async fn get_ballot_encryption_key(key: u64) -> Result<Vec<u8>, VoteError> {
+    backup::check()?;
+    pause::check()?;
+    let caller: Principal = ic_cdk::caller();
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if visibility::can_see(&value, &caller) => value,
+        _ => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !proposal.sealed_ballots {
+        return Err(VoteError::ProposalNotSealed);
+    }
+
+    let name: String = config::vetkd_key().ok_or(VoteError::VetKdNotConfigured)?;
+    let (reply,): (VetKDPublicKeyReply,) = call(
+        Principal::management_canister(),
+        "vetkd_public_key",
+        (VetKDPublicKeyRequest {
+            canister_id: None,
+            context: CONTEXT.to_vec(),
+            key_id: key_id(name),
+        },),
+    )
+    .await
+    .map_err(|_| VoteError::VetKdCallFailed)?;
+
+    Ok(reply.public_key)
+}
+
+// Anybody may restart an unsealing that stopped, the ballots are counted the same either way.
+#[ic_cdk::update]
+fn unseal_ballots(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) if visibility::can_see(&value, &caller) => value,
+        _ => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.is_active || !proposal.sealed_ballots || !is_pending(key) {
+        return Err(VoteError::NothingToUnseal);
+    }
+
+    update(key, |unsealing| unsealing.error = None);
+    start(key);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_unsealing(key: u64) -> Option<Unsealing> {
+    if !visibility::can_see_key(key, &ic_cdk::caller()) {
+        return None;
+    }
+
+    UNSEALINGS.with(|u| u.borrow().get(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+        Made offline with the master key 7: the derived public key is 7·G2, and `VETKEY` is
+        the vetKey of proposal 7 under it. Every ciphertext uses a seed of its own.
+    */
+    const DERIVED_PUBLIC_KEY: &str = "8d0273f6bf31ed37c3b8d68083ec3d8e20b5f2cc170fa24b9b5be35b34ed013f9a921f1cad1644d4bdb14674247234c8049cd1dbb2d2c3581e54c088135fef36505a6823d61b859437bfc79b617030dc8b40e32bad1fa85b9c0f368af6d38d3c";
+    const VETKEY: &str = "b2361635a152ca6ba1f2b71974e25d2782974d543723ad3a041a5403c0cd72d840e727654af2bf47e8c9cdea9d47a39f";
+    const VOTER: &str = "tek7g-2zmny-nzjwg-ansf7-rkxv6-z32x6-3flbb-ous5d-pygjx-wkhlc-jae";
+    const OTHER_VOTER: &str = "3lzzj-nplwg-bdhrc-2kccb-uzgaa-5xd5j-fdx6r-tfrqd-zmrwe-q3jqx-nqe";
+
+    // Reject, bound to `VOTER`.
+    const REJECT: &str = "49432049424500018a839e97cfcc69701a6460411b6bf04d4cbf153cc2314792c9318c0aa31a3673bb2760c39ae4ab7ccbb1e996598de0a005813815d56e3b09ef24e3d21d49a5b2318819a207f2c76ad2d73ea14a9f31311f87884cf9ae608468233e172cdf85686dbac446b9d5eac1d42e80b670bc96847459f2a8467dc6380d53a2cf43a03bfe26a2e54613ad3bc6680ec9bde9cde1cb935fa61c9214f0252925b79367d0";
+    // Approve, bound to `OTHER_VOTER`.
+    const APPROVE_OF_OTHER_VOTER: &str = "4943204942450001a53923b7f464fbef523244300327aad048f88d08eba07ad11df281cb34effbd409be6dd2832d7472937e18af0afcb547034d4f7abb1b0c78900ffff3a310bd6bf0a01c7dc062492b8c4840fd281b2bd6cfdca688a220bd114957e05f4b7c552eec97fc5e4c84afb3db6b2904805b61844512671ff0c8c38493f1e2cef959adf9c7b9d7c6aa082bfbb89b35ff8067067836d1f81a202f7d341f0eaf027824";
+    // 9, which is no choice, bound to `VOTER`.
+    const NO_CHOICE: &str = "494320494245000187119f0b5eb551492a186a7644a911e2e983cf6245f4f78e88c971cbcdf2fbe5c6c7ad201f2cd4ebdb1e8eabd1f04d0514b97cc3081a3547bf23cbb66a62087f36fcb07e50982a472364553021fc44e00e0c7af7f59320345868fec304220db99e7ff59d9dd096ce2acd6bbd3b04bc9a3f0eb261c315c17a0664052bccf547e5a7fc503f959b43f3af1a525a573ba2e6cd72072988b68335078f2561ba18";
+    // Approve, bound to `VOTER`, but encrypted to proposal 8.
+    const FOR_OTHER_PROPOSAL: &str = "4943204942450001b5a394f290753c61d1a000c9b075be79044858ddbca7ce87e71d4d4b637314a51d5d7f76b737cf880808e261687afef3178d01606ffd12d89967255b2e65c9321cb12ef0acd24add07323f2846661ea376c8ef2e671548cf9941d811acd9ccec484b32e227c2e8d4425fa8f3ea145a5a6ab231bf5f3248b54c00b5b8fdd9e6724b63d79642b8bba3b86e7bf0424bb1ff5ba81399acf988d02058fbeba7c9";
+
+    fn vetkey() -> VetKey {
+        VetKey::deserialize(&hex::decode(VETKEY).unwrap()).unwrap()
+    }
+
+    fn principal(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
+    }
+
+    fn open_hex(voter: &str, ciphertext: &str) -> Option<Choice> {
+        open(
+            &vetkey(),
+            &principal(voter),
+            &hex::decode(ciphertext).unwrap(),
+        )
+    }
+
+    #[test]
+    fn vetkey_belongs_to_the_proposal() {
+        let public_key: DerivedPublicKey =
+            DerivedPublicKey::deserialize(&hex::decode(DERIVED_PUBLIC_KEY).unwrap()).unwrap();
+
+        assert!(ic_vetkeys::verify_bls_signature(
+            &public_key,
+            &7u64.to_be_bytes(),
+            &hex::decode(VETKEY).unwrap()
+        ));
+        assert!(!ic_vetkeys::verify_bls_signature(
+            &public_key,
+            &8u64.to_be_bytes(),
+            &hex::decode(VETKEY).unwrap()
+        ));
+    }
+
+    #[test]
+    fn opens_a_ballot_of_its_voter() {
+        assert!(matches!(open_hex(VOTER, REJECT), Some(Choice::Reject)));
+        assert!(matches!(
+            open_hex(OTHER_VOTER, APPROVE_OF_OTHER_VOTER),
+            Some(Choice::Approve)
+        ));
+    }
+
+    #[test]
+    fn spoils_what_does_not_match() {
+        // Copied from somebody else.
+        assert!(open_hex(VOTER, APPROVE_OF_OTHER_VOTER).is_none());
+        assert!(open_hex(OTHER_VOTER, REJECT).is_none());
+        assert!(open_hex(VOTER, NO_CHOICE).is_none());
+        assert!(open_hex(VOTER, FOR_OTHER_PROPOSAL).is_none());
+
+        let mut tampered: Vec<u8> = hex::decode(REJECT).unwrap();
+        let last: usize = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(open(&vetkey(), &principal(VOTER), &tampered).is_none());
+        assert!(open(&vetkey(), &principal(VOTER), &[]).is_none());
+    }
+
+    #[test]
+    fn counts_in_the_order_ballots_were_cast() {
+        let sealed = |cast_at: u64| SealedBallot {
+            ciphertext: vec![],
+            weight: 1,
+            cast_at,
+        };
+        let (first, second, third) = (
+            principal(OTHER_VOTER),
+            principal(VOTER),
+            Principal::anonymous(),
+        );
+        let ballots: Vec<(Principal, SealedBallot)> = vec![
+            (third, sealed(30)),
+            (first, sealed(10)),
+            (second, sealed(20)),
+        ];
+
+        let order: Vec<(Principal, u64)> = first_cast(ballots.clone(), 3)
+            .into_iter()
+            .map(|(voter, ballot)| (voter, ballot.cast_at))
+            .collect();
+        assert_eq!(order, vec![(first, 10), (second, 20), (third, 30)]);
+
+        let batch: Vec<Principal> = first_cast(ballots, 2)
+            .into_iter()
+            .map(|(voter, _)| voter)
+            .collect();
+        assert_eq!(batch, vec![first, second]);
+    }
+}
@@ -0,0 +1,355 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, CanisterInstallMode, CanisterSettings, CreateCanisterArgument,
+    InstallCodeArgument,
+};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    archive, authenticated_caller, config, create_for, get_memory, rate_limit, receipts,
+    stats::Stats, visibility, vote_as, Choice, CreateProposal, Memory, ProposalView, Role,
+    VoteError, PROPOSAL_MAP, SHARD_MEMORY_ID, SHARD_ROUTE_MEMORY_ID, SHARD_WASM_MEMORY_ID,
+};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// Like upgrades, the module goes to `install_code` in one message.
+const MAX_WASM_SIZE: usize = 2 * 1024 * 1024 - 64 * 1024;
+const MAX_SHARDS: u32 = 100;
+
+/*
+    When one canister isn't enough, this crate can run as a router in front of workers.
+    The router spawns its workers itself, from a module the admin uploaded (this crate again,
+    installed with the router as `router` in its config). A new proposal goes to the worker
+    its key hashes to, and stays there: the route is stored, so spawning more workers
+    doesn't move proposals that already exist.
+
+    Workers trust the router to say on whose behalf it calls, and nobody else.
+    Keys are one space across the router and its workers: once there is a worker the router
+    creates nothing itself, and a key it already holds can't go to a worker.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Shard {
+    canister: Principal,
+    created_at: u64,
+}
+
+impl Storable for Shard {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Shard {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Chunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Shard index -> worker, indexes start at 0 and have no gaps.
+    static SHARDS: RefCell<StableBTreeMap<u32, Shard, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SHARD_MEMORY_ID)));
+
+    // Proposal key -> index of the shard that holds it.
+    static ROUTES: RefCell<StableBTreeMap<u64, u32, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SHARD_ROUTE_MEMORY_ID)));
+
+    // Chunk index -> bytes of the module new workers are installed with.
+    static WASM: RefCell<StableBTreeMap<u32, Chunk, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SHARD_WASM_MEMORY_ID)));
+}
+
+fn check_admin() -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    Ok(())
+}
+
+// Only the router of a worker gets to act for somebody else.
+fn check_router() -> Result<(), VoteError> {
+    if config::router() != Some(ic_cdk::caller()) {
+        return Err(VoteError::access_rejected(Role::Router, ic_cdk::caller()));
+    }
+
+    Ok(())
+}
+
+fn shard_count() -> u32 {
+    SHARDS.with(|s| s.borrow().len() as u32)
+}
+
+fn shard(index: u32) -> Option<Principal> {
+    SHARDS.with(|s| s.borrow().get(&index).map(|shard| shard.canister))
+}
+
+fn route(key: u64) -> Option<Principal> {
+    shard(ROUTES.with(|r| r.borrow().get(&key))?)
+}
+
+// Where a new proposal goes, the first 8 bytes of the hashed key pick the shard.
+fn assign(key: u64) -> Option<u32> {
+    let count: u32 = shard_count();
+
+    if count == 0 {
+        return None;
+    }
+
+    let digest = Sha256::digest(key.to_be_bytes());
+    let prefix: [u8; 8] = digest[..8].try_into().unwrap();
+    Some((u64::from_be_bytes(prefix) % count as u64) as u32)
+}
+
+// Called when a proposal is created here, a router with workers leaves that to them.
+pub(crate) fn check_local_creation() -> Result<(), VoteError> {
+    if shard_count() > 0 {
+        return Err(VoteError::ProposalsAreSharded);
+    }
+
+    Ok(())
+}
+
+fn unavailable<E>(_: E) -> VoteError {
+    VoteError::ShardUnavailable
+}
+
+// Every chunk is `CHUNK_SIZE` bytes, only the last one may be shorter. Uploading an index again replaces it.
+#[ic_cdk::update]
+fn upload_shard_wasm_chunk(index: u32, bytes: Vec<u8>) -> Result<(), VoteError> {
+    check_admin()?;
+
+    if bytes.is_empty()
+        || bytes.len() > CHUNK_SIZE
+        || index as usize >= MAX_WASM_SIZE.div_ceil(CHUNK_SIZE)
+    {
+        return Err(VoteError::InvalidWasmChunk);
+    }
+
+    WASM.with(|w| w.borrow_mut().insert(index, Chunk(bytes)));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn clear_shard_wasm() -> Result<(), VoteError> {
+    check_admin()?;
+    WASM.with(|w| {
+        let mut map = w.borrow_mut();
+        let indexes: Vec<u32> = map.iter().map(|(index, _)| index).collect();
+
+        for index in indexes {
+            map.remove(&index);
+        }
+    });
+    Ok(())
+}
+
+/*
+    Creates a worker with `cycles` and installs the uploaded module on it, if it hashes to
+    `wasm_sha256` (so a half finished upload can't be installed by accident). The router
+    and the admin become its controllers.
+*/
+#[ic_cdk::update]
+async fn spawn_shard(wasm_sha256: Vec<u8>, cycles: u128) -> Result<Principal, VoteError> {
+    check_admin()?;
+
+    if shard_count() >= MAX_SHARDS {
+        return Err(VoteError::TooManyShards);
+    }
+
+    let wasm_module: Vec<u8> = WASM.with(|w| {
+        w.borrow()
+            .iter()
+            .enumerate()
+            .take_while(|(expected, (index, _))| *index as usize == *expected)
+            .flat_map(|(_, (_, chunk))| chunk.0)
+            .collect()
+    });
+
+    if wasm_module.len() > MAX_WASM_SIZE
+        || Sha256::digest(&wasm_module).as_slice() != wasm_sha256.as_slice()
+    {
+        return Err(VoteError::InvalidWasmChunk);
+    }
+
+    let settings: CanisterSettings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::id(), config::admin()]),
+        compute_allocation: None,
+        memory_allocation: None,
+        freezing_threshold: None,
+        reserved_cycles_limit: None,
+    };
+    let (record,) = create_canister(
+        CreateCanisterArgument {
+            settings: Some(settings),
+        },
+        cycles,
+    )
+    .await
+    .map_err(|_| VoteError::ShardSpawnFailed)?;
+
+    let arg: Option<config::CanisterConfig> = Some(config::worker_config(ic_cdk::id()));
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: record.canister_id,
+        wasm_module,
+        arg: Encode!(&arg).unwrap(),
+    })
+    .await
+    .map_err(|_| VoteError::ShardSpawnFailed)?;
+
+    SHARDS.with(|s| {
+        let mut map = s.borrow_mut();
+        let index: u32 = map.last_key_value().map_or(0, |(index, _)| index + 1);
+        map.insert(
+            index,
+            Shard {
+                canister: record.canister_id,
+                created_at: ic_cdk::api::time(),
+            },
+        );
+    });
+
+    Ok(record.canister_id)
+}
+
+#[ic_cdk::query]
+fn get_shards() -> Vec<Shard> {
+    SHARDS.with(|s| s.borrow().iter().map(|(_, shard)| shard).collect())
+}
+
+#[ic_cdk::query]
+fn get_shard_of(key: u64) -> Option<Principal> {
+    route(key)
+}
+
+// Router side: `create_proposal`, on whichever worker the key belongs to.
+#[ic_cdk::update]
+async fn routed_create_proposal(key: u64, proposal: CreateProposal) -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    // Proposals created here before the first worker keep their keys.
+    if PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) {
+        return Err(VoteError::AlreadyExists);
+    }
+    if archive::is_archived(key) {
+        return Err(VoteError::ProposalArchived);
+    }
+
+    let index: u32 = match ROUTES.with(|r| r.borrow().get(&key)) {
+        Some(index) => index,
+        None => assign(key).ok_or(VoteError::ShardUnavailable)?,
+    };
+    let worker: Principal = shard(index).ok_or(VoteError::ShardUnavailable)?;
+
+    let res: (Result<u64, VoteError>,) =
+        ic_cdk::call(worker, "router_create_proposal", (caller, key, proposal))
+            .await
+            .map_err(unavailable)?;
+
+    // A nonce retry can answer with another key, that one lives on the same worker.
+    let created: u64 = res.0?;
+    ROUTES.with(|r| r.borrow_mut().insert(created, index));
+    Ok(created)
+}
+
+#[ic_cdk::update]
+async fn routed_vote(key: u64, choice: Choice) -> Result<receipts::VoteReceipt, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    let worker: Principal = route(key).ok_or(VoteError::NoSuchProposal)?;
+    let res: (Result<receipts::VoteReceipt, VoteError>,) =
+        ic_cdk::call(worker, "router_vote", (caller, key, choice))
+            .await
+            .map_err(unavailable)?;
+
+    res.0
+}
+
+// Only from a composite query, like `archive::fetch`.
+pub(crate) async fn fetch(key: u64, caller: Principal) -> Option<ProposalView> {
+    let worker: Principal = route(key)?;
+    let res: Result<(Option<ProposalView>,), _> =
+        ic_cdk::call(worker, "router_get_proposal", (caller, key)).await;
+
+    res.ok()?.0
+}
+
+#[ic_cdk::query(composite = true)]
+async fn routed_get_proposal(key: u64) -> Option<ProposalView> {
+    fetch(key, ic_cdk::caller()).await
+}
+
+// The stats of all workers added up. A worker that doesn't answer makes the whole call fail.
+#[ic_cdk::query(composite = true)]
+async fn get_sharded_stats() -> Result<Stats, VoteError> {
+    let workers: Vec<Principal> = get_shards()
+        .into_iter()
+        .map(|shard| shard.canister)
+        .collect();
+    let mut total: Stats = Stats::default();
+
+    for worker in workers {
+        let (stats,): (Stats,) = ic_cdk::call(worker, "get_stats", ())
+            .await
+            .map_err(unavailable)?;
+        total.add(&stats);
+    }
+
+    Ok(total)
+}
+
+// Worker side: what the router forwards, run as the principal it names.
+#[ic_cdk::update]
+async fn router_create_proposal(
+    caller: Principal,
+    key: u64,
+    proposal: CreateProposal,
+) -> Result<u64, VoteError> {
+    check_router()?;
+    authenticated_caller()?;
+    create_for(caller, key, proposal, false).await
+}
+
+#[ic_cdk::update]
+async fn router_vote(
+    caller: Principal,
+    key: u64,
+    choice: Choice,
+) -> Result<receipts::VoteReceipt, VoteError> {
+    check_router()?;
+    authenticated_caller()?;
+    vote_as(caller, key, choice).await
+}
+
+#[ic_cdk::query]
+fn router_get_proposal(caller: Principal, key: u64) -> Option<ProposalView> {
+    check_router().ok()?;
+    PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .and_then(|proposal| visibility::present(proposal, &caller))
+}
@@ -0,0 +1,274 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{
+    authenticated_caller, get_memory, rate_limit, receipts, vote_as, Choice, Memory,
+    StorablePrincipal, VoteError, MAX_BATCH_SIZE, SIGNED_BALLOT_NONCE_MEMORY_ID,
+};
+
+// Signed by every ballot, so a signature can't be reused anywhere else.
+const DOMAIN: &[u8] = b"\x0eicp-vote-ballot";
+
+// SubjectPublicKeyInfo prefixes, the principal of a key is derived from its DER encoding.
+const ED25519_DER_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+const SECP256K1_DER_PREFIX: [u8; 23] = [
+    0x30, 0x56, 0x30, 0x10, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x05, 0x2b,
+    0x81, 0x04, 0x00, 0x0a, 0x03, 0x42, 0x00,
+];
+
+/*
+    Lets a relayer submit ballots for voters who never call the canister themselves.
+    A ballot counts for the self-authenticating principal of the key that signed it,
+    the exact same principal the voter would have with that key in a wallet.
+    Every ballot of a voter carries a higher nonce than the one before, so an old ballot
+    can't be submitted again to take back a vote they changed since.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) enum SignatureScheme {
+    Ed25519,   // 32 byte public key, 64 byte signature.
+    Secp256k1, // SEC1 public key, 64 byte signature over the SHA-256 of the message.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct SignedBallot {
+    proposal_key: u64,
+    choice: Choice,
+    expires_at: u64, // Nanoseconds, a leaked ballot can't be submitted forever.
+    nonce: u64,      // At least `get_next_ballot_nonce` of the voter.
+    scheme: SignatureScheme,
+    public_key: Vec<u8>,
+    signature: Vec<u8>, // Over `ballot_message(proposal_key, choice, expires_at, nonce)`.
+}
+
+thread_local! {
+    // Voter -> nonce of the last signed ballot submitted for them.
+    static NONCES: RefCell<StableBTreeMap<StorablePrincipal, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SIGNED_BALLOT_NONCE_MEMORY_ID)));
+}
+
+fn message(
+    canister: Principal,
+    proposal_key: u64,
+    choice: Choice,
+    expires_at: u64,
+    nonce: u64,
+) -> Vec<u8> {
+    let mut message: Vec<u8> = DOMAIN.to_vec();
+    message.push(canister.as_slice().len() as u8);
+    message.extend_from_slice(canister.as_slice());
+    message.extend_from_slice(&proposal_key.to_be_bytes());
+    message.push(choice.as_byte());
+    message.extend_from_slice(&expires_at.to_be_bytes());
+    message.extend_from_slice(&nonce.to_be_bytes());
+    message
+}
+
+fn ballot_message(proposal_key: u64, choice: Choice, expires_at: u64, nonce: u64) -> Vec<u8> {
+    message(ic_cdk::id(), proposal_key, choice, expires_at, nonce)
+}
+
+fn last_nonce(voter: Principal) -> u64 {
+    NONCES
+        .with(|n| n.borrow().get(&StorablePrincipal(voter)))
+        .unwrap_or(0)
+}
+
+/*
+    Checks the signature and returns the voter it belongs to. The nonce is used up here,
+    whatever becomes of the vote, the voter signs a new ballot to try again.
+*/
+fn verify(ballot: &SignedBallot) -> Result<Principal, VoteError> {
+    if ic_cdk::api::time() >= ballot.expires_at {
+        return Err(VoteError::BallotExpired);
+    }
+
+    let message: Vec<u8> = ballot_message(
+        ballot.proposal_key,
+        ballot.choice,
+        ballot.expires_at,
+        ballot.nonce,
+    );
+    let voter: Principal = signer(ballot, &message)?;
+
+    if ballot.nonce <= last_nonce(voter) {
+        return Err(VoteError::StaleBallot);
+    }
+    NONCES.with(|n| {
+        n.borrow_mut()
+            .insert(StorablePrincipal(voter), ballot.nonce)
+    });
+
+    Ok(voter)
+}
+
+// The principal of the key that signed `message`, if the signature is right.
+fn signer(ballot: &SignedBallot, message: &[u8]) -> Result<Principal, VoteError> {
+    let der: Vec<u8> = match ballot.scheme {
+        SignatureScheme::Ed25519 => {
+            use ed25519_dalek::{Signature, VerifyingKey};
+
+            let bytes: [u8; 32] = ballot
+                .public_key
+                .as_slice()
+                .try_into()
+                .map_err(|_| VoteError::InvalidSignature)?;
+            let key: VerifyingKey =
+                VerifyingKey::from_bytes(&bytes).map_err(|_| VoteError::InvalidSignature)?;
+            let signature: Signature = Signature::from_slice(&ballot.signature)
+                .map_err(|_| VoteError::InvalidSignature)?;
+
+            key.verify_strict(message, &signature)
+                .map_err(|_| VoteError::InvalidSignature)?;
+
+            [ED25519_DER_PREFIX.as_slice(), bytes.as_slice()].concat()
+        }
+        SignatureScheme::Secp256k1 => {
+            use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            let key: VerifyingKey = VerifyingKey::from_sec1_bytes(&ballot.public_key)
+                .map_err(|_| VoteError::InvalidSignature)?;
+            let signature: Signature = Signature::from_slice(&ballot.signature)
+                .map_err(|_| VoteError::InvalidSignature)?;
+
+            key.verify(message, &signature)
+                .map_err(|_| VoteError::InvalidSignature)?;
+
+            // Principals are derived from the uncompressed point, whichever form was sent.
+            let point = key.to_encoded_point(false);
+            [SECP256K1_DER_PREFIX.as_slice(), point.as_bytes()].concat()
+        }
+    };
+
+    Ok(Principal::self_authenticating(der))
+}
+
+// What a voter has to sign, so wallets don't have to rebuild the format.
+#[ic_cdk::query]
+fn get_ballot_message(proposal_key: u64, choice: Choice, expires_at: u64, nonce: u64) -> Vec<u8> {
+    ballot_message(proposal_key, choice, expires_at, nonce)
+}
+
+// The lowest nonce the next signed ballot of `voter` can have.
+#[ic_cdk::query]
+fn get_next_ballot_nonce(voter: Principal) -> u64 {
+    last_nonce(voter).saturating_add(1)
+}
+
+/*
+    Like `vote_many`, but every ballot is cast by the voter who signed it.
+    The relayer pays the rate limit, each voter still votes once per proposal.
+*/
+#[ic_cdk::update]
+async fn submit_signed_votes(
+    batch: Vec<SignedBallot>,
+) -> Vec<Result<receipts::VoteReceipt, VoteError>> {
+    if batch.len() > MAX_BATCH_SIZE {
+        ic_cdk::trap("Too many ballots in one batch.");
+    }
+
+    let checked: Result<Principal, VoteError> = authenticated_caller().and_then(|caller| {
+        rate_limit::consume(caller, batch.len() as u64)?;
+        Ok(caller)
+    });
+
+    if let Err(err) = checked {
+        return batch.iter().map(|_| Err(err.clone())).collect();
+    }
+
+    let mut results: Vec<Result<receipts::VoteReceipt, VoteError>> =
+        Vec::with_capacity(batch.len());
+
+    for ballot in batch {
+        let res: Result<receipts::VoteReceipt, VoteError> = match verify(&ballot) {
+            Ok(voter) => vote_as(voter, ballot.proposal_key, ballot.choice).await,
+            Err(err) => Err(err),
+        };
+        results.push(res);
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANISTER: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+    const EXPIRES_AT: u64 = 1_700_000_000_000_000_000;
+
+    // Signed over `message(CANISTER, 7, Approve, EXPIRES_AT, 1)` with the secret key [7; 32].
+    const ED25519_PUBLIC_KEY: &str =
+        "ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421eea691446d22c";
+    const ED25519_SIGNATURE: &str = "bc4f585c15b748e94b25b4942195b1a864895c486953407327165ed59f0ff94e1617e0a8b963df1dd86c70a35d24741e33daa7a2dc6e4bc5f37eba1ef193820e";
+    const SECP256K1_PUBLIC_KEY: &str =
+        "02989c0b76cb563971fdc9bef31ec06c3560f3249d6ee9e5d83c57625596e05f6f";
+    const SECP256K1_SIGNATURE: &str = "13dd2ed2f64b0ef51545af6596e187471a932f00cd64b6abd8f4a1420a7334100ac9d7850215a96c904714bf3f4e2b55e03c7b07b35032ca6d361c42e0ec4588";
+
+    fn ballot(scheme: SignatureScheme, public_key: &str, signature: &str) -> SignedBallot {
+        SignedBallot {
+            proposal_key: 7,
+            choice: Choice::Approve,
+            expires_at: EXPIRES_AT,
+            nonce: 1,
+            scheme,
+            public_key: hex::decode(public_key).unwrap(),
+            signature: hex::decode(signature).unwrap(),
+        }
+    }
+
+    fn signed_message(nonce: u64) -> Vec<u8> {
+        let canister: Principal = Principal::from_text(CANISTER).unwrap();
+        message(canister, 7, Choice::Approve, EXPIRES_AT, nonce)
+    }
+
+    fn tampered(mut ballot: SignedBallot) -> SignedBallot {
+        ballot.signature[0] ^= 1;
+        ballot
+    }
+
+    #[test]
+    fn ed25519_vector() {
+        let ballot: SignedBallot = ballot(
+            SignatureScheme::Ed25519,
+            ED25519_PUBLIC_KEY,
+            ED25519_SIGNATURE,
+        );
+
+        assert_eq!(
+            signer(&ballot, &signed_message(1)).unwrap().to_text(),
+            "tek7g-2zmny-nzjwg-ansf7-rkxv6-z32x6-3flbb-ous5d-pygjx-wkhlc-jae"
+        );
+        // The nonce is signed, a ballot can't be sent again under a new one.
+        assert!(signer(&ballot, &signed_message(2)).is_err());
+        assert!(signer(&tampered(ballot), &signed_message(1)).is_err());
+    }
+
+    #[test]
+    fn secp256k1_vector() {
+        let ballot: SignedBallot = ballot(
+            SignatureScheme::Secp256k1,
+            SECP256K1_PUBLIC_KEY,
+            SECP256K1_SIGNATURE,
+        );
+
+        assert_eq!(
+            signer(&ballot, &signed_message(1)).unwrap().to_text(),
+            "3lzzj-nplwg-bdhrc-2kccb-uzgaa-5xd5j-fdx6r-tfrqd-zmrwe-q3jqx-nqe"
+        );
+        assert!(signer(&ballot, &signed_message(2)).is_err());
+        assert!(signer(&tampered(ballot), &signed_message(1)).is_err());
+    }
+
+    #[test]
+    fn wrong_scheme_is_rejected() {
+        let ballot: SignedBallot = ballot(
+            SignatureScheme::Secp256k1,
+            ED25519_PUBLIC_KEY,
+            ED25519_SIGNATURE,
+        );
+
+        assert!(signer(&ballot, &signed_message(1)).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, decision::Outcome, get_memory, pause, visibility, Memory,
+    Proposal, Role, VoteError, PROPOSAL_MAP, SIGNED_RESULT_MEMORY_ID,
+};
+
+const DOMAIN: &[u8] = b"\x0eicp-vote-result";
+const MAX_ERROR_LEN: usize = 500;
+
+/*
+    Results signed with the canister's threshold ECDSA key can be checked anywhere
+    secp256k1 can be checked, e.g. with `ecrecover` in an Ethereum contract.
+    The signature is over `digest`, the SHA-256 of everything below worked out in `digest_of`.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct SignedResult {
+    approve: u64,
+    reject: u64,
+    pass: u64,
+    abstain: u64,
+    voter_count: u64,
+    outcome: Outcome,
+    finalized_at: u64,
+    digest: Vec<u8>,
+    signature: Option<Vec<u8>>, // 64 bytes r || s, `None` until the signing call came back.
+    error: Option<String>,      // Why the last signing attempt failed.
+}
+
+impl Storable for SignedResult {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SignedResult {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> its last result, replaced when a workflow closes voting again.
+    static SIGNED_RESULTS: RefCell<StableBTreeMap<u64, SignedResult, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SIGNED_RESULT_MEMORY_ID)));
+}
+
+pub(crate) fn key_id(name: String) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name,
+    }
+}
+
+// One key for all results, so verifiers only ever need one address.
+pub(crate) fn derivation_path() -> Vec<Vec<u8>> {
+    vec![b"results".to_vec()]
+}
+
+fn outcome_byte(outcome: Outcome) -> u8 {
+    match outcome {
+        Outcome::Passed => 0,
+        Outcome::Rejected => 1,
+        Outcome::QuorumNotMet => 2,
+    }
+}
+
+// Fixed size big endian fields, simple to rebuild in Solidity with `abi.encodePacked`.
+fn digest_of(key: u64, result: &SignedResult) -> Vec<u8> {
+    let canister: Principal = ic_cdk::id();
+
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update([canister.as_slice().len() as u8]);
+    hasher.update(canister.as_slice());
+    hasher.update(key.to_be_bytes());
+    hasher.update(result.approve.to_be_bytes());
+    hasher.update(result.reject.to_be_bytes());
+    hasher.update(result.pass.to_be_bytes());
+    hasher.update(result.abstain.to_be_bytes());
+    hasher.update(result.voter_count.to_be_bytes());
+    hasher.update([outcome_byte(result.outcome)]);
+    hasher.update(result.finalized_at.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+// Called by `decision::finalize`. The signature arrives in the background.
+pub(crate) fn request(key: u64, proposal: &Proposal) {
+    let (name, outcome) = match (config::ecdsa_key(), proposal.outcome) {
+        (Some(name), Some(outcome)) => (name, outcome),
+        _ => return,
+    };
+
+    let mut result: SignedResult = SignedResult {
+        approve: proposal.approve,
+        reject: proposal.reject,
+        pass: proposal.pass,
+        abstain: proposal.abstain,
+        voter_count: proposal.voter_count(),
+        outcome,
+        finalized_at: ic_cdk::api::time(),
+        digest: vec![],
+        signature: None,
+        error: None,
+    };
+    result.digest = digest_of(key, &result);
+
+    SIGNED_RESULTS.with(|s| s.borrow_mut().insert(key, result));
+    ic_cdk::spawn(sign(key, name));
+}
+
+pub(crate) fn digest(key: u64) -> Option<Vec<u8>> {
+    SIGNED_RESULTS
+        .with(|s| s.borrow().get(&key))
+        .map(|result| result.digest)
+}
+
+pub(crate) fn remove(key: u64) {
+    SIGNED_RESULTS.with(|s| s.borrow_mut().remove(&key));
+}
+
+async fn sign(key: u64, name: String) {
+    let digest: Vec<u8> = match SIGNED_RESULTS.with(|s| s.borrow().get(&key)) {
+        Some(result) => result.digest,
+        None => return,
+    };
+
+    let res = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: digest.clone(),
+        derivation_path: derivation_path(),
+        key_id: key_id(name),
+    })
+    .await;
+
+    SIGNED_RESULTS.with(|s| {
+        let mut map = s.borrow_mut();
+
+        // Only for the result that was signed, a newer one gets its own call.
+        let mut result: SignedResult = match map.get(&key) {
+            Some(result) if result.digest == digest => result,
+            _ => return,
+        };
+
+        match res {
+            Ok((response,)) => {
+                result.signature = Some(response.signature);
+                result.error = None;
+            }
+            Err((code, message)) => {
+                let error: String = format!("{:?}: {}", code, message);
+                result.error = Some(error.chars().take(MAX_ERROR_LEN).collect());
+            }
+        }
+        map.insert(key, result);
+    });
+}
+
+// Like the counts on the proposal, hidden while the results are under embargo.
+#[ic_cdk::query]
+fn get_signed_result(key: u64) -> Option<SignedResult> {
+    let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+    visibility::present(proposal, &ic_cdk::caller())?.outcome?;
+
+    SIGNED_RESULTS.with(|s| s.borrow().get(&key))
+}
+
+// The SEC1 compressed key every result is signed with.
+#[ic_cdk::update]
+async fn get_result_signing_key() -> Result<Vec<u8>, VoteError> {
+    pause::check()?;
+    let name: String = config::ecdsa_key().ok_or(VoteError::InvalidConfig)?;
+
+    let res = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: derivation_path(),
+        key_id: key_id(name),
+    })
+    .await;
+
+    match res {
+        Ok((response,)) => Ok(response.public_key),
+        Err(_) => Err(VoteError::SigningFailed),
+    }
+}
+
+// Signing calls can fail (e.g. out of cycles), the admin can try again once that's fixed.
+#[ic_cdk::update]
+fn retry_result_signing(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    let name: String = config::ecdsa_key().ok_or(VoteError::InvalidConfig)?;
+
+    match SIGNED_RESULTS.with(|s| s.borrow().get(&key)) {
+        Some(result) if result.signature.is_none() => {}
+        _ => return Err(VoteError::NothingToSign),
+    }
+
+    ic_cdk::spawn(sign(key, name));
+    Ok(())
+}
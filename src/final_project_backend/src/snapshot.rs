@@ -0,0 +1,247 @@
+use candid::{CandidType, Deserialize, Principal};
+use futures::future::join_all;
+use ic_stable_structures::StableBTreeMap;
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::{
+    authenticated_caller, changes, get_memory, icrc, staking, visibility, Memory, Proposal, Role,
+    StorablePrincipal, VoteError, MAX_BATCH_SIZE, PROPOSAL_MAP, SNAPSHOT_MEMORY_ID,
+};
+
+// How many imported balances one `import_snapshot` call may carry.
+const MAX_IMPORT_SIZE: usize = 1000;
+// Every voter is a ledger call while the creation awaits, ten rounds of `MAX_BATCH_SIZE` at most.
+const MAX_LEDGER_VOTERS: usize = MAX_BATCH_SIZE * 10;
+
+/*
+    For token-weighted votes the voting power of everybody eligible is fixed when the
+    proposal is created, so buying tokens in the middle of a vote doesn't change anything.
+    The balances either come from an ICRC-1 ledger or get imported by the owner.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) enum SnapshotSource {
+    Ledger {
+        ledger: Principal,
+        voters: Vec<Principal>,
+    },
+    Imported,
+    // Everybody with tokens locked in `staking`, weighted by their stake and its lock.
+    Staked,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Snapshot {
+    ledger: Option<Principal>,
+    taken_at: u64,
+    pub(crate) total_weight: u64,
+    pub(crate) voter_count: u64,
+    staked: bool,
+}
+
+thread_local! {
+    // (proposal key, voter) -> voting power.
+    static SNAPSHOTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SNAPSHOT_MEMORY_ID)));
+}
+
+pub(crate) fn validate(source: &SnapshotSource) -> Result<(), VoteError> {
+    match source {
+        SnapshotSource::Ledger { voters, .. } if voters.len() > MAX_LEDGER_VOTERS => {
+            Err(VoteError::TooManySnapshotVoters)
+        }
+        _ => Ok(()),
+    }
+}
+
+/*
+    Reads the balances of all listed voters. The ledger calls go out in batches
+    of `MAX_BATCH_SIZE` at a time so we don't overflow the canister's output queue.
+    Nothing is stored yet, the caller does that with `store` once the proposal exists.
+*/
+pub(crate) async fn take(
+    source: &SnapshotSource,
+) -> Result<(Snapshot, Vec<(Principal, u64)>), VoteError> {
+    let (ledger, voters) = match source {
+        SnapshotSource::Ledger { ledger, voters } => (*ledger, voters),
+        SnapshotSource::Imported => return Ok((summarize(None, &[], false), vec![])),
+        SnapshotSource::Staked => {
+            let balances: Vec<(Principal, u64)> = staking::powers()?;
+            return Ok((summarize(staking::ledger(), &balances, true), balances));
+        }
+    };
+
+    let mut balances: Vec<(Principal, u64)> = Vec::with_capacity(voters.len());
+
+    for batch in voters.chunks(MAX_BATCH_SIZE) {
+        let calls = batch
+            .iter()
+            .map(|voter| icrc::balance_of(ledger, icrc::Account::from(*voter)));
+
+        for (voter, res) in batch.iter().zip(join_all(calls).await) {
+            match res {
+                Ok(balance) => balances.push((*voter, balance)),
+                Err(_) => return Err(VoteError::LedgerCallFailed),
+            }
+        }
+    }
+
+    Ok((summarize(Some(ledger), &balances, false), balances))
+}
+
+fn summarize(ledger: Option<Principal>, balances: &[(Principal, u64)], staked: bool) -> Snapshot {
+    Snapshot {
+        ledger,
+        taken_at: ic_cdk::api::time(),
+        total_weight: balances
+            .iter()
+            .map(|(_, b)| *b)
+            .fold(0, u64::saturating_add),
+        voter_count: balances.len() as u64,
+        staked,
+    }
+}
+
+pub(crate) fn store(key: u64, balances: Vec<(Principal, u64)>) {
+    SNAPSHOTS.with(|s| {
+        let mut map = s.borrow_mut();
+
+        for (voter, balance) in balances {
+            map.insert((key, StorablePrincipal(voter)), balance);
+        }
+    });
+}
+
+pub(crate) fn remove(key: u64) {
+    SNAPSHOTS.with(|s| {
+        let voters: Vec<(u64, StorablePrincipal)> = s
+            .borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(k, _)| k)
+            .collect();
+
+        let mut map = s.borrow_mut();
+        for voter in voters {
+            map.remove(&voter);
+        }
+    });
+}
+
+// Where a new round of the proposal takes its snapshot from, the same voters get asked again.
+pub(crate) fn source_of(key: u64, snapshot: &Snapshot) -> SnapshotSource {
+    if snapshot.staked {
+        return SnapshotSource::Staked;
+    }
+
+    match snapshot.ledger {
+        Some(ledger) => SnapshotSource::Ledger {
+            ledger,
+            voters: voters(key, usize::MAX),
+        },
+        None => SnapshotSource::Imported,
+    }
+}
+
+// Everybody with voting power on proposal `key`, in principal order.
+pub(crate) fn voters(key: u64, limit: usize) -> Vec<Principal> {
+    SNAPSHOTS.with(|s| {
+        s.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .filter(|(_, balance)| *balance > 0)
+            .take(limit)
+            .map(|((_, voter), _)| voter.0)
+            .collect()
+    })
+}
+
+// Voting power of `voter` on proposal `key`. Proposals without a snapshot give everybody one vote.
+pub(crate) fn weight_of(key: u64, proposal: &Proposal, voter: &Principal) -> Option<u64> {
+    match proposal.snapshot {
+        Some(_) => SNAPSHOTS
+            .with(|s| s.borrow().get(&(key, StorablePrincipal(*voter))))
+            .filter(|weight| *weight > 0),
+        None => Some(1),
+    }
+}
+
+/*
+    For `Imported` snapshots the owner uploads the balances themselves,
+    in as many calls as needed, but only until the first vote comes in.
+*/
+#[ic_cdk::update]
+fn import_snapshot(key: u64, balances: Vec<(Principal, u64)>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if balances.len() > MAX_IMPORT_SIZE {
+        ic_cdk::trap("Too many balances in one import.");
+    }
+
+    PROPOSAL_MAP.with(|p| {
+        let proposal_opt: Option<Proposal> = p.borrow().get(&key);
+        let mut proposal: Proposal = match proposal_opt {
+            Some(value) => value,
+            None => return Err(VoteError::NoSuchProposal),
+        };
+
+        if proposal.owner != caller {
+            return Err(VoteError::access_rejected(Role::Owner, caller));
+        }
+
+        if proposal.voter_count() != 0 {
+            return Err(VoteError::ProposalHasVotes);
+        }
+
+        let snapshot: &mut Snapshot = match proposal.snapshot.as_mut() {
+            Some(snapshot) if snapshot.ledger.is_none() => snapshot,
+            _ => return Err(VoteError::NoImportedSnapshot),
+        };
+
+        for (voter, balance) in balances.iter() {
+            let old: Option<u64> =
+                SNAPSHOTS.with(|s| s.borrow().get(&(key, StorablePrincipal(*voter))));
+
+            match old {
+                Some(old) => snapshot.total_weight = snapshot.total_weight.saturating_sub(old),
+                None => snapshot.voter_count += 1,
+            }
+            snapshot.total_weight = snapshot.total_weight.saturating_add(*balance);
+        }
+
+        store(key, balances);
+        p.borrow_mut().insert(key, proposal);
+        changes::record_change(key);
+
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_voting_power(key: u64, voter: Principal) -> Option<u64> {
+    let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+
+    if !visibility::can_see(&proposal, &ic_cdk::caller()) {
+        return None;
+    }
+
+    weight_of(key, &proposal, &voter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_the_voters_asked_at_the_ledger() {
+        let source = |voters: usize| SnapshotSource::Ledger {
+            ledger: Principal::anonymous(),
+            voters: vec![Principal::anonymous(); voters],
+        };
+
+        assert!(validate(&source(MAX_LEDGER_VOTERS)).is_ok());
+        assert!(matches!(
+            validate(&source(MAX_LEDGER_VOTERS + 1)),
+            Err(VoteError::TooManySnapshotVoters)
+        ));
+    }
+}
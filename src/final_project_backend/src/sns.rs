@@ -0,0 +1,194 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{storable::Blob, StableBTreeMap};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::{get_memory, Memory, Proposal, StorablePrincipal, VoteError, SNS_VOTES_MEMORY_ID};
+
+// Neurons counted per voter at most, this also bounds the number of `list_neurons` pages we fetch.
+const MAX_NEURONS_PER_VOTER: usize = 100;
+const NEURONS_PAGE_SIZE: u32 = 50;
+
+/*
+    SNS-gated proposals take their voting power from neurons staked in an SNS governance canister.
+    Like in the real governance, the staked amount gets a bonus that grows with the dissolve delay,
+    and neurons with a too short dissolve delay can't vote at all.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct SnsGate {
+    governance: Principal,
+    min_dissolve_delay_seconds: u64,
+    max_dissolve_delay_seconds: u64, // Delays above this don't give a bigger bonus.
+    max_dissolve_delay_bonus_percentage: u64, // Bonus at `max_dissolve_delay_seconds`, e.g. 100 doubles the power.
+}
+
+/*
+    Only the parts of the SNS governance interface we need.
+    Candid ignores the fields we leave out when decoding.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct NeuronId {
+    id: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum DissolveState {
+    DissolveDelaySeconds(u64),
+    WhenDissolvedTimestampSeconds(u64),
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Neuron {
+    id: Option<NeuronId>,
+    cached_neuron_stake_e8s: u64,
+    neuron_fees_e8s: u64,
+    staked_maturity_e8s_equivalent: Option<u64>,
+    dissolve_state: Option<DissolveState>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct ListNeurons {
+    of_principal: Option<Principal>,
+    limit: u32,
+    start_page_at: Option<NeuronId>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct ListNeuronsResponse {
+    neurons: Vec<Neuron>,
+}
+
+type NeuronKey = Blob<32>;
+
+thread_local! {
+    // (proposal key, neuron id) -> principal that voted with it. A neuron can have several hotkeys, it still only votes once.
+    static USED_NEURONS: RefCell<StableBTreeMap<(u64, NeuronKey), StorablePrincipal, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SNS_VOTES_MEMORY_ID)));
+}
+
+impl Neuron {
+    fn key(&self) -> Option<NeuronKey> {
+        self.id
+            .as_ref()
+            .and_then(|id| NeuronKey::try_from(id.id.as_slice()).ok())
+    }
+
+    fn dissolve_delay_seconds(&self, now_seconds: u64) -> u64 {
+        match self.dissolve_state {
+            Some(DissolveState::DissolveDelaySeconds(delay)) => delay,
+            Some(DissolveState::WhenDissolvedTimestampSeconds(at)) => {
+                at.saturating_sub(now_seconds)
+            }
+            None => 0,
+        }
+    }
+
+    fn voting_power(&self, gate: &SnsGate, now_seconds: u64) -> u64 {
+        let delay: u64 = self.dissolve_delay_seconds(now_seconds);
+
+        if delay < gate.min_dissolve_delay_seconds {
+            return 0;
+        }
+
+        let stake: u128 = self
+            .cached_neuron_stake_e8s
+            .saturating_sub(self.neuron_fees_e8s)
+            .saturating_add(self.staked_maturity_e8s_equivalent.unwrap_or(0))
+            as u128;
+
+        let bonus: u128 = if gate.max_dissolve_delay_seconds == 0 {
+            0
+        } else {
+            let capped_delay: u128 = delay.min(gate.max_dissolve_delay_seconds) as u128;
+            stake * capped_delay * gate.max_dissolve_delay_bonus_percentage as u128
+                / (gate.max_dissolve_delay_seconds as u128 * 100)
+        };
+
+        u64::try_from(stake + bonus).unwrap_or(u64::MAX)
+    }
+}
+
+// Every neuron `voter` is a controller or hotkey of. This awaits, `claim` re-checks afterwards.
+pub(crate) async fn neurons_of(gate: &SnsGate, voter: Principal) -> Result<Vec<Neuron>, VoteError> {
+    let mut neurons: Vec<Neuron> = vec![];
+
+    loop {
+        let request: ListNeurons = ListNeurons {
+            of_principal: Some(voter),
+            limit: NEURONS_PAGE_SIZE,
+            start_page_at: neurons.last().and_then(|n| n.id.clone()),
+        };
+        let res: Result<(ListNeuronsResponse,), _> =
+            ic_cdk::call(gate.governance, "list_neurons", (request,)).await;
+
+        let page: Vec<Neuron> = match res {
+            Ok((response,)) => response.neurons,
+            Err(_) => return Err(VoteError::GovernanceCallFailed),
+        };
+        let last_page: bool = page.len() < NEURONS_PAGE_SIZE as usize;
+
+        neurons.extend(page);
+
+        if last_page || neurons.len() >= MAX_NEURONS_PER_VOTER {
+            neurons.truncate(MAX_NEURONS_PER_VOTER);
+            return Ok(neurons);
+        }
+    }
+}
+
+/*
+    Marks the voter's neurons that haven't voted on this proposal yet as used
+    and returns their combined voting power. Runs in the same message as the tally update.
+*/
+pub(crate) fn claim(
+    key: u64,
+    proposal: &Proposal,
+    voter: Principal,
+    neurons: &[Neuron],
+) -> Result<u64, VoteError> {
+    let gate: &SnsGate = match &proposal.sns_gate {
+        Some(gate) => gate,
+        None => return Ok(1),
+    };
+    let now_seconds: u64 = ic_cdk::api::time() / 1_000_000_000;
+
+    USED_NEURONS.with(|u| {
+        let mut used = u.borrow_mut();
+
+        let unused: Vec<(NeuronKey, u64)> = neurons
+            .iter()
+            .filter_map(|n| n.key().map(|k| (k, n.voting_power(gate, now_seconds))))
+            .filter(|(k, power)| *power > 0 && !used.contains_key(&(key, *k)))
+            .collect();
+
+        if unused.is_empty() {
+            return Err(VoteError::NotEligible);
+        }
+
+        let weight: u64 = unused
+            .iter()
+            .map(|(_, power)| *power)
+            .fold(0, u64::saturating_add);
+
+        for (neuron, _) in unused {
+            used.insert((key, neuron), StorablePrincipal(voter));
+        }
+
+        Ok(weight)
+    })
+}
+
+pub(crate) fn remove(key: u64) {
+    USED_NEURONS.with(|u| {
+        let neurons: Vec<(u64, NeuronKey)> = u
+            .borrow()
+            .range((key, NeuronKey::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(k, _)| k)
+            .collect();
+
+        let mut used = u.borrow_mut();
+        for neuron in neurons {
+            used.remove(&neuron);
+        }
+    });
+}
@@ -0,0 +1,132 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    get_memory, Memory, Proposal, PROPOSAL_MAP, SORT_CREATED_MEMORY_ID, SORT_ENDING_MEMORY_ID,
+    SORT_KEYS_MEMORY_ID, SORT_RECENT_MEMORY_ID, SORT_VOTES_MEMORY_ID,
+};
+
+/*
+    Orders for `list_proposals` besides key order. Each one has an index of its own,
+    kept up to date on every write, so a page costs the same however many proposals there are.
+*/
+#[derive(Debug, Clone, Copy, CandidType, Deserialize)]
+pub(crate) enum SortBy {
+    CreatedAsc,
+    CreatedDesc,
+    MostVotes,
+    EndingSoonest, // Only open proposals that have an end.
+}
+
+// What a proposal is filed under right now, so its old index entries can be found again.
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+struct SortKeys {
+    created_at: u64,
+    votes: u64,
+    ends_at: Option<u64>,
+}
+
+impl Storable for SortKeys {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SortKeys {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static SORT_KEYS: RefCell<StableBTreeMap<u64, SortKeys, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SORT_KEYS_MEMORY_ID)));
+
+    // The btree only iterates forwards, descending orders are stored with the value flipped.
+    static BY_CREATED: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SORT_CREATED_MEMORY_ID)));
+    static BY_RECENT: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SORT_RECENT_MEMORY_ID)));
+    static BY_VOTES: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SORT_VOTES_MEMORY_ID)));
+    static BY_ENDING: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SORT_ENDING_MEMORY_ID)));
+}
+
+fn file(key: u64, keys: &SortKeys, add: bool) {
+    let entries = [
+        (&BY_CREATED, Some(keys.created_at)),
+        (&BY_RECENT, Some(u64::MAX - keys.created_at)),
+        (&BY_VOTES, Some(u64::MAX - keys.votes)),
+        (&BY_ENDING, keys.ends_at),
+    ];
+
+    for (index, value) in entries {
+        if let Some(value) = value {
+            index.with(|i| {
+                if add {
+                    i.borrow_mut().insert((value, key), ());
+                } else {
+                    i.borrow_mut().remove(&(value, key));
+                }
+            });
+        }
+    }
+}
+
+// Called after every write to `PROPOSAL_MAP`, including removals.
+pub(crate) fn refresh(key: u64) {
+    let old: Option<SortKeys> = SORT_KEYS.with(|s| s.borrow().get(&key));
+    let new: Option<SortKeys> =
+        PROPOSAL_MAP
+            .with(|p| p.borrow().get(&key))
+            .map(|proposal: Proposal| SortKeys {
+                created_at: proposal.created_at,
+                votes: proposal.voter_count(),
+                ends_at: proposal.voting_ends_at.filter(|_| proposal.is_active),
+            });
+
+    if old == new {
+        return;
+    }
+
+    if let Some(old) = &old {
+        file(key, old, false);
+    }
+
+    match new {
+        Some(new) => {
+            file(key, &new, true);
+            SORT_KEYS.with(|s| s.borrow_mut().insert(key, new));
+        }
+        None => {
+            SORT_KEYS.with(|s| s.borrow_mut().remove(&key));
+        }
+    }
+}
+
+// Files whatever was stored before there were indexes, called from `post_upgrade`.
+pub(crate) fn backfill() {
+    let indexed: u64 = SORT_KEYS.with(|s| s.borrow().len());
+    let stored: u64 = PROPOSAL_MAP.with(|p| p.borrow().len());
+
+    if indexed == stored {
+        return;
+    }
+
+    let keys: Vec<u64> = PROPOSAL_MAP.with(|p| p.borrow().iter().map(|(key, _)| key).collect());
+    for key in keys {
+        refresh(key);
+    }
+}
+
+// Hands the proposal keys in `sort` order to `f`.
+pub(crate) fn with_keys<R>(sort: SortBy, f: impl FnOnce(&mut dyn Iterator<Item = u64>) -> R) -> R {
+    let index = match sort {
+        SortBy::CreatedAsc => &BY_CREATED,
+        SortBy::CreatedDesc => &BY_RECENT,
+        SortBy::MostVotes => &BY_VOTES,
+        SortBy::EndingSoonest => &BY_ENDING,
+    };
+
+    index.with(|i| f(&mut i.borrow().iter().map(|((_, key), _)| key)))
+}
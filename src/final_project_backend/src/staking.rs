@@ -0,0 +1,205 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, ballots, config, get_memory, icrc, Memory, StorablePrincipal, VoteError,
+    PROPOSAL_MAP, STAKE_MEMORY_ID,
+};
+
+// Stakes are kept here, apart from the treasury and the deposit escrow.
+const STAKE_SUBACCOUNT: [u8; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2,
+];
+
+/*
+    Voting power for those who commit to the outcome: tokens locked in the canister count
+    for a proposal with a `Staked` snapshot. A longer lock counts more, the power is the
+    stake plus up to the stake again as the remaining lock approaches `max_lock_duration`.
+    Like any snapshot the power is read when the proposal is created.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct StakingConfig {
+    ledger: Principal,
+    max_lock_duration: u64, // Nanoseconds.
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Stake {
+    amount: u64,
+    locked_until: u64,
+    staked_at: u64,
+}
+
+impl Storable for Stake {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Stake {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Staker -> their stake, one per staker. Staking again adds to it.
+    static STAKES: RefCell<StableBTreeMap<StorablePrincipal, Stake, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(STAKE_MEMORY_ID)));
+}
+
+pub(crate) fn validate(staking: &StakingConfig) -> bool {
+    staking.max_lock_duration > 0
+}
+
+fn stake_account() -> icrc::Account {
+    icrc::Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(STAKE_SUBACCOUNT.to_vec()),
+    }
+}
+
+fn power_of(stake: &Stake, max_lock_duration: u64, now: u64) -> u64 {
+    let remaining: u64 = stake
+        .locked_until
+        .saturating_sub(now)
+        .min(max_lock_duration);
+    let bonus: u128 = stake.amount as u128 * remaining as u128 / max_lock_duration as u128;
+
+    stake.amount.saturating_add(bonus as u64)
+}
+
+// Everybody with voting power right now, for a `Staked` snapshot.
+pub(crate) fn powers() -> Result<Vec<(Principal, u64)>, VoteError> {
+    let staking: StakingConfig = config::staking().ok_or(VoteError::StakingDisabled)?;
+    let now: u64 = ic_cdk::api::time();
+
+    Ok(STAKES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(staker, stake)| (staker.0, power_of(&stake, staking.max_lock_duration, now)))
+            .filter(|(_, power)| *power > 0)
+            .collect()
+    }))
+}
+
+pub(crate) fn ledger() -> Option<Principal> {
+    config::staking().map(|staking| staking.ledger)
+}
+
+// Read and written without an await in between, a stake that came in meanwhile isn't lost.
+fn add(staker: Principal, amount: u64, locked_until: u64) -> Stake {
+    let stake: Stake = match get_stake(staker) {
+        Some(mut stake) => {
+            stake.amount = stake.amount.saturating_add(amount);
+            stake.locked_until = stake.locked_until.max(locked_until);
+            stake
+        }
+        None => Stake {
+            amount,
+            locked_until,
+            staked_at: ic_cdk::api::time(),
+        },
+    };
+    STAKES.with(|s| {
+        s.borrow_mut()
+            .insert(StorablePrincipal(staker), stake.clone())
+    });
+
+    stake
+}
+
+/*
+    The staker approves the canister for `amount` (plus the fee) on the ledger beforehand.
+    Staking again adds to the stake, the lock ends at the later of the two.
+*/
+#[ic_cdk::update]
+async fn stake(amount: u64, lock_duration: u64) -> Result<Stake, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    let staking: StakingConfig = config::staking().ok_or(VoteError::StakingDisabled)?;
+
+    if amount == 0 || lock_duration == 0 || lock_duration > staking.max_lock_duration {
+        return Err(VoteError::InvalidStake);
+    }
+
+    icrc::transfer_from(staking.ledger, caller.into(), stake_account(), amount)
+        .await
+        .map_err(|_| VoteError::LedgerCallFailed)?;
+
+    let now: u64 = ic_cdk::api::time();
+    Ok(add(caller, amount, now.saturating_add(lock_duration)))
+}
+
+/*
+    Pays the whole stake back, less the ledger fee, once the lock is over
+    and no proposal the staker voted on is still open. Returns the block.
+*/
+#[ic_cdk::update]
+async fn unstake() -> Result<u64, VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    let ledger: Principal = ledger().ok_or(VoteError::StakingDisabled)?;
+
+    let stake: Stake = match get_stake(caller) {
+        Some(value) => value,
+        None => return Err(VoteError::InvalidStake),
+    };
+
+    if ic_cdk::api::time() < stake.locked_until {
+        return Err(VoteError::StakeLocked {
+            until: stake.locked_until,
+        });
+    }
+
+    let open_votes: bool = ballots::keys_of(caller).iter().any(|key| {
+        PROPOSAL_MAP
+            .with(|p| p.borrow().get(key))
+            .is_some_and(|proposal| proposal.is_active)
+    });
+
+    if open_votes {
+        return Err(VoteError::VotesStillOpen);
+    }
+
+    // Taken out before the await, so a second call finds nothing left to pay back.
+    STAKES.with(|s| s.borrow_mut().remove(&StorablePrincipal(caller)));
+
+    let res: Result<u64, String> = match icrc::fee(ledger).await {
+        Ok(fee) if fee >= stake.amount => Err("the fee is larger than the stake".to_string()),
+        Ok(fee) => {
+            icrc::transfer(
+                ledger,
+                Some(STAKE_SUBACCOUNT.to_vec()),
+                caller.into(),
+                stake.amount - fee,
+                None,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+
+    // The staker may have staked again in the meantime, the two are put back together.
+    res.map_err(|_| {
+        add(caller, stake.amount, stake.locked_until);
+        VoteError::LedgerCallFailed
+    })
+}
+
+#[ic_cdk::query]
+fn get_stake(staker: Principal) -> Option<Stake> {
+    STAKES.with(|s| s.borrow().get(&StorablePrincipal(staker)))
+}
+
+// What the staker would bring into a proposal created right now.
+#[ic_cdk::query]
+fn get_staked_power(staker: Principal) -> u64 {
+    match (config::staking(), get_stake(staker)) {
+        (Some(staking), Some(stake)) => {
+            power_of(&stake, staking.max_lock_duration, ic_cdk::api::time())
+        }
+        _ => 0,
+    }
+}
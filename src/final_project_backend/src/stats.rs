@@ -0,0 +1,171 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    decision::Outcome, get_memory, Memory, Proposal, PROPOSAL_MAP, STATS_INDEX_MEMORY_ID,
+    STATS_MEMORY_ID,
+};
+
+/*
+    Counting proposals by status on every call would mean scanning all of them.
+    Instead every write (see `changes::record_change`) moves the proposal from the counter
+    of its old status to the one of its new status. Archived proposals keep being counted.
+*/
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub(crate) struct Stats {
+    total: u64,
+    open: u64,
+    closed: u64, // Every proposal that isn't open, whether it has a result or not.
+    passed: u64,
+    rejected: u64, // Quorum not met included.
+    executed: u64,
+}
+
+impl Stats {
+    // For adding up the stats of several shards.
+    pub(crate) fn add(&mut self, other: &Stats) {
+        self.total += other.total;
+        self.open += other.open;
+        self.closed += other.closed;
+        self.passed += other.passed;
+        self.rejected += other.rejected;
+        self.executed += other.executed;
+    }
+}
+
+impl Storable for Stats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+const OPEN: u8 = 0;
+const CLOSED: u8 = 1;
+const PASSED: u8 = 2;
+const REJECTED: u8 = 3;
+
+thread_local! {
+    static STATS: RefCell<StableCell<Stats, Memory>> = RefCell::new(StableCell::init(get_memory(STATS_MEMORY_ID), Stats::default()).unwrap());
+
+    // Proposal key -> status it's counted under.
+    static COUNTED_AS: RefCell<StableBTreeMap<u64, u8, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(STATS_INDEX_MEMORY_ID)));
+}
+
+// A result under embargo isn't known yet, not even as a number.
+fn status_of(proposal: &Proposal) -> u8 {
+    if proposal.is_active {
+        return OPEN;
+    }
+
+    match proposal.outcome {
+        _ if proposal.results_hidden_until.is_some() => CLOSED,
+        Some(Outcome::Passed) => PASSED,
+        Some(Outcome::Rejected) | Some(Outcome::QuorumNotMet) => REJECTED,
+        None => CLOSED,
+    }
+}
+
+fn update(f: impl FnOnce(&mut Stats)) {
+    STATS.with(|s| {
+        let mut stats: Stats = s.borrow().get().clone();
+        f(&mut stats);
+        s.borrow_mut().set(stats).unwrap();
+    });
+}
+
+fn count(stats: &mut Stats, status: u8, added: bool) {
+    let delta = |n: &mut u64| {
+        *n = if added { *n + 1 } else { n.saturating_sub(1) };
+    };
+
+    delta(&mut stats.total);
+    match status {
+        OPEN => delta(&mut stats.open),
+        PASSED => {
+            delta(&mut stats.closed);
+            delta(&mut stats.passed);
+        }
+        REJECTED => {
+            delta(&mut stats.closed);
+            delta(&mut stats.rejected);
+        }
+        _ => delta(&mut stats.closed),
+    }
+}
+
+// Called after every write to `PROPOSAL_MAP`, including removals.
+pub(crate) fn refresh(key: u64) {
+    let old: Option<u8> = COUNTED_AS.with(|c| c.borrow().get(&key));
+    let new: Option<u8> = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .map(|p| status_of(&p));
+
+    if old == new {
+        return;
+    }
+
+    update(|stats| {
+        if let Some(old) = old {
+            count(stats, old, false);
+        }
+        if let Some(new) = new {
+            count(stats, new, true);
+        }
+    });
+
+    COUNTED_AS.with(|c| match new {
+        Some(new) => c.borrow_mut().insert(key, new),
+        None => c.borrow_mut().remove(&key),
+    });
+}
+
+// Counts whatever was stored before there were counters, called from `post_upgrade`.
+pub(crate) fn backfill() {
+    let counted: u64 = COUNTED_AS.with(|c| c.borrow().len());
+    let stored: u64 = PROPOSAL_MAP.with(|p| p.borrow().len());
+
+    if counted == stored {
+        return;
+    }
+
+    let keys: Vec<u64> = PROPOSAL_MAP.with(|p| p.borrow().iter().map(|(key, _)| key).collect());
+    for key in keys {
+        refresh(key);
+    }
+}
+
+// An archived proposal is final, it keeps the status it was counted under.
+pub(crate) fn keep_archived(key: u64) {
+    COUNTED_AS.with(|c| c.borrow_mut().remove(&key));
+}
+
+pub(crate) fn on_executed() {
+    update(|stats| stats.executed += 1);
+}
+
+// For `/metrics`.
+pub(crate) fn by_status() -> Vec<(&'static str, u64)> {
+    let stats: Stats = STATS.with(|s| s.borrow().get().clone());
+
+    vec![
+        ("open", stats.open),
+        ("closed", stats.closed),
+        ("passed", stats.passed),
+        ("rejected", stats.rejected),
+        ("executed", stats.executed),
+    ]
+}
+
+pub(crate) fn current() -> Stats {
+    STATS.with(|s| s.borrow().get().clone())
+}
+
+#[ic_cdk::query]
+fn get_stats() -> Stats {
+    current()
+}
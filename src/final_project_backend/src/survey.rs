@@ -0,0 +1,289 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    attestation, authenticated_caller, bans, changes, drafts, editors, embargo, events, get_memory,
+    listing, participation, rate_limit, visibility, voter_list, window, workflow, Memory, Proposal,
+    VoteError, MAX_BATCH_SIZE, PROPOSAL_MAP, SURVEY_TALLY_MEMORY_ID, SURVEY_TEXT_MEMORY_ID,
+};
+
+const MAX_QUESTIONS: usize = 20;
+const MAX_OPTIONS: usize = 20;
+const MAX_QUESTION_LEN: usize = 500;
+const MAX_OPTION_LEN: usize = 100;
+const MAX_ANSWER_LEN: usize = 500;
+
+const FREE_TEXT: u32 = u32::MAX;
+
+/*
+    A survey asks several questions at once instead of approve or reject. Every respondent
+    counts once, so surveys can't be weighted or gated. The answers aren't kept per
+    respondent: options only go into the tallies, free text is filed by question in arrival order.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Survey {
+    questions: Vec<Question>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Question {
+    text: String,
+    options: Vec<String>, // Empty for a question that only takes free text.
+    allow_free_text: bool,
+    required: bool,
+}
+
+// One per question, in the order of the questions. An answer can pick an option, write something, or both.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Answer {
+    option: Option<u32>,
+    text: Option<String>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct QuestionResult {
+    counts: Vec<u64>, // By option.
+    free_text_answers: u64,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct SurveyResults {
+    respondents: u64,
+    questions: Vec<QuestionResult>,
+}
+
+type TallyKey = (u64, (u32, u32)); // (proposal key, (question, option))
+type TextKey = (u64, (u32, u64)); // (proposal key, (question, index))
+
+struct FreeText(String);
+
+impl Storable for FreeText {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(&self.0).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        FreeText(Decode!(bytes.as_ref(), String).unwrap())
+    }
+}
+
+impl BoundedStorable for FreeText {
+    const MAX_SIZE: u32 = MAX_ANSWER_LEN as u32 + 20;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // How often an option was picked. Option `FREE_TEXT` counts the free text answers.
+    static TALLIES: RefCell<StableBTreeMap<TallyKey, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SURVEY_TALLY_MEMORY_ID)));
+
+    // Free text answers, in the order they came in.
+    static TEXTS: RefCell<StableBTreeMap<TextKey, FreeText, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(SURVEY_TEXT_MEMORY_ID)));
+}
+
+pub(crate) fn validate(survey: &Survey) -> Result<(), VoteError> {
+    if survey.questions.is_empty() || survey.questions.len() > MAX_QUESTIONS {
+        return Err(VoteError::InvalidSurvey);
+    }
+
+    for question in &survey.questions {
+        let valid: bool = !question.text.is_empty()
+            && question.text.len() <= MAX_QUESTION_LEN
+            && question.options.len() <= MAX_OPTIONS
+            && question
+                .options
+                .iter()
+                .all(|option| !option.is_empty() && option.len() <= MAX_OPTION_LEN)
+            && (!question.options.is_empty() || question.allow_free_text);
+
+        if !valid {
+            return Err(VoteError::InvalidSurvey);
+        }
+    }
+
+    Ok(())
+}
+
+// Every question has to be there, the required ones answered.
+fn check_answers(survey: &Survey, answers: &[Answer]) -> Result<(), VoteError> {
+    if answers.len() != survey.questions.len() {
+        return Err(VoteError::IncompleteSurvey);
+    }
+
+    for (question, answer) in survey.questions.iter().zip(answers) {
+        if answer
+            .option
+            .is_some_and(|option| option as usize >= question.options.len())
+        {
+            return Err(VoteError::InvalidAnswer);
+        }
+
+        if let Some(text) = &answer.text {
+            if !question.allow_free_text || text.is_empty() || text.len() > MAX_ANSWER_LEN {
+                return Err(VoteError::InvalidAnswer);
+            }
+        }
+
+        if question.required && answer.option.is_none() && answer.text.is_none() {
+            return Err(VoteError::IncompleteSurvey);
+        }
+    }
+
+    Ok(())
+}
+
+fn count(key: u64, question: u32, option: u32) -> u64 {
+    TALLIES.with(|t| t.borrow().get(&(key, (question, option))).unwrap_or(0))
+}
+
+fn increment(key: u64, question: u32, option: u32) -> u64 {
+    let count: u64 = count(key, question, option);
+    TALLIES.with(|t| t.borrow_mut().insert((key, (question, option)), count + 1));
+    count
+}
+
+pub(crate) fn remove(key: u64) {
+    TALLIES.with(|t| {
+        let mut map = t.borrow_mut();
+        let entries: Vec<TallyKey> = map
+            .range((key, (0, 0))..=(key, (u32::MAX, u32::MAX)))
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in entries {
+            map.remove(&entry);
+        }
+    });
+    TEXTS.with(|t| {
+        let mut map = t.borrow_mut();
+        let entries: Vec<TextKey> = map
+            .range((key, (0, 0))..=(key, (u32::MAX, u64::MAX)))
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in entries {
+            map.remove(&entry);
+        }
+    });
+}
+
+// Answers are final, like a vote on a proposal without vote changes.
+#[ic_cdk::update]
+fn submit_survey(key: u64, answers: Vec<Answer>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+    bans::check(&caller)?;
+
+    let mut proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !visibility::can_see(&proposal, &caller) {
+        return Err(VoteError::NoSuchProposal);
+    }
+
+    let survey: Survey = match &proposal.survey {
+        Some(value) => value.clone(),
+        None => return Err(VoteError::NotASurvey),
+    };
+    voter_list::check(key, &proposal, &caller)?;
+    attestation::check(&proposal, &caller)?;
+
+    if proposal.voted.contains(&caller) {
+        return Err(VoteError::AlreadyVoted { at: None });
+    }
+
+    if !proposal.is_active || !workflow::voting_allowed(key) || drafts::is_pending(key) {
+        return Err(VoteError::not_active(&proposal));
+    }
+
+    if proposal.listing == listing::ListingStatus::Suspended {
+        return Err(VoteError::ProposalSuspended);
+    }
+    window::check(&proposal)?;
+    check_answers(&survey, &answers)?;
+
+    for (question, answer) in answers.into_iter().enumerate() {
+        let question: u32 = question as u32;
+
+        if let Some(option) = answer.option {
+            increment(key, question, option);
+        }
+
+        if let Some(text) = answer.text {
+            let index: u64 = increment(key, question, FREE_TEXT);
+            TEXTS.with(|t| {
+                t.borrow_mut()
+                    .insert((key, (question, index)), FreeText(text))
+            });
+        }
+    }
+
+    proposal.voted.push(caller);
+    participation::on_vote(caller);
+    events::on_voted(key, &proposal, caller);
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+
+    Ok(())
+}
+
+// Hidden like the counts of any other proposal while the caller can't see its results.
+#[ic_cdk::query]
+fn get_survey_results(key: u64) -> Option<SurveyResults> {
+    let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+
+    if !visibility::can_see(&proposal, &ic_cdk::caller())
+        || !embargo::results_visible(&proposal, &ic_cdk::caller())
+    {
+        return None;
+    }
+
+    let survey: Survey = proposal.survey.clone()?;
+
+    let questions: Vec<QuestionResult> = survey
+        .questions
+        .iter()
+        .enumerate()
+        .map(|(question, details)| {
+            let question: u32 = question as u32;
+            let counts: Vec<u64> = (0..details.options.len() as u32)
+                .map(|option| count(key, question, option))
+                .collect();
+
+            QuestionResult {
+                counts,
+                free_text_answers: count(key, question, FREE_TEXT),
+            }
+        })
+        .collect();
+
+    Some(SurveyResults {
+        respondents: proposal.voter_count(),
+        questions,
+    })
+}
+
+// The texts can say anything, only the people running the survey get to read them.
+#[ic_cdk::query]
+fn get_survey_answers(key: u64, question: u32, offset: u64, limit: u64) -> Vec<String> {
+    let caller: Principal = ic_cdk::caller();
+
+    let allowed: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| editors::can_edit(&proposal, &caller));
+
+    if !allowed {
+        return vec![];
+    }
+
+    TEXTS.with(|t| {
+        t.borrow()
+            .range((key, (question, offset))..=(key, (question, u64::MAX)))
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|(_, text)| text.0)
+            .collect()
+    })
+}
@@ -0,0 +1,160 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::{decision, embargo, visibility, Choice, Proposal, PROPOSAL_MAP};
+
+/*
+    Everything a results page needs, worked out here so every frontend shows the same numbers.
+    Percentages are shares of the total weight (the head count when votes aren't weighted).
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct Tally {
+    // The counts are `None` while the caller isn't allowed to see them, see `embargo`.
+    approve: Option<u64>,
+    reject: Option<u64>,
+    pass: Option<u64>,
+    abstain: Option<u64>,
+    approve_percent: Option<f64>,
+    reject_percent: Option<f64>,
+    pass_percent: Option<f64>,
+    abstain_percent: Option<f64>,
+    total_votes: u64,
+    total_weight: Option<u64>,
+    // Only known when a snapshot or an eligibility root says who could vote: voters who voted out of everybody in it.
+    eligible_voters: Option<u64>,
+    turnout_percent: Option<f64>,
+    quorum: u32,
+    quorum_met: bool,
+    leading_choice: Option<Choice>, // `None` while nobody voted or on a tie. Abstain never leads.
+    outcome: Option<decision::Outcome>, // What the proposal's decision rule made of the counts, once voting closed.
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+fn leading_choice(proposal: &Proposal) -> Option<Choice> {
+    let counts: [(Choice, u64); 3] = [
+        (Choice::Approve, proposal.approve),
+        (Choice::Reject, proposal.reject),
+        (Choice::Pass, proposal.pass),
+    ];
+    let max: u64 = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let mut leaders = counts.iter().filter(|(_, count)| *count == max);
+
+    match (leaders.next(), leaders.next()) {
+        (Some((choice, _)), None) if max > 0 => Some(*choice),
+        _ => None,
+    }
+}
+
+// Follows the embargo and the results visibility like `get_proposal`.
+#[ic_cdk::query]
+fn get_tally(key: u64) -> Option<Tally> {
+    let caller: Principal = ic_cdk::caller();
+    let proposal: Proposal = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .filter(|proposal| visibility::can_see(proposal, &caller))?;
+
+    Some(present(&proposal, &caller))
+}
+
+// The tally as `caller` gets to see it.
+pub(crate) fn present(proposal: &Proposal, caller: &Principal) -> Tally {
+    let mut tally: Tally = tally_of(proposal);
+
+    if !embargo::results_visible(proposal, caller) {
+        tally.approve = None;
+        tally.reject = None;
+        tally.pass = None;
+        tally.abstain = None;
+        tally.approve_percent = None;
+        tally.reject_percent = None;
+        tally.pass_percent = None;
+        tally.abstain_percent = None;
+        tally.total_weight = None;
+        tally.leading_choice = None;
+        tally.outcome = None;
+    }
+
+    tally
+}
+
+impl Tally {
+    // The weight behind every choice, for exports.
+    pub(crate) fn counts(&self) -> [(Choice, Option<u64>); 4] {
+        [
+            (Choice::Approve, self.approve),
+            (Choice::Reject, self.reject),
+            (Choice::Pass, self.pass),
+            (Choice::Abstain, self.abstain),
+        ]
+    }
+}
+
+pub(crate) fn tally_of(proposal: &Proposal) -> Tally {
+    let total_weight: u64 = proposal
+        .approve
+        .saturating_add(proposal.reject)
+        .saturating_add(proposal.pass)
+        .saturating_add(proposal.abstain);
+    let total_votes: u64 = proposal.voter_count();
+    let eligible_voters: Option<u64> = proposal
+        .snapshot
+        .as_ref()
+        .map(|s| s.voter_count)
+        .or(proposal.eligibility_root.as_ref().map(|r| r.voter_count));
+
+    Tally {
+        approve: Some(proposal.approve),
+        reject: Some(proposal.reject),
+        pass: Some(proposal.pass),
+        abstain: Some(proposal.abstain),
+        approve_percent: Some(percent(proposal.approve, total_weight)),
+        reject_percent: Some(percent(proposal.reject, total_weight)),
+        pass_percent: Some(percent(proposal.pass, total_weight)),
+        abstain_percent: Some(percent(proposal.abstain, total_weight)),
+        total_votes,
+        total_weight: Some(total_weight),
+        eligible_voters,
+        turnout_percent: eligible_voters.map(|eligible| percent(total_votes, eligible)),
+        quorum: proposal.quorum,
+        quorum_met: decision::quorum_met(proposal),
+        leading_choice: leading_choice(proposal),
+        outcome: proposal.outcome,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposal_store;
+
+    #[test]
+    fn works_out_shares_of_the_total_weight() {
+        let tally: Tally = tally_of(&proposal_store::sample());
+
+        assert_eq!(tally.total_weight, Some(3));
+        assert_eq!(tally.total_votes, 3);
+        assert_eq!(tally.reject_percent, Some(100.0 / 3.0));
+        assert_eq!(tally.pass_percent, Some(0.0));
+        assert_eq!(tally.leading_choice, Some(Choice::Approve));
+    }
+
+    #[test]
+    fn nobody_leads_a_tie_or_an_empty_vote() {
+        let mut proposal: Proposal = proposal_store::sample();
+        proposal.reject = 2;
+        assert_eq!(tally_of(&proposal).leading_choice, None);
+
+        proposal.approve = 0;
+        proposal.reject = 0;
+        let tally: Tally = tally_of(&proposal);
+        assert_eq!(tally.leading_choice, None);
+        assert_eq!(tally.approve_percent, Some(0.0));
+    }
+}
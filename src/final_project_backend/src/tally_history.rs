@@ -0,0 +1,126 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+use crate::{
+    embargo, get_memory, timers, visibility, Memory, Proposal, PROPOSAL_MAP, TALLY_COUNT_MEMORY_ID,
+    TALLY_HISTORY_MEMORY_ID,
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(600);
+
+// Points kept per proposal, the oldest go first. About a day and a half at the interval above.
+const CAPACITY: u64 = 200;
+
+// Open proposals one run samples, so a lot of them can't eat the instruction limit.
+const MAX_SCAN: usize = 1000;
+
+/*
+    Every ten minutes the counts of each open proposal are written down,
+    so frontends can chart how support moved while voting was going on.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct TallyPoint {
+    timestamp: u64,
+    approve: u64,
+    reject: u64,
+    pass: u64,
+    abstain: u64,
+    voters: u64,
+}
+
+impl Storable for TallyPoint {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TallyPoint {
+    const MAX_SIZE: u32 = 150;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // (proposal key, slot) -> point. The slot is the sample number modulo `CAPACITY`.
+    static HISTORY: RefCell<StableBTreeMap<(u64, u64), TallyPoint, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(TALLY_HISTORY_MEMORY_ID)));
+
+    // Proposal key -> how many samples were ever taken.
+    static SAMPLES: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(TALLY_COUNT_MEMORY_ID)));
+}
+
+pub(crate) fn start_sampling() {
+    timers::set_timer_interval(SAMPLE_INTERVAL, sample_open_proposals);
+}
+
+pub(crate) fn remove(key: u64) {
+    let samples: u64 = match SAMPLES.with(|s| s.borrow_mut().remove(&key)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    HISTORY.with(|h| {
+        let mut map = h.borrow_mut();
+        for slot in 0..samples.min(CAPACITY) {
+            map.remove(&(key, slot));
+        }
+    });
+}
+
+fn sample_open_proposals() {
+    let now: u64 = ic_cdk::api::time();
+
+    let open: Vec<(u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .iter()
+            .filter(|(_, proposal)| proposal.is_active)
+            .take(MAX_SCAN)
+            .collect()
+    });
+
+    for (key, proposal) in open {
+        let samples: u64 = SAMPLES.with(|s| s.borrow().get(&key)).unwrap_or(0);
+        let point: TallyPoint = TallyPoint {
+            timestamp: now,
+            approve: proposal.approve,
+            reject: proposal.reject,
+            pass: proposal.pass,
+            abstain: proposal.abstain,
+            voters: proposal.voter_count(),
+        };
+
+        HISTORY.with(|h| h.borrow_mut().insert((key, samples % CAPACITY), point));
+        SAMPLES.with(|s| s.borrow_mut().insert(key, samples + 1));
+    }
+}
+
+// Oldest first. Empty while the results are hidden from the caller, the history would give them away.
+#[ic_cdk::query]
+fn get_tally_history(key: u64) -> Vec<TallyPoint> {
+    let caller: Principal = ic_cdk::caller();
+
+    let visible: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| {
+            visibility::can_see(&proposal, &caller)
+                && proposal.results_hidden_until.is_none()
+                && embargo::results_visible(&proposal, &caller)
+        });
+
+    if !visible {
+        return vec![];
+    }
+
+    let samples: u64 = SAMPLES.with(|s| s.borrow().get(&key)).unwrap_or(0);
+    let first: u64 = samples.saturating_sub(CAPACITY);
+
+    HISTORY.with(|h| {
+        let map = h.borrow();
+        (first..samples)
+            .filter_map(|sample| map.get(&(key, sample % CAPACITY)))
+            .collect()
+    })
+}
@@ -0,0 +1,285 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, changes, config, decision, decision::Outcome, delegation, embargo,
+    execution, get_memory, visibility, window, Memory, Proposal, Role, VoteError, PROPOSAL_MAP,
+    TIE_MEMORY_ID,
+};
+
+// A tie can be extended for at most a year.
+const MAX_EXTENSION: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/*
+    What happens when approve and reject end up equal, picked at creation. Every decision
+    rule fails a tie on its own, `Fail` keeps that. The other policies hold the outcome back
+    until the tie is broken, the final report is written then and lists the path taken.
+    Voting is extended once at most, a second tie fails.
+*/
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize)]
+pub(crate) enum TieBreak {
+    #[default]
+    Fail,
+    CreatorDecides, // With `break_tie`.
+    RandomViaRawRand,
+    ExtendVoting(u64), // Nanoseconds, voting keeps running that long after the tie.
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+pub(crate) enum TieBreakPath {
+    Failed,
+    Extended { until: u64 },
+    AwaitingCreator,
+    CreatorDecided { passed: bool },
+    AwaitingDraw, // `draw_tie` tries again if the draw got lost.
+    Drawn { passed: bool },
+}
+
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+struct TiePath(Vec<TieBreakPath>);
+
+impl Storable for TiePath {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TiePath {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Proposal key -> every step taken on its ties, in order.
+    static TIES: RefCell<StableBTreeMap<u64, TiePath, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(TIE_MEMORY_ID)));
+}
+
+pub(crate) fn validate(tie_break: &TieBreak) -> Result<(), VoteError> {
+    match tie_break {
+        TieBreak::ExtendVoting(duration) if *duration == 0 || *duration > MAX_EXTENSION => {
+            Err(VoteError::InvalidTieBreak)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Nobody voting either way isn't a tie to break.
+fn is_tie(proposal: &Proposal) -> bool {
+    decision::quorum_met(proposal) && proposal.approve == proposal.reject && proposal.approve > 0
+}
+
+pub(crate) fn path_of(key: u64) -> Vec<TieBreakPath> {
+    TIES.with(|t| t.borrow().get(&key)).unwrap_or_default().0
+}
+
+// A step that waited for a decision is replaced by the decision.
+fn record(key: u64, step: TieBreakPath) {
+    let mut path: Vec<TieBreakPath> = path_of(key);
+
+    if matches!(
+        path.last(),
+        Some(TieBreakPath::AwaitingCreator | TieBreakPath::AwaitingDraw)
+    ) {
+        path.pop();
+    }
+    path.push(step);
+
+    TIES.with(|t| t.borrow_mut().insert(key, TiePath(path)));
+}
+
+fn awaiting(key: u64) -> Option<TieBreakPath> {
+    path_of(key).pop().filter(|step| {
+        matches!(
+            step,
+            TieBreakPath::AwaitingCreator | TieBreakPath::AwaitingDraw
+        )
+    })
+}
+
+pub(crate) fn remove(key: u64) {
+    TIES.with(|t| t.borrow_mut().remove(&key));
+}
+
+/*
+    Called by `close` before anything else. Voting on a tied proposal set to
+    `ExtendVoting` runs on instead of closing, the caller writes the proposal back.
+*/
+pub(crate) fn extend(key: u64, proposal: &mut Proposal) -> bool {
+    let duration: u64 = match proposal.tie_break {
+        TieBreak::ExtendVoting(duration) => duration,
+        _ => return false,
+    };
+
+    let extended: bool = path_of(key)
+        .iter()
+        .any(|step| matches!(step, TieBreakPath::Extended { .. }));
+
+    // The delegated weight only goes into the counts on close, a copy shows what they would be.
+    let mut counted: Proposal = proposal.clone();
+    delegation::apply(key, &mut counted);
+
+    if extended || !is_tie(&counted) {
+        return false;
+    }
+
+    let until: u64 = ic_cdk::api::time().saturating_add(duration);
+    proposal.voting_ends_at = Some(until);
+    window::schedule_close(key, until);
+    record(key, TieBreakPath::Extended { until });
+    true
+}
+
+/*
+    Called by `decision::finalize` with the outcome the rule gave. `None` holds
+    the outcome back until the creator or the draw breaks the tie.
+*/
+pub(crate) fn settle(key: u64, proposal: &Proposal, outcome: Outcome) -> Option<Outcome> {
+    if !is_tie(proposal) {
+        return Some(outcome);
+    }
+
+    match proposal.tie_break {
+        TieBreak::CreatorDecides => {
+            record(key, TieBreakPath::AwaitingCreator);
+            None
+        }
+        TieBreak::RandomViaRawRand => {
+            record(key, TieBreakPath::AwaitingDraw);
+            ic_cdk::spawn(draw(key));
+            None
+        }
+        // Extending already happened in `close`, or the proposal closed through its workflow.
+        TieBreak::Fail | TieBreak::ExtendVoting(_) => {
+            record(key, TieBreakPath::Failed);
+            Some(outcome)
+        }
+    }
+}
+
+// Gives a tie that was held back its outcome, and runs what a closing proposal runs once it has one.
+fn resolve(key: u64, passed: bool, step: TieBreakPath) {
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal: Proposal = match p.borrow().get(&key) {
+            Some(value) => value,
+            None => return,
+        };
+
+        // Opened again meanwhile, or broken already.
+        if proposal.is_active || proposal.outcome.is_some() || awaiting(key).is_none() {
+            return;
+        }
+
+        record(key, step);
+        proposal.outcome = Some(if passed {
+            Outcome::Passed
+        } else {
+            Outcome::Rejected
+        });
+        decision::conclude(key, &mut proposal);
+        execution::queue(key, &proposal);
+
+        p.borrow_mut().insert(key, proposal);
+        changes::record_change(key);
+    });
+}
+
+async fn draw(key: u64) {
+    // Lost on failure, `draw_tie` asks again.
+    if let Ok((bytes,)) = raw_rand().await {
+        let passed: bool = bytes.first().is_some_and(|byte| byte & 1 == 1);
+        resolve(key, passed, TieBreakPath::Drawn { passed });
+    }
+}
+
+#[ic_cdk::update]
+fn break_tie(key: u64, passed: bool) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if proposal.owner != caller && !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Owner, caller));
+    }
+
+    if awaiting(key) != Some(TieBreakPath::AwaitingCreator) {
+        return Err(VoteError::NoTieToBreak);
+    }
+
+    resolve(key, passed, TieBreakPath::CreatorDecided { passed });
+    Ok(())
+}
+
+// Anybody may retry a draw that didn't come through, the randomness decides either way.
+#[ic_cdk::update]
+async fn draw_tie(key: u64) -> Result<(), VoteError> {
+    authenticated_caller()?;
+
+    if awaiting(key) != Some(TieBreakPath::AwaitingDraw) {
+        return Err(VoteError::NoTieToBreak);
+    }
+
+    let (bytes,) = raw_rand()
+        .await
+        .map_err(|_| VoteError::RandomnessUnavailable)?;
+    let passed: bool = bytes.first().is_some_and(|byte| byte & 1 == 1);
+
+    resolve(key, passed, TieBreakPath::Drawn { passed });
+    Ok(())
+}
+
+// Like the final report, nothing while the results are under embargo.
+#[ic_cdk::query]
+fn get_tie_break(key: u64) -> Vec<TieBreakPath> {
+    let caller: Principal = ic_cdk::caller();
+    let visible: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| {
+            visibility::can_see(&proposal, &caller) && embargo::results_visible(&proposal, &caller)
+        });
+
+    if !visible {
+        return vec![];
+    }
+
+    path_of(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposal_store;
+
+    #[test]
+    fn only_an_even_split_that_counts_is_a_tie() {
+        let mut proposal: Proposal = proposal_store::sample();
+        assert!(!is_tie(&proposal));
+
+        proposal.approve = 1;
+        assert!(is_tie(&proposal));
+
+        proposal.quorum = 4;
+        assert!(!is_tie(&proposal));
+
+        proposal.quorum = 0;
+        proposal.approve = 0;
+        proposal.reject = 0;
+        assert!(!is_tie(&proposal));
+    }
+
+    #[test]
+    fn limits_how_long_voting_can_be_extended() {
+        assert!(validate(&TieBreak::ExtendVoting(0)).is_err());
+        assert!(validate(&TieBreak::ExtendVoting(MAX_EXTENSION + 1)).is_err());
+        assert!(validate(&TieBreak::ExtendVoting(MAX_EXTENSION)).is_ok());
+    }
+}
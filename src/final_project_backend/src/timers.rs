@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use crate::{backup, pause};
+
+// How long a timer that came due while it was held waits before it looks again.
+const HOLD_RETRY: Duration = Duration::from_secs(60);
+
+/*
+    Every timer of the canister is set through here. While a backup is coming in
+    the maps on the heap don't match stable memory, a timer writing through them
+    would corrupt the restored image. While the canister is paused nothing may move
+    either, no transfer retried and no proposal closed. So a one-shot timer that comes
+    due then waits and looks again later, nothing it had to do gets lost, and an interval
+    skips its turn.
+*/
+fn held() -> bool {
+    backup::is_importing() || pause::check().is_err()
+}
+
+pub(crate) fn set_timer(delay: Duration, f: impl FnOnce() + 'static) {
+    ic_cdk_timers::set_timer(delay, move || {
+        if held() {
+            set_timer(HOLD_RETRY, f);
+        } else {
+            f();
+        }
+    });
+}
+
+pub(crate) fn set_timer_interval(interval: Duration, mut f: impl FnMut() + 'static) {
+    ic_cdk_timers::set_timer_interval(interval, move || {
+        if !held() {
+            f();
+        }
+    });
+}
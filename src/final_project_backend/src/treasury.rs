@@ -0,0 +1,157 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{get_memory, icrc, Memory, VoteError, PAYOUT_TIME_MEMORY_ID, TREASURY_MEMORY_ID};
+
+// Largest page `get_treasury_transfers` returns.
+const MAX_TRANSFER_PAGE: u64 = 100;
+const MAX_ERROR_LEN: usize = 500;
+
+/*
+    The treasury is simply the canister's main account on every ICRC-1 ledger
+    (forfeited deposits end up there too). The only way to move funds out of it
+    is a passed `TransferProposal`, which goes through the same timelock as any other payload.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct TransferProposal {
+    to: icrc::Account,
+    amount: u64,
+    ledger: Principal,
+}
+
+// One entry per attempt, failed ones included, so the history of every token that left is complete.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct TransferRecord {
+    seq: u64,
+    proposal_key: u64,
+    ledger: Principal,
+    to: icrc::Account,
+    amount: u64,
+    executed_at: u64,
+    block: Option<u64>,
+    error: Option<String>,
+}
+
+impl Storable for TransferRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TransferRecord {
+    const MAX_SIZE: u32 = 1000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Sequence number -> record. Records are only ever appended.
+    static TRANSFERS: RefCell<StableBTreeMap<u64, TransferRecord, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(TREASURY_MEMORY_ID)));
+
+    // Proposal key -> `created_at_time` of its first attempt, every later one is sent with the same.
+    static PAYOUT_TIMES: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(PAYOUT_TIME_MEMORY_ID)));
+}
+
+pub(crate) fn validate(transfer: &TransferProposal) -> Result<(), VoteError> {
+    let subaccount_ok: bool = transfer
+        .to
+        .subaccount
+        .as_ref()
+        .is_none_or(|s| s.len() == 32);
+
+    if transfer.amount == 0 || !subaccount_ok {
+        return Err(VoteError::InvalidExecutionPayload);
+    }
+
+    Ok(())
+}
+
+/*
+    Every attempt to pay a proposal carries the same `created_at_time` and a memo made of its key,
+    so a retry after a reply that got lost is answered `Duplicate` by the ledger instead of paying twice.
+*/
+fn dedup(proposal_key: u64) -> icrc::Dedup {
+    let created_at_time: u64 = PAYOUT_TIMES.with(|p| {
+        let first: Option<u64> = p.borrow().get(&proposal_key);
+        first.unwrap_or_else(|| {
+            let now: u64 = ic_cdk::api::time();
+            p.borrow_mut().insert(proposal_key, now);
+            now
+        })
+    });
+
+    icrc::Dedup {
+        created_at_time,
+        memo: [b"treasury:".as_slice(), &proposal_key.to_be_bytes()].concat(),
+    }
+}
+
+// Runs the transfer of a proposal whose timelock is over. Returns the ledger block index.
+pub(crate) async fn execute(proposal_key: u64, transfer: &TransferProposal) -> Result<u64, String> {
+    let res: Result<u64, String> = icrc::transfer(
+        transfer.ledger,
+        None,
+        transfer.to.clone(),
+        transfer.amount,
+        Some(dedup(proposal_key)),
+    )
+    .await;
+
+    TRANSFERS.with(|t| {
+        let seq: u64 = t.borrow().last_key_value().map_or(0, |(seq, _)| seq + 1);
+        let record: TransferRecord = TransferRecord {
+            seq,
+            proposal_key,
+            ledger: transfer.ledger,
+            to: transfer.to.clone(),
+            amount: transfer.amount,
+            executed_at: ic_cdk::api::time(),
+            block: res.as_ref().ok().copied(),
+            error: res
+                .as_ref()
+                .err()
+                .map(|err| err.chars().take(MAX_ERROR_LEN).collect()),
+        };
+        t.borrow_mut().insert(seq, record);
+    });
+
+    res
+}
+
+// Whether the treasury could pay the transfer right now, the balance may still change until it runs.
+pub(crate) async fn preflight(transfer: &TransferProposal) -> Result<(), String> {
+    let balance: u64 = icrc::balance_of(transfer.ledger, ic_cdk::id().into()).await?;
+    let fee: u64 = icrc::fee(transfer.ledger).await?;
+
+    if balance < transfer.amount.saturating_add(fee) {
+        return Err(format!(
+            "the treasury holds {} but the transfer needs {} including the fee",
+            balance,
+            transfer.amount.saturating_add(fee)
+        ));
+    }
+
+    Ok(())
+}
+
+// Where to send funds to the treasury.
+#[ic_cdk::query]
+fn get_treasury_account() -> icrc::Account {
+    ic_cdk::id().into()
+}
+
+#[ic_cdk::query]
+fn get_treasury_transfers(offset: u64, limit: u64) -> Vec<TransferRecord> {
+    TRANSFERS.with(|t| {
+        t.borrow()
+            .range(offset..)
+            .take(limit.min(MAX_TRANSFER_PAGE) as usize)
+            .map(|(_, record)| record)
+            .collect()
+    })
+}
@@ -0,0 +1,215 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::{CanisterInstallMode, InstallCodeArgument};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, config, editors, execution::ExecutionPayload, get_memory, visibility,
+    Memory, Proposal, Role, VoteError, PROPOSAL_MAP, WASM_CHUNK_MEMORY_ID,
+};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// The whole module goes out in one `install_code` call, that caps it below the message limit.
+const MAX_WASM_SIZE: usize = 2 * 1024 * 1024 - 64 * 1024;
+const MAX_ARG_SIZE: usize = 2000;
+
+/*
+    A proposal to upgrade a canister only names the hash of the module, that's what people
+    vote on. The module itself is uploaded in chunks by the owner or an editor, whenever
+    they like: after the timelock `execute_proposal` puts it together and installs it only
+    if it hashes to exactly what was voted on.
+
+    The canister can't upgrade itself. Without a `canister` the upgrade goes to the
+    configured governor, a controller that offers `install_code` with the management
+    canister's signature and is expected to reply before it installs.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) struct CanisterUpgrade {
+    canister: Option<Principal>, // `None` upgrades this canister, through the governor.
+    wasm_sha256: Vec<u8>,
+    arg: Vec<u8>, // Candid encoded argument of `post_upgrade`.
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct WasmUpload {
+    chunks: u32,
+    size: u64,
+    sha256: Vec<u8>,
+    matches: bool, // Whether it's the module the proposal asks for.
+}
+
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Chunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // (proposal key, chunk index) -> bytes of the module.
+    static CHUNKS: RefCell<StableBTreeMap<(u64, u32), Chunk, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(WASM_CHUNK_MEMORY_ID)));
+}
+
+pub(crate) fn validate(upgrade: &CanisterUpgrade) -> Result<(), VoteError> {
+    let target_ok: bool = match upgrade.canister {
+        Some(canister) => canister != ic_cdk::id(),
+        None => config::governor().is_some(),
+    };
+
+    if !target_ok || upgrade.wasm_sha256.len() != 32 || upgrade.arg.len() > MAX_ARG_SIZE {
+        return Err(VoteError::InvalidExecutionPayload);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn remove(key: u64) {
+    CHUNKS.with(|c| {
+        let mut map = c.borrow_mut();
+        let indexes: Vec<(u64, u32)> = map
+            .range((key, 0)..=(key, u32::MAX))
+            .map(|(index, _)| index)
+            .collect();
+
+        for index in indexes {
+            map.remove(&index);
+        }
+    });
+}
+
+// The chunks in order, up to the first one that's missing.
+fn assemble(key: u64) -> Vec<u8> {
+    CHUNKS.with(|c| {
+        let mut wasm: Vec<u8> = vec![];
+
+        for (expected, ((_, index), chunk)) in
+            c.borrow().range((key, 0)..=(key, u32::MAX)).enumerate()
+        {
+            if index as usize != expected {
+                break;
+            }
+            wasm.extend_from_slice(&chunk.0);
+        }
+
+        wasm
+    })
+}
+
+fn upgrade_of(proposal: &Proposal) -> Option<&CanisterUpgrade> {
+    match &proposal.execution {
+        Some(ExecutionPayload::Upgrade(upgrade)) => Some(upgrade),
+        _ => None,
+    }
+}
+
+// The uploaded module, if it's complete and the one that was voted on.
+fn checked_module(key: u64, upgrade: &CanisterUpgrade) -> Result<Vec<u8>, String> {
+    let wasm_module: Vec<u8> = assemble(key);
+
+    if wasm_module.len() > MAX_WASM_SIZE {
+        return Err("the uploaded module is too large".to_string());
+    }
+
+    if Sha256::digest(&wasm_module).as_slice() != upgrade.wasm_sha256.as_slice() {
+        return Err("the uploaded module doesn't match the hash".to_string());
+    }
+
+    Ok(wasm_module)
+}
+
+// What `execute` would find wrong, without installing anything.
+pub(crate) fn preflight(key: u64, upgrade: &CanisterUpgrade) -> Result<(), String> {
+    checked_module(key, upgrade)?;
+
+    if candid::de::IDLDeserialize::new(&upgrade.arg).is_err() {
+        return Err("the argument of post_upgrade isn't valid Candid".to_string());
+    }
+
+    Ok(())
+}
+
+// Runs once the timelock is over. The chunks are dropped when the module is installed.
+pub(crate) async fn execute(key: u64, upgrade: &CanisterUpgrade) -> Result<Principal, String> {
+    let wasm_module: Vec<u8> = checked_module(key, upgrade)?;
+
+    let (callee, canister_id): (Principal, Principal) = match upgrade.canister {
+        Some(canister) => (Principal::management_canister(), canister),
+        None => match config::governor() {
+            Some(governor) => (governor, ic_cdk::id()),
+            None => return Err("no governor is configured".to_string()),
+        },
+    };
+
+    let arg: InstallCodeArgument = InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade,
+        canister_id,
+        wasm_module,
+        arg: upgrade.arg.clone(),
+    };
+
+    let res: Result<(), _> = ic_cdk::call(callee, "install_code", (arg,)).await;
+    res.map_err(|(code, message)| format!("{:?}: {}", code, message))?;
+
+    remove(key);
+    Ok(canister_id)
+}
+
+// Every chunk is `CHUNK_SIZE` bytes, only the last one may be shorter. Uploading an index again replaces it.
+#[ic_cdk::update]
+fn upload_wasm_chunk(key: u64, index: u32, bytes: Vec<u8>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    let proposal: Proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return Err(VoteError::NoSuchProposal),
+    };
+
+    if !editors::can_edit(&proposal, &caller) {
+        return Err(VoteError::access_rejected(Role::Editor, caller));
+    }
+
+    if upgrade_of(&proposal).is_none() {
+        return Err(VoteError::InvalidExecutionPayload);
+    }
+
+    if bytes.is_empty()
+        || bytes.len() > CHUNK_SIZE
+        || index as usize >= MAX_WASM_SIZE.div_ceil(CHUNK_SIZE)
+    {
+        return Err(VoteError::InvalidWasmChunk);
+    }
+
+    CHUNKS.with(|c| c.borrow_mut().insert((key, index), Chunk(bytes)));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_wasm_upload(key: u64) -> Option<WasmUpload> {
+    let proposal: Proposal = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .filter(|proposal| visibility::can_see(proposal, &ic_cdk::caller()))?;
+    let upgrade: &CanisterUpgrade = upgrade_of(&proposal)?;
+
+    let wasm: Vec<u8> = assemble(key);
+    let sha256: Vec<u8> = Sha256::digest(&wasm).to_vec();
+
+    Some(WasmUpload {
+        chunks: CHUNKS.with(|c| c.borrow().range((key, 0)..=(key, u32::MAX)).count() as u32),
+        size: wasm.len() as u64,
+        matches: sha256 == upgrade.wasm_sha256,
+        sha256,
+    })
+}
@@ -0,0 +1,257 @@
+use candid::{CandidType, Deserialize};
+
+use crate::{config, visibility, ChoiceStyle, CreateProposal, VoteError};
+
+pub(crate) const MAX_TITLE_LEN: usize = 100;
+const MAX_SUMMARY_LEN: usize = 500;
+const MAX_URL_LEN: usize = 300;
+const MAX_CATEGORY_LEN: usize = 50;
+const PAYLOAD_HASH_LEN: usize = 32; // SHA-256.
+const MAX_CHOICE_LABEL_LEN: usize = 32;
+
+/*
+    Says which field of `CreateProposal` is wrong and why, so a frontend can point at it
+    instead of showing a generic error. Shared by create and edit.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct ValidationError {
+    field: String,
+    reason: ValidationReason,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum ValidationReason {
+    Empty,
+    TooLong { max: u64 },
+    ControlCharacters, // Only newlines and tabs, and only in the description and summary.
+    InvalidFormat,
+}
+
+fn invalid(field: &str, reason: ValidationReason) -> VoteError {
+    VoteError::ValidationFailed(ValidationError {
+        field: field.to_string(),
+        reason,
+    })
+}
+
+// Strings are valid UTF-8 by the time they get here, what's left are invisible characters.
+fn check_text(field: &str, text: &str, max: usize, multiline: bool) -> Result<(), VoteError> {
+    if text.len() > max {
+        return Err(invalid(
+            field,
+            ValidationReason::TooLong { max: max as u64 },
+        ));
+    }
+
+    let allowed = |c: char| multiline && (c == '\n' || c == '\t');
+    if text.chars().any(|c| c.is_control() && !allowed(c)) {
+        return Err(invalid(field, ValidationReason::ControlCharacters));
+    }
+
+    Ok(())
+}
+
+fn check_choice(field: &str, style: &Option<ChoiceStyle>) -> Result<(), VoteError> {
+    let style: &ChoiceStyle = match style {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    if style.label.trim().is_empty() {
+        return Err(invalid(field, ValidationReason::Empty));
+    }
+    check_text(field, &style.label, MAX_CHOICE_LABEL_LEN, false)?;
+
+    let valid_color = |color: &String| {
+        color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if style
+        .color
+        .as_ref()
+        .is_some_and(|color| !valid_color(color))
+    {
+        return Err(invalid(field, ValidationReason::InvalidFormat));
+    }
+
+    Ok(())
+}
+
+// Options and tags get their limits here as well once proposals have them.
+pub(crate) fn validate(proposal: &CreateProposal) -> Result<(), VoteError> {
+    // One level only, the follow-up can't carry another one.
+    if let Some(follow_up) = &proposal.on_pass_create {
+        if follow_up.on_pass_create.is_some() {
+            return Err(invalid("on_pass_create", ValidationReason::InvalidFormat));
+        }
+        validate(follow_up)?;
+    }
+
+    if proposal.title.trim().is_empty() {
+        return Err(invalid("title", ValidationReason::Empty));
+    }
+    check_text("title", &proposal.title, MAX_TITLE_LEN, false)?;
+    check_text("summary", &proposal.summary, MAX_SUMMARY_LEN, true)?;
+    check_text(
+        "description",
+        &proposal.description,
+        config::max_description_len(),
+        true,
+    )?;
+
+    if let Some(url) = &proposal.url {
+        check_text("url", url, MAX_URL_LEN, false)?;
+
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(invalid("url", ValidationReason::InvalidFormat));
+        }
+    }
+
+    if let Some(category) = &proposal.category {
+        check_text("category", category, MAX_CATEGORY_LEN, false)?;
+    }
+
+    if proposal
+        .payload_hash
+        .as_ref()
+        .is_some_and(|hash| hash.len() != PAYLOAD_HASH_LEN)
+    {
+        return Err(invalid("payload_hash", ValidationReason::InvalidFormat));
+    }
+
+    // A share of the registered voters needs voters to be registered in the first place.
+    if let Some(percent) = proposal.quorum_percent {
+        if percent == 0 || percent > 100 || proposal.eligible_voters.is_none() {
+            return Err(invalid("quorum_percent", ValidationReason::InvalidFormat));
+        }
+    }
+
+    let labels = &proposal.choice_labels;
+    check_choice("choice_labels.approve", &labels.approve)?;
+    check_choice("choice_labels.reject", &labels.reject)?;
+    check_choice("choice_labels.pass", &labels.pass)?;
+    check_choice("choice_labels.abstain", &labels.abstain)?;
+
+    visibility::validate(&proposal.visibility)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChoiceLabels;
+
+    fn create() -> CreateProposal {
+        CreateProposal {
+            title: "Fund the docs".to_string(),
+            summary: String::new(),
+            url: None,
+            payload_hash: None,
+            description: "First line\n\tand an indented second one.".to_string(),
+            is_active: true,
+            results_embargo: None,
+            category: None,
+            voting_power: None,
+            nft_gate: None,
+            sns_gate: None,
+            eligibility_root: None,
+            execution: None,
+            visibility: visibility::Visibility::Public,
+            decision_rule: None,
+            abstention: None,
+            voting_starts_at: None,
+            voting_ends_at: None,
+            client_nonce: None,
+            depends_on: vec![],
+            eligible_voters: None,
+            save_as_draft: false,
+            public_ballots: false,
+            allow_vote_changes: false,
+            quorum_percent: None,
+            survey: None,
+            choice_labels: ChoiceLabels {
+                approve: None,
+                reject: None,
+                pass: None,
+                abstain: None,
+            },
+            results_visibility: None,
+            role_weighted: false,
+            kind: None,
+            members_only: false,
+            tie_break: None,
+            frontend_only: false,
+            personhood_gated: false,
+            on_pass_create: None,
+            weighting: None,
+            sealed_ballots: false,
+        }
+    }
+
+    fn field_of(res: Result<(), VoteError>) -> String {
+        match res {
+            Err(VoteError::ValidationFailed(error)) => error.field,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_a_plain_proposal() {
+        assert!(validate(&create()).is_ok());
+    }
+
+    #[test]
+    fn names_the_field_that_is_wrong() {
+        let mut proposal: CreateProposal = create();
+        proposal.title = " ".to_string();
+        assert_eq!(field_of(validate(&proposal)), "title");
+
+        let mut proposal: CreateProposal = create();
+        proposal.title = "a".repeat(MAX_TITLE_LEN + 1);
+        assert_eq!(field_of(validate(&proposal)), "title");
+
+        let mut proposal: CreateProposal = create();
+        proposal.url = Some("javascript:alert(1)".to_string());
+        assert_eq!(field_of(validate(&proposal)), "url");
+
+        let mut proposal: CreateProposal = create();
+        proposal.payload_hash = Some(vec![0; 20]);
+        assert_eq!(field_of(validate(&proposal)), "payload_hash");
+    }
+
+    #[test]
+    fn allows_newlines_only_where_text_is_multiline() {
+        let mut proposal: CreateProposal = create();
+        proposal.summary = "Two\nlines".to_string();
+        assert!(validate(&proposal).is_ok());
+
+        proposal.title = "Two\nlines".to_string();
+        assert_eq!(field_of(validate(&proposal)), "title");
+    }
+
+    #[test]
+    fn checks_the_choice_colors() {
+        let mut proposal: CreateProposal = create();
+        proposal.choice_labels.approve = Some(ChoiceStyle {
+            label: "For".to_string(),
+            color: Some("#12ab9F".to_string()),
+        });
+        assert!(validate(&proposal).is_ok());
+
+        proposal.choice_labels.reject = Some(ChoiceStyle {
+            label: "Against".to_string(),
+            color: Some("red".to_string()),
+        });
+        assert_eq!(field_of(validate(&proposal)), "choice_labels.reject");
+    }
+
+    #[test]
+    fn takes_one_level_of_follow_ups() {
+        let mut follow_up: CreateProposal = create();
+        follow_up.on_pass_create = Some(Box::new(create()));
+
+        let mut proposal: CreateProposal = create();
+        proposal.on_pass_create = Some(Box::new(follow_up));
+        assert_eq!(field_of(validate(&proposal)), "on_pass_create");
+    }
+}
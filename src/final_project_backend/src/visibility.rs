@@ -0,0 +1,56 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::{config, embargo, Proposal, ProposalView, VoteError, PROPOSAL_MAP};
+
+// Keeps a restricted proposal well within its storage bound.
+const MAX_ACCESS_LIST: usize = 20;
+
+/*
+    Several organizations can share one canister: a restricted proposal only exists
+    for the principals on its list (plus its owner and the admin). Everybody else
+    gets the same answer as for a key that was never used.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub(crate) enum Visibility {
+    Public,
+    Restricted(Vec<Principal>),
+}
+
+pub(crate) fn validate(visibility: &Visibility) -> Result<(), VoteError> {
+    match visibility {
+        Visibility::Restricted(list) if list.len() > MAX_ACCESS_LIST => {
+            Err(VoteError::AccessListTooLong)
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn is_public(proposal: &Proposal) -> bool {
+    matches!(proposal.visibility, Visibility::Public)
+}
+
+pub(crate) fn can_see(proposal: &Proposal, caller: &Principal) -> bool {
+    match &proposal.visibility {
+        Visibility::Public => true,
+        Visibility::Restricted(list) => {
+            proposal.owner == *caller || list.contains(caller) || config::is_admin(caller)
+        }
+    }
+}
+
+// What `caller` may read of a proposal: nothing when it's hidden from them, otherwise it with the embargo applied.
+pub(crate) fn present(proposal: Proposal, caller: &Principal) -> Option<ProposalView> {
+    if can_see(&proposal, caller) {
+        Some(embargo::redact(proposal, caller))
+    } else {
+        None
+    }
+}
+
+// For the queries that are about a proposal without returning it.
+pub(crate) fn can_see_key(key: u64, caller: &Principal) -> bool {
+    PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| can_see(&proposal, caller))
+}
@@ -0,0 +1,146 @@
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{
+    config, get_memory, membership, visibility, Memory, Proposal, StorablePrincipal, VoteError,
+    MAX_BATCH_SIZE, PROPOSAL_MAP, VOTER_LIST_MEMORY_ID,
+};
+
+// Keeps the creation call within the message size limit.
+const MAX_VOTER_LIST_LEN: usize = 10_000;
+
+/*
+    A proposal can be limited to a list of voters picked by its creator. The list lives here
+    and not on the proposal, so a long one doesn't count against the proposal's size limit.
+    `voter_list` on the proposal says whether there is one.
+*/
+thread_local! {
+    // (proposal key, voter) for every voter on a list.
+    static VOTER_LISTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(VOTER_LIST_MEMORY_ID)));
+}
+
+pub(crate) fn validate(voters: &[Principal]) -> Result<(), VoteError> {
+    if voters.is_empty() || voters.len() > MAX_VOTER_LIST_LEN {
+        return Err(VoteError::InvalidVoterList);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn store(key: u64, voters: &[Principal]) {
+    VOTER_LISTS.with(|v| {
+        let mut map = v.borrow_mut();
+        for voter in voters {
+            map.insert((key, StorablePrincipal(*voter)), ());
+        }
+    });
+}
+
+pub(crate) fn add(key: u64, voter: Principal) {
+    VOTER_LISTS.with(|v| v.borrow_mut().insert((key, StorablePrincipal(voter)), ()));
+}
+
+pub(crate) fn remove(key: u64) {
+    VOTER_LISTS.with(|v| {
+        let mut map = v.borrow_mut();
+        let voters: Vec<(u64, StorablePrincipal)> = map
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in voters {
+            map.remove(&entry);
+        }
+    });
+}
+
+// Takes the voter off every list. There is no index by voter, so this goes through all of them.
+pub(crate) fn remove_voter(voter: Principal) -> u64 {
+    VOTER_LISTS.with(|v| {
+        let mut map = v.borrow_mut();
+        let entries: Vec<(u64, StorablePrincipal)> = map
+            .iter()
+            .filter(|((_, member), _)| member.0 == voter)
+            .map(|(entry, _)| entry)
+            .collect();
+
+        for entry in &entries {
+            map.remove(entry);
+        }
+        entries.len() as u64
+    })
+}
+
+pub(crate) fn members(key: u64) -> Vec<Principal> {
+    VOTER_LISTS.with(|v| {
+        v.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, voter), _)| voter.0)
+            .collect()
+    })
+}
+
+pub(crate) fn count(key: u64) -> u64 {
+    VOTER_LISTS.with(|v| {
+        v.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .count() as u64
+    })
+}
+
+// Proposals without a list (and not `members_only`) are open to everybody the other rules let through.
+pub(crate) fn check(key: u64, proposal: &Proposal, voter: &Principal) -> Result<(), VoteError> {
+    if proposal.voter_list
+        && !VOTER_LISTS.with(|v| v.borrow().contains_key(&(key, StorablePrincipal(*voter))))
+    {
+        return Err(VoteError::NotEligible);
+    }
+
+    if proposal.members_only && !membership::is_member(voter) {
+        return Err(VoteError::NotEligible);
+    }
+
+    Ok(())
+}
+
+// Pages through the list in principal order. Only the owner and the admin get to see who is on it.
+#[ic_cdk::query]
+fn get_voter_list(key: u64, offset: u64, limit: u64) -> Vec<Principal> {
+    let caller: Principal = ic_cdk::caller();
+
+    let allowed: bool = PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .is_some_and(|proposal| {
+            visibility::can_see(&proposal, &caller)
+                && (proposal.owner == caller || config::is_admin(&caller))
+        });
+
+    if !allowed {
+        return vec![];
+    }
+
+    VOTER_LISTS.with(|v| {
+        v.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .skip(offset as usize)
+            .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+            .map(|((_, voter), _)| voter.0)
+            .collect()
+    })
+}
+
+// Whether `voter` may vote as far as the list goes, anybody who can see the proposal can ask.
+#[ic_cdk::query]
+fn is_on_voter_list(key: u64, voter: Principal) -> bool {
+    let caller: Principal = ic_cdk::caller();
+
+    PROPOSAL_MAP
+        .with(|p| p.borrow().get(&key))
+        .filter(|proposal| visibility::can_see(proposal, &caller))
+        .is_some_and(|proposal| check(key, &proposal, &voter).is_ok())
+}
@@ -0,0 +1,202 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{
+    authenticated_caller, get_memory, rate_limit, visibility, Memory, StorablePrincipal, VoteError,
+    INBOX_MEMORY_ID, INBOX_SEQ_MEMORY_ID, MAX_BATCH_SIZE, PROPOSAL_MAP, WATCHER_MEMORY_ID,
+};
+
+// Every watcher gets a copy, so a proposal's watchers are bounded.
+const MAX_WATCHERS: usize = 1000;
+// Oldest notifications make room for new ones.
+const MAX_INBOX: usize = 100;
+
+/*
+    For frontends without push infrastructure: whoever watches a proposal gets a
+    notification in their inbox when something happens to it, and polls
+    `get_notifications` instead of the whole proposal. A notification only says what
+    happened, the outcome and the counts are read from the proposal as usual.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum NotificationKind {
+    Closed, // Voting ended and the outcome is decided, see `get_tally`.
+    Executed,
+    ExecutionFailed,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct Notification {
+    seq: u64, // Never reused, for `clear_notifications`.
+    timestamp: u64,
+    proposal_key: u64,
+    kind: NotificationKind,
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Notification {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // (proposal key, watcher).
+    static WATCHERS: RefCell<StableBTreeMap<(u64, StorablePrincipal), (), Memory>> = RefCell::new(StableBTreeMap::init(get_memory(WATCHER_MEMORY_ID)));
+
+    // (recipient, seq) -> notification.
+    static INBOX: RefCell<StableBTreeMap<(StorablePrincipal, u64), Notification, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(INBOX_MEMORY_ID)));
+
+    // Shared by all inboxes, so a cleared inbox doesn't hand out the same numbers again.
+    static LAST_SEQ: RefCell<StableCell<u64, Memory>> = RefCell::new(StableCell::init(get_memory(INBOX_SEQ_MEMORY_ID), 0).unwrap());
+}
+
+fn watchers(key: u64) -> Vec<Principal> {
+    WATCHERS.with(|w| {
+        w.borrow()
+            .range((key, StorablePrincipal::default())..)
+            .take_while(|((k, _), _)| *k == key)
+            .map(|((_, watcher), _)| watcher.0)
+            .collect()
+    })
+}
+
+fn deliver(recipient: Principal, proposal_key: u64, kind: NotificationKind) {
+    let recipient: StorablePrincipal = StorablePrincipal(recipient);
+    let seq: u64 = LAST_SEQ.with(|s| {
+        let next = *s.borrow().get() + 1;
+        s.borrow_mut().set(next).unwrap();
+        next
+    });
+
+    INBOX.with(|i| {
+        let mut inbox = i.borrow_mut();
+        let seqs: Vec<u64> = inbox
+            .range((recipient, 0)..=(recipient, u64::MAX))
+            .map(|((_, seq), _)| seq)
+            .collect();
+
+        for seq in seqs.iter().take((seqs.len() + 1).saturating_sub(MAX_INBOX)) {
+            inbox.remove(&(recipient, *seq));
+        }
+
+        let notification: Notification = Notification {
+            seq,
+            timestamp: ic_cdk::api::time(),
+            proposal_key,
+            kind,
+        };
+        inbox.insert((recipient, seq), notification);
+    });
+}
+
+// Watchers who can no longer see the proposal aren't told.
+fn notify(key: u64, kind: NotificationKind) {
+    let proposal = match PROPOSAL_MAP.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    for watcher in watchers(key) {
+        if visibility::can_see(&proposal, &watcher) {
+            deliver(watcher, key, kind.clone());
+        }
+    }
+}
+
+pub(crate) fn on_closed(key: u64) {
+    notify(key, NotificationKind::Closed);
+}
+
+pub(crate) fn on_executed(key: u64, succeeded: bool) {
+    let kind: NotificationKind = if succeeded {
+        NotificationKind::Executed
+    } else {
+        NotificationKind::ExecutionFailed
+    };
+    notify(key, kind);
+}
+
+// The notifications already delivered stay in the inboxes.
+pub(crate) fn remove(key: u64) {
+    for watcher in watchers(key) {
+        WATCHERS.with(|w| w.borrow_mut().remove(&(key, StorablePrincipal(watcher))));
+    }
+}
+
+#[ic_cdk::update]
+fn watch_proposal(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+    rate_limit::consume(caller, 1)?;
+
+    if !visibility::can_see_key(key, &caller) {
+        return Err(VoteError::NoSuchProposal);
+    }
+
+    let watching: bool =
+        WATCHERS.with(|w| w.borrow().contains_key(&(key, StorablePrincipal(caller))));
+
+    if !watching && watchers(key).len() >= MAX_WATCHERS {
+        return Err(VoteError::TooManyWatchers);
+    }
+
+    WATCHERS.with(|w| w.borrow_mut().insert((key, StorablePrincipal(caller)), ()));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn unwatch_proposal(key: u64) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    WATCHERS.with(|w| w.borrow_mut().remove(&(key, StorablePrincipal(caller))));
+    Ok(())
+}
+
+// Newest first.
+#[ic_cdk::query]
+fn get_notifications(offset: u64, limit: u64) -> Vec<Notification> {
+    let caller: StorablePrincipal = StorablePrincipal(ic_cdk::caller());
+
+    let inbox: Vec<Notification> = INBOX.with(|i| {
+        i.borrow()
+            .range((caller, 0)..=(caller, u64::MAX))
+            .map(|(_, notification)| notification)
+            .collect()
+    });
+
+    inbox
+        .into_iter()
+        .rev()
+        .skip(offset as usize)
+        .take(limit.min(MAX_BATCH_SIZE as u64) as usize)
+        .collect()
+}
+
+// Everything up to and including `up_to`, or the whole inbox.
+#[ic_cdk::update]
+fn clear_notifications(up_to: Option<u64>) -> Result<(), VoteError> {
+    let caller: StorablePrincipal = StorablePrincipal(authenticated_caller()?);
+    let up_to: u64 = up_to.unwrap_or(u64::MAX);
+
+    INBOX.with(|i| {
+        let mut inbox = i.borrow_mut();
+        let seqs: Vec<u64> = inbox
+            .range((caller, 0)..=(caller, up_to))
+            .map(|((_, seq), _)| seq)
+            .collect();
+
+        for seq in seqs {
+            inbox.remove(&(caller, seq));
+        }
+    });
+
+    Ok(())
+}
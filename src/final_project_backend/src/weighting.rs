@@ -0,0 +1,105 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{snapshot, Proposal, VoteError};
+
+/*
+    Keeps a few large holders from deciding a token weighted vote on their own. The cap is
+    taken off the snapshot balance first, the curve then applies to what's left: with
+    `SquareRoot` a holder of 10,000 tokens counts 100, one of 100 tokens counts 10.
+    Applied as each vote is counted, for direct votes and delegated ones alike,
+    so the live counts are the weighted ones too. Only for proposals with `voting_power`.
+*/
+#[derive(Debug, Clone, Default, PartialEq, CandidType, Deserialize, Serialize)]
+pub(crate) enum Curve {
+    #[default]
+    Linear,
+    SquareRoot,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize, Serialize)]
+pub(crate) enum WeightCap {
+    Absolute(u64),
+    PercentOfTotal(u32), // Of the snapshot's total weight, 1 to 100.
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize, Serialize)]
+pub(crate) struct Weighting {
+    cap: Option<WeightCap>,
+    curve: Curve,
+}
+
+// What the weighting did, for the final report.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct WeightingReport {
+    weighting: Weighting,
+    raw_weight: u64, // The snapshot balances of the direct voters, before the weighting.
+    capped_voters: u64, // Direct voters whose balance was over the cap.
+}
+
+pub(crate) fn validate(weighting: &Weighting, voting_power: bool) -> Result<(), VoteError> {
+    let cap_ok: bool = match weighting.cap {
+        Some(WeightCap::Absolute(cap)) => cap > 0,
+        Some(WeightCap::PercentOfTotal(percent)) => (1..=100).contains(&percent),
+        None => true,
+    };
+
+    if !voting_power || !cap_ok {
+        return Err(VoteError::InvalidWeighting);
+    }
+    Ok(())
+}
+
+fn cap_of(proposal: &Proposal, cap: &WeightCap) -> u64 {
+    match cap {
+        WeightCap::Absolute(cap) => *cap,
+        WeightCap::PercentOfTotal(percent) => {
+            let total: u64 = proposal
+                .snapshot
+                .as_ref()
+                .map_or(0, |snapshot| snapshot.total_weight);
+            // Never below one, a small snapshot would let nobody count otherwise.
+            ((total as u128 * *percent as u128 / 100) as u64).max(1)
+        }
+    }
+}
+
+// The weight a snapshot balance counts with on this proposal.
+pub(crate) fn effective(proposal: &Proposal, raw: u64) -> u64 {
+    let weighting: &Weighting = match &proposal.weighting {
+        Some(value) => value,
+        None => return raw,
+    };
+
+    let capped: u64 = match &weighting.cap {
+        Some(cap) => raw.min(cap_of(proposal, cap)),
+        None => raw,
+    };
+
+    match weighting.curve {
+        Curve::Linear => capped,
+        Curve::SquareRoot => capped.isqrt(),
+    }
+}
+
+pub(crate) fn report(key: u64, proposal: &Proposal) -> Option<WeightingReport> {
+    let weighting: Weighting = proposal.weighting.clone()?;
+    let cap: Option<u64> = weighting.cap.as_ref().map(|cap| cap_of(proposal, cap));
+
+    let mut raw_weight: u64 = 0;
+    let mut capped_voters: u64 = 0;
+    for voter in &proposal.voted {
+        let raw: u64 = snapshot::weight_of(key, proposal, voter).unwrap_or(0);
+        raw_weight = raw_weight.saturating_add(raw);
+
+        if cap.is_some_and(|cap| raw > cap) {
+            capped_voters += 1;
+        }
+    }
+
+    Some(WeightingReport {
+        weighting,
+        raw_weight,
+        capped_voters,
+    })
+}
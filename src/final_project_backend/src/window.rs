@@ -0,0 +1,124 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::StableBTreeMap;
+use std::{cell::RefCell, time::Duration};
+
+use crate::{
+    changes, close, get_memory, timers, visibility, Memory, Proposal, VoteError, PROPOSAL_MAP,
+    VOTING_WINDOW_MEMORY_ID,
+};
+
+/*
+    A proposal can say when voting opens and closes. Outside of that window `vote`
+    turns everybody away, and at the end a timer closes the proposal like `end_proposal` would.
+*/
+thread_local! {
+    // Proposal key -> when its voting ends. Needed to re-arm the timers after an upgrade.
+    static PENDING_CLOSES: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(VOTING_WINDOW_MEMORY_ID)));
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct VotingWindow {
+    starts_at: Option<u64>,
+    ends_at: Option<u64>,
+    starts_in: Option<u64>, // Nanoseconds until voting opens, `None` once it has.
+    remaining: Option<u64>, // Nanoseconds until voting closes, `None` without an end.
+}
+
+pub(crate) fn validate(starts_at: Option<u64>, ends_at: Option<u64>) -> Result<(), VoteError> {
+    let now: u64 = ic_cdk::api::time();
+
+    let ok: bool = match (starts_at, ends_at) {
+        (Some(start), Some(end)) => start < end && now < end,
+        (None, Some(end)) => now < end,
+        _ => true,
+    };
+
+    if !ok {
+        return Err(VoteError::InvalidVotingWindow);
+    }
+
+    Ok(())
+}
+
+// Also turns voters away while a moderator holds the voting, see `interventions`.
+pub(crate) fn check(proposal: &Proposal) -> Result<(), VoteError> {
+    let now: u64 = ic_cdk::api::time();
+
+    if proposal.voting_suspended_at.is_some() {
+        return Err(VoteError::VotingSuspended);
+    }
+
+    if proposal.voting_starts_at.is_some_and(|start| now < start) {
+        return Err(VoteError::VotingNotStarted);
+    }
+
+    if proposal.voting_ends_at.is_some_and(|end| now >= end) {
+        return Err(VoteError::VotingEnded);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn schedule_close(key: u64, ends_at: u64) {
+    PENDING_CLOSES.with(|p| p.borrow_mut().insert(key, ends_at));
+    arm_timer(key, ends_at);
+}
+
+pub(crate) fn remove(key: u64) {
+    PENDING_CLOSES.with(|p| p.borrow_mut().remove(&key));
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = PENDING_CLOSES.with(|p| p.borrow().iter().collect());
+
+    for (key, ends_at) in pending {
+        arm_timer(key, ends_at);
+    }
+}
+
+fn arm_timer(key: u64, ends_at: u64) {
+    let delay: u64 = ends_at.saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || {
+        close_voting(key, ends_at)
+    });
+}
+
+fn close_voting(key: u64, ends_at: u64) {
+    // A proposal deleted and created again in the meantime has a timer of its own.
+    if PENDING_CLOSES.with(|p| p.borrow().get(&key)) != Some(ends_at) {
+        return;
+    }
+    PENDING_CLOSES.with(|p| p.borrow_mut().remove(&key));
+
+    PROPOSAL_MAP.with(|p| {
+        let proposal_opt: Option<Proposal> = p.borrow().get(&key);
+
+        // Ended by its owner already, or deleted.
+        if let Some(mut proposal) = proposal_opt.filter(|proposal| proposal.is_active) {
+            close(key, &mut proposal);
+            p.borrow_mut().insert(key, proposal);
+            changes::record_change(key);
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_voting_window(key: u64) -> Option<VotingWindow> {
+    let proposal: Proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+
+    if !visibility::can_see(&proposal, &ic_cdk::caller()) {
+        return None;
+    }
+
+    let now: u64 = ic_cdk::api::time();
+
+    Some(VotingWindow {
+        starts_at: proposal.voting_starts_at,
+        ends_at: proposal.voting_ends_at,
+        starts_in: proposal
+            .voting_starts_at
+            .filter(|start| now < *start)
+            .map(|start| start - now),
+        remaining: proposal.voting_ends_at.map(|end| end.saturating_sub(now)),
+    })
+}
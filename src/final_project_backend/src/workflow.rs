@@ -0,0 +1,335 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+use crate::{
+    authenticated_caller, changes, close, config, get_memory, timers, Memory, Proposal, Role,
+    VoteError, PROPOSAL_MAP, WORKFLOW_MEMORY_ID, WORKFLOW_PENDING_MEMORY_ID,
+    WORKFLOW_PROGRESS_MEMORY_ID,
+};
+
+const MAX_CATEGORY_LEN: usize = 64;
+const MAX_STAGE_NAME_LEN: usize = 64;
+const MAX_STAGES: usize = 10;
+
+/*
+    A workflow is the list of stages every proposal of a category goes through,
+    e.g. Idea -> Discussion (7d) -> Formal vote (5d) -> Timelock (2d) -> Execution.
+    Timers move the proposal from one stage to the next, and the stage decides
+    what is allowed while the proposal is in it.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub(crate) struct WorkflowStage {
+    name: String,
+    duration: Option<u64>, // Nanoseconds. Only the last stage may go without one, it's where the proposal stays.
+    allows_editing: bool,
+    allows_voting: bool,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct Workflow {
+    stages: Vec<WorkflowStage>,
+}
+
+impl Storable for Workflow {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Workflow {
+    const MAX_SIZE: u32 = 2000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CategoryKey(String);
+
+impl Storable for CategoryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        CategoryKey(String::from_utf8(bytes.into_owned()).unwrap())
+    }
+}
+
+impl BoundedStorable for CategoryKey {
+    const MAX_SIZE: u32 = MAX_CATEGORY_LEN as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct StageEntry {
+    name: String,
+    starts_at: u64,
+    ends_at: Option<u64>,
+}
+
+/*
+    The stages are copied into the proposal's progress when it is created,
+    so changing a workflow later doesn't move the goalposts of running proposals.
+*/
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct StageProgress {
+    category: String,
+    current_stage: u32,
+    stages: Vec<WorkflowStage>,
+    timeline: Vec<StageEntry>,
+}
+
+impl Storable for StageProgress {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for StageProgress {
+    const MAX_SIZE: u32 = 4000;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl StageProgress {
+    fn stage(&self) -> &WorkflowStage {
+        &self.stages[self.current_stage as usize]
+    }
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub(crate) struct StageStatus {
+    category: String,
+    current_stage: u32,
+    stage: WorkflowStage,
+    timeline: Vec<StageEntry>,
+}
+
+thread_local! {
+    // Category -> workflow template.
+    static WORKFLOWS: RefCell<StableBTreeMap<CategoryKey, Workflow, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(WORKFLOW_MEMORY_ID)));
+
+    // Proposal key -> where it is in its workflow.
+    static PROGRESS: RefCell<StableBTreeMap<u64, StageProgress, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(WORKFLOW_PROGRESS_MEMORY_ID)));
+
+    // Proposal key -> end of its current stage. Needed to re-arm the timers after an upgrade.
+    static PENDING_ADVANCES: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(StableBTreeMap::init(get_memory(WORKFLOW_PENDING_MEMORY_ID)));
+}
+
+fn validate(category: &str, workflow: &Workflow) -> bool {
+    let stages = &workflow.stages;
+
+    !category.is_empty()
+        && category.len() <= MAX_CATEGORY_LEN
+        && !stages.is_empty()
+        && stages.len() <= MAX_STAGES
+        && stages
+            .iter()
+            .all(|s| !s.name.is_empty() && s.name.len() <= MAX_STAGE_NAME_LEN)
+        && stages[..stages.len() - 1]
+            .iter()
+            .all(|s| s.duration.is_some())
+}
+
+#[ic_cdk::update]
+fn set_workflow(category: String, stages: Vec<WorkflowStage>) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    let workflow: Workflow = Workflow { stages };
+
+    if !validate(&category, &workflow) {
+        return Err(VoteError::InvalidWorkflow);
+    }
+
+    WORKFLOWS.with(|w| w.borrow_mut().insert(CategoryKey(category), workflow));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn remove_workflow(category: String) -> Result<(), VoteError> {
+    let caller: Principal = authenticated_caller()?;
+
+    if !config::is_admin(&caller) {
+        return Err(VoteError::access_rejected(Role::Admin, caller));
+    }
+
+    match WORKFLOWS.with(|w| w.borrow_mut().remove(&CategoryKey(category))) {
+        Some(_) => Ok(()),
+        None => Err(VoteError::InvalidWorkflow),
+    }
+}
+
+#[ic_cdk::query]
+fn list_workflows() -> Vec<(String, Vec<WorkflowStage>)> {
+    WORKFLOWS.with(|w| {
+        w.borrow()
+            .iter()
+            .map(|(category, workflow)| (category.0, workflow.stages))
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_proposal_stage(key: u64) -> Option<StageStatus> {
+    PROGRESS
+        .with(|p| p.borrow().get(&key))
+        .map(|progress| StageStatus {
+            category: progress.category.clone(),
+            current_stage: progress.current_stage,
+            stage: progress.stage().clone(),
+            timeline: progress.timeline,
+        })
+}
+
+/*
+    Puts a new proposal into the first stage of its category's workflow.
+    Returns whether voting is open in that stage, or `None` when the category has no workflow.
+*/
+pub(crate) fn start(key: u64, category: &str) -> Option<bool> {
+    if category.len() > MAX_CATEGORY_LEN {
+        return None;
+    }
+
+    let workflow: Workflow =
+        WORKFLOWS.with(|w| w.borrow().get(&CategoryKey(category.to_string())))?;
+
+    let mut timeline: Vec<StageEntry> = Vec::with_capacity(workflow.stages.len());
+    let mut starts_at: u64 = ic_cdk::api::time();
+
+    for stage in workflow.stages.iter() {
+        let ends_at: Option<u64> = stage.duration.map(|d| starts_at.saturating_add(d));
+        timeline.push(StageEntry {
+            name: stage.name.clone(),
+            starts_at,
+            ends_at,
+        });
+        starts_at = ends_at.unwrap_or(starts_at);
+    }
+
+    let progress: StageProgress = StageProgress {
+        category: category.to_string(),
+        current_stage: 0,
+        stages: workflow.stages,
+        timeline,
+    };
+    let allows_voting: bool = progress.stage().allows_voting;
+
+    if let Some(ends_at) = progress.timeline[0].ends_at {
+        schedule_advance(key, ends_at);
+    }
+    PROGRESS.with(|p| p.borrow_mut().insert(key, progress));
+
+    Some(allows_voting)
+}
+
+// Stops the workflow where it is, e.g. when the owner ends the proposal by hand.
+pub(crate) fn stop(key: u64) {
+    PENDING_ADVANCES.with(|p| p.borrow_mut().remove(&key));
+}
+
+pub(crate) fn remove(key: u64) {
+    stop(key);
+    PROGRESS.with(|p| p.borrow_mut().remove(&key));
+}
+
+pub(crate) fn is_managed(key: u64) -> bool {
+    PROGRESS.with(|p| p.borrow().contains_key(&key))
+}
+
+// Whether a timer is still going to move the proposal to another stage.
+pub(crate) fn is_running(key: u64) -> bool {
+    PENDING_ADVANCES.with(|p| p.borrow().contains_key(&key))
+}
+
+pub(crate) fn editing_allowed(key: u64) -> bool {
+    PROGRESS
+        .with(|p| p.borrow().get(&key))
+        .is_none_or(|progress| progress.stage().allows_editing)
+}
+
+pub(crate) fn voting_allowed(key: u64) -> bool {
+    PROGRESS
+        .with(|p| p.borrow().get(&key))
+        .is_none_or(|progress| progress.stage().allows_voting)
+}
+
+pub(crate) fn rearm_timers() {
+    let pending: Vec<(u64, u64)> = PENDING_ADVANCES.with(|p| p.borrow().iter().collect());
+
+    for (key, due) in pending {
+        arm_timer(key, due);
+    }
+}
+
+fn schedule_advance(key: u64, due: u64) {
+    PENDING_ADVANCES.with(|p| p.borrow_mut().insert(key, due));
+    arm_timer(key, due);
+}
+
+fn arm_timer(key: u64, due: u64) {
+    let delay: u64 = due.saturating_sub(ic_cdk::api::time());
+    timers::set_timer(Duration::from_nanos(delay), move || advance(key, due));
+}
+
+fn advance(key: u64, due: u64) {
+    // A stale timer (the workflow was stopped, or re-armed after an upgrade) has nothing to do.
+    if PENDING_ADVANCES.with(|p| p.borrow().get(&key)) != Some(due) {
+        return;
+    }
+    PENDING_ADVANCES.with(|p| p.borrow_mut().remove(&key));
+
+    let mut progress: StageProgress = match PROGRESS.with(|p| p.borrow().get(&key)) {
+        Some(value) => value,
+        None => return,
+    };
+
+    let proposal_opt: Option<Proposal> = PROPOSAL_MAP.with(|p| p.borrow().get(&key));
+    let mut proposal: Proposal = match proposal_opt {
+        Some(value) => value,
+        // The proposal got deleted, drop its progress as well.
+        None => {
+            PROGRESS.with(|p| p.borrow_mut().remove(&key));
+            return;
+        }
+    };
+
+    if progress.current_stage as usize + 1 >= progress.stages.len() {
+        return;
+    }
+    let allows_voting: bool = progress.stages[progress.current_stage as usize + 1].allows_voting;
+
+    if proposal.is_active && !allows_voting {
+        close(key, &mut proposal);
+
+        // A tie extended the vote, the proposal stays in this stage until its window closes it.
+        if proposal.is_active {
+            PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+            changes::record_change(key);
+            return;
+        }
+    } else if allows_voting {
+        proposal.is_active = true;
+        proposal.outcome = None;
+        proposal.closed_at = None;
+    }
+    progress.current_stage += 1;
+
+    if let Some(ends_at) = progress.timeline[progress.current_stage as usize].ends_at {
+        schedule_advance(key, ends_at);
+    }
+    PROGRESS.with(|p| p.borrow_mut().insert(key, progress));
+
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    changes::record_change(key);
+}
@@ -0,0 +1,444 @@
+/*
+    Runs the real canister in PocketIC: many principals creating proposals, voting and delegating,
+    with upgrades in between, and checks that what's in stable memory comes out the same.
+    Needs the PocketIC server (POCKET_IC_BIN), a release build of the canister and one of
+    the baseline, `scripts/integration-tests.sh` does all of it and runs these with `--ignored`.
+*/
+use candid::{decode_one, encode_args, encode_one, CandidType, Deserialize, Principal, Reserved};
+use pocket_ic::{PocketIc, WasmResult};
+
+const WASM: &str = "../../target/wasm32-unknown-unknown/release/final_project_backend.wasm";
+const BASELINE_WASM: &str =
+    "../../target/baseline/wasm32-unknown-unknown/release/final_project_backend.wasm";
+
+// Only the fields the tests look at, Candid skips the rest. The admin always sees the counts.
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+struct Proposal {
+    title: String,
+    owner: Principal,
+    approve: Option<u64>,
+    reject: Option<u64>,
+    pass: Option<u64>,
+    is_active: bool,
+    delegated: DelegatedWeight,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+struct DelegatedWeight {
+    approve: Option<u64>,
+    reject: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+struct Stats {
+    total: u64,
+    open: u64,
+    closed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize)]
+struct BallotRecord {
+    choice: Choice,
+    weight: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize)]
+enum Choice {
+    Approve,
+    Reject,
+    Pass,
+    Abstain,
+}
+
+#[derive(Debug, Clone, Copy, CandidType)]
+enum SortBy {
+    MostVotes,
+}
+
+#[derive(CandidType)]
+enum Visibility {
+    Public,
+}
+
+#[derive(CandidType)]
+struct ChoiceLabels {}
+
+// The optional fields are left out, they come in as `None`.
+#[derive(CandidType)]
+struct CreateProposal {
+    title: String,
+    summary: String,
+    description: String,
+    is_active: bool,
+    visibility: Visibility,
+    depends_on: Vec<u64>,
+    save_as_draft: bool,
+    public_ballots: bool,
+    allow_vote_changes: bool,
+    choice_labels: ChoiceLabels,
+    role_weighted: bool,
+    members_only: bool,
+    frontend_only: bool,
+    personhood_gated: bool,
+    sealed_ballots: bool,
+}
+
+#[derive(CandidType)]
+struct DelegationPolicy {}
+
+// What `create_proposal` took in the baseline.
+#[derive(CandidType)]
+struct BaselineCreateProposal {
+    description: String,
+    is_active: bool,
+}
+
+// The config as the release that introduced it took it, the fields added since are left out.
+#[derive(CandidType)]
+struct FirstConfig {
+    admin: Principal,
+    default_quorum: u32,
+    max_description_len: u32,
+    allow_public_proposals: bool,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct Config {
+    admin: Principal,
+    default_quorum: u32,
+}
+
+#[derive(CandidType)]
+struct ConfigUpdate {
+    delegation: Option<Option<DelegationPolicy>>,
+}
+
+type CallResult<T> = Result<T, Reserved>;
+
+// Everything the tests compare across an upgrade.
+#[derive(Debug, PartialEq)]
+struct State {
+    by_key: Vec<(u64, Proposal)>,
+    by_votes: Vec<(u64, Proposal)>,
+    stats: Stats,
+}
+
+struct Env {
+    pic: PocketIc,
+    canister: Principal,
+    admin: Principal,
+    wasm: Vec<u8>,
+}
+
+fn user(n: u64) -> Principal {
+    Principal::self_authenticating(n.to_be_bytes())
+}
+
+fn proposal(title: &str) -> CreateProposal {
+    CreateProposal {
+        title: title.to_string(),
+        summary: String::new(),
+        description: String::new(),
+        is_active: true,
+        visibility: Visibility::Public,
+        depends_on: vec![],
+        save_as_draft: false,
+        public_ballots: true,
+        allow_vote_changes: false,
+        choice_labels: ChoiceLabels {},
+        role_weighted: false,
+        members_only: false,
+        frontend_only: false,
+        personhood_gated: false,
+        sealed_ballots: false,
+    }
+}
+
+fn reply(res: Result<WasmResult, pocket_ic::UserError>) -> Vec<u8> {
+    match res.expect("the call failed") {
+        WasmResult::Reply(bytes) => bytes,
+        WasmResult::Reject(message) => panic!("rejected: {}", message),
+    }
+}
+
+impl Env {
+    fn new() -> Self {
+        // No config, the installer becomes the admin.
+        Env::with_installed(WASM, encode_one(None::<()>).unwrap())
+    }
+
+    // Installs the wasm at `path`, `upgrade` moves it on to the current build.
+    fn with_installed(path: &str, arg: Vec<u8>) -> Self {
+        let wasm: Vec<u8> = std::fs::read(WASM).expect("build the canister first");
+        let installed: Vec<u8> = std::fs::read(path).expect("build the canister first");
+        let pic: PocketIc = PocketIc::new();
+        let admin: Principal = user(0);
+
+        let canister: Principal = pic.create_canister_with_settings(Some(admin), None);
+        pic.add_cycles(canister, 100_000_000_000_000);
+        pic.install_canister(canister, installed, arg, Some(admin));
+
+        let env: Env = Env {
+            pic,
+            canister,
+            admin,
+            wasm,
+        };
+        env.settle();
+        env
+    }
+
+    // Votes wait for the ballot secret, which the canister draws from `raw_rand` right after the install.
+    fn settle(&self) {
+        for _ in 0..5 {
+            self.pic.tick();
+        }
+    }
+
+    fn update<T: for<'a> Deserialize<'a> + CandidType>(
+        &self,
+        sender: Principal,
+        method: &str,
+        arg: Vec<u8>,
+    ) -> T {
+        let bytes: Vec<u8> = reply(self.pic.update_call(self.canister, sender, method, arg));
+        decode_one(&bytes).unwrap()
+    }
+
+    fn query<T: for<'a> Deserialize<'a> + CandidType>(&self, method: &str, arg: Vec<u8>) -> T {
+        let bytes: Vec<u8> = reply(self.pic.query_call(self.canister, self.admin, method, arg));
+        decode_one(&bytes).unwrap()
+    }
+
+    fn create(&self, owner: Principal, key: u64) {
+        let arg: Vec<u8> = encode_args((key, proposal(&format!("Proposal {}", key)))).unwrap();
+        let res: CallResult<u64> = self.update(owner, "create_proposal", arg);
+        assert_eq!(res.ok(), Some(key));
+    }
+
+    fn vote(&self, voter: Principal, key: u64, choice: Choice) -> bool {
+        let res: CallResult<Reserved> =
+            self.update(voter, "vote", encode_args((key, choice)).unwrap());
+        res.is_ok()
+    }
+
+    fn get(&self, key: u64) -> Proposal {
+        let res: Option<Proposal> = self.query("get_proposal", encode_one(key).unwrap());
+        res.expect("the proposal is gone")
+    }
+
+    fn upgrade(&self) {
+        self.upgrade_with(encode_one(None::<()>).unwrap());
+    }
+
+    fn upgrade_with(&self, arg: Vec<u8>) {
+        self.pic
+            .upgrade_canister(self.canister, self.wasm.clone(), arg, Some(self.admin))
+            .expect("the upgrade failed");
+        self.settle();
+    }
+
+    fn config(&self) -> Config {
+        self.query("get_config", encode_args(()).unwrap())
+    }
+
+    fn state(&self) -> State {
+        let by_key: Vec<(u64, Proposal)> = self.query(
+            "list_proposals",
+            encode_args((0u64, 100u64, None::<()>, None::<SortBy>)).unwrap(),
+        );
+        let by_votes: Vec<(u64, Proposal)> = self.query(
+            "list_proposals",
+            encode_args((0u64, 100u64, None::<()>, Some(SortBy::MostVotes))).unwrap(),
+        );
+        let stats: Stats = self.query("get_stats", encode_args(()).unwrap());
+
+        State {
+            by_key,
+            by_votes,
+            stats,
+        }
+    }
+
+    fn ballots(&self, key: u64) -> Vec<(Principal, BallotRecord)> {
+        self.query("list_ballots", encode_args((key, 0u64, 100u64)).unwrap())
+    }
+}
+
+#[test]
+#[ignore = "needs POCKET_IC_BIN and the release wasm, see scripts/integration-tests.sh"]
+fn proposals_ballots_and_indexes_survive_upgrades() {
+    let env: Env = Env::new();
+
+    for key in 0..15 {
+        env.create(user(100 + key), key);
+    }
+    // Proposal `key` gets `key` approvals, so the votes index has a clear order.
+    // That's 14 votes for the busiest voter, under what the rate limit lets through at once.
+    for key in 0..15 {
+        for voter in 0..key {
+            assert!(env.vote(user(1_000 + voter), key, Choice::Approve));
+        }
+    }
+    assert!(env.vote(user(2_000), 3, Choice::Reject));
+
+    let before: State = env.state();
+    let ballots_before: Vec<(Principal, BallotRecord)> = env.ballots(3);
+    assert_eq!(before.by_key.len(), 15);
+    assert_eq!(before.by_votes.first().map(|(key, _)| *key), Some(14));
+    assert_eq!(before.stats.total, 15);
+    assert_eq!(ballots_before.len(), 4);
+
+    env.upgrade();
+    assert_eq!(env.state(), before);
+    assert_eq!(env.ballots(3), ballots_before);
+
+    // Nobody can vote twice because an upgrade went by.
+    assert!(!env.vote(user(1_000), 14, Choice::Reject));
+    assert!(env.vote(user(2_001), 14, Choice::Approve));
+    assert_eq!(env.get(14).approve, Some(15));
+
+    env.upgrade();
+    assert_eq!(env.get(14).approve, Some(15));
+    assert_eq!(env.state().by_votes.first().map(|(key, _)| *key), Some(14));
+}
+
+#[test]
+#[ignore = "needs POCKET_IC_BIN and the release wasm, see scripts/integration-tests.sh"]
+fn concurrent_votes_are_counted_once() {
+    let env: Env = Env::new();
+    env.create(user(1), 0);
+
+    // Every voter sends two ballots at once, both are in flight before either is executed.
+    let mut messages = vec![];
+    for voter in 0..50 {
+        for choice in [Choice::Approve, Choice::Reject] {
+            let arg: Vec<u8> = encode_args((0u64, choice)).unwrap();
+            messages.push(
+                env.pic
+                    .submit_call(env.canister, user(1_000 + voter), "vote", arg)
+                    .unwrap(),
+            );
+        }
+    }
+
+    let accepted: usize = messages
+        .into_iter()
+        .map(|message| reply(env.pic.await_call(message)))
+        .filter(|bytes| decode_one::<CallResult<Reserved>>(bytes).unwrap().is_ok())
+        .count();
+
+    let proposal: Proposal = env.get(0);
+    assert_eq!(accepted, 50);
+    assert_eq!(proposal.approve.unwrap() + proposal.reject.unwrap(), 50);
+    assert_eq!(env.ballots(0).len(), 50);
+
+    env.upgrade();
+    assert_eq!(env.get(0), proposal);
+}
+
+#[test]
+#[ignore = "needs POCKET_IC_BIN and the release wasm, see scripts/integration-tests.sh"]
+fn delegations_survive_upgrades() {
+    let env: Env = Env::new();
+    let update: ConfigUpdate = ConfigUpdate {
+        delegation: Some(Some(DelegationPolicy {})),
+    };
+    let res: CallResult<()> = env.update(env.admin, "set_config", encode_one(update).unwrap());
+    assert!(res.is_ok());
+
+    let owner: Principal = user(1);
+    let delegate: Principal = user(2);
+    env.create(owner, 0);
+
+    for delegator in 0..10 {
+        let res: CallResult<()> = env.update(
+            user(1_000 + delegator),
+            "delegate_to",
+            encode_one(delegate).unwrap(),
+        );
+        assert!(res.is_ok());
+    }
+
+    env.upgrade();
+    assert!(env.vote(delegate, 0, Choice::Approve));
+    // Votes of their own override the delegation.
+    assert!(env.vote(user(1_000), 0, Choice::Reject));
+
+    env.upgrade();
+    let res: CallResult<()> = env.update(owner, "end_proposal", encode_one(0u64).unwrap());
+    assert!(res.is_ok());
+
+    let proposal: Proposal = env.get(0);
+    assert!(!proposal.is_active);
+    assert_eq!(proposal.delegated.approve, Some(9));
+    assert_eq!(proposal.approve, Some(10));
+    assert_eq!(proposal.reject, Some(1));
+}
+
+#[test]
+#[ignore = "needs POCKET_IC_BIN and both wasms, see scripts/integration-tests.sh"]
+fn upgrades_from_the_baseline() {
+    let env: Env = Env::with_installed(BASELINE_WASM, encode_args(()).unwrap());
+    let owner: Principal = user(1);
+
+    for key in 0..3u64 {
+        let create: BaselineCreateProposal = BaselineCreateProposal {
+            description: format!("Baseline {}\nWhat it is about.", key),
+            is_active: true,
+        };
+        let _: Option<Reserved> = env.update(
+            owner,
+            "create_proposal",
+            encode_args((key, create)).unwrap(),
+        );
+    }
+    // The baseline has no `Abstain`, and counts `Pass` down from zero.
+    for voter in 0..3 {
+        assert!(env.vote(user(1_000 + voter), 0, Choice::Approve));
+    }
+    assert!(env.vote(user(1_003), 0, Choice::Reject));
+    assert!(env.vote(user(1_004), 0, Choice::Pass));
+    assert!(env.vote(user(1_005), 0, Choice::Pass));
+    assert!(env.vote(user(1_000), 1, Choice::Approve));
+    let res: CallResult<()> = env.update(owner, "end_proposal", encode_one(1u64).unwrap());
+    assert!(res.is_ok());
+
+    let config: FirstConfig = FirstConfig {
+        admin: env.admin,
+        default_quorum: 2,
+        max_description_len: 1000,
+        allow_public_proposals: true,
+    };
+    env.upgrade_with(encode_one(Some(config)).unwrap());
+
+    let open: Proposal = env.get(0);
+    assert_eq!(open.title, "Baseline 0");
+    assert_eq!(open.owner, owner);
+    assert_eq!(
+        (open.approve, open.reject, open.pass),
+        (Some(3), Some(1), Some(2))
+    );
+    assert!(open.is_active);
+
+    let closed: Proposal = env.get(1);
+    assert_eq!((closed.approve, closed.pass), (Some(1), Some(0)));
+    assert!(!closed.is_active);
+    assert_eq!(env.state().by_key.len(), 3);
+    let stats: Stats = env.state().stats;
+    assert_eq!((stats.total, stats.open, stats.closed), (3, 2, 1));
+    assert_eq!(env.config().default_quorum, 2);
+
+    // The baseline's voters are still on the proposal, they can't vote again.
+    assert!(!env.vote(user(1_000), 0, Choice::Approve));
+    assert!(env.vote(user(1_006), 0, Choice::Pass));
+    assert!(!env.vote(user(1_006), 1, Choice::Approve));
+
+    // Without an argument the next upgrade keeps the config, and nothing is migrated twice.
+    env.upgrade();
+    assert_eq!(env.config().admin, env.admin);
+    assert_eq!(env.config().default_quorum, 2);
+    assert_eq!(env.get(0).pass, Some(3));
+    assert_eq!(env.state().by_key.len(), 3);
+    assert_eq!(env.state().stats.total, 3);
+}